@@ -46,7 +46,7 @@
         private_key = toString cfg.client.private_key;
       };
     }
-    // lib.optionalAttrs (cfg.deployment.cache_url != null || cfg.deployment.max_deployment_age_minutes != 30 || !cfg.deployment.dry_run_first || cfg.deployment.fallback_to_local_build || cfg.deployment.deployment_timeout_minutes != 60 || cfg.deployment.deployment_poll_interval != "15m") {
+    // lib.optionalAttrs (cfg.deployment.cache_url != null || cfg.deployment.max_deployment_age_minutes != 30 || !cfg.deployment.dry_run_first || cfg.deployment.fallback_to_local_build || cfg.deployment.deployment_timeout_minutes != 60 || cfg.deployment.deployment_poll_interval != "15m" || cfg.deployment.max_concurrent_copies != 1 || cfg.deployment.prefetch_next_target || cfg.deployment.paused) {
       deployment =
         {
           max_deployment_age_minutes = cfg.deployment.max_deployment_age_minutes;
@@ -55,6 +55,9 @@
           deployment_timeout_minutes = cfg.deployment.deployment_timeout_minutes;
           deployment_poll_interval = cfg.deployment.deployment_poll_interval;
           require_sigs = cfg.deployment.require_sigs;
+          max_concurrent_copies = cfg.deployment.max_concurrent_copies;
+          prefetch_next_target = cfg.deployment.prefetch_next_target;
+          paused = cfg.deployment.paused;
         }
         // lib.optionalAttrs (cfg.deployment.cache_url != null) {
           cache_url = cfg.deployment.cache_url;
@@ -136,6 +139,9 @@
           enable_whitelist = cfg.vulnix.enable_whitelist;
           extra_args = cfg.vulnix.extra_args;
           poll_interval = cfg.vulnix.poll_interval;
+          generate_sbom = cfg.vulnix.generate_sbom;
+          sbom_format = cfg.vulnix.sbom_format;
+          scan_build_inputs = cfg.vulnix.scan_build_inputs;
         }
         // lib.optionalAttrs (cfg.vulnix.whitelist_path != null) {
           whitelist_path = toString cfg.vulnix.whitelist_path;
@@ -166,6 +172,12 @@
         // lib.optionalAttrs (cfg.cache.push_filter != null) {
           push_filter = cfg.cache.push_filter;
         }
+        // lib.optionalAttrs (cfg.cache.max_push_closure_size_bytes != null) {
+          max_push_closure_size_bytes = cfg.cache.max_push_closure_size_bytes;
+        }
+        // lib.optionalAttrs (cfg.cache.push_outputs != {}) {
+          push_outputs = cfg.cache.push_outputs;
+        }
         // lib.optionalAttrs (cfg.cache.s3_region != null) {
           s3_region = cfg.cache.s3_region;
         }
@@ -872,11 +884,26 @@ in {
         default = "1m";
         description = "Polling interval for CVE jobs";
       };
+      generate_sbom = lib.mkOption {
+        type = lib.types.bool;
+        default = true;
+        description = "Record an SBOM for every scanned derivation";
+      };
+      sbom_format = lib.mkOption {
+        type = lib.types.enum ["cyclonedx" "spdx"];
+        default = "cyclonedx";
+        description = "Format of the recorded SBOMs";
+      };
+      scan_build_inputs = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = "Also scan the build-time inputs of each derivation, not only its runtime closure";
+      };
     };
 
     cache = {
       cache_type = lib.mkOption {
-        type = lib.types.enum ["S3" "Attic" "Http" "Nix"];
+        type = lib.types.enum ["S3" "Attic" "Http" "Nix" "SshNg" "Cachix"];
         default = "Nix";
         description = "Type of cache to use";
       };
@@ -905,6 +932,17 @@ in {
         default = null;
         description = "Push filter";
       };
+      max_push_closure_size_bytes = lib.mkOption {
+        type = lib.types.nullOr lib.types.ints.unsigned;
+        default = null;
+        description = "Fail pushes of paths whose closure is larger than this many bytes";
+      };
+      push_outputs = lib.mkOption {
+        type = lib.types.attrsOf lib.types.str;
+        default = {};
+        example = {mytool = "bin";};
+        description = "Output to push instead of `out`, keyed by system hostname or package name";
+      };
       parallel_uploads = lib.mkOption {
         type = lib.types.ints.positive;
         default = 4;
@@ -1005,6 +1043,21 @@ in {
         default = true;
         description = "Check sigs before deployment";
       };
+      max_concurrent_copies = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 1;
+        description = "Prefetch copies the agent runs at once; deployments always run one at a time";
+      };
+      prefetch_next_target = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = "Copy the upcoming target from the cache before it is deployed";
+      };
+      paused = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = "Kill switch: stop the forge from changing any system's desired target";
+      };
     };
     systems = lib.mkOption {
       type = lib.types.listOf (lib.types.submodule {
@@ -1538,7 +1591,8 @@ in {
 
       path = with pkgs;
         [nix git vulnix systemd nix-fast-build nix-eval-jobs]
-        ++ lib.optional (cfg.cache.cache_type == "Attic") attic-client;
+        ++ lib.optional (cfg.cache.cache_type == "Attic") attic-client
+        ++ lib.optional (cfg.cache.cache_type == "Cachix") cachix;
 
       # Merge existing env with any Environment=… pairs from systemd_properties
       environment = lib.mkMerge [
@@ -0,0 +1,11 @@
+-- Allow cache push jobs to be deferred while a destination's circuit breaker
+-- is open, and make the existing 'permanently_failed' status legal.
+ALTER TABLE cache_push_jobs
+    DROP CONSTRAINT IF EXISTS cache_push_jobs_status_check;
+
+ALTER TABLE cache_push_jobs
+    ADD CONSTRAINT cache_push_jobs_status_check CHECK (status IN ('pending', 'in_progress', 'completed', 'failed', 'permanently_failed', 'deferred'));
+
+CREATE INDEX IF NOT EXISTS idx_cache_push_jobs_deferred ON cache_push_jobs (retry_after)
+WHERE
+    status = 'deferred';
@@ -0,0 +1,7 @@
+-- Coarse classification of build failures (network, hash_mismatch, ...)
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS failure_kind text;
+
+CREATE INDEX IF NOT EXISTS idx_derivations_failure_kind ON derivations (failure_kind)
+WHERE
+    failure_kind IS NOT NULL;
@@ -0,0 +1,128 @@
+-- Dependency-aware build ordering
+--
+-- Package derivations that more than one queued system depends on are
+-- offered first so they are realised once instead of by every system build
+-- that needs them. Systems are held back while one of their dependencies is
+-- being built by another worker.
+CREATE OR REPLACE VIEW view_buildable_derivations AS
+WITH queued_systems AS (
+    -- NixOS systems waiting to build (dry-run-complete or build-pending)
+    SELECT
+        d.id,
+        d.derivation_name,
+        d.derivation_type,
+        d.derivation_path,
+        d.status_id,
+        c.commit_timestamp
+    FROM
+        derivations d
+        JOIN commits c ON c.id = d.commit_id
+    WHERE
+        d.derivation_type = 'nixos'
+        AND d.status_id IN (5, 7)
+        AND d.derivation_path IS NOT NULL
+        AND d.attempt_count <= 5
+),
+shared_dependencies AS (
+    -- Unbuilt, unreserved packages needed by two or more queued systems
+    SELECT
+        p.id,
+        p.derivation_name,
+        p.derivation_type,
+        p.derivation_path,
+        p.status_id,
+        (ARRAY_AGG(qs.id ORDER BY qs.commit_timestamp DESC, qs.id))[1] AS nixos_id,
+        MAX(qs.commit_timestamp) AS nixos_commit_ts,
+        COUNT(DISTINCT qs.id) AS dependent_systems
+    FROM
+        queued_systems qs
+        JOIN derivation_dependencies dd ON dd.derivation_id = qs.id
+        JOIN derivations p ON p.id = dd.depends_on_id
+        LEFT JOIN build_reservations br ON br.derivation_id = p.id
+    WHERE
+        p.derivation_type = 'package'
+        AND p.status_id IN (5, 7)
+        AND p.derivation_path IS NOT NULL
+        AND COALESCE(p.attempt_count, 0) <= 5
+        AND br.id IS NULL
+    GROUP BY
+        p.id,
+        p.derivation_name,
+        p.derivation_type,
+        p.derivation_path,
+        p.status_id
+    HAVING
+        COUNT(DISTINCT qs.id) > 1
+),
+buildable_systems AS (
+    -- Unreserved systems with no dependency currently being built
+    SELECT
+        qs.id,
+        qs.derivation_name,
+        qs.derivation_type,
+        qs.derivation_path,
+        qs.status_id,
+        qs.id AS nixos_id,
+        qs.commit_timestamp AS nixos_commit_ts
+    FROM
+        queued_systems qs
+        LEFT JOIN build_reservations br ON br.derivation_id = qs.id
+    WHERE
+        br.id IS NULL
+        AND NOT EXISTS (
+            SELECT
+                1
+            FROM
+                derivation_dependencies dd
+                JOIN derivations p ON p.id = dd.depends_on_id
+            WHERE
+                dd.derivation_id = qs.id
+                AND p.status_id = 8 -- BuildInProgress
+)
+),
+candidates AS (
+    SELECT
+        id,
+        derivation_name,
+        derivation_type,
+        derivation_path,
+        status_id,
+        nixos_id,
+        nixos_commit_ts,
+        0 AS tier,
+        dependent_systems AS fan_out
+    FROM
+        shared_dependencies
+    UNION ALL
+    SELECT
+        id,
+        derivation_name,
+        derivation_type,
+        derivation_path,
+        status_id,
+        nixos_id,
+        nixos_commit_ts,
+        1 AS tier,
+        0 AS fan_out
+    FROM
+        buildable_systems
+)
+SELECT
+    id,
+    derivation_name,
+    derivation_type,
+    derivation_path,
+    status_id,
+    nixos_id,
+    nixos_commit_ts,
+    0::bigint AS active_workers,
+    ROW_NUMBER() OVER (ORDER BY tier,
+        fan_out DESC,
+        nixos_commit_ts DESC,
+        id ASC) AS queue_position
+FROM
+    candidates
+ORDER BY
+    queue_position;
+
+COMMENT ON VIEW view_buildable_derivations IS 'Derivations ready to be claimed by workers: shared package dependencies first (most dependent systems first), then systems whose dependencies are not being built elsewhere (newest commits first)';
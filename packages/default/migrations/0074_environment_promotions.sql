@@ -0,0 +1,17 @@
+-- Record of store paths promoted from one environment to another.
+-- Rows sharing a promotion_id were promoted together in one call.
+CREATE TABLE IF NOT EXISTS environment_promotions (
+    id serial PRIMARY KEY,
+    promotion_id uuid NOT NULL,
+    from_environment_id uuid NOT NULL REFERENCES environments (id),
+    to_environment_id uuid NOT NULL REFERENCES environments (id),
+    source_hostname text NOT NULL,
+    target_hostname text NOT NULL,
+    store_path text NOT NULL,
+    previous_target text,
+    promoted_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_environment_promotions_promotion_id ON environment_promotions (promotion_id);
+
+CREATE INDEX IF NOT EXISTS idx_environment_promotions_target ON environment_promotions (target_hostname, promoted_at DESC);
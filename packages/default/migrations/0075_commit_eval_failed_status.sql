@@ -0,0 +1,14 @@
+-- Commits that exhaust their evaluation attempts are dead-lettered as
+-- 'eval_failed' and only retried after a manual reset
+ALTER TABLE commits
+    DROP CONSTRAINT IF EXISTS commits_evaluation_status_check;
+
+UPDATE
+    commits
+SET
+    evaluation_status = 'eval_failed'
+WHERE
+    evaluation_status = 'failed';
+
+ALTER TABLE commits
+    ADD CONSTRAINT commits_evaluation_status_check CHECK (evaluation_status IN ('pending', 'in_progress', 'complete', 'eval_failed'));
@@ -0,0 +1,6 @@
+-- Cancellation requests for in-progress builds
+--
+-- The building worker polls this on every heartbeat; a request older than the
+-- current attempt's started_at is ignored so a retried build is not killed.
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS cancellation_requested_at timestamptz;
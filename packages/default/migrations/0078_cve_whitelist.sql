@@ -0,0 +1,14 @@
+-- Accepted CVE risks
+--
+-- Findings for a whitelisted CVE are still recorded in package_vulnerabilities
+-- but flagged is_whitelisted and left out of a scan's severity counts until
+-- expires_at passes, after which the next scan reports them again.
+CREATE TABLE IF NOT EXISTS cve_whitelist (
+    cve_id varchar(20) PRIMARY KEY,
+    reason text NOT NULL,
+    expires_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    updated_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_cve_whitelist_expires_at ON cve_whitelist (expires_at);
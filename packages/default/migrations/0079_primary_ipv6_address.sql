@@ -0,0 +1,3 @@
+-- Dual-stack hosts report their IPv6 primary alongside the IPv4 one
+ALTER TABLE system_states
+    ADD COLUMN IF NOT EXISTS primary_ipv6_address text;
@@ -0,0 +1,4 @@
+-- NAR hash of a built derivation's store path, handed to agents so they can
+-- check that what they copied from the cache is exactly what was built
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS nar_hash text;
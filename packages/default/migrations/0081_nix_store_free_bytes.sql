@@ -0,0 +1,3 @@
+-- Free space on the agent's nix store filesystem, reported with system state
+ALTER TABLE system_states
+    ADD COLUMN IF NOT EXISTS nix_store_free_bytes bigint;
@@ -0,0 +1,6 @@
+-- Hostname of the builder that produced a derivation's store path, so
+-- host-specific build problems can be traced back to a machine
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS built_by_host text;
+
+CREATE INDEX IF NOT EXISTS idx_derivations_built_by_host ON derivations (built_by_host);
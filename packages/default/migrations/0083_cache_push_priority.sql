@@ -0,0 +1,37 @@
+-- Push priority for cache jobs: toplevels of systems waiting to deploy them
+-- go ahead of package dependencies so rollouts aren't stuck behind the queue
+ALTER TABLE cache_push_jobs
+    ADD COLUMN IF NOT EXISTS priority integer NOT NULL DEFAULT 0;
+
+-- 100 for a NixOS toplevel whose system follows auto_latest (and deploys it
+-- as soon as it is pushed) or has it as its desired_target, 0 otherwise
+CREATE OR REPLACE FUNCTION cache_push_priority (p_derivation_id integer)
+    RETURNS integer
+    AS $$
+    SELECT
+        CASE WHEN EXISTS (
+            SELECT
+                1
+            FROM
+                derivations d
+                JOIN systems s ON s.hostname = d.derivation_name
+            WHERE
+                d.id = p_derivation_id
+                AND d.derivation_type = 'nixos'
+                AND s.is_active
+                AND (s.deployment_policy = 'auto_latest'
+                    OR s.desired_target = d.store_path)) THEN
+            100
+        ELSE
+            0
+        END;
+$$
+LANGUAGE sql
+STABLE;
+
+UPDATE
+    cache_push_jobs
+SET
+    priority = cache_push_priority (derivation_id)
+WHERE
+    status IN ('pending', 'failed', 'deferred');
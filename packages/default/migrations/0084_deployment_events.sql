@@ -0,0 +1,35 @@
+-- Append-only audit trail of deployment target changes: who or what set a
+-- host's desired_target, and what the agent reported after deploying it
+CREATE TABLE IF NOT EXISTS deployment_events (
+    id bigserial PRIMARY KEY,
+    hostname text NOT NULL,
+    -- 'target_set' when the forge changes desired_target,
+    -- 'deployed' when the agent reports a cf_deployment state change
+    event_type text NOT NULL,
+    old_target text,
+    new_target text,
+    -- deployment policy (or 'promotion') responsible for the change
+    policy text,
+    -- for 'deployed': 'succeeded' if the host landed on its desired_target,
+    -- 'off_target' otherwise
+    outcome text,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_deployment_events_hostname ON deployment_events (hostname, id DESC);
+
+CREATE OR REPLACE FUNCTION reject_deployment_event_changes ()
+    RETURNS TRIGGER
+    AS $$
+BEGIN
+    RAISE EXCEPTION 'deployment_events is append-only';
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS deployment_events_append_only ON deployment_events;
+
+CREATE TRIGGER deployment_events_append_only
+    BEFORE UPDATE OR DELETE ON deployment_events
+    FOR EACH ROW
+    EXECUTE FUNCTION reject_deployment_event_changes ();
@@ -0,0 +1,3 @@
+-- How a cf_deployment report's system reached the host, for cache hit rates
+ALTER TABLE system_states
+    ADD COLUMN IF NOT EXISTS deployment_source text CHECK (deployment_source IN ('cache', 'local_build'));
@@ -0,0 +1,5 @@
+-- Nix system (e.g. 'aarch64-linux') a derivation builds for. Builders only
+-- claim derivations for the systems they can build; NULL means unknown and
+-- any builder may take it.
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS system_arch text;
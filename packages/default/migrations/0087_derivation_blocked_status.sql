@@ -0,0 +1,8 @@
+-- Derivations that cannot build because a dependency has failed. They are
+-- not offered to workers (view_buildable_derivations only lists
+-- dry-run-complete and build-pending) and go back to build-pending once no
+-- dependency is failed any more.
+INSERT INTO derivation_statuses (id, name, description, is_terminal, is_success, display_order)
+    VALUES (15, 'blocked', 'Waiting on a failed dependency', FALSE, FALSE, 82)
+ON CONFLICT (id)
+    DO NOTHING;
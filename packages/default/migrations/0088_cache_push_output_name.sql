@@ -0,0 +1,4 @@
+-- Which output of a multi-output derivation a cache push job pushes. NULL
+-- means the default `out` output.
+ALTER TABLE cache_push_jobs
+    ADD COLUMN IF NOT EXISTS output_name text;
@@ -0,0 +1,4 @@
+-- Hostname of the builder whose last attempt at a derivation failed, so a
+-- retry can be steered to a different machine
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS failed_on_host text;
@@ -0,0 +1,4 @@
+-- Systems that are only ever served from the binary cache. A missing store
+-- path on one of these is flagged for an operator instead of rebuilt locally
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS cache_only boolean NOT NULL DEFAULT false;
@@ -0,0 +1,4 @@
+-- Builder labels (e.g. bigmem) a derivation needs; only workers offering all
+-- of them claim it
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS required_labels text[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,8 @@
+-- Commits whose derivations have all been built (or were already built) are
+-- marked 'built', so a fully processed commit is distinguishable from one
+-- that is merely evaluated
+ALTER TABLE commits
+    DROP CONSTRAINT IF EXISTS commits_evaluation_status_check;
+
+ALTER TABLE commits
+    ADD CONSTRAINT commits_evaluation_status_check CHECK (evaluation_status IN ('pending', 'in_progress', 'complete', 'built', 'eval_failed'));
@@ -0,0 +1,10 @@
+-- Software bill of materials of a built derivation, regenerated whenever it
+-- is CVE scanned
+CREATE TABLE IF NOT EXISTS derivation_sboms (
+    derivation_id integer PRIMARY KEY REFERENCES derivations (id) ON DELETE CASCADE,
+    -- 'cyclonedx' or 'spdx'
+    format text NOT NULL,
+    document jsonb NOT NULL,
+    component_count integer NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
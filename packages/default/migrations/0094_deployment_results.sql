@@ -0,0 +1,40 @@
+-- Last deployment result each agent reported with its heartbeat
+CREATE TABLE IF NOT EXISTS deployment_results (
+    hostname text PRIMARY KEY,
+    -- Target the forge asked for when the result came about
+    target text,
+    -- DeploymentResult variant, e.g. 'success_from_cache' or 'failed'
+    result text NOT NULL,
+    error text,
+    -- The serialized DeploymentResult
+    detail jsonb NOT NULL,
+    -- When this result was first reported
+    reported_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+-- Per active system with a desired target: 'converged' when it runs that
+-- target, 'failed' when its last attempt at it failed, else 'converging'
+CREATE OR REPLACE VIEW view_deployment_convergence AS
+SELECT
+    s.hostname,
+    s.desired_target,
+    current_state.store_path AS current_target,
+    dr.result AS last_result,
+    dr.error AS last_error,
+    dr.reported_at AS last_reported_at,
+    CASE
+        WHEN current_state.store_path = s.desired_target THEN 'converged'
+        WHEN dr.result = 'failed' AND dr.target = s.desired_target THEN 'failed'
+        ELSE 'converging'
+    END AS convergence
+FROM systems s
+LEFT JOIN LATERAL (
+    SELECT ss.store_path
+    FROM system_states ss
+    WHERE ss.hostname = s.hostname
+    ORDER BY ss.timestamp DESC
+    LIMIT 1
+) current_state ON true
+LEFT JOIN deployment_results dr ON dr.hostname = s.hostname
+WHERE s.is_active = true
+  AND s.desired_target IS NOT NULL;
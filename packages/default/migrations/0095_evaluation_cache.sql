@@ -0,0 +1,10 @@
+-- Fully evaluated commit for each set of evaluation inputs of a flake: the
+-- hash of its flake.lock, the sources that can affect evaluation and the
+-- configured systems. Commits with the same key reuse its derivations.
+CREATE TABLE IF NOT EXISTS evaluation_cache (
+    flake_id integer NOT NULL REFERENCES flakes (id) ON DELETE CASCADE,
+    input_key text NOT NULL,
+    commit_id integer NOT NULL REFERENCES commits (id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (flake_id, input_key)
+);
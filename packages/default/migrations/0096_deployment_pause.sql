@@ -0,0 +1,9 @@
+-- Global kill switch: while paused the deployment policy manager changes no
+-- system's desired target
+CREATE TABLE IF NOT EXISTS deployment_pause (
+    id boolean PRIMARY KEY DEFAULT true CHECK (id),
+    paused boolean NOT NULL DEFAULT false,
+    changed_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO deployment_pause (id) VALUES (true) ON CONFLICT DO NOTHING;
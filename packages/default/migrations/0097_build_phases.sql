@@ -0,0 +1,6 @@
+-- Phase a running build is in (download, unpack, configure, build, install,
+-- fixup) and whole seconds spent in each so far, written with the build
+-- heartbeat
+ALTER TABLE derivations
+    ADD COLUMN IF NOT EXISTS build_phase text,
+    ADD COLUMN IF NOT EXISTS build_phase_seconds jsonb;
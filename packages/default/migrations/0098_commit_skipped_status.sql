@@ -0,0 +1,20 @@
+-- Commits an operator marked as never to be evaluated, e.g. known-broken
+-- commits in the history of a newly onboarded flake, are 'skipped'
+ALTER TABLE commits
+    DROP CONSTRAINT IF EXISTS commits_evaluation_status_check;
+
+ALTER TABLE commits
+    ADD CONSTRAINT commits_evaluation_status_check CHECK (evaluation_status IN ('pending', 'in_progress', 'complete', 'built', 'eval_failed', 'skipped'));
+
+-- Who skipped or unskipped a commit, and why
+CREATE TABLE IF NOT EXISTS commit_skip_log (
+    id serial PRIMARY KEY,
+    commit_id integer NOT NULL REFERENCES commits (id) ON DELETE CASCADE,
+    -- 'skip' or 'unskip'
+    action text NOT NULL CHECK (action IN ('skip', 'unskip')),
+    actor text NOT NULL,
+    reason text,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_commit_skip_log_commit ON commit_skip_log (commit_id, created_at);
@@ -0,0 +1,8 @@
+-- A derivation is pushed to every cache destination of its environments, so
+-- it may have one pending or in-progress job per destination rather than
+-- one in total
+DROP INDEX IF EXISTS idx_cache_push_jobs_derivation_unique;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_cache_push_jobs_derivation_unique ON cache_push_jobs (derivation_id, COALESCE(cache_destination, ''))
+WHERE
+    status IN ('pending', 'in_progress');
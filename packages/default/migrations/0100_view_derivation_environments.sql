@@ -0,0 +1,21 @@
+-- Environments each derivation belongs to, used to pick its cache
+-- destinations. A system toplevel belongs to the environment of its own
+-- system; a package to the environment of every system depending on it.
+-- Derivations of systems without an environment are absent.
+CREATE OR REPLACE VIEW view_derivation_environments AS
+SELECT
+    d.id AS derivation_id,
+    e.name AS environment_name
+FROM derivations d
+JOIN systems s ON s.hostname = d.derivation_name
+JOIN environments e ON e.id = s.environment_id
+WHERE d.derivation_type = 'nixos'
+UNION
+SELECT
+    dd.depends_on_id AS derivation_id,
+    e.name AS environment_name
+FROM derivation_dependencies dd
+JOIN derivations sd ON sd.id = dd.derivation_id
+JOIN systems s ON s.hostname = sd.derivation_name
+JOIN environments e ON e.id = s.environment_id
+WHERE sd.derivation_type = 'nixos';
@@ -1,23 +1,34 @@
 use anyhow::{Context, Result, bail};
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
-use crystal_forge::deployment::agent::{AgentDeploymentManager, DeploymentResult, readlink_path};
+use clap::{Parser, Subcommand};
+use crystal_forge::deployment::agent::{
+    AgentDeploymentManager, DeploymentReport, DeploymentResult, nix_store_free_bytes,
+    readlink_path, take_deployment_source,
+};
 use crystal_forge::handlers::agent::heartbeat::LogResponse;
-use crystal_forge::config::CrystalForgeConfig;
+use crystal_forge::config::{CrystalForgeConfig, NotificationEvent};
 use crystal_forge::models::system_states::SystemState;
+use crystal_forge::notifications::{self, Notification};
+use crystal_forge::shutdown::{self, ShutdownRx};
+use crystal_forge::telemetry;
 use ed25519_dalek::{Signer, SigningKey};
+use nix::errno::Errno;
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
 use reqwest::blocking::Client;
 use serde_json::Value;
+use std::os::fd::{AsFd, AsRawFd};
 use std::{ffi::OsStr, fs, path::PathBuf, process::Command, sync::Arc};
+use tokio::io::unix::AsyncFd;
 use tokio::sync::Mutex;
-use tokio::time::{Duration, sleep};
-use tracing::{error, info};
-use tracing_subscriber::EnvFilter;
+use tokio::time::Duration;
+use tracing::{Instrument, error, info};
 
 // Agent state that holds the deployment manager
 struct AgentState {
     deployment_manager: AgentDeploymentManager,
+    /// Reported with every heartbeat until the next deployment result
+    last_deployment: Option<DeploymentReport>,
 }
 
 impl AgentState {
@@ -25,18 +36,58 @@ impl AgentState {
         let cfg = CrystalForgeConfig::load()?;
         let deployment_manager = AgentDeploymentManager::new(cfg.deployment.clone());
 
-        Ok(Self { deployment_manager })
+        Ok(Self {
+            deployment_manager,
+            last_deployment: None,
+        })
     }
 }
 
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Crystal Forge agent", long_about = None)]
+struct Cli {
+    /// Run a one-off task instead of watching the system
+    #[command(subcommand)]
+    command: Option<AgentCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum AgentCommand {
+    /// Report nix store free space against the deploy threshold, then exit
+    DiskSpace,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
+    let cli = Cli::parse();
+    let _telemetry = telemetry::init("crystal-forge-agent");
+
+    if let Some(AgentCommand::DiskSpace) = cli.command {
+        let cfg = CrystalForgeConfig::load()?;
+        let free = nix_store_free_bytes()?;
+        let required = cfg.deployment.min_free_store_bytes;
+        println!(
+            "/nix/store free: {} bytes (minimum for deploy: {} bytes)",
+            free, required
+        );
+        if free < required {
+            bail!("insufficient disk space for deployment");
+        }
+        return Ok(());
+    }
+
     // Initialize agent state with deployment manager
     let agent_state = Arc::new(Mutex::new(AgentState::new()?));
-    watch_system(agent_state).await
+
+    let (shutdown_tx, shutdown_rx) = shutdown::channel();
+    tokio::spawn(async move {
+        shutdown::wait_for_signal().await;
+        let _ = shutdown_tx.send(true);
+    });
+
+    watch_system(agent_state, shutdown_rx).await?;
+    info!("Crystal Forge Agent stopped");
+    Ok(())
 }
 
 fn deriver_drv(path: &OsStr) -> Result<String> {
@@ -111,6 +162,7 @@ fn deriver_drv_with_test_fallback(path: &OsStr) -> Result<String> {
 fn create_signed_payload(
     current_system: &OsStr,
     context: &str,
+    last_deployment: Option<DeploymentReport>,
 ) -> Result<(SystemState, String, String)> {
     let cfg = CrystalForgeConfig::load()?;
     let client_cfg = &cfg.client;
@@ -119,7 +171,17 @@ fn create_signed_payload(
     // Guarantees a .drv (or returns an error)
 
     let current_system_str = current_system.to_string_lossy();
-    let payload = SystemState::gather(&hostname, context, current_system_str.as_ref())?;
+    let mut payload = SystemState::gather(&hostname, context, current_system_str.as_ref())?;
+    // The first report of a system we just deployed is the deployment result
+    if let Some(source) = take_deployment_source(&current_system_str) {
+        payload.change_reason = "cf_deployment".to_string();
+        payload.deployment_source = Some(source.as_str().to_string());
+    }
+    payload.last_deployment = last_deployment;
+    let deployment_cfg = &cfg.deployment;
+    payload.wants_prefetch = deployment_cfg.prefetch_next_target
+        && !deployment_cfg.dry_run
+        && deployment_cfg.cache_url.is_some();
     let payload_json = serde_json::to_string(&payload)?;
 
     let key_bytes = STANDARD
@@ -143,7 +205,8 @@ pub fn post_system_state_change(current_system: &OsStr, context: &str) -> Result
     let cfg = CrystalForgeConfig::load()?;
     let client_cfg = &cfg.client;
 
-    let (payload, payload_json, signature_b64) = create_signed_payload(current_system, context)?;
+    let (payload, payload_json, signature_b64) =
+        create_signed_payload(current_system, context, None)?;
     let hostname = hostname::get()?.to_string_lossy().into_owned();
 
     // Send to state endpoint
@@ -184,7 +247,9 @@ pub async fn post_system_heartbeat_with_deployment(
     let cfg = CrystalForgeConfig::load()?;
     let client_cfg = &cfg.client;
 
-    let (payload, payload_json, signature_b64) = create_signed_payload(current_system, context)?;
+    let last_deployment = agent_state.lock().await.last_deployment.clone();
+    let (payload, payload_json, signature_b64) =
+        create_signed_payload(current_system, context, last_deployment)?;
     let hostname = hostname::get()?.to_string_lossy().into_owned();
 
     // Send to heartbeat endpoint
@@ -221,11 +286,38 @@ pub async fn post_system_heartbeat_with_deployment(
         .context("failed to parse LogResponse from server")?;
 
     // Process deployment with our deployment manager
+    let agent_update = log_response.agent_update.clone();
+    let desired_target = log_response.desired_target.clone();
     let mut state = agent_state.lock().await;
     let deployment_result = state
         .deployment_manager
         .process_heartbeat_response(log_response)
+        .instrument(telemetry::commit_span("deploy", None, None))
         .await?;
+    if !matches!(deployment_result, DeploymentResult::NoDeploymentNeeded) {
+        state.last_deployment = Some(DeploymentReport {
+            target: desired_target,
+            result: deployment_result.clone(),
+        });
+    }
+
+    // Only update the agent while the system itself is settled
+    if let Some(agent_path) = agent_update.as_deref()
+        && matches!(
+            deployment_result,
+            DeploymentResult::NoDeploymentNeeded | DeploymentResult::AlreadyOnTarget
+        )
+    {
+        match state
+            .deployment_manager
+            .apply_agent_update(agent_path)
+            .await
+        {
+            Ok(true) => println!("🔄 Agent update to {} started", agent_path),
+            Ok(false) => {}
+            Err(e) => eprintln!("❌ Agent update to {} failed: {:#}", agent_path, e),
+        }
+    }
 
     match deployment_result {
         DeploymentResult::SuccessFromCache { ref cache_url } => {
@@ -253,6 +345,15 @@ pub async fn post_system_heartbeat_with_deployment(
             ref desired_target,
         } => {
             eprintln!("❌ Deployment failed for {}: {}", desired_target, error);
+            drop(state);
+            let hostname = hostname::get()?.to_string_lossy().into_owned();
+            notifications::notify(Notification::new(
+                NotificationEvent::DeployFailed,
+                hostname,
+                None,
+                &format!("{}: {}", desired_target, error),
+            ))
+            .await;
         }
         DeploymentResult::NoDeploymentNeeded => {
             println!("ℹ️ No deployment needed");
@@ -260,6 +361,9 @@ pub async fn post_system_heartbeat_with_deployment(
         DeploymentResult::AlreadyOnTarget => {
             println!("ℹ️ Already on target configuration");
         }
+        DeploymentResult::DryRun { ref desired_target } => {
+            println!("🧪 Dry run: would have deployed {}", desired_target);
+        }
     }
 
     Ok(())
@@ -294,10 +398,12 @@ where
 
 /// Runs a loop that watches for inotify events and handles "current-system" changes using
 /// provided readlink and insertion callbacks. Designed for testing and flexibility.
+/// Returns once shutdown is requested; a report already in progress is finished first.
 async fn watch_for_system_changes<F>(
-    inotify: &mut Inotify,
+    inotify: &Inotify,
     readlink_fn: F,
     agent_state: Arc<Mutex<AgentState>>,
+    mut shutdown: ShutdownRx,
 ) -> Result<()>
 where
     F: Fn(&str) -> Result<PathBuf>,
@@ -313,8 +419,22 @@ where
     )
     .await?;
 
+    let readable = AsyncFd::new(inotify.as_fd().as_raw_fd())?;
     loop {
-        for event in inotify.read_events()? {
+        let mut guard = tokio::select! {
+            guard = readable.readable() => guard?,
+            _ = shutdown::requested(&mut shutdown) => return Ok(()),
+        };
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(Errno::EAGAIN) => {
+                guard.clear_ready();
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        for event in events {
             if let Some(name) = event.name {
                 println!("Detected change to /run/current-system");
                 report_current_system_derivation_async(
@@ -331,8 +451,11 @@ where
 
 async fn run_periodic_heartbeat_loop_with_deployment(
     agent_state: Arc<Mutex<AgentState>>,
+    mut shutdown: ShutdownRx,
 ) -> Result<()> {
-    sleep(Duration::from_secs(600)).await;
+    if shutdown::sleep_or_shutdown(Duration::from_secs(600), &mut shutdown).await {
+        return Ok(());
+    }
     info!("💓 Starting heartbeat loop with deployment support (every 10m)...");
     loop {
         if let Err(e) = report_current_system_derivation_async(
@@ -345,7 +468,9 @@ async fn run_periodic_heartbeat_loop_with_deployment(
         {
             error!("❌ Heartbeat failed: {e}");
         }
-        sleep(Duration::from_secs(600)).await;
+        if shutdown::sleep_or_shutdown(Duration::from_secs(600), &mut shutdown).await {
+            return Ok(());
+        }
     }
 }
 
@@ -376,21 +501,26 @@ where
 }
 
 /// Initializes an inotify watcher on `/run` for "current-system" and records updates
-/// to the system state in the database.
-pub async fn watch_system(agent_state: Arc<Mutex<AgentState>>) -> Result<()> {
-    let mut inotify = Inotify::init(InitFlags::empty())?;
+/// to the system state in the database. On shutdown both loops finish the report or
+/// deployment they are in the middle of before returning.
+pub async fn watch_system(agent_state: Arc<Mutex<AgentState>>, shutdown: ShutdownRx) -> Result<()> {
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK)?;
     inotify.add_watch(
         "/run",
         AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO,
     )?;
 
     // Spawn the heartbeat loop with deployment support
-    tokio::spawn(run_periodic_heartbeat_loop_with_deployment(
+    let heartbeat = tokio::spawn(run_periodic_heartbeat_loop_with_deployment(
         agent_state.clone(),
+        shutdown.clone(),
     ));
 
     // Use deployment-aware watch loop for file system changes
-    watch_for_system_changes(&mut inotify, readlink_path, agent_state.clone()).await
+    watch_for_system_changes(&inotify, readlink_path, agent_state.clone(), shutdown).await?;
+
+    info!("🛑 Waiting for the heartbeat loop to finish...");
+    heartbeat.await?
 }
 
 #[cfg(test)]
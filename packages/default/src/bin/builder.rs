@@ -1,65 +1,115 @@
+use clap::{Parser, Subcommand};
 use crystal_forge::builder::{run_build_loop, run_cache_push_loop, run_cve_scan_loop};
 use crystal_forge::config::CrystalForgeConfig;
+use crystal_forge::db;
+use crystal_forge::derivations::cache::verify_cache_destination;
+use crystal_forge::queries::cache_push::requeue_missing_from_cache;
 use crystal_forge::server::memory_monitor_task;
-use tokio::signal;
-use tracing::{error, info};
-use tracing_subscriber::EnvFilter;
+use crystal_forge::shutdown;
+use crystal_forge::telemetry;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// How long to wait for workers to release their reservations after a signal
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Crystal Forge builder", long_about = None)]
+struct Cli {
+    /// Run a one-off task instead of the build loops
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Requeue cache pushes lost to a cache outage, then exit
+    CatchUp,
+    /// Probe the cache destination without pushing, then exit
+    VerifyCache,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
+    let cli = Cli::parse();
+    let _telemetry = telemetry::init("crystal-forge-builder");
 
     let cfg = CrystalForgeConfig::load()?;
+
+    if let Some(Command::VerifyCache) = cli.command {
+        let health = verify_cache_destination(&cfg.cache).await?;
+        println!("{}", serde_json::to_string_pretty(&health)?);
+        if !health.is_healthy() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     CrystalForgeConfig::validate_db_connection().await?;
 
     info!("Starting Crystal Forge Builder...");
     let pool = CrystalForgeConfig::db_pool().await?;
 
     tokio::spawn(memory_monitor_task(pool.clone()));
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    db::migrate(&pool).await?;
 
     let cache_config = &cfg.cache;
 
-    let build_handle = tokio::spawn(run_build_loop(pool.clone()));
-    let cve_scan_handle = tokio::spawn(run_cve_scan_loop(pool.clone()));
+    if let Some(Command::CatchUp) = cli.command {
+        let Some(destination) = cache_config.push_to.as_deref() else {
+            anyhow::bail!("catch-up requires cache.push_to to be configured");
+        };
+        requeue_missing_from_cache(&pool, destination).await?;
+        return Ok(());
+    }
+
+    let (shutdown_tx, shutdown_rx) = shutdown::channel();
+
+    let mut handles = vec![
+        (
+            "Build",
+            tokio::spawn(run_build_loop(pool.clone(), shutdown_rx.clone())),
+        ),
+        (
+            "CVE scan",
+            tokio::spawn(run_cve_scan_loop(pool.clone(), shutdown_rx.clone())),
+        ),
+    ];
 
     if cache_config.push_after_build {
-        let cache_handle = tokio::spawn(run_cache_push_loop(pool.clone()));
+        handles.push((
+            "Cache push",
+            tokio::spawn(run_cache_push_loop(pool.clone(), shutdown_rx.clone())),
+        ));
         info!("✅ Build, CVE scan, and cache push loops started");
-
-        tokio::select! {
-            result = build_handle => {
-                error!("Build loop exited unexpectedly: {:?}", result);
-            }
-            result = cve_scan_handle => {
-                error!("CVE scan loop exited unexpectedly: {:?}", result);
-            }
-            result = cache_handle => {
-                error!("Cache push loop exited unexpectedly: {:?}", result);
-            }
-            _ = signal::ctrl_c() => {
-                info!("Received shutdown signal");
-            }
-        }
     } else {
         info!("📤 Cache push disabled in configuration");
         info!("✅ Build and CVE scan loops started");
+    }
+
+    let first_exit = futures::future::select_all(handles.iter_mut().map(|(_, h)| h));
 
-        tokio::select! {
-            result = build_handle => {
-                error!("Build loop exited unexpectedly: {:?}", result);
-            }
-            result = cve_scan_handle => {
-                error!("CVE scan loop exited unexpectedly: {:?}", result);
-            }
-            _ = signal::ctrl_c() => {
-                info!("Received shutdown signal");
-            }
+    tokio::select! {
+        (result, index, _) = first_exit => {
+            let (name, _) = handles.remove(index);
+            error!("{} loop exited unexpectedly: {:?}", name, result);
+        }
+        _ = shutdown::wait_for_signal() => {
+            info!("Received shutdown signal");
         }
     }
 
     info!("Shutting down Crystal Forge Builder...");
+    let _ = shutdown_tx.send(true);
+
+    let drain = futures::future::join_all(handles.into_iter().map(|(_, h)| h));
+
+    if tokio::time::timeout(SHUTDOWN_GRACE, drain).await.is_err() {
+        warn!(
+            "⚠️ Loops did not stop within {:?}, exiting anyway",
+            SHUTDOWN_GRACE
+        );
+    }
+
     Ok(())
 }
@@ -4,42 +4,73 @@ use axum::{
     routing::{get, post},
 };
 use base64::{Engine as _, engine::general_purpose};
+use clap::{Parser, Subcommand};
 use crystal_forge::{
     config::CrystalForgeConfig,
+    db,
+    derivations::progress,
     flake::commits::initialize_flake_commits,
     handlers::{
         agent::{heartbeat, state},
         agent_request::CFState,
-        status,
+        commits, deployments, derivations, health, reservations, status,
         webhook::webhook_handler,
+        workers,
     },
     queries::derivations::reset_non_terminal_derivations,
     server::memory_monitor_task,
     server::spawn_background_tasks,
+    shutdown, telemetry,
 };
 use ed25519_dalek::VerifyingKey;
 use std::collections::HashMap;
 use tokio::net::TcpListener;
 
 use tracing::{debug, info};
-use tracing_subscriber::EnvFilter;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Crystal Forge server", long_about = None)]
+struct Cli {
+    /// Run a one-off task instead of the server
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report configuration problems without touching the database, then exit
+    CheckConfig,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env()) // uses RUST_LOG
-        .init();
+    let cli = Cli::parse();
+    let _telemetry = telemetry::init("crystal-forge-server");
 
     println!("Crystal Forge: Starting...");
 
     // Load and validate config
     let cfg = CrystalForgeConfig::load()?;
+
+    if let Some(Command::CheckConfig) = cli.command {
+        let problems = cfg.validate()?;
+        for problem in &problems {
+            eprintln!("❌ {}", problem);
+        }
+        if !problems.is_empty() {
+            anyhow::bail!("configuration has {} problem(s)", problems.len());
+        }
+        println!("✅ Configuration is valid");
+        return Ok(());
+    }
+
     CrystalForgeConfig::validate_db_connection().await?;
 
     debug!("======== INITIALIZING DATABASE ========");
     let pool = CrystalForgeConfig::db_pool().await?;
     tokio::spawn(memory_monitor_task(pool.clone()));
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    tokio::spawn(progress::forward_notifications(pool.clone()));
+    db::migrate(&pool).await?;
     cfg.sync_systems_to_db(&pool).await?;
     let background_pool = pool.clone();
     let deployment_pool = pool.clone();
@@ -47,7 +78,8 @@ async fn main() -> anyhow::Result<()> {
     // TODO: Update this to get the first N commits on the first time
     reset_non_terminal_derivations(&pool).await?;
     initialize_flake_commits(&flake_init_pool, &cfg.flakes.watched).await?;
-    spawn_background_tasks(cfg.clone(), background_pool);
+    let (shutdown_tx, shutdown_rx) = shutdown::channel();
+    spawn_background_tasks(cfg.clone(), background_pool, shutdown_rx);
 
     // Start HTTP server
     info!("Starting Crystal Forge Server...");
@@ -55,17 +87,56 @@ async fn main() -> anyhow::Result<()> {
     info!("Host: 0.0.0.0");
     info!("Port: {}", server_cfg.port);
 
-    let state = CFState::new(pool);
+    let state = CFState::new(pool, cfg.clone());
     let app = Router::new()
         .route("/status", get(status::status))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
         .route("/system_state", post(state::update))
         .route("/agent/heartbeat", post(heartbeat::log))
         .route("/agent/state", post(state::update))
         .route("/webhook", post(webhook_handler))
+        .route("/workers", get(workers::list))
+        .route(
+            "/commits/:hash/derivations",
+            get(derivations::by_commit_hash),
+        )
+        .route("/commits/:hash/builds", post(derivations::queue_attr_build))
+        .route("/commits/:hash/graph", get(derivations::dependency_graph))
+        .route("/commits/:hash/skip", post(commits::skip))
+        .route("/commits/:hash/unskip", post(commits::unskip_commit))
+        .route(
+            "/derivations/reschedule",
+            post(derivations::reschedule_derivations),
+        )
+        .route("/derivations/:id/cancel", post(derivations::cancel_build))
+        .route("/derivations/:id/sbom", get(derivations::sbom))
+        .route("/builds/:id/progress", get(derivations::build_progress))
+        .route("/reservations", get(reservations::list))
+        .route(
+            "/reservations/:derivation_id/release",
+            post(reservations::force_release),
+        )
+        .route(
+            "/deployments/auto-latest/preview",
+            get(deployments::preview_auto_latest),
+        )
+        .route(
+            "/deployments/pause",
+            get(deployments::pause_status).post(deployments::pause),
+        )
+        .route("/deployments/resume", post(deployments::resume))
         .with_state(state);
 
     let listener = TcpListener::bind(("0.0.0.0", server_cfg.port)).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown::wait_for_signal().await;
+            let _ = shutdown_tx.send(true);
+        })
+        .await?;
+
+    info!("Crystal Forge Server stopped");
 
     Ok(())
 }
@@ -0,0 +1,71 @@
+use crate::config::CacheConfig;
+
+/// Pauses build claims while the cache push backlog is too long.
+///
+/// Claiming stops once the backlog reaches the high-water mark and only
+/// resumes after it has drained below the low-water mark, so workers don't
+/// flap around a single threshold.
+#[derive(Debug, Clone)]
+pub struct PushBackpressure {
+    high_water: u64,
+    low_water: u64,
+    paused: bool,
+}
+
+impl PushBackpressure {
+    pub fn new(high_water: u64, low_water: u64) -> Self {
+        Self {
+            high_water,
+            low_water,
+            paused: false,
+        }
+    }
+
+    pub fn from_config(cache_config: &CacheConfig) -> Self {
+        Self::new(
+            cache_config.push_backlog_high_water,
+            cache_config.push_backlog_low_water,
+        )
+    }
+
+    /// Whether a high-water mark is configured at all
+    pub fn is_enabled(&self) -> bool {
+        self.high_water > 0
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Feed the current backlog; returns whether claiming should pause
+    pub fn update(&mut self, backlog: u64) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        if self.paused {
+            self.paused = backlog >= self.low_water;
+        } else {
+            self.paused = backlog >= self.high_water;
+        }
+        self.paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pauses_at_high_water_and_resumes_below_low_water() {
+        let mut backpressure = PushBackpressure::new(100, 20);
+        assert!(!backpressure.update(99));
+        assert!(backpressure.update(100));
+        assert!(backpressure.update(50));
+        assert!(backpressure.update(20));
+        assert!(!backpressure.update(19));
+        assert!(!backpressure.update(80));
+
+        let mut disabled = PushBackpressure::new(0, 0);
+        assert!(!disabled.update(1_000_000));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::config::CacheConfig;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Consecutive-failure circuit breaker for a single cache destination.
+///
+/// Closed: pushes go through. After `threshold` consecutive destination-level
+/// failures it opens for `cooldown`, during which pushes are refused. Once the
+/// cooldown elapses a single attempt is let through (half-open) while every
+/// other caller is still refused; its success closes the circuit, its failure
+/// re-opens it immediately.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: u32,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed,
+    /// Refusing pushes until the cooldown ends
+    Open {
+        until: Instant,
+    },
+    /// A probe was let through at `since`. Should it never report back,
+    /// another probe is allowed after a further cooldown.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            consecutive_failures: 0,
+            state: State::Closed,
+        }
+    }
+
+    /// Whether a push may be attempted right now
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            State::Closed => true,
+            State::Open { until } if now < until => false,
+            State::HalfOpen { since } if now < since + self.cooldown => false,
+            State::Open { .. } | State::HalfOpen { .. } => {
+                // Let this caller probe the destination, nobody else
+                self.state = State::HalfOpen { since: now };
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.state = State::Closed;
+    }
+
+    /// Record a destination-level failure. Returns true if this opened the circuit.
+    pub fn record_failure(&mut self, now: Instant) -> bool {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let was_open = matches!(self.state, State::Open { .. });
+        if matches!(self.state, State::HalfOpen { .. })
+            || (self.threshold > 0 && self.consecutive_failures >= self.threshold)
+        {
+            self.state = State::Open {
+                until: now + self.cooldown,
+            };
+            return !was_open;
+        }
+        false
+    }
+
+    /// Record a failure that says nothing about the destination. A probe
+    /// ending that way makes room for the next one.
+    pub fn record_inconclusive(&mut self, now: Instant) {
+        if matches!(self.state, State::HalfOpen { .. }) {
+            self.state = State::Open { until: now };
+        }
+    }
+
+    /// Time left before another push may be attempted, if the circuit is
+    /// not closed
+    pub fn remaining_cooldown(&self, now: Instant) -> Option<Duration> {
+        let until = match self.state {
+            State::Closed => return None,
+            State::Open { until } => until,
+            State::HalfOpen { since } => since + self.cooldown,
+        };
+        (now < until).then(|| until - now)
+    }
+}
+
+static CACHE_BREAKERS: OnceLock<Mutex<HashMap<String, CircuitBreaker>>> = OnceLock::new();
+
+fn breakers() -> &'static Mutex<HashMap<String, CircuitBreaker>> {
+    CACHE_BREAKERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn with_breaker<T>(
+    destination: &str,
+    cache_config: &CacheConfig,
+    f: impl FnOnce(&mut CircuitBreaker) -> T,
+) -> T {
+    let mut map = breakers().lock().unwrap_or_else(|e| e.into_inner());
+    let breaker = map.entry(destination.to_string()).or_insert_with(|| {
+        CircuitBreaker::new(
+            cache_config.circuit_breaker_threshold,
+            Duration::from_secs(cache_config.circuit_breaker_cooldown_seconds),
+        )
+    });
+    f(breaker)
+}
+
+/// Check the destination's breaker before pushing.
+/// Returns `Err(remaining)` with the time left on the cooldown if it is open.
+pub fn check_destination(destination: &str, cache_config: &CacheConfig) -> Result<(), Duration> {
+    if cache_config.circuit_breaker_threshold == 0 {
+        return Ok(());
+    }
+    with_breaker(destination, cache_config, |b| {
+        let now = Instant::now();
+        if b.allow(now) {
+            Ok(())
+        } else {
+            Err(b.remaining_cooldown(now).unwrap_or_default())
+        }
+    })
+}
+
+/// Feed a push outcome back into the destination's breaker
+pub fn record_destination_result(
+    destination: &str,
+    cache_config: &CacheConfig,
+    result: &anyhow::Result<()>,
+) {
+    if cache_config.circuit_breaker_threshold == 0 {
+        return;
+    }
+    with_breaker(destination, cache_config, |b| match result {
+        Ok(()) => {
+            if b.consecutive_failures >= b.threshold {
+                info!(
+                    "🔌 Cache destination {} recovered, closing circuit",
+                    destination
+                );
+            }
+            b.record_success();
+        }
+        Err(e) if is_destination_error(&e.to_string()) => {
+            if b.record_failure(Instant::now()) {
+                warn!(
+                    "🔌 Cache destination {} failed {} times in a row, deferring pushes for {:?}",
+                    destination, b.consecutive_failures, b.cooldown
+                );
+            }
+        }
+        // Failures specific to the path (missing, bad signature, ...) say nothing
+        // about the destination's health
+        Err(_) => b.record_inconclusive(Instant::now()),
+    });
+}
+
+/// Heuristic: does this push error mean the destination itself is unhealthy?
+pub fn is_destination_error(err_msg: &str) -> bool {
+    let msg = err_msg.to_lowercase();
+    [
+        "connection refused",
+        "connection reset",
+        "could not connect",
+        "couldn't connect",
+        "failed to connect",
+        "timed out",
+        "name or service not known",
+        "could not resolve host",
+        "temporary failure in name resolution",
+        "no route to host",
+        "network is unreachable",
+        "ssl connect error",
+        "http error 502",
+        "http error 503",
+        "http error 504",
+        "bad gateway",
+        "service unavailable",
+        "gateway timeout",
+    ]
+    .iter()
+    .any(|needle| msg.contains(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let now = Instant::now();
+        let mut b = CircuitBreaker::new(3, Duration::from_secs(60));
+
+        assert!(!b.record_failure(now));
+        assert!(!b.record_failure(now));
+        assert!(b.allow(now));
+        assert!(b.record_failure(now));
+        assert!(!b.allow(now));
+        assert_eq!(b.remaining_cooldown(now), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_breaker_half_open_then_reopens_or_closes() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(61);
+        let mut b = CircuitBreaker::new(2, Duration::from_secs(60));
+        b.record_failure(now);
+        b.record_failure(now);
+
+        // Cooldown over: one probe allowed, a failure re-opens straight away
+        assert!(b.allow(later));
+        assert!(!b.allow(later));
+        assert!(b.record_failure(later));
+        assert!(!b.allow(later));
+
+        // Success after the next cooldown closes it
+        let much_later = later + Duration::from_secs(61);
+        assert!(b.allow(much_later));
+        assert!(!b.allow(much_later));
+        b.record_success();
+        assert!(b.allow(much_later));
+        assert!(!b.record_failure(much_later));
+    }
+
+    #[test]
+    fn test_half_open_admits_another_probe_when_one_is_inconclusive_or_lost() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(61);
+        let mut b = CircuitBreaker::new(1, Duration::from_secs(60));
+        b.record_failure(now);
+
+        assert!(b.allow(later));
+        b.record_inconclusive(later);
+        assert!(b.allow(later));
+        assert!(!b.allow(later));
+
+        // The probe never reports back
+        let much_later = later + Duration::from_secs(61);
+        assert!(b.allow(much_later));
+    }
+
+    #[test]
+    fn test_is_destination_error() {
+        assert!(is_destination_error(
+            "error: unable to download 'https://cache/nix-cache-info': HTTP error 503"
+        ));
+        assert!(is_destination_error(
+            "curl: (7) Failed to connect: Connection refused"
+        ));
+        assert!(!is_destination_error("path '/nix/store/abc' is not valid"));
+    }
+}
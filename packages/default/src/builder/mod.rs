@@ -1,44 +1,76 @@
 use crate::log::{WorkerState, WorkerStatus, get_build_status, get_cve_status};
 use crate::config::CacheType;
-use crate::config::{BuildConfig, CacheConfig, CrystalForgeConfig};
+use crate::config::{
+    BuildConfig, CacheConfig, CrystalForgeConfig, NixBuildOptions, NotificationEvent, SbomFormat,
+};
+use crate::db;
+use crate::derivations::cache::paths_present_in_store;
+use crate::derivations::utils::{get_closure_size, get_nar_hash, is_output_of};
 use crate::derivations::{Derivation, DerivationType};
+use crate::notifications::{self, Notification};
 use crate::queries::build_reservations;
 use crate::queries::cache_push::CachePushJob;
-use crate::queries::cache_push::create_cache_push_job;
+use crate::queries::cache_push::{cache_destinations_for_derivation, create_cache_push_job};
 use crate::queries::cache_push::{
-    cleanup_stale_cache_push_jobs, get_pending_cache_push_jobs, mark_cache_push_completed,
-    mark_cache_push_failed, mark_cache_push_in_progress,
+    cleanup_stale_cache_push_jobs, count_cache_push_backlog, get_pending_cache_push_jobs,
+    mark_cache_push_completed, mark_cache_push_deferred, mark_cache_push_failed,
+    mark_cache_push_in_progress, mark_cache_push_rejected,
+};
+use crate::queries::commits::{
+    get_commit_by_id, get_commit_distances_from_head, get_commit_hash_for_derivation,
+    get_commits_by_ids,
 };
 use crate::queries::cve_scans::{
     create_cve_scan, get_targets_needing_cve_scan, mark_cve_scan_failed, mark_scan_in_progress,
     save_scan_results,
 };
+use crate::queries::deployment::get_desired_target_store_paths;
 use crate::queries::derivations::get_derivation_by_id;
 use crate::queries::derivations::{
-    EvaluationStatus, handle_derivation_failure, mark_target_build_complete,
+    EvaluationStatus, handle_derivation_failure, lock_derivation, mark_target_build_complete,
     update_derivation_status,
 };
-use crate::queries::derivations::{batch_queue_cache_jobs, reset_derivation_for_rebuild};
+use crate::queries::derivations::{
+    batch_queue_cache_jobs, block_derivations_with_failed_dependencies, find_missing_store_paths,
+    flag_missing_cache_only_derivation, reset_derivation_for_rebuild, set_derivation_built_by_host,
+    set_derivation_failed_on_host, set_derivation_nar_hash, unblock_derivations,
+};
+use crate::queries::sboms::save_sbom;
+use crate::sbom;
+use crate::server::available_memory_mb;
+use crate::shutdown::{self, ShutdownRx};
+use crate::telemetry::commit_span;
 use crate::vulnix::vulnix_runner::VulnixRunner;
 use anyhow::{Context, Result};
-use futures::FutureExt;
 use sqlx::PgPool;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock};
 use tokio::fs;
-use tokio::sync::Semaphore;
+use tokio::sync::{RwLock, Semaphore};
 use tokio::time::sleep;
 use tokio::time::timeout;
 use tokio::time::{Duration, Instant};
-use tracing::{debug, error, info, warn};
+use tracing::{Instrument, debug, error, info, warn};
+
+pub mod backpressure;
+pub mod circuit_breaker;
+
+/// How often blocked derivations are re-checked against their dependencies
+const BLOCKED_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 /// Runs the continuous build loop with multiple workers
-pub async fn run_build_loop(pool: PgPool) {
+///
+/// Returns once `shutdown` fires and every worker has finished or released
+/// its current reservation.
+pub async fn run_build_loop(pool: PgPool, mut shutdown: ShutdownRx) {
     let cfg = CrystalForgeConfig::load().unwrap_or_else(|e| {
         warn!("Failed to load Crystal Forge config: {}, using defaults", e);
         CrystalForgeConfig::default()
     });
     let build_config = cfg.get_build_config();
     let cache_config = cfg.get_cache_config();
+    let system_build_options = Arc::new(cfg.system_build_options());
+    let environment_cache_destinations = Arc::new(cfg.environment_cache_destinations());
     let num_workers = build_config.max_concurrent_derivations;
 
     info!("🏗 Starting {} continuous build workers...", num_workers);
@@ -62,22 +94,103 @@ pub async fn run_build_loop(pool: PgPool) {
         }
     }
 
+    let reservation_lease = build_config.reservation_lease();
+    if reservation_lease.as_secs() != build_config.reservation_lease_seconds {
+        warn!(
+            "⚠️ reservation_lease_seconds={} is too short for heartbeat_interval={:?}, using {:?}",
+            build_config.reservation_lease_seconds,
+            build_config.heartbeat_interval,
+            reservation_lease
+        );
+    }
+
     // Spawn stale reservation cleanup task
+    let reservation_grace = build_config.reservation_startup_grace;
     let cleanup_pool = pool.clone();
+    let cleanup_shutdown = shutdown.clone();
     tokio::spawn(async move {
-        run_reservation_cleanup_loop(cleanup_pool).await;
+        run_reservation_cleanup_loop(
+            cleanup_pool,
+            reservation_lease,
+            reservation_grace,
+            cleanup_shutdown,
+        )
+        .await;
     });
 
+    // Spawn blocked derivation maintenance
+    let blocked_pool = pool.clone();
+    let blocked_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        run_blocked_derivation_loop(blocked_pool, blocked_shutdown).await;
+    });
+
+    // Spawn stuck-build watchdog for this host's workers
+    if !build_config.stuck_worker_threshold.is_zero() {
+        let watchdog_pool = pool.clone();
+        let watchdog_shutdown = shutdown.clone();
+        let worker_pattern = format!("{}-worker-%", hostname);
+        let threshold = build_config.stuck_worker_threshold;
+        let webhook = build_config.stuck_worker_webhook.clone();
+        tokio::spawn(async move {
+            run_stuck_build_watchdog(
+                watchdog_pool,
+                worker_pattern,
+                threshold,
+                webhook,
+                watchdog_shutdown,
+            )
+            .await;
+        });
+    }
+
+    // Spawn GC root keeper for store paths systems are deploying
+    if !build_config.target_gc_root_interval.is_zero() {
+        let roots_pool = pool.clone();
+        let roots_shutdown = shutdown.clone();
+        let interval = build_config.target_gc_root_interval;
+        tokio::spawn(async move {
+            run_target_gc_root_loop(roots_pool, interval, roots_shutdown).await;
+        });
+    }
+
+    // Spawn audit for built store paths lost to garbage collection
+    if !build_config.store_audit_interval.is_zero() {
+        let audit_pool = pool.clone();
+        let audit_shutdown = shutdown.clone();
+        let interval = build_config.store_audit_interval;
+        tokio::spawn(async move {
+            run_store_path_audit_loop(audit_pool, interval, audit_shutdown).await;
+        });
+    }
+
     // Spawn worker pool
     let mut handles = Vec::new();
     for worker_id in 0..num_workers {
         let pool = pool.clone();
         let build_config = build_config.clone();
         let cache_config = cache_config.clone();
+        let system_build_options = system_build_options.clone();
+        let environment_cache_destinations = environment_cache_destinations.clone();
         let worker_uuid = format!("{}-worker-{}", hostname, worker_id);
+        let hostname = hostname.clone();
+        let startup_jitter = cfg.startup_jitter;
+        let shutdown = shutdown.clone();
 
         let handle = tokio::spawn(async move {
-            build_worker(worker_id, worker_uuid, pool, build_config, cache_config).await;
+            build_worker(
+                worker_id,
+                worker_uuid,
+                hostname,
+                pool,
+                build_config,
+                cache_config,
+                system_build_options,
+                environment_cache_destinations,
+                startup_jitter,
+                shutdown,
+            )
+            .await;
         });
         handles.push(handle);
     }
@@ -86,42 +199,89 @@ pub async fn run_build_loop(pool: PgPool) {
     for handle in handles {
         let _ = handle.await;
     }
+
+    // Drained workers stop on their own; stay up until the builder is shut
+    // down so it isn't restarted straight back into the drain
+    if !*shutdown.borrow() {
+        info!("🚰 All build workers drained, waiting for shutdown");
+        shutdown::requested(&mut shutdown).await;
+    }
+
+    info!("🛑 All build workers stopped");
+}
+
+/// Whether the builder is being drained (`build.drain_file` exists)
+async fn drain_requested(build_config: &BuildConfig) -> bool {
+    fs::try_exists(&build_config.drain_file)
+        .await
+        .unwrap_or(false)
+}
+
+/// How long a cached commit label ("abcd1234 (HEAD~3)") is reused before
+/// it is looked up again; HEAD moves, so the distance goes stale
+const COMMIT_LABEL_TTL: Duration = Duration::from_secs(60);
+
+/// commit id -> (label, when it was fetched), shared by all build workers
+static COMMIT_LABELS: OnceLock<RwLock<HashMap<i32, (String, Instant)>>> = OnceLock::new();
+
+fn commit_labels() -> &'static RwLock<HashMap<i32, (String, Instant)>> {
+    COMMIT_LABELS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Re-fetch labels for `commit_ids` with one commits query and one distance
+/// query, dropping anything that has expired along the way
+async fn refresh_commit_labels(pool: &PgPool, commit_ids: &[i32]) -> Result<()> {
+    let commits = get_commits_by_ids(pool, commit_ids).await?;
+    let distances = get_commit_distances_from_head(pool, commit_ids).await?;
+
+    let now = Instant::now();
+    let mut labels = commit_labels().write().await;
+    labels.retain(|_, (_, fetched_at)| now.duration_since(*fetched_at) < COMMIT_LABEL_TTL);
+    for commit in commits {
+        let short_hash = &commit.git_commit_hash[..commit.git_commit_hash.len().min(8)];
+        let label = match distances.get(&commit.id) {
+            Some(distance) => format!("{} (HEAD~{})", short_hash, distance),
+            None => short_hash.to_string(),
+        };
+        labels.insert(commit.id, (label, now));
+    }
+    Ok(())
 }
 
 /// Build a task description for display/logging
 ///
-/// This helper function encapsulates all the commit-related queries
-/// that were previously embedded in build_worker
+/// Commit labels come from a short-lived shared cache; on a miss the commit is
+/// fetched together with every other expired entry, so many workers claiming
+/// work at once don't each run their own commit and distance queries.
 async fn build_task_description(pool: &PgPool, derivation: &Derivation) -> String {
-    if let Some(commit_id) = derivation.commit_id {
-        match crate::queries::commits::get_commit_by_id(pool, commit_id).await {
-            Ok(commit) => {
-                // Try to get distance from HEAD
-                let distance_info = match commit.get_flake(pool).await {
-                    Ok(flake) => {
-                        match crate::queries::commits::get_commit_distance_from_head(
-                            pool, &flake, &commit,
-                        )
-                        .await
-                        {
-                            Ok(distance) => format!(" (HEAD~{})", distance),
-                            Err(_) => String::new(),
-                        }
-                    }
-                    Err(_) => String::new(),
-                };
+    let Some(commit_id) = derivation.commit_id else {
+        return derivation.derivation_name.clone();
+    };
 
-                format!(
-                    "{} @ {}{}",
-                    derivation.derivation_name,
-                    &commit.git_commit_hash[..8],
-                    distance_info
-                )
-            }
-            Err(_) => format!("{} @ commit#{}", derivation.derivation_name, commit_id),
+    let stale_ids = {
+        let labels = commit_labels().read().await;
+        if let Some((label, fetched_at)) = labels.get(&commit_id)
+            && fetched_at.elapsed() < COMMIT_LABEL_TTL
+        {
+            return format!("{} @ {}", derivation.derivation_name, label);
         }
-    } else {
-        derivation.derivation_name.clone()
+        labels
+            .iter()
+            .filter(|(id, (_, fetched_at))| {
+                **id != commit_id && fetched_at.elapsed() >= COMMIT_LABEL_TTL
+            })
+            .map(|(id, _)| *id)
+            .chain(std::iter::once(commit_id))
+            .collect::<Vec<_>>()
+    };
+
+    if let Err(e) = refresh_commit_labels(pool, &stale_ids).await {
+        debug!("Failed to refresh commit labels: {}", e);
+    }
+
+    match commit_labels().read().await.get(&commit_id) {
+        Some((label, _)) => format!("{} @ {}", derivation.derivation_name, label),
+        None => format!("{} @ commit#{}", derivation.derivation_name, commit_id),
     }
 }
 
@@ -149,12 +309,20 @@ fn update_worker_status(worker_id: usize, state: WorkerState, current_task: Opti
 /// 1. Timeout protection prevents workers from getting stuck for hours
 /// 2. Helper functions for task description and status updates
 /// 3. Better error handling and logging
+/// 4. On shutdown an in-flight build is abandoned and its reservation released
+/// 5. While draining, the worker stops after its current build
+#[allow(clippy::too_many_arguments)]
 async fn build_worker(
     worker_id: usize,
     worker_uuid: String,
+    hostname: String,
     pool: PgPool,
     build_config: BuildConfig,
     cache_config: CacheConfig,
+    system_build_options: Arc<HashMap<String, NixBuildOptions>>,
+    environment_cache_destinations: Arc<HashMap<String, String>>,
+    startup_jitter: Duration,
+    mut shutdown: ShutdownRx,
 ) {
     update_worker_status(
         worker_id,
@@ -162,13 +330,33 @@ async fn build_worker(
         Some("claiming work".to_string()),
     );
 
-    info!("Worker {} ({}) started", worker_id, worker_uuid);
+    let claim_systems = build_config.claim_systems();
+    info!(
+        "Worker {} ({}) started, building for {} with labels [{}]",
+        worker_id,
+        worker_uuid,
+        claim_systems.join(", "),
+        build_config.builder_labels.join(", ")
+    );
+
+    if shutdown::jittered_start(startup_jitter, &mut shutdown).await {
+        update_worker_status(worker_id, WorkerState::Idle, None);
+        return;
+    }
 
     // Spawn heartbeat task for this worker
     let heartbeat_pool = pool.clone();
     let heartbeat_uuid = worker_uuid.clone();
+    let heartbeat_interval = build_config.heartbeat_interval;
+    let heartbeat_shutdown = shutdown.clone();
     tokio::spawn(async move {
-        worker_heartbeat_loop(heartbeat_uuid, heartbeat_pool).await;
+        worker_heartbeat_loop(
+            heartbeat_uuid,
+            heartbeat_pool,
+            heartbeat_interval,
+            heartbeat_shutdown,
+        )
+        .await;
     });
 
     // Get the build timeout from config (with a reasonable maximum)
@@ -184,14 +372,105 @@ async fn build_worker(
         build_timeout.as_secs_f64()
     );
 
+    let mut backpressure = backpressure::PushBackpressure::from_config(&cache_config);
+
     loop {
+        if *shutdown.borrow() {
+            break;
+        }
+
+        // Finish what we have, but take nothing new while draining
+        if drain_requested(&build_config).await {
+            update_worker_status(
+                worker_id,
+                WorkerState::Draining,
+                Some("drained, not claiming work".to_string()),
+            );
+            info!(
+                "🚰 Worker {} ({}) drained, not claiming more work",
+                worker_id, worker_uuid
+            );
+            return;
+        }
+
+        // Don't pile another build onto a host that is already short of memory
+        if build_config.min_free_memory_mb > 0
+            && let Some(available_mb) = available_memory_mb().await
+            && available_mb < build_config.min_free_memory_mb
+        {
+            update_worker_status(
+                worker_id,
+                WorkerState::Sleeping,
+                Some(format!("waiting for memory ({} MiB free)", available_mb)),
+            );
+            debug!(
+                "Worker {} waiting: {} MiB available, {} MiB required",
+                worker_id, available_mb, build_config.min_free_memory_mb
+            );
+            if shutdown::sleep_or_shutdown(Duration::from_secs(15), &mut shutdown).await {
+                break;
+            }
+            continue;
+        }
+
+        // Let the cache catch up before adding more closures to push
+        if backpressure.is_enabled() {
+            match count_cache_push_backlog(&pool).await {
+                Ok(backlog) => {
+                    let backlog = backlog.max(0) as u64;
+                    let was_paused = backpressure.is_paused();
+                    let paused = backpressure.update(backlog);
+                    if paused != was_paused {
+                        if paused {
+                            warn!(
+                                "⏸️ Worker {} pausing claims: {} cache pushes queued",
+                                worker_id, backlog
+                            );
+                        } else {
+                            info!(
+                                "▶️ Worker {} resuming claims: {} cache pushes queued",
+                                worker_id, backlog
+                            );
+                        }
+                    }
+                    if paused {
+                        update_worker_status(
+                            worker_id,
+                            WorkerState::Sleeping,
+                            Some(format!("waiting for cache pushes ({} queued)", backlog)),
+                        );
+                        if shutdown::sleep_or_shutdown(Duration::from_secs(15), &mut shutdown).await
+                        {
+                            break;
+                        }
+                        continue;
+                    }
+                }
+                Err(e) => warn!(
+                    "⚠️ Worker {} could not check the cache push backlog: {}",
+                    worker_id, e
+                ),
+            }
+        }
+
         update_worker_status(
             worker_id,
             WorkerState::Working,
             Some("claiming work".to_string()),
         );
 
-        match build_reservations::claim_next_derivation(&pool, &worker_uuid).await {
+        match db::retry_connection("claiming work", || {
+            build_reservations::claim_next_derivation(
+                &pool,
+                &worker_uuid,
+                &hostname,
+                build_config.scheduling,
+                &claim_systems,
+                &build_config.builder_labels,
+            )
+        })
+        .await
+        {
             Ok(Some(mut derivation)) => {
                 info!(
                     "✅ Worker {} CLAIMED derivation {}",
@@ -205,8 +484,7 @@ async fn build_worker(
                 );
 
                 // Build task description using helper function (no embedded SQL)
-                let task_description = derivation.derivation_name.clone();
-                // let task_description = build_task_description(&pool, &derivation).await;
+                let task_description = build_task_description(&pool, &derivation).await;
 
                 update_worker_status(
                     worker_id,
@@ -223,6 +501,23 @@ async fn build_worker(
                     derivation.attempt_count
                 );
 
+                // NixOS derivations are named after their host; apply that
+                // host's build tuning if it has any
+                let effective_build_config = match derivation.derivation_type {
+                    DerivationType::NixOS => system_build_options
+                        .get(&derivation.derivation_name)
+                        .map(|options| {
+                            debug!(
+                                "Applying nix_build_options for {}: {:?}",
+                                derivation.derivation_name, options
+                            );
+                            build_config.with_overrides(options)
+                        }),
+                    DerivationType::Package | DerivationType::FlakeAttr => None,
+                };
+                let derivation_build_config =
+                    effective_build_config.as_ref().unwrap_or(&build_config);
+
                 let start = std::time::Instant::now();
 
                 info!(
@@ -231,12 +526,46 @@ async fn build_worker(
                 );
                 info!("  → Step 1: About to call derivation.build()");
 
-                let build_result =
-                    tokio::time::timeout(build_timeout, derivation.build(&pool, &build_config))
-                        .await;
+                let commit_hash = get_commit_hash_for_derivation(&pool, derivation.id)
+                    .await
+                    .unwrap_or_default();
+                let span = commit_span("build", commit_hash.as_deref(), Some(derivation.id));
+
+                let build_result = tokio::select! {
+                    result = tokio::time::timeout(
+                        build_timeout,
+                        derivation
+                            .build(&pool, derivation_build_config)
+                            .instrument(span),
+                    ) => result,
+                    _ = shutdown::requested(&mut shutdown) => {
+                        warn!(
+                            "🛑 Worker {} shutting down mid-build, releasing {}",
+                            worker_id, task_description
+                        );
+                        if let Err(e) = build_reservations::release_reservation(
+                            &pool,
+                            &worker_uuid,
+                            derivation.id,
+                        )
+                        .await
+                        {
+                            error!("Failed to release reservation on shutdown: {}", e);
+                        }
+                        break;
+                    }
+                };
 
                 info!("  → Step 2: derivation.build() returned");
 
+                // Check the result before anything signs or pushes it
+                let build_result = match build_result {
+                    Ok(Ok(store_path)) if build_config.verify_build_outputs => {
+                        Ok(verify_build_output(&derivation, store_path).await)
+                    }
+                    other => other,
+                };
+
                 match build_result {
                     // Build succeeded within timeout
                     Ok(Ok(store_path)) => {
@@ -263,11 +592,12 @@ async fn build_worker(
 
                         // TODO: Include the name of the server that built the derivation
                         if let Some(ref store_path) = derivation.store_path {
-                            if let Err(e) = create_cache_push_job(
+                            if let Err(e) = queue_cache_pushes(
                                 &pool,
-                                derivation.id,
-                                store_path,                      // &String coerces to &str
-                                cache_config.push_to.as_deref(), // Option<String> -> Option<&str>
+                                &derivation,
+                                store_path,
+                                &cache_config,
+                                &environment_cache_destinations,
                             )
                             .await
                             {
@@ -286,12 +616,20 @@ async fn build_worker(
                         if let Err(e) = mark_build_complete_and_release(
                             &pool,
                             &worker_uuid,
+                            &hostname,
                             derivation.id,
                             &store_path,
                         )
                         .await
                         {
                             error!("failed to mark build complete: {}", e);
+                        } else {
+                            run_post_build_hook(
+                                &build_config,
+                                &store_path,
+                                &derivation.derivation_name,
+                            )
+                            .await;
                         }
                     }
 
@@ -306,9 +644,14 @@ async fn build_worker(
                             e
                         );
 
-                        if let Err(e2) =
-                            mark_build_failed_and_release(&pool, &worker_uuid, &derivation, &e)
-                                .await
+                        if let Err(e2) = mark_build_failed_and_release(
+                            &pool,
+                            &worker_uuid,
+                            &hostname,
+                            &derivation,
+                            &e,
+                        )
+                        .await
                         {
                             error!("Failed to mark build failed: {}", e2);
                         }
@@ -334,6 +677,7 @@ async fn build_worker(
                         if let Err(e2) = mark_build_failed_and_release(
                             &pool,
                             &worker_uuid,
+                            &hostname,
                             &derivation,
                             &timeout_error,
                         )
@@ -349,20 +693,27 @@ async fn build_worker(
             Ok(None) => {
                 update_worker_status(worker_id, WorkerState::Idle, None);
                 debug!("Worker {} idle, no work available", worker_id);
-                sleep(std::time::Duration::from_secs(5)).await;
+                if shutdown::sleep_or_shutdown(Duration::from_secs(5), &mut shutdown).await {
+                    break;
+                }
             }
 
             // Error claiming work
             Err(e) => {
                 error!("Worker {} error claiming work: {}", worker_id, e);
-                sleep(std::time::Duration::from_secs(10)).await;
+                if shutdown::sleep_or_shutdown(Duration::from_secs(10), &mut shutdown).await {
+                    break;
+                }
             }
         }
     }
+
+    update_worker_status(worker_id, WorkerState::Idle, None);
+    info!("🛑 Worker {} ({}) stopped", worker_id, worker_uuid);
 }
 
 /// Runs the periodic CVE scanning loop
-pub async fn run_cve_scan_loop(pool: PgPool) {
+pub async fn run_cve_scan_loop(pool: PgPool, mut shutdown: ShutdownRx) {
     let cfg = CrystalForgeConfig::load().unwrap_or_else(|e| {
         warn!("Failed to load Crystal Forge config: {}, using defaults", e);
         CrystalForgeConfig::default()
@@ -383,23 +734,55 @@ pub async fn run_cve_scan_loop(pool: PgPool) {
 
     debug!("🔧 Using vulnix version: {:?}", vulnix_version);
     debug!(
-        "🔧 Vulnix config: timeout={}s, whitelist={}, extra_args={:?}",
+        "🔧 Vulnix config: timeout={}s, concurrency={}, whitelist={}, extra_args={:?}",
         vulnix_config.timeout_seconds(),
+        vulnix_config.max_concurrent_scans,
         vulnix_config.enable_whitelist,
         vulnix_config.extra_args
     );
 
-    let vulnix_runner = VulnixRunner::with_config(&vulnix_config);
+    let vulnix_runner = VulnixRunner::with_config(&vulnix_config).with_build_config(&cfg.build);
+    let max_concurrent_scans = vulnix_config.max_concurrent_scans.max(1);
+    let sbom_format = vulnix_config
+        .generate_sbom
+        .then_some(vulnix_config.sbom_format);
+
+    if shutdown::jittered_start(cfg.startup_jitter, &mut shutdown).await {
+        return;
+    }
+
+    {
+        let mut statuses = get_cve_status().write().await;
+        for scanner_id in 0..max_concurrent_scans {
+            statuses.push(WorkerStatus {
+                worker_id: scanner_id,
+                current_task: None,
+                started_at: None,
+                state: WorkerState::Idle,
+            });
+        }
+    }
 
     loop {
-        if let Err(e) = scan_derivations(&pool, &vulnix_runner, vulnix_version.clone()).await {
+        if let Err(e) = scan_derivations(
+            &pool,
+            &vulnix_runner,
+            vulnix_version.clone(),
+            max_concurrent_scans,
+            sbom_format,
+        )
+        .await
+        {
             error!("❌ Error in CVE scan cycle: {e}");
         }
 
-        sleep(vulnix_config.poll_interval).await;
+        if shutdown::sleep_or_shutdown(vulnix_config.poll_interval, &mut shutdown).await {
+            info!("🛑 CVE scan loop stopped");
+            return;
+        }
     }
 }
-pub async fn run_cache_push_workers(pool: PgPool) {
+pub async fn run_cache_push_workers(pool: PgPool, shutdown: ShutdownRx) {
     let cfg = CrystalForgeConfig::load().unwrap_or_default();
     let cache_cfg = cfg.get_cache_config();
 
@@ -416,22 +799,31 @@ pub async fn run_cache_push_workers(pool: PgPool) {
     // (Optional) one tiny background task to reclaim stuck jobs
     {
         let pool = pool.clone();
+        let mut shutdown = shutdown.clone();
         tokio::spawn(async move {
             loop {
                 if let Err(e) = cleanup_stale_cache_push_jobs(&pool, 60).await {
                     warn!("cleanup_stale_cache_push_jobs: {e:#}");
                 }
-                sleep(Duration::from_secs(30)).await;
+                if shutdown::sleep_or_shutdown(Duration::from_secs(30), &mut shutdown).await {
+                    break;
+                }
             }
         });
     }
     {
         let pool = pool.clone();
         let destination = cache_cfg.push_to.clone().unwrap(); // Safe because we checked above
+        let environment_destinations = cfg.environment_cache_destinations();
+        let startup_jitter = cfg.startup_jitter;
+        let mut shutdown = shutdown.clone();
         tokio::spawn(async move {
             info!("📤 Starting cache job creation loop (every 30s)...");
+            if shutdown::jittered_start(startup_jitter, &mut shutdown).await {
+                return;
+            }
             loop {
-                match batch_queue_cache_jobs(&pool, &destination).await {
+                match batch_queue_cache_jobs(&pool, &destination, &environment_destinations).await {
                     Ok(count) if count > 0 => {
                         info!("📤 Created {} new cache push jobs", count);
                     }
@@ -442,7 +834,9 @@ pub async fn run_cache_push_workers(pool: PgPool) {
                         warn!("Failed to batch queue cache jobs: {}", e);
                     }
                 }
-                sleep(Duration::from_secs(30)).await;
+                if shutdown::sleep_or_shutdown(Duration::from_secs(30), &mut shutdown).await {
+                    break;
+                }
             }
         });
     }
@@ -452,6 +846,8 @@ pub async fn run_cache_push_workers(pool: PgPool) {
         let pool = pool.clone();
         let cache_cfg = cache_cfg.clone();
         let build_cfg = build_cfg.clone();
+        let startup_jitter = cfg.startup_jitter;
+        let shutdown = shutdown.clone();
 
         // Pre-register worker status (reuse build status list, or make a dedicated one)
         {
@@ -465,7 +861,7 @@ pub async fn run_cache_push_workers(pool: PgPool) {
         }
 
         handles.push(tokio::spawn(async move {
-            cache_worker(worker_id, pool, cache_cfg, build_cfg).await;
+            cache_worker(worker_id, pool, cache_cfg, build_cfg, startup_jitter, shutdown).await;
         }));
     }
 
@@ -475,7 +871,7 @@ pub async fn run_cache_push_workers(pool: PgPool) {
 }
 
 /// Runs the periodic cache push loop with robust error handling
-pub async fn run_cache_push_loop(pool: PgPool) {
+pub async fn run_cache_push_loop(pool: PgPool, shutdown: ShutdownRx) {
     let cfg = CrystalForgeConfig::load().unwrap_or_default();
     let cache_cfg = cfg.get_cache_config();
 
@@ -484,10 +880,12 @@ pub async fn run_cache_push_loop(pool: PgPool) {
         return;
     }
 
-    let worker_count = match cache_cfg.cache_type {
+    let worker_count = match cache_cfg.effective_cache_type() {
         CacheType::S3 => cache_cfg.parallel_uploads.max(1) as usize,
-        CacheType::Attic => 1,
-        CacheType::Http | CacheType::Nix => cache_cfg.parallel_uploads.max(1) as usize,
+        CacheType::Attic | CacheType::Cachix => 1,
+        CacheType::Http | CacheType::Nix | CacheType::SshNg => {
+            cache_cfg.parallel_uploads.max(1) as usize
+        }
     };
 
     let build_cfg = cfg.get_build_config();
@@ -497,12 +895,15 @@ pub async fn run_cache_push_loop(pool: PgPool) {
     // (Optional) one tiny background task to reclaim stuck jobs
     {
         let pool = pool.clone();
+        let mut shutdown = shutdown.clone();
         tokio::spawn(async move {
             loop {
                 if let Err(e) = cleanup_stale_cache_push_jobs(&pool, 60).await {
                     warn!("cleanup_stale_cache_push_jobs: {e:#}");
                 }
-                sleep(Duration::from_secs(30)).await;
+                if shutdown::sleep_or_shutdown(Duration::from_secs(30), &mut shutdown).await {
+                    break;
+                }
             }
         });
     }
@@ -512,6 +913,8 @@ pub async fn run_cache_push_loop(pool: PgPool) {
         let pool = pool.clone();
         let cache_cfg = cache_cfg.clone();
         let build_cfg = build_cfg.clone();
+        let startup_jitter = cfg.startup_jitter;
+        let shutdown = shutdown.clone();
 
         // Pre-register worker status (reuse build status list, or make a dedicated one)
         {
@@ -525,7 +928,7 @@ pub async fn run_cache_push_loop(pool: PgPool) {
         }
 
         handles.push(tokio::spawn(async move {
-            cache_worker(worker_id, pool, cache_cfg, build_cfg).await;
+            cache_worker(worker_id, pool, cache_cfg, build_cfg, startup_jitter, shutdown).await;
         }));
     }
 
@@ -539,13 +942,24 @@ async fn cache_worker(
     pool: PgPool,
     cache_cfg: CacheConfig,
     build_cfg: BuildConfig,
+    startup_jitter: Duration,
+    mut shutdown: ShutdownRx,
 ) {
     let status_id = 10_000 + worker_id;
     let tick = cache_cfg.poll_interval;
 
     info!("🚚 cache-worker {worker_id} started (tick {tick:?})");
 
+    if shutdown::jittered_start(startup_jitter, &mut shutdown).await {
+        return;
+    }
+
     loop {
+        if *shutdown.borrow() {
+            info!("🛑 cache-worker {worker_id} stopped");
+            return;
+        }
+
         // update status: looking for work
         {
             let mut s = get_build_status().write().await;
@@ -559,7 +973,9 @@ async fn cache_worker(
         // small DB timeout so a wedged DB doesn’t pin the worker forever
         let jobs = match timeout(
             Duration::from_secs(30),
-            get_pending_cache_push_jobs(&pool, Some(1)),
+            db::retry_connection("fetching cache push jobs", || {
+                get_pending_cache_push_jobs(&pool, Some(1))
+            }),
         )
         .await
         {
@@ -585,10 +1001,15 @@ async fn cache_worker(
                 }
             }
             debug!("cache-worker {worker_id}: idle");
-            sleep(tick).await;
+            shutdown::sleep_or_shutdown(tick, &mut shutdown).await;
             continue;
         };
 
+        // skip destinations whose circuit is open instead of burning an attempt
+        if defer_if_circuit_open(&pool, &cache_cfg, &job).await {
+            continue;
+        }
+
         // mark job in-progress and do the push
         if let Err(e) = mark_cache_push_in_progress(&pool, job.id).await {
             warn!("cache-worker {worker_id}: failed to mark in-progress: {e:#}");
@@ -597,8 +1018,17 @@ async fn cache_worker(
             continue;
         }
 
-        if let Err(e) =
-            process_one_job(&pool, &cache_cfg, &build_cfg, job, worker_id, status_id).await
+        let commit_hash = get_commit_hash_for_derivation(&pool, job.derivation_id)
+            .await
+            .unwrap_or_default();
+        let span = commit_span(
+            "cache_push",
+            commit_hash.as_deref(),
+            Some(job.derivation_id),
+        );
+        if let Err(e) = process_one_job(&pool, &cache_cfg, &build_cfg, job, worker_id, status_id)
+            .instrument(span)
+            .await
         {
             error!("cache-worker {worker_id}: job failed: {e:#}");
         }
@@ -609,7 +1039,7 @@ async fn process_one_job(
     pool: &PgPool,
     cache_cfg: &CacheConfig,
     build_cfg: &BuildConfig,
-    job: CachePushJob,
+    mut job: CachePushJob,
     worker_id: usize,
     status_id: usize,
 ) -> Result<()> {
@@ -633,12 +1063,19 @@ async fn process_one_job(
         .await
         .context("fetch derivation")?;
 
-    // Prefer job.store_path; else fall back to derivation.store_path / derivation_path (your push method handles .drv → store resolution)
-    let path = job
-        .store_path
-        .or_else(|| derivation.store_path.clone())
-        .or_else(|| derivation.derivation_path.clone())
-        .ok_or_else(|| anyhow::anyhow!("no store/derivation path for {}", job.derivation_id))?;
+    // A job for a specific output resolves it from the .drv; otherwise prefer job.store_path,
+    // else fall back to derivation.store_path / derivation_path (your push method handles .drv → store resolution)
+    let path = match (&job.output_name, &derivation.derivation_path) {
+        (Some(output), Some(drv_path)) => {
+            Derivation::resolve_drv_to_store_path(drv_path, Some(output)).await?
+        }
+        _ => job
+            .store_path
+            .clone()
+            .or_else(|| derivation.store_path.clone())
+            .or_else(|| derivation.derivation_path.clone())
+            .ok_or_else(|| anyhow::anyhow!("no store/derivation path for {}", job.derivation_id))?,
+    };
 
     // Fast path check if it looks like a nix store path and actually exists
     if path.starts_with("/nix/store/") && !tokio::fs::try_exists(&path).await.unwrap_or(false) {
@@ -647,9 +1084,30 @@ async fn process_one_job(
         return Ok(());
     }
 
+    // check the resolved path, which for an output job is not job.store_path
+    job.store_path = Some(path.clone());
+    let Some(job) = skip_jobs_already_in_cache(pool, vec![job], cache_cfg)
+        .await
+        .pop()
+    else {
+        return Ok(());
+    };
+
+    if path.starts_with("/nix/store/") && reject_if_oversized(pool, cache_cfg, job.id, &path).await
+    {
+        return Ok(());
+    }
+
     // Do the push using your existing implementation on Derivation
     let started = std::time::Instant::now();
-    match derivation.push_to_cache(&path, cache_cfg, build_cfg).await {
+    let job_cache_cfg = cache_cfg.for_destination(job.cache_destination.as_deref());
+    let result = derivation
+        .push_to_cache(&path, &job_cache_cfg, build_cfg)
+        .await;
+    if let Some(destination) = job_destination(&job.cache_destination, cache_cfg) {
+        circuit_breaker::record_destination_result(destination, cache_cfg, &result);
+    }
+    match result {
         Ok(()) => {
             let duration_ms = (started.elapsed().as_millis() as i32).max(0);
             mark_cache_push_completed(pool, job.id, None, Some(duration_ms)).await?;
@@ -680,298 +1138,381 @@ async fn process_one_job(
     Ok(())
 }
 
+/// Set the status of one CVE scanner slot
+async fn set_cve_scanner_status(scanner_id: usize, state: WorkerState, task: Option<String>) {
+    let mut statuses = get_cve_status().write().await;
+    if let Some(status) = statuses.iter_mut().find(|s| s.worker_id == scanner_id) {
+        status.started_at = task.as_ref().map(|_| std::time::Instant::now());
+        status.current_task = task;
+        status.state = state;
+    }
+}
+
 /// Process derivations that need CVE scanning
+///
+/// Up to `max_concurrent_scans` derivations are fetched and scanned
+/// concurrently, each scanner slot pulling the next derivation once its
+/// current scan finishes. With an `sbom_format`, each scanned derivation
+/// also gets an SBOM.
 async fn scan_derivations(
     pool: &PgPool,
     vulnix_runner: &VulnixRunner,
     vulnix_version: Option<String>,
+    max_concurrent_scans: usize,
+    sbom_format: Option<SbomFormat>,
 ) -> Result<()> {
-    // Get derivations that need CVE scanning (those with build-complete status)
-    // Update status: looking for work
-    {
-        let mut status = get_cve_status().write().await; // Use helper function
-        *status = Some(WorkerStatus {
-            worker_id: 0,
-            current_task: Some("finding scan targets".to_string()),
-            started_at: Some(std::time::Instant::now()),
-            state: WorkerState::Working,
-        });
+    set_cve_scanner_status(
+        0,
+        WorkerState::Working,
+        Some("finding scan targets".to_string()),
+    )
+    .await;
+
+    let derivations =
+        match get_targets_needing_cve_scan(pool, Some(max_concurrent_scans as i64)).await {
+            Ok(derivations) => derivations,
+            Err(e) => {
+                error!("❌ Failed to get derivations needing CVE scan: {e}");
+                set_cve_scanner_status(0, WorkerState::Idle, None).await;
+                return Ok(());
+            }
+        };
+
+    if derivations.is_empty() {
+        info!("🔍 No derivations need CVE scanning");
+        set_cve_scanner_status(0, WorkerState::Idle, None).await;
+        return Ok(());
     }
 
-    match get_targets_needing_cve_scan(pool, Some(1)).await {
-        Ok(derivations) => {
-            if derivations.is_empty() {
-                info!("🔍 No derivations need CVE scanning");
-                // Update status: idle
+    let queue = tokio::sync::Mutex::new(std::collections::VecDeque::from(derivations));
+    let scanners = (0..max_concurrent_scans).map(|scanner_id| {
+        let queue = &queue;
+        let vulnix_version = vulnix_version.clone();
+        async move {
+            loop {
+                let Some(derivation) = queue.lock().await.pop_front() else {
+                    break;
+                };
+                set_cve_scanner_status(
+                    scanner_id,
+                    WorkerState::Working,
+                    Some(format!("scanning {}", derivation.derivation_name)),
+                )
+                .await;
+
+                if let Err(e) = scan_derivation(
+                    pool,
+                    vulnix_runner,
+                    vulnix_version.clone(),
+                    &derivation,
+                    sbom_format,
+                )
+                .await
                 {
-                    let mut status = get_cve_status().write().await;
-                    *status = Some(WorkerStatus {
-                        worker_id: 0,
-                        current_task: None,
-                        started_at: None,
-                        state: WorkerState::Idle,
-                    });
+                    error!("❌ CVE scan of {} aborted: {e}", derivation.derivation_name);
                 }
-                info!("No derivations need CVE scanning");
-                return Ok(());
             }
+            set_cve_scanner_status(scanner_id, WorkerState::Idle, None).await;
+        }
+    });
+    futures::future::join_all(scanners).await;
 
-            let derivation = &derivations[0];
+    Ok(())
+}
 
-            // Update status: scanning specific derivation
-            {
-                let mut status = get_cve_status().write().await;
-                *status = Some(WorkerStatus {
-                    worker_id: 0,
-                    current_task: Some(format!("scanning {}", derivation.derivation_name)),
-                    started_at: Some(std::time::Instant::now()),
-                    state: WorkerState::Working,
-                });
-            }
+/// Scan a single derivation, recording the scan and its results
+async fn scan_derivation(
+    pool: &PgPool,
+    vulnix_runner: &VulnixRunner,
+    vulnix_version: Option<String>,
+    derivation: &Derivation,
+    sbom_format: Option<SbomFormat>,
+) -> Result<()> {
+    let Some(ref path) = derivation.store_path else {
+        warn!("❌ No derivation path set for derivation");
+        return Ok(());
+    };
 
-            // Check if the derivation path exists
-            if let Some(ref path) = derivation.store_path {
-                match fs::try_exists(path).await {
-                    Ok(true) => {
-                        info!(
-                            "🔍 Starting CVE scan for derivation: {}",
-                            derivation.derivation_name
-                        );
+    match fs::try_exists(path).await {
+        Ok(true) => {
+            info!(
+                "🔍 Starting CVE scan for derivation: {}",
+                derivation.derivation_name
+            );
 
-                        // Create a new scan record before starting
-                        let scan_id =
-                            create_cve_scan(pool, derivation.id, "vulnix", vulnix_version.clone())
-                                .await?;
+            // Create a new scan record before starting
+            let scan_id =
+                create_cve_scan(pool, derivation.id, "vulnix", vulnix_version.clone()).await?;
 
-                        // Mark scan as in progress
-                        mark_scan_in_progress(pool, scan_id).await?;
+            // Mark scan as in progress
+            mark_scan_in_progress(pool, scan_id).await?;
 
-                        let start_time = std::time::Instant::now();
+            let start_time = std::time::Instant::now();
 
-                        // Run CVE scan using the vulnix runner
-                        match vulnix_runner
-                            .scan_derivation(&pool, derivation.id, vulnix_version)
-                            .await
-                        {
-                            Ok(vulnix_entries) => {
-                                let scan_duration_ms =
-                                    Some(start_time.elapsed().as_millis() as i32);
-                                let stats =
-                                    crate::vulnix::vulnix_parser::VulnixParser::calculate_stats(
-                                        &vulnix_entries,
-                                    );
-
-                                // Save the detailed scan results to database
-                                save_scan_results(pool, scan_id, &vulnix_entries, scan_duration_ms)
-                                    .await?;
-
-                                info!(
-                                    "✅ CVE scan completed for {}: {}",
-                                    derivation.derivation_name, stats
-                                );
-                            }
-                            Err(e) => {
-                                error!(
-                                    "❌ CVE scan failed for {}: {}",
-                                    derivation.derivation_name, e
-                                );
-                                if let Err(save_err) =
-                                    mark_cve_scan_failed(pool, derivation, &e.to_string()).await
-                                {
-                                    error!("❌ Failed to mark CVE scan as failed: {save_err}");
-                                }
-                            }
-                        }
-                    }
-                    Ok(false) => {
-                        warn!("❌ Derivation path does not exist: {}", path);
-                        update_derivation_status(
-                            &pool,
-                            derivation.id,
-                            EvaluationStatus::DryRunComplete,
-                            derivation.derivation_path.as_deref(),
-                            Some("Missing Nix Store Path"),
-                            derivation.store_path.as_deref(),
-                        )
-                        .await?;
+            // Run CVE scan using the vulnix runner
+            match vulnix_runner
+                .scan_derivation(pool, derivation.id, vulnix_version)
+                .await
+            {
+                Ok(vulnix_entries) => {
+                    let scan_duration_ms = Some(start_time.elapsed().as_millis() as i32);
+
+                    // Save the detailed scan results to database
+                    let stats =
+                        save_scan_results(pool, scan_id, &vulnix_entries, scan_duration_ms).await?;
+
+                    info!(
+                        "✅ CVE scan completed for {}: {}",
+                        derivation.derivation_name, stats
+                    );
+
+                    if stats.critical_count > 0 {
+                        notifications::notify(Notification::new(
+                            NotificationEvent::CveCritical,
+                            &derivation.derivation_name,
+                            None,
+                            &stats.to_string(),
+                        ))
+                        .await;
                     }
-                    Err(e) => {
-                        error!("❌ Error checking derivation path {}: {}", path, e);
+                }
+                Err(e) => {
+                    error!(
+                        "❌ CVE scan failed for {}: {}",
+                        derivation.derivation_name, e
+                    );
+                    if let Err(save_err) =
+                        mark_cve_scan_failed(pool, derivation, &e.to_string()).await
+                    {
+                        error!("❌ Failed to mark CVE scan as failed: {save_err}");
                     }
                 }
-            } else {
-                warn!("❌ No derivation path set for derivation");
             }
+
+            if let Some(format) = sbom_format {
+                record_sbom(pool, derivation, format).await;
+            }
+        }
+        Ok(false) => {
+            warn!("❌ Derivation path does not exist: {}", path);
+            update_derivation_status(
+                pool,
+                derivation.id,
+                EvaluationStatus::DryRunComplete,
+                derivation.derivation_path.as_deref(),
+                Some("Missing Nix Store Path"),
+                derivation.store_path.as_deref(),
+            )
+            .await?;
+        }
+        Err(e) => {
+            error!("❌ Error checking derivation path {}: {}", path, e);
         }
-        Err(e) => error!("❌ Failed to get derivations needing CVE scan: {e}"),
     }
+
     Ok(())
 }
 
-/// Wrapper around process_cache_pushes that ensures errors don't propagate
-async fn process_cache_pushes_safe(
-    pool: &PgPool,
-    cache_config: &CacheConfig,
-    build_config: &BuildConfig,
-) -> Result<usize> {
-    let result =
-        std::panic::AssertUnwindSafe(process_cache_pushes(pool, cache_config, build_config))
-            .catch_unwind()
-            .await;
-
-    match result {
-        Ok(res) => res,
-        Err(_) => {
-            error!("💥 Cache push process panicked! Recovering...");
-            Err(anyhow::anyhow!("Cache push process panicked"))
+/// Generate and store the SBOM of a scanned derivation. Failures are only
+/// logged so they never hold up CVE scanning.
+async fn record_sbom(pool: &PgPool, derivation: &Derivation, format: SbomFormat) {
+    let sbom = match sbom::generate(derivation, format).await {
+        Ok(sbom) => sbom,
+        Err(e) => {
+            warn!(
+                "⚠️ SBOM generation failed for {}: {:#}",
+                derivation.derivation_name, e
+            );
+            return;
         }
+    };
+    match save_sbom(pool, derivation.id, &sbom).await {
+        Ok(()) => info!(
+            "📋 Recorded {} SBOM for {} ({} components)",
+            format.as_str(),
+            derivation.derivation_name,
+            sbom.component_count
+        ),
+        Err(e) => warn!(
+            "⚠️ Failed to store SBOM for {}: {}",
+            derivation.derivation_name, e
+        ),
     }
 }
 
-/// Process cache pushes for completed builds (one at a time to avoid batching issues)
-pub async fn process_cache_pushes(
+/// The destination a job pushes to: its own, or the configured default
+fn job_destination<'a>(
+    destination: &'a Option<String>,
+    cache_config: &'a CacheConfig,
+) -> Option<&'a str> {
+    destination.as_deref().or(cache_config.push_to.as_deref())
+}
+
+/// If the job's destination circuit is open, park the job as deferred.
+/// Returns true when the job was deferred and should not be pushed now.
+async fn defer_if_circuit_open(
     pool: &PgPool,
     cache_config: &CacheConfig,
-    build_config: &BuildConfig,
-) -> Result<usize> {
-    // ← Changed from Result<()> to Result<usize>
-    let Some(destination) = cache_config.push_to.as_deref() else {
-        debug!("⭐️ No cache destination configured, skipping cache push");
-        return Ok(0); // ← Changed from Ok(()) to Ok(0)
+    job: &CachePushJob,
+) -> bool {
+    let Some(destination) = job_destination(&job.cache_destination, cache_config) else {
+        return false;
     };
 
-    let db_timeout = std::time::Duration::from_secs(30);
+    let Err(remaining) = circuit_breaker::check_destination(destination, cache_config) else {
+        return false;
+    };
 
-    // Always try to cleanup stale jobs first
-    let _ = tokio::time::timeout(
-        std::time::Duration::from_secs(10),
-        cleanup_stale_cache_push_jobs(pool, 5),
+    let defer_secs = remaining.as_secs().max(1);
+    debug!(
+        "🔌 Circuit open for {}, deferring cache push job {} for {}s",
+        destination, job.id, defer_secs
+    );
+    if let Err(e) = mark_cache_push_deferred(
+        pool,
+        job.id,
+        defer_secs,
+        &format!("Deferred: circuit open for {}", destination),
     )
-    .await;
-
-    // Get pending jobs (up to 5 at a time for batching)
-    let jobs_result =
-        tokio::time::timeout(db_timeout, get_pending_cache_push_jobs(pool, Some(5))).await;
-
-    match jobs_result {
-        Ok(Ok(jobs)) if !jobs.is_empty() => {
-            let job_count = jobs.len();
-            if let Err(e) = process_batch_cache_push(pool, jobs, cache_config, build_config).await {
-                error!("❌ Failed to process batch cache push: {}", e);
-            }
-            Ok(job_count)
-        }
-        Ok(Err(e)) => {
-            error!("❌ Failed to get pending cache push jobs: {e}");
-            Ok(0)
-        }
-        Err(_) => {
-            error!("⏱️ Timeout getting pending cache push jobs");
-            Ok(0)
-        }
-        _ => Ok(0),
+    .await
+    {
+        warn!("Failed to defer cache push job {}: {}", job.id, e);
     }
+    true
 }
 
-async fn process_batch_cache_push(
+/// If `max_push_closure_size_bytes` is set and the closure of `store_path` is
+/// larger, fail the job for good. Returns true when the job was rejected and
+/// should not be pushed. A size that can't be read lets the push go ahead.
+async fn reject_if_oversized(
     pool: &PgPool,
-    jobs: Vec<crate::queries::cache_push::CachePushJob>,
     cache_config: &CacheConfig,
-    build_config: &BuildConfig,
-) -> Result<()> {
-    if jobs.is_empty() {
-        return Ok(());
+    job_id: i32,
+    store_path: &str,
+) -> bool {
+    if cache_config.max_push_closure_size_bytes.is_none() {
+        return false;
     }
 
-    info!("📤 Processing {} cache push jobs (parallel)", jobs.len());
-
-    // Process up to 3 jobs concurrently
-    let mut tasks = Vec::new();
-
-    for job in jobs {
-        let pool = pool.clone();
-        let cache_config = cache_config.clone();
-        let build_config = build_config.clone();
-
-        let task = tokio::spawn(async move {
-            if let Some(store_path) = job.store_path {
-                // Check if path exists
-                if !tokio::fs::try_exists(&store_path).await.unwrap_or(false) {
-                    warn!("❌ Store path doesn't exist: {}", store_path);
-                    let _ = mark_cache_push_failed(
-                        &pool,
-                        job.id,
-                        &format!("Store path does not exist: {}", store_path),
-                    )
-                    .await;
-                    return;
-                }
+    let closure_size = match get_closure_size(store_path).await {
+        Ok(size) => size,
+        Err(e) => {
+            warn!("⚠️ Could not read closure size of {}: {}", store_path, e);
+            return false;
+        }
+    };
+    let Some(error) = cache_config.push_size_error(store_path, closure_size) else {
+        return false;
+    };
 
-                // Mark in-progress
-                if mark_cache_push_in_progress(&pool, job.id).await.is_err() {
-                    return;
-                }
+    if let Err(e) = mark_cache_push_rejected(pool, job_id, &error).await {
+        warn!("Failed to reject cache push job {}: {}", job_id, e);
+    }
+    true
+}
 
-                // Get derivation
-                let derivation = match crate::queries::derivations::get_derivation_by_id(
-                    &pool,
-                    job.derivation_id,
-                )
-                .await
-                {
-                    Ok(d) => d,
-                    Err(e) => {
-                        let _ = mark_cache_push_failed(&pool, job.id, &e.to_string()).await;
-                        return;
-                    }
-                };
+/// Drop jobs whose store path is already in their destination (e.g. pushed by
+/// another builder that produced the same path), marking them completed.
+///
+/// The lookup is one batched query per destination and is bounded by
+/// `dedup_check_timeout_seconds`; if it fails or times out every job is kept.
+async fn skip_jobs_already_in_cache(
+    pool: &PgPool,
+    jobs: Vec<CachePushJob>,
+    cache_config: &CacheConfig,
+) -> Vec<CachePushJob> {
+    if cache_config.dedup_check_timeout_seconds == 0 || cache_config.force_repush {
+        return jobs;
+    }
+    let check_timeout = Duration::from_secs(cache_config.dedup_check_timeout_seconds);
+
+    let mut by_destination: HashMap<String, Vec<String>> = HashMap::new();
+    for job in &jobs {
+        if let (Some(destination), Some(store_path)) = (
+            job_destination(&job.cache_destination, cache_config),
+            &job.store_path,
+        ) {
+            by_destination
+                .entry(destination.to_string())
+                .or_default()
+                .push(store_path.clone());
+        }
+    }
 
-                // Push with retry
-                let start = std::time::Instant::now();
-                match derivation
-                    .push_to_cache_with_retry(&store_path, &cache_config, &build_config)
-                    .await
-                {
-                    Ok(()) => {
-                        let duration_ms = start.elapsed().as_millis() as i32;
-                        let _ =
-                            mark_cache_push_completed(&pool, job.id, None, Some(duration_ms)).await;
-                        info!("✅ Pushed {} (job {})", derivation.derivation_name, job.id);
-                    }
-                    Err(e) => {
-                        let _ = mark_cache_push_failed(&pool, job.id, &e.to_string()).await;
-                        error!("❌ Failed to push job {}: {}", job.id, e);
-                    }
-                }
+    let mut present: HashMap<String, HashSet<String>> = HashMap::new();
+    for (destination, store_paths) in by_destination {
+        let Some(store_uri) = cache_config.destination_store_uri(&destination) else {
+            continue;
+        };
+        let lookup = paths_present_in_store(&store_uri, &store_paths, cache_config);
+        match timeout(check_timeout, lookup).await {
+            Ok(Ok(paths)) => {
+                present.insert(destination, paths);
             }
-        });
+            Ok(Err(e)) => warn!("⚠️ Dedup check against {} failed: {}", destination, e),
+            Err(_) => warn!(
+                "⚠️ Dedup check against {} timed out after {:?}, pushing everything",
+                destination, check_timeout
+            ),
+        }
+    }
 
-        tasks.push(task);
+    let mut remaining = Vec::with_capacity(jobs.len());
+    for job in jobs {
+        let already_present = job_destination(&job.cache_destination, cache_config)
+            .and_then(|destination| present.get(destination))
+            .zip(job.store_path.as_ref())
+            .is_some_and(|(paths, store_path)| paths.contains(store_path));
 
-        // Limit concurrency - wait if we have 3 running
-        if tasks.len() >= 3 {
-            if let Some(task) = tasks.pop() {
-                let _ = task.await;
-            }
+        if !already_present {
+            remaining.push(job);
+            continue;
         }
-    }
 
-    // Wait for remaining tasks
-    for task in tasks {
-        let _ = task.await;
+        info!(
+            "⏭️ {} already in cache, skipping push (job {})",
+            job.store_path.as_deref().unwrap_or_default(),
+            job.id
+        );
+        if let Err(e) = mark_cache_push_completed(pool, job.id, None, Some(0)).await {
+            warn!("Failed to mark cache push job {} completed: {}", job.id, e);
+        }
     }
 
-    Ok(())
+    remaining
 }
 
 /// Cleanup loop for stale reservations
-async fn run_reservation_cleanup_loop(pool: PgPool) {
-    info!("🧹 Starting reservation cleanup loop...");
+///
+/// Reservations whose heartbeat is older than `lease` are reclaimed. The sweep
+/// runs at least twice per lease so a dead worker's derivation doesn't wait
+/// much longer than the lease to be picked up again.
+///
+/// Nothing is reclaimed during the first `grace` after startup: reservations
+/// held by builders on other hosts can look stale until their next
+/// heartbeat, and reclaiming them would rebuild work that is still running.
+async fn run_reservation_cleanup_loop(
+    pool: PgPool,
+    lease: Duration,
+    grace: Duration,
+    mut shutdown: ShutdownRx,
+) {
+    info!(
+        "🧹 Starting reservation cleanup loop (lease {}s, startup grace {:?})...",
+        lease.as_secs(),
+        grace
+    );
+    if shutdown::sleep_or_shutdown(grace, &mut shutdown).await {
+        return;
+    }
+    let sweep_interval = (lease / 2).min(Duration::from_secs(60));
 
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+        if shutdown::sleep_or_shutdown(sweep_interval, &mut shutdown).await {
+            return;
+        }
 
-        match build_reservations::cleanup_stale_reservations(&pool, 300).await {
+        match build_reservations::cleanup_stale_reservations(&pool, lease.as_secs() as i64).await {
             Ok(reclaimed) if !reclaimed.is_empty() => {
                 warn!(
                     "🧹 Reclaimed {} stale reservations: {:?}",
@@ -987,28 +1528,325 @@ async fn run_reservation_cleanup_loop(pool: PgPool) {
     }
 }
 
-/// Mark build complete and release reservation
+/// Periodically block queued derivations whose dependencies failed, and
+/// unblock the ones whose failed dependencies have since been reset or built
+async fn run_blocked_derivation_loop(pool: PgPool, mut shutdown: ShutdownRx) {
+    info!("⛔ Starting blocked derivation loop...");
+
+    loop {
+        if shutdown::sleep_or_shutdown(BLOCKED_RECHECK_INTERVAL, &mut shutdown).await {
+            return;
+        }
+
+        match block_derivations_with_failed_dependencies(&pool, None).await {
+            Ok(0) => {}
+            Ok(blocked) => warn!("⛔ Blocked {} derivations on failed dependencies", blocked),
+            Err(e) => error!("❌ Failed to block derivations: {}", e),
+        }
+        match unblock_derivations(&pool).await {
+            Ok(0) => {}
+            Ok(unblocked) => info!("✅ Unblocked {} derivations", unblocked),
+            Err(e) => error!("❌ Failed to unblock derivations: {}", e),
+        }
+    }
+}
+
+/// Periodically reset built derivations whose store path no longer exists
+/// so they are built again, instead of waiting for a cache push to notice.
+/// Those of cache-only systems are flagged for an operator instead.
+async fn run_store_path_audit_loop(pool: PgPool, interval: Duration, mut shutdown: ShutdownRx) {
+    info!(
+        "🔎 Starting store path audit loop (every {}s)...",
+        interval.as_secs()
+    );
+
+    loop {
+        if shutdown::sleep_or_shutdown(interval, &mut shutdown).await {
+            return;
+        }
+
+        // Walk the built derivations a page at a time
+        let mut cursor = Some(0);
+        let mut missing_total = 0;
+        while let Some(after_id) = cursor {
+            let missing = match find_missing_store_paths(&pool, after_id).await {
+                Ok((missing, next)) => {
+                    cursor = next;
+                    missing
+                }
+                Err(e) => {
+                    error!("❌ Store path audit failed: {}", e);
+                    break;
+                }
+            };
+            missing_total += missing.len();
+            reset_missing_store_paths(&pool, missing).await;
+        }
+        if missing_total == 0 {
+            debug!("🔎 All built store paths are present");
+        }
+    }
+}
+
+/// Queue rebuilds of derivations found by [`find_missing_store_paths`]
+async fn reset_missing_store_paths(pool: &PgPool, missing: Vec<Derivation>) {
+    if missing.is_empty() {
+        return;
+    }
+
+    warn!(
+        "🔎 {} built derivations are missing their store path, queueing rebuilds",
+        missing.len()
+    );
+    for derivation in missing {
+        match reset_derivation_for_rebuild(pool, derivation.id).await {
+            Ok(true) => {}
+            // Cache-only systems are never built here
+            Ok(false) => match flag_missing_cache_only_derivation(pool, derivation.id).await {
+                Ok(true) => warn!(
+                    "🚩 {} is cache-only and its store path is gone, needs manual attention",
+                    derivation.derivation_name
+                ),
+                Ok(false) => {}
+                Err(e) => error!(
+                    "❌ Failed to flag {} for attention: {}",
+                    derivation.derivation_name, e
+                ),
+            },
+            Err(e) => error!(
+                "❌ Failed to reset {} for rebuild: {}",
+                derivation.derivation_name, e
+            ),
+        }
+    }
+}
+
+/// Watchdog for builds that have stopped producing output
+///
+/// Warns (and POSTs to `webhook`, if set) once per build attempt whose
+/// quiet time exceeds `threshold`, long before the build timeout fires.
+async fn run_stuck_build_watchdog(
+    pool: PgPool,
+    worker_pattern: String,
+    threshold: Duration,
+    webhook: Option<String>,
+    mut shutdown: ShutdownRx,
+) {
+    info!(
+        "🐕 Starting stuck build watchdog (threshold {}s)...",
+        threshold.as_secs()
+    );
+    let check_interval = (threshold / 2).min(Duration::from_secs(60));
+    let client = reqwest::Client::new();
+    // (derivation_id, started_at) of attempts already reported
+    let mut alerted: HashSet<(i32, Option<chrono::DateTime<chrono::Utc>>)> = HashSet::new();
+
+    loop {
+        if shutdown::sleep_or_shutdown(check_interval, &mut shutdown).await {
+            return;
+        }
+
+        let stuck = match build_reservations::find_stuck_builds(
+            &pool,
+            &worker_pattern,
+            threshold.as_secs() as i64,
+        )
+        .await
+        {
+            Ok(stuck) => stuck,
+            Err(e) => {
+                error!("❌ Error checking for stuck builds: {}", e);
+                continue;
+            }
+        };
+
+        let current: HashSet<_> = stuck
+            .iter()
+            .map(|b| (b.derivation_id, b.started_at))
+            .collect();
+        alerted.retain(|key| current.contains(key));
+
+        for build in stuck {
+            if !alerted.insert((build.derivation_id, build.started_at)) {
+                continue;
+            }
+
+            warn!(
+                "🐕 Build of {} (derivation {}) on {} has been quiet for {}s (phase: {}, last target: {})",
+                build.derivation_name,
+                build.derivation_id,
+                build.worker_id,
+                build.quiet_seconds,
+                build.build_phase.as_deref().unwrap_or("unknown"),
+                build.build_current_target.as_deref().unwrap_or("unknown")
+            );
+
+            if let Some(url) = &webhook {
+                let payload = serde_json::json!({
+                    "text": format!(
+                        "Build of {} on {} has been quiet for {}s",
+                        build.derivation_name, build.worker_id, build.quiet_seconds
+                    ),
+                    "event": "stuck_build",
+                    "build": build,
+                });
+                match client.post(url).json(&payload).send().await {
+                    Ok(resp) if !resp.status().is_success() => {
+                        warn!("⚠️ Stuck build webhook returned {}", resp.status());
+                    }
+                    Err(e) => warn!("⚠️ Failed to call stuck build webhook: {}", e),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Fail a build whose store path isn't an output of its derivation. A
+/// failed check is logged and the path accepted.
+async fn verify_build_output(derivation: &Derivation, store_path: String) -> Result<String> {
+    let Some(drv_path) = derivation.derivation_path.as_deref() else {
+        return Ok(store_path);
+    };
+    match is_output_of(drv_path, &store_path).await {
+        Ok(true) => Ok(store_path),
+        Ok(false) => Err(anyhow::anyhow!(
+            "built store path {} is not an output of {}",
+            store_path,
+            drv_path
+        )),
+        Err(e) => {
+            warn!("⚠️ Could not verify outputs of {}: {}", drv_path, e);
+            Ok(store_path)
+        }
+    }
+}
+
+/// Mark build complete, record the building host and release reservation
 async fn mark_build_complete_and_release(
     pool: &PgPool,
     worker_uuid: &str,
+    hostname: &str,
     derivation_id: i32,
     store_path: &str,
 ) -> Result<()> {
-    let mut tx = pool.begin().await?;
+    db::retry_transaction("build completion", || async {
+        let mut tx = pool.begin().await?;
 
-    // Delete reservation
-    build_reservations::delete_reservation(&mut *tx, worker_uuid, derivation_id).await?;
+        // Lock the derivation before deleting its reservation, the order
+        // every reservation-releasing transaction uses
+        lock_derivation(&mut *tx, derivation_id).await?;
+        build_reservations::delete_reservation(&mut *tx, worker_uuid, derivation_id).await?;
 
-    // Mark complete
-    mark_target_build_complete(&mut *tx, derivation_id, store_path).await?;
+        // Mark complete
+        mark_target_build_complete(&mut *tx, derivation_id, store_path).await?;
+        set_derivation_built_by_host(&mut *tx, derivation_id, hostname).await?;
 
-    tx.commit().await?;
+        tx.commit().await?;
+        Ok(())
+    })
+    .await?;
 
     // Create GC root to prevent cleanup before cache push
     if let Err(e) = create_gc_root(store_path, derivation_id).await {
         warn!("Failed to create GC root for {}: {}", store_path, e);
     }
 
+    // Record the NAR hash so agents can verify what they copy from the cache
+    match get_nar_hash(store_path).await {
+        Ok(nar_hash) => {
+            if let Err(e) = set_derivation_nar_hash(pool, derivation_id, &nar_hash).await {
+                warn!("Failed to record NAR hash for {}: {}", store_path, e);
+            }
+        }
+        Err(e) => warn!("Failed to read NAR hash of {}: {}", store_path, e),
+    }
+
+    Ok(())
+}
+
+/// Run `build.post_build_hook` for a finished build. Failures and timeouts
+/// are only logged; the build has already been recorded as complete.
+async fn run_post_build_hook(build_config: &BuildConfig, store_path: &str, derivation_name: &str) {
+    let Some(hook) = build_config.post_build_hook.as_deref() else {
+        return;
+    };
+
+    let mut cmd = tokio::process::Command::new(hook);
+    cmd.args([store_path, derivation_name])
+        .env("CF_STORE_PATH", store_path)
+        .env("CF_DERIVATION_NAME", derivation_name)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+
+    match timeout(build_config.post_build_hook_timeout, cmd.output()).await {
+        Ok(Ok(output)) if output.status.success() => {
+            debug!("🪝 post-build hook succeeded for {}", derivation_name);
+        }
+        Ok(Ok(output)) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            warn!(
+                "⚠️ post-build hook {} failed for {} ({}): {}",
+                hook,
+                derivation_name,
+                output.status,
+                crate::log::redact::redact(stderr.trim())
+            );
+        }
+        Ok(Err(e)) => warn!("⚠️ failed to run post-build hook {}: {}", hook, e),
+        Err(_) => warn!(
+            "⏱️ post-build hook {} timed out after {:?} for {}",
+            hook, build_config.post_build_hook_timeout, derivation_name
+        ),
+    }
+}
+
+/// Output `push_outputs` selects for `derivation`, looked up by its name
+/// (the hostname of a system) and then its package name
+fn push_output_name<'a>(cache_config: &'a CacheConfig, derivation: &Derivation) -> Option<&'a str> {
+    [
+        Some(derivation.derivation_name.as_str()),
+        derivation.pname.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|name| cache_config.push_outputs.get(name))
+    .map(String::as_str)
+}
+
+/// Queue a cache push of a freshly built derivation to each destination of
+/// the environments it belongs to (or the global destination)
+async fn queue_cache_pushes(
+    pool: &PgPool,
+    derivation: &Derivation,
+    store_path: &str,
+    cache_config: &CacheConfig,
+    environment_cache_destinations: &HashMap<String, String>,
+) -> Result<()> {
+    let derivation_id = derivation.id;
+    let output_name = push_output_name(cache_config, derivation);
+    let Some(default_destination) = cache_config.push_to.as_deref() else {
+        create_cache_push_job(pool, derivation_id, store_path, None, output_name).await?;
+        return Ok(());
+    };
+
+    let destinations = cache_destinations_for_derivation(
+        pool,
+        derivation_id,
+        default_destination,
+        environment_cache_destinations,
+    )
+    .await?;
+    for destination in &destinations {
+        create_cache_push_job(
+            pool,
+            derivation_id,
+            store_path,
+            Some(destination),
+            output_name,
+        )
+        .await?;
+    }
     Ok(())
 }
 
@@ -1016,27 +1854,65 @@ async fn mark_build_complete_and_release(
 async fn mark_build_failed_and_release(
     pool: &PgPool,
     worker_uuid: &str,
+    hostname: &str,
     derivation: &Derivation,
     error: &anyhow::Error,
 ) -> Result<()> {
-    let mut tx = pool.begin().await?;
-
-    // Delete reservation
-    build_reservations::delete_reservation(&mut *tx, worker_uuid, derivation.id).await?;
+    let blocked = db::retry_transaction("build failure", || async {
+        let mut tx = pool.begin().await?;
+
+        lock_derivation(&mut *tx, derivation.id).await?;
+        build_reservations::delete_reservation(&mut *tx, worker_uuid, derivation.id).await?;
+
+        // Mark failed, and stop offering the builds that depend on it
+        handle_derivation_failure(&mut *tx, derivation, "build", error).await?;
+        set_derivation_failed_on_host(&mut *tx, derivation.id, hostname).await?;
+        let blocked =
+            block_derivations_with_failed_dependencies(&mut *tx, Some(derivation.id)).await?;
+
+        tx.commit().await?;
+        Ok(blocked)
+    })
+    .await?;
+    if blocked > 0 {
+        warn!(
+            "⛔ Blocked {} derivations that depend on {}",
+            blocked, derivation.derivation_name
+        );
+    }
 
-    // Mark failed
-    handle_derivation_failure(&mut *tx, derivation, "build", error).await?;
+    let commit = match derivation.commit_id {
+        Some(commit_id) => get_commit_by_id(pool, commit_id)
+            .await
+            .ok()
+            .map(|c| c.git_commit_hash),
+        None => None,
+    };
+    notifications::notify(Notification::new(
+        NotificationEvent::BuildFailed,
+        &derivation.derivation_name,
+        commit,
+        &format!("{:#}", error),
+    ))
+    .await;
 
-    tx.commit().await?;
     Ok(())
 }
 
 /// Worker heartbeat loop - updates reservation heartbeat every 30 seconds
-async fn worker_heartbeat_loop(worker_uuid: String, pool: PgPool) {
-    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(30));
+async fn worker_heartbeat_loop(
+    worker_uuid: String,
+    pool: PgPool,
+    heartbeat_interval: Duration,
+    mut shutdown: ShutdownRx,
+) {
+    let mut interval = tokio::time::interval(heartbeat_interval);
 
     loop {
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown::requested(&mut shutdown) => return,
+        }
 
         match build_reservations::update_heartbeat(&pool, &worker_uuid).await {
             Ok(count) if count > 0 => {
@@ -1053,6 +1929,92 @@ async fn worker_heartbeat_loop(worker_uuid: String, pool: PgPool) {
     }
 }
 
+/// Directory of the GC roots that keep deployment targets alive
+const TARGET_GC_ROOT_DIR: &str = "/var/cache/crystal-forge/gc-roots/targets";
+
+/// Keep `desired_target` store paths rooted until no system wants them
+async fn run_target_gc_root_loop(pool: PgPool, interval: Duration, mut shutdown: ShutdownRx) {
+    info!(
+        "📌 Starting deployment target GC root loop (every {}s)...",
+        interval.as_secs()
+    );
+
+    loop {
+        match refresh_target_gc_roots(&pool).await {
+            Ok((added, removed)) if added + removed > 0 => {
+                info!("📌 Target GC roots: {} added, {} released", added, removed)
+            }
+            Ok(_) => debug!("📌 Target GC roots are up to date"),
+            Err(e) => error!("❌ Failed to refresh target GC roots: {}", e),
+        }
+
+        if shutdown::sleep_or_shutdown(interval, &mut shutdown).await {
+            return;
+        }
+    }
+}
+
+/// Root every desired target present in the local store and drop the roots
+/// of paths no system references any more. Returns `(added, removed)`.
+async fn refresh_target_gc_roots(pool: &PgPool) -> Result<(usize, usize)> {
+    let wanted: HashMap<String, String> = get_desired_target_store_paths(pool)
+        .await?
+        .into_iter()
+        .filter_map(|path| Some((target_gc_root_name(&path)?.to_string(), path)))
+        .collect();
+    fs::create_dir_all(TARGET_GC_ROOT_DIR).await?;
+
+    let mut existing = HashSet::new();
+    let mut removed = 0;
+    let mut entries = fs::read_dir(TARGET_GC_ROOT_DIR).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if wanted.contains_key(&name) {
+            existing.insert(name);
+        } else if let Err(e) = fs::remove_file(entry.path()).await {
+            warn!(
+                "Failed to release GC root {}: {}",
+                entry.path().display(),
+                e
+            );
+        } else {
+            debug!("Released target GC root {}", name);
+            removed += 1;
+        }
+    }
+
+    let mut added = 0;
+    for (name, store_path) in &wanted {
+        if existing.contains(name) || !fs::try_exists(store_path).await.unwrap_or(false) {
+            continue;
+        }
+        let root = format!("{}/{}", TARGET_GC_ROOT_DIR, name);
+        let output = tokio::process::Command::new("nix-store")
+            .args(["--realise", store_path, "--add-root", &root, "--indirect"])
+            .output()
+            .await?;
+        if output.status.success() {
+            debug!("Created target GC root: {} -> {}", root, store_path);
+            added += 1;
+        } else {
+            warn!(
+                "Failed to root {}: {}",
+                store_path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+
+    Ok((added, removed))
+}
+
+/// File name of the GC root for a store path: its `/nix/store` entry name
+fn target_gc_root_name(store_path: &str) -> Option<&str> {
+    store_path
+        .strip_prefix("/nix/store/")
+        .filter(|name| !name.is_empty() && !name.contains('/') && !name.starts_with('.'))
+}
+
 pub async fn get_gc_root_path(derivation_id: i32) -> String {
     let gc_root_dir = "/var/cache/crystal-forge/gc-roots";
     tokio::fs::create_dir_all(gc_root_dir)
@@ -1091,3 +2053,62 @@ pub async fn remove_gc_root(derivation_id: i32) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_gc_root_name_is_the_store_entry() {
+        assert_eq!(
+            target_gc_root_name("/nix/store/abc123-nixos-system-web01"),
+            Some("abc123-nixos-system-web01")
+        );
+        assert_eq!(target_gc_root_name("/nix/store/"), None);
+        assert_eq!(target_gc_root_name("/nix/store/abc/etc"), None);
+        assert_eq!(target_gc_root_name("/tmp/abc"), None);
+        assert_eq!(target_gc_root_name("/nix/store/.links"), None);
+    }
+
+    #[tokio::test]
+    async fn queues_the_configured_output() {
+        let Some(pool) = crate::db::test_pool().await else {
+            return;
+        };
+        let name = format!("push-output-test-{}", uuid::Uuid::new_v4());
+        let derivation_id: i32 = sqlx::query_scalar(
+            r#"
+            INSERT INTO derivations (derivation_type, derivation_name, pname, status_id)
+            VALUES ('package', $1, 'mytool', 10)
+            RETURNING id
+            "#,
+        )
+        .bind(&name)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let derivation = get_derivation_by_id(&pool, derivation_id).await.unwrap();
+        let cache_config = CacheConfig {
+            push_outputs: HashMap::from([("mytool".to_string(), "bin".to_string())]),
+            ..CacheConfig::default()
+        };
+
+        queue_cache_pushes(
+            &pool,
+            &derivation,
+            "/nix/store/aaa-mytool",
+            &cache_config,
+            &HashMap::new(),
+        )
+        .await
+        .unwrap();
+
+        let outputs: Vec<Option<String>> =
+            sqlx::query_scalar("SELECT output_name FROM cache_push_jobs WHERE derivation_id = $1")
+                .bind(derivation_id)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(outputs, vec![Some("bin".to_string())]);
+    }
+}
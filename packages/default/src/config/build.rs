@@ -1,4 +1,7 @@
+use crate::deployment::glob_matches;
+use base64::Engine;
 use serde::Deserialize;
+use std::path::PathBuf;
 use std::time::Duration;
 
 /// Configuration for nix build resource limits and behavior
@@ -54,6 +57,255 @@ pub struct BuildConfig {
     /// Default: 0 (let single builds use all cores)
     #[serde(default = "default_cores_per_job")]
     pub cores_per_job: usize,
+
+    /// Passed as `--option substituters`.
+    /// Empty means use whatever nix.conf says.
+    pub substituters: Vec<String>,
+
+    /// Substituters added to the ones nix.conf (or `substituters`) already
+    /// configures. Passed as `--option extra-substituters`.
+    pub extra_substituters: Vec<String>,
+
+    /// Public keys (`name:base64`) trusted in addition to nix.conf's, for
+    /// signatures from `extra_substituters`. Passed as
+    /// `--option extra-trusted-public-keys`.
+    pub extra_trusted_public_keys: Vec<String>,
+
+    /// How often a build worker refreshes the heartbeat on its reservations
+    #[serde(with = "humantime_serde")]
+    pub heartbeat_interval: Duration,
+    /// Minimum time between build progress writes to the derivation row
+    /// while a build streams output. Progress in between is coalesced into
+    /// the next write. Also bounds how quickly a cancellation is noticed;
+    /// zero writes on every 5 second check.
+    #[serde(with = "humantime_serde")]
+    pub status_write_interval: Duration,
+    /// Seconds without a heartbeat before a reservation is considered dead
+    /// and its derivation handed to another worker. Must exceed two
+    /// heartbeat intervals so one missed heartbeat doesn't reclaim live work.
+    pub reservation_lease_seconds: u64,
+    /// After the builder starts, how long stale reservations are left alone
+    /// so builds running on other hosts get to heartbeat first. Zero starts
+    /// reclaiming right away.
+    #[serde(with = "humantime_serde")]
+    pub reservation_startup_grace: Duration,
+
+    /// How long a running build may go without output before the watchdog
+    /// warns that its worker looks stuck. Zero disables the watchdog.
+    #[serde(with = "humantime_serde")]
+    pub stuck_worker_threshold: Duration,
+    /// Optional URL that receives a JSON POST for every stuck build
+    pub stuck_worker_webhook: Option<String>,
+
+    /// Order in which workers claim queued derivations
+    pub scheduling: SchedulingMode,
+
+    /// How often to look for built derivations whose store path has vanished
+    /// from the local store and queue them for rebuild. Zero disables the
+    /// audit; only enable it where every builder shares one nix store.
+    #[serde(with = "humantime_serde")]
+    pub store_audit_interval: Duration,
+
+    /// How often GC roots are synced with the store paths systems are meant
+    /// to deploy (`desired_target`), so a target isn't collected while
+    /// agents are still pulling it. Zero disables the roots.
+    #[serde(with = "humantime_serde")]
+    pub target_gc_root_interval: Duration,
+
+    /// Workers wait instead of claiming a build while the host has less
+    /// than this much available memory (MemAvailable, in MiB). Zero disables
+    /// the check.
+    pub min_free_memory_mb: u64,
+
+    /// Niceness (-20..=19) builds run at, so co-located services keep the CPU
+    pub nice: Option<i32>,
+
+    /// I/O scheduling class builds run under
+    pub ionice_class: Option<IoniceClass>,
+
+    /// Nix systems (e.g. `aarch64-linux`) this builder claims derivations
+    /// for. Empty means just the host's own system. Derivations whose system
+    /// is unknown can be claimed by any builder.
+    pub systems: Vec<String>,
+
+    /// Resources this builder offers, e.g. `bigmem` or `gpu`. Only
+    /// derivations whose required labels are all in this set are claimed.
+    pub builder_labels: Vec<String>,
+
+    /// Labels a derivation requires of its builder, assigned by name when
+    /// it is evaluated. Derivations no rule matches go to any builder.
+    pub label_rules: Vec<LabelRule>,
+
+    /// Extra regexes whose matches are masked in build and cache output and
+    /// in stored error messages. Values of secret env vars such as
+    /// `ATTIC_TOKEN` are always masked.
+    pub redact_patterns: Vec<String>,
+
+    /// Program run after every successful build with the store path and
+    /// derivation name as arguments (also exported as `CF_STORE_PATH` and
+    /// `CF_DERIVATION_NAME`). Failures are logged and don't fail the build.
+    pub post_build_hook: Option<String>,
+    /// How long the post-build hook may run before it is killed
+    #[serde(with = "humantime_serde")]
+    pub post_build_hook_timeout: Duration,
+    /// Check that a finished build's store path is one of the outputs of
+    /// its derivation before signing, pushing or recording it, and fail the
+    /// build if not. On by default in debug builds only, since it costs a
+    /// `nix-store` call per build.
+    pub verify_build_outputs: bool,
+
+    /// While this file exists, build workers finish their current build and
+    /// then stop claiming work, so the builder can be taken out of rotation.
+    /// Remove it and restart the builder to resume.
+    pub drain_file: PathBuf,
+}
+
+/// Builder labels required by derivations whose name matches `pattern`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LabelRule {
+    /// Glob over the derivation name (the hostname for NixOS systems)
+    pub pattern: String,
+    pub labels: Vec<String>,
+}
+
+/// I/O scheduling class passed to `ionice -c`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoniceClass {
+    Realtime,
+    BestEffort,
+    /// Only gets disk time when nothing else wants it
+    Idle,
+}
+
+impl IoniceClass {
+    fn as_arg(self) -> &'static str {
+        match self {
+            IoniceClass::Realtime => "1",
+            IoniceClass::BestEffort => "2",
+            IoniceClass::Idle => "3",
+        }
+    }
+}
+
+/// Exec properties that systemd rejects on scope units. Builds get their
+/// priority from [`BuildConfig::nice`] and [`BuildConfig::ionice_class`].
+pub const SCOPE_UNSUPPORTED_PROPERTIES: &[&str] =
+    &["Nice", "IOScheduling", "CPUScheduling", "CPUAffinity"];
+
+/// How build workers pick the next derivation from the queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchedulingMode {
+    /// Strict queue order; a large new commit can take every worker
+    #[default]
+    Fifo,
+    /// Favour the commit with the fewest builds in progress so claims are
+    /// interleaved across commits
+    Fair,
+}
+
+/// Per-system overrides layered on top of the global [`BuildConfig`]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NixBuildOptions {
+    /// Overrides `cores_per_job` (0 = all cores)
+    pub cores: Option<usize>,
+    /// Overrides `max_jobs`
+    pub max_jobs: Option<usize>,
+    /// Overrides `substituters`
+    pub substituters: Option<Vec<String>>,
+    /// Overrides `extra_substituters`; an empty list keeps the system's
+    /// builds on nix.conf's substituters only
+    pub extra_substituters: Option<Vec<String>>,
+    /// Overrides `extra_trusted_public_keys`
+    pub extra_trusted_public_keys: Option<Vec<String>>,
+}
+
+/// Characters that have no business in a nix option value and would be
+/// dangerous if the value ever reached a shell
+const SHELL_METACHARACTERS: &[char] = &[';', '|', '`', '$', '(', ')', '<', '>', '\\', '"', '\''];
+
+/// URL schemes nix can substitute from
+const SUBSTITUTER_SCHEMES: &[&str] = &["http", "https", "s3", "ssh", "ssh-ng", "file"];
+
+/// Reject option values that are empty or contain whitespace or shell
+/// metacharacters
+fn validate_option_value(what: &str, value: &str) -> Result<(), String> {
+    if value.is_empty() {
+        return Err(format!("{} must not be empty", what));
+    }
+    if let Some(c) = value
+        .chars()
+        .find(|c| c.is_whitespace() || c.is_control() || SHELL_METACHARACTERS.contains(c))
+    {
+        return Err(format!(
+            "{} '{}' contains forbidden character {:?}",
+            what, value, c
+        ));
+    }
+    Ok(())
+}
+
+/// A substituter must be a URL with a scheme nix knows
+fn validate_substituter(url: &str) -> Result<(), String> {
+    validate_option_value("substituter", url)?;
+    match url.split_once("://") {
+        Some((scheme, rest)) if SUBSTITUTER_SCHEMES.contains(&scheme) && !rest.is_empty() => Ok(()),
+        _ => Err(format!(
+            "substituter '{}' must be a {} URL",
+            url,
+            SUBSTITUTER_SCHEMES.join("/")
+        )),
+    }
+}
+
+/// A trusted public key must be `name:base64` of a 32 byte ed25519 key
+fn validate_public_key(key: &str) -> Result<(), String> {
+    validate_option_value("trusted public key", key)?;
+    let valid = key.split_once(':').is_some_and(|(name, encoded)| {
+        !name.is_empty()
+            && base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .is_ok_and(|bytes| bytes.len() == 32)
+    });
+    if !valid {
+        return Err(format!(
+            "trusted public key '{}' must be name:base64 of an ed25519 key",
+            key
+        ));
+    }
+    Ok(())
+}
+
+/// Check substituter URLs and trusted keys
+fn validate_substitution(
+    substituters: &[String],
+    extra_substituters: &[String],
+    trusted_public_keys: &[String],
+) -> Result<(), String> {
+    substituters
+        .iter()
+        .chain(extra_substituters)
+        .try_for_each(|url| validate_substituter(url))?;
+    trusted_public_keys
+        .iter()
+        .try_for_each(|key| validate_public_key(key))
+}
+
+impl NixBuildOptions {
+    /// Reject malformed substituter URLs and keys, and option strings that
+    /// contain whitespace or shell metacharacters
+    pub fn validate(&self) -> Result<(), String> {
+        validate_substitution(
+            self.substituters.as_deref().unwrap_or_default(),
+            self.extra_substituters.as_deref().unwrap_or_default(),
+            self.extra_trusted_public_keys
+                .as_deref()
+                .unwrap_or_default(),
+        )
+    }
 }
 
 impl Default for BuildConfig {
@@ -69,6 +321,29 @@ impl Default for BuildConfig {
             max_concurrent_derivations: default_max_concurrent_derivations(),
             max_jobs: default_max_jobs(),
             cores_per_job: default_cores_per_job(),
+            substituters: Vec::new(),
+            extra_substituters: Vec::new(),
+            extra_trusted_public_keys: Vec::new(),
+            heartbeat_interval: Duration::from_secs(30),
+            status_write_interval: Duration::from_secs(30),
+            reservation_lease_seconds: 300,
+            reservation_startup_grace: Duration::from_secs(300),
+            stuck_worker_threshold: Duration::from_secs(600), // 10 minutes
+            stuck_worker_webhook: None,
+            scheduling: SchedulingMode::default(),
+            store_audit_interval: Duration::ZERO,
+            target_gc_root_interval: Duration::from_secs(300),
+            min_free_memory_mb: 0,
+            nice: None,
+            ionice_class: None,
+            systems: Vec::new(),
+            builder_labels: Vec::new(),
+            label_rules: Vec::new(),
+            redact_patterns: Vec::new(),
+            post_build_hook: None,
+            post_build_hook_timeout: Duration::from_secs(60),
+            verify_build_outputs: cfg!(debug_assertions),
+            drain_file: PathBuf::from("/var/lib/crystal-forge/drain"),
 
             // Systemd defaults
             systemd_memory_max: Some("4G".to_string()),
@@ -124,6 +399,48 @@ impl BuildConfig {
         if self.offline {
             cmd.arg("--offline");
         }
+
+        if !self.substituters.is_empty() {
+            cmd.args(["--option", "substituters", &self.substituters.join(" ")]);
+        }
+        if !self.extra_substituters.is_empty() {
+            cmd.args([
+                "--option",
+                "extra-substituters",
+                &self.extra_substituters.join(" "),
+            ]);
+        }
+        if !self.extra_trusted_public_keys.is_empty() {
+            cmd.args([
+                "--option",
+                "extra-trusted-public-keys",
+                &self.extra_trusted_public_keys.join(" "),
+            ]);
+        }
+
+        // Credentials for private flake repos fetched by this command
+        super::apply_flake_auth(cmd);
+    }
+
+    /// Copy of this config with a system's [`NixBuildOptions`] applied on top
+    pub fn with_overrides(&self, options: &NixBuildOptions) -> BuildConfig {
+        let mut config = self.clone();
+        if let Some(cores) = options.cores {
+            config.cores_per_job = cores;
+        }
+        if let Some(max_jobs) = options.max_jobs {
+            config.max_jobs = max_jobs;
+        }
+        if let Some(substituters) = &options.substituters {
+            config.substituters = substituters.clone();
+        }
+        if let Some(extra) = &options.extra_substituters {
+            config.extra_substituters = extra.clone();
+        }
+        if let Some(keys) = &options.extra_trusted_public_keys {
+            config.extra_trusted_public_keys = keys.clone();
+        }
+        config
     }
 
     /// Get the Nix build arguments based on configuration.
@@ -175,6 +492,61 @@ impl BuildConfig {
         cmd
     }
 
+    /// `nice`/`ionice` prefix to run build commands under; empty when no
+    /// priority is configured
+    pub fn priority_wrapper(&self) -> Vec<String> {
+        let mut wrapper = Vec::new();
+        if let Some(nice) = self.nice {
+            wrapper.extend(["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        }
+        if let Some(class) = self.ionice_class {
+            wrapper.extend([
+                "ionice".to_string(),
+                "-c".to_string(),
+                class.as_arg().to_string(),
+            ]);
+        }
+        wrapper
+    }
+
+    /// Command running `program` under [`BuildConfig::priority_wrapper`]
+    pub fn prioritized_command(&self, program: &str) -> tokio::process::Command {
+        let mut wrapper = self.priority_wrapper();
+        if wrapper.is_empty() {
+            return tokio::process::Command::new(program);
+        }
+        let mut cmd = tokio::process::Command::new(wrapper.remove(0));
+        cmd.args(wrapper).arg(program);
+        cmd
+    }
+
+    /// Systems to claim builds for: [`BuildConfig::systems`], or the host's
+    /// own nix system when none are configured
+    pub fn claim_systems(&self) -> Vec<String> {
+        if !self.systems.is_empty() {
+            return self.systems.clone();
+        }
+        let os = match std::env::consts::OS {
+            "macos" => "darwin",
+            os => os,
+        };
+        vec![format!("{}-{}", std::env::consts::ARCH, os)]
+    }
+
+    /// Builder labels required by the derivation `derivation_name`: the
+    /// labels of every matching rule, sorted and deduplicated
+    pub fn required_labels(&self, derivation_name: &str) -> Vec<String> {
+        let mut labels: Vec<String> = self
+            .label_rules
+            .iter()
+            .filter(|rule| glob_matches(&rule.pattern, derivation_name))
+            .flat_map(|rule| rule.labels.iter().cloned())
+            .collect();
+        labels.sort();
+        labels.dedup();
+        labels
+    }
+
     /// Get timeout for build process (use the shorter of the two timeouts)
     pub fn process_timeout(&self) -> Duration {
         // Add some buffer time for process cleanup
@@ -191,13 +563,121 @@ impl BuildConfig {
         self.timeout.as_secs()
     }
 
+    /// Reservation lease to enforce, raised to just over two heartbeat
+    /// intervals if configured lower
+    pub fn reservation_lease(&self) -> Duration {
+        let configured = Duration::from_secs(self.reservation_lease_seconds);
+        let minimum = self.heartbeat_interval * 2 + Duration::from_secs(1);
+        configured.max(minimum)
+    }
+
     /// Check if systemd should be used for this build
     pub fn should_use_systemd(&self) -> bool {
         self.use_systemd_scope
     }
 
+    /// Check that reservations outlive a couple of missed heartbeats.
+    /// Unlike [`BuildConfig::validate`] this does not depend on the host.
+    pub fn validate_reservation_timing(&self) -> Result<(), String> {
+        if self.heartbeat_interval.is_zero() {
+            return Err("heartbeat_interval must be greater than zero".to_string());
+        }
+        if Duration::from_secs(self.reservation_lease_seconds) <= self.heartbeat_interval * 2 {
+            return Err(format!(
+                "reservation_lease_seconds ({}) must be more than twice heartbeat_interval ({:?})",
+                self.reservation_lease_seconds, self.heartbeat_interval
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check `substituters`, `extra_substituters` and
+    /// `extra_trusted_public_keys`
+    pub fn validate_substituters(&self) -> Result<(), String> {
+        validate_substitution(
+            &self.substituters,
+            &self.extra_substituters,
+            &self.extra_trusted_public_keys,
+        )
+    }
+
+    /// Check that every `redact_patterns` entry is a valid regex
+    pub fn validate_redact_patterns(&self) -> Result<(), String> {
+        for pattern in &self.redact_patterns {
+            regex::Regex::new(pattern)
+                .map_err(|e| format!("invalid redact pattern '{}': {}", pattern, e))?;
+        }
+        Ok(())
+    }
+
+    /// Check that labels and label rules are non-empty
+    pub fn validate_labels(&self) -> Result<(), String> {
+        if self
+            .builder_labels
+            .iter()
+            .any(|label| label.trim().is_empty())
+        {
+            return Err("builder_labels must not contain empty labels".to_string());
+        }
+        for rule in &self.label_rules {
+            if rule.pattern.is_empty() {
+                return Err("label rule pattern must not be empty".to_string());
+            }
+            if rule.labels.is_empty() || rule.labels.iter().any(|label| label.trim().is_empty()) {
+                return Err(format!(
+                    "label rule '{}' must list at least one non-empty label",
+                    rule.pattern
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that a configured post-build hook names a program and has
+    /// time to run
+    pub fn validate_post_build_hook(&self) -> Result<(), String> {
+        let Some(hook) = &self.post_build_hook else {
+            return Ok(());
+        };
+        if hook.trim().is_empty() {
+            return Err("post_build_hook must not be empty".to_string());
+        }
+        if self.post_build_hook_timeout.is_zero() {
+            return Err("post_build_hook_timeout must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check the build priority settings, including that no priority is set
+    /// through `systemd_properties`, which scope units would refuse
+    pub fn validate_priority(&self) -> Result<(), String> {
+        if let Some(nice) = self.nice
+            && !(-20..=19).contains(&nice)
+        {
+            return Err(format!("nice must be between -20 and 19, got {}", nice));
+        }
+        if let Some(property) = self.systemd_properties.iter().find(|p| {
+            SCOPE_UNSUPPORTED_PROPERTIES
+                .iter()
+                .any(|pre| p.starts_with(pre))
+        }) {
+            return Err(format!(
+                "systemd property '{}' is not allowed on build scopes; use nice / ionice_class instead",
+                property
+            ));
+        }
+        Ok(())
+    }
+
     /// Validate configuration and warn about potential issues.
     pub fn validate(&self) -> Result<(), String> {
+        self.validate_reservation_timing()?;
+        self.validate_priority()?;
+        self.validate_redact_patterns()?;
+        self.validate_substituters()?;
+        self.validate_labels()?;
+        self.validate_post_build_hook()?;
+
         // Try to get CPU count
         let cpu_count = num_cpus::get();
 
@@ -322,4 +802,151 @@ mod tests {
         let args = config.nix_build_args();
         assert_eq!(args, vec!["--max-jobs", "2", "--cores", "4"]);
     }
+
+    #[test]
+    fn test_priority_wraps_commands_and_rejects_scope_properties() {
+        assert!(BuildConfig::default().priority_wrapper().is_empty());
+
+        let config = BuildConfig {
+            nice: Some(10),
+            ionice_class: Some(IoniceClass::Idle),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.priority_wrapper(),
+            vec!["nice", "-n", "10", "ionice", "-c", "3"]
+        );
+        assert!(config.validate_priority().is_ok());
+
+        let too_nice = BuildConfig {
+            nice: Some(20),
+            ..Default::default()
+        };
+        assert!(too_nice.validate_priority().is_err());
+
+        let scope_nice = BuildConfig {
+            systemd_properties: vec!["IOSchedulingClass=idle".to_string()],
+            ..Default::default()
+        };
+        assert!(scope_nice.validate_priority().is_err());
+    }
+
+    #[test]
+    fn test_reservation_lease_outlives_two_heartbeats() {
+        let build = BuildConfig {
+            heartbeat_interval: Duration::from_secs(30),
+            reservation_lease_seconds: 60,
+            ..Default::default()
+        };
+        assert!(build.validate().is_err());
+        assert_eq!(build.reservation_lease(), Duration::from_secs(61));
+
+        let build = BuildConfig {
+            heartbeat_interval: Duration::from_secs(20),
+            reservation_lease_seconds: 60,
+            ..Default::default()
+        };
+        assert_eq!(build.reservation_lease(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_system_overrides_layer_on_global() {
+        let global = BuildConfig {
+            max_jobs: 2,
+            cores_per_job: 4,
+            substituters: vec!["https://cache.nixos.org".to_string()],
+            ..Default::default()
+        };
+        let options = NixBuildOptions {
+            cores: Some(0),
+            ..Default::default()
+        };
+
+        let effective = global.with_overrides(&options);
+        assert_eq!(effective.cores_per_job, 0);
+        assert_eq!(effective.max_jobs, 2);
+        assert_eq!(effective.substituters, global.substituters);
+    }
+
+    #[test]
+    fn test_extra_substituters_and_keys_are_validated() {
+        let key = format!(
+            "cache.example.com-1:{}",
+            base64::engine::general_purpose::STANDARD.encode([7u8; 32])
+        );
+        let global = BuildConfig {
+            extra_substituters: vec!["https://cache.example.com".to_string()],
+            extra_trusted_public_keys: vec![key.clone()],
+            ..Default::default()
+        };
+        assert!(global.validate_substituters().is_ok());
+
+        let sensitive = NixBuildOptions {
+            extra_substituters: Some(Vec::new()),
+            ..Default::default()
+        };
+        let effective = global.with_overrides(&sensitive);
+        assert!(effective.extra_substituters.is_empty());
+        assert_eq!(effective.extra_trusted_public_keys, vec![key]);
+
+        for bad_url in ["cache.example.com", "ftp://cache.example.com", "https://"] {
+            let options = NixBuildOptions {
+                extra_substituters: Some(vec![bad_url.to_string()]),
+                ..Default::default()
+            };
+            assert!(options.validate().is_err(), "{bad_url} should be rejected");
+        }
+        for bad_key in ["no-colon", ":AAAA", "name:not-base64!", "name:AAAA"] {
+            let options = NixBuildOptions {
+                extra_trusted_public_keys: Some(vec![bad_key.to_string()]),
+                ..Default::default()
+            };
+            assert!(options.validate().is_err(), "{bad_key} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_nix_build_options_reject_metacharacters() {
+        let ok = NixBuildOptions {
+            substituters: Some(vec![
+                "https://cache.example.com?priority=10&trusted=1".to_string(),
+            ]),
+            ..Default::default()
+        };
+        assert!(ok.validate().is_ok());
+
+        for bad in ["https://a; rm -rf /", "$(whoami)", "a b", "x`y`"] {
+            let options = NixBuildOptions {
+                substituters: Some(vec![bad.to_string()]),
+                ..Default::default()
+            };
+            assert!(options.validate().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_required_labels_union_matching_rules() {
+        let rule = |pattern: &str, labels: &[&str]| LabelRule {
+            pattern: pattern.to_string(),
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+        };
+        let build = BuildConfig {
+            label_rules: vec![
+                rule("electron-*", &["bigmem"]),
+                rule("*-app", &["bigmem", "gpu"]),
+            ],
+            ..Default::default()
+        };
+        assert!(build.validate_labels().is_ok());
+
+        assert_eq!(build.required_labels("electron-app"), vec!["bigmem", "gpu"]);
+        assert_eq!(build.required_labels("electron-tools"), vec!["bigmem"]);
+        assert!(build.required_labels("web01").is_empty());
+
+        let bad = BuildConfig {
+            label_rules: vec![rule("electron-*", &[])],
+            ..Default::default()
+        };
+        assert!(bad.validate_labels().is_err());
+    }
 }
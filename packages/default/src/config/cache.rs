@@ -1,7 +1,11 @@
 use crate::config::duration_serde;
 use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::time::Duration;
+use tracing::warn;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct CacheConfig {
@@ -11,7 +15,13 @@ pub struct CacheConfig {
     #[serde(default)]
     pub push_after_build: bool,
     pub signing_key: Option<String>,
-    pub compression: Option<String>,
+    /// NAR compression for binary cache destinations (S3, HTTP, file).
+    /// Unset keeps Nix's default (xz); unknown values fall back to it.
+    #[serde(default, deserialize_with = "deserialize_compression")]
+    pub compression: Option<CompressionAlgo>,
+    /// Level for `compression`, clamped to what the algorithm supports
+    #[serde(default)]
+    pub compression_level: Option<i32>,
     pub push_filter: Option<Vec<String>>,
     #[serde(default = "CacheConfig::default_parallel_uploads")]
     pub parallel_uploads: u32, // TODO: do some docs or something this is only for s3 uploads otherwise we use attics jobs
@@ -23,6 +33,14 @@ pub struct CacheConfig {
     pub attic_cache_name: Option<String>,
     pub attic_ignore_upstream_cache_filter: bool, // Fixed typo: upsream -> upstream
     pub attic_jobs: u32,                          // parallel upload method in attic
+    // SSH store specific (ssh-ng://)
+    /// Extra options passed to ssh via NIX_SSHOPTS, e.g. ["-p", "2222"]
+    #[serde(default)]
+    pub ssh_options: Vec<String>,
+    /// Private key used to reach the store (added as `?ssh-key=` to push_to)
+    pub ssh_key: Option<String>,
+    /// known_hosts file to verify the store host against
+    pub ssh_known_hosts_path: Option<String>,
     // Retry configuration
     #[serde(default)]
     pub max_retries: u32,
@@ -41,15 +59,174 @@ pub struct CacheConfig {
     #[serde(default)]
     pub force_repush: bool,
     pub require_sigs: bool,
+    /// Consecutive destination-level failures before the circuit opens and
+    /// pushes to that destination are deferred (0 disables the breaker)
+    #[serde(default = "CacheConfig::default_circuit_breaker_threshold")]
+    pub circuit_breaker_threshold: u32,
+    /// How long an open circuit skips the destination before trying again
+    #[serde(default = "CacheConfig::default_circuit_breaker_cooldown_seconds")]
+    pub circuit_breaker_cooldown_seconds: u64,
+    /// Before a batch push, ask the destination which paths it already has
+    /// and skip those. Bounds how long that query may take (0 disables it).
+    #[serde(default = "CacheConfig::default_dedup_check_timeout_seconds")]
+    pub dedup_check_timeout_seconds: u64,
+    /// Per-destination settings, looked up by name from `push_to` or an
+    /// environment's `cache_push_to`. Lets several Attic servers with their
+    /// own credentials be used side by side.
+    #[serde(default)]
+    pub destinations: Vec<CacheDestination>,
+    /// Build workers stop claiming new builds once this many cache push jobs
+    /// are waiting, so un-pushed closures don't fill the disk while the
+    /// cache is slow (0 disables the limit)
+    #[serde(default)]
+    pub push_backlog_high_water: u64,
+    /// Claiming resumes once the backlog has drained below this many jobs
+    #[serde(default)]
+    pub push_backlog_low_water: u64,
+    /// Paths whose closure (the path and everything it references) is
+    /// larger than this are not pushed; their jobs fail for good so an
+    /// operator can look at them (unset: no limit)
+    #[serde(default)]
+    pub max_push_closure_size_bytes: Option<u64>,
+    /// Output to push instead of `out`, keyed by system hostname or package
+    /// name, e.g. `{ mytool = "bin" }`
+    #[serde(default)]
+    pub push_outputs: HashMap<String, String>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+/// Settings for one named push destination. Unset fields fall back to the
+/// top-level cache settings and the `ATTIC_*` environment variables.
+#[derive(Clone, Default, Deserialize)]
+pub struct CacheDestination {
+    /// Name used in `push_to` / `cache_push_to` to select this destination
+    pub name: String,
+    /// Attic server URL (default: `ATTIC_SERVER_URL`)
+    pub endpoint: Option<String>,
+    /// Attic token with push permission (default: `ATTIC_TOKEN`)
+    pub token: Option<String>,
+    /// File holding the token, to keep it out of the config file
+    pub token_file: Option<String>,
+    /// Attic client remote to log in as (default: the destination name)
+    pub remote: Option<String>,
+    /// Attic cache to push to (default: `attic_cache_name`)
+    pub cache_name: Option<String>,
+}
+
+impl std::fmt::Debug for CacheDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheDestination")
+            .field("name", &self.name)
+            .field("endpoint", &self.endpoint)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("token_file", &self.token_file)
+            .field("remote", &self.remote)
+            .field("cache_name", &self.cache_name)
+            .finish()
+    }
+}
+
+impl CacheDestination {
+    /// The configured token, read from `token_file` if it isn't inline
+    pub fn resolve_token(&self) -> Option<String> {
+        if let Some(token) = &self.token {
+            return Some(token.clone());
+        }
+        let path = self.token_file.as_ref()?;
+        match std::fs::read_to_string(path) {
+            Ok(token) => Some(token.trim().to_string()),
+            Err(e) => {
+                warn!(
+                    "⚠️ Failed to read token_file {} of cache destination {}: {}",
+                    path, self.name, e
+                );
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
 pub enum CacheType {
     S3,
     Attic,
     Http,
     #[default]
     Nix,
+    /// Plain Nix store reachable over SSH (`push_to = "ssh-ng://user@host"`)
+    SshNg,
+    /// Cachix binary cache (`push_to = "cachix://mycache"`). Cachix signs
+    /// pushed paths itself.
+    Cachix,
+}
+
+/// Scheme of `push_to` values naming a Cachix cache
+pub const CACHIX_SCHEME: &str = "cachix://";
+
+/// NAR compression algorithms a Nix binary cache can use
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgo {
+    Xz,
+    Zstd,
+    Gzip,
+    Bzip2,
+    #[serde(rename = "br")]
+    Brotli,
+    None,
+}
+
+impl CompressionAlgo {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "xz" => Some(Self::Xz),
+            "zstd" => Some(Self::Zstd),
+            "gzip" => Some(Self::Gzip),
+            "bzip2" => Some(Self::Bzip2),
+            "br" | "brotli" => Some(Self::Brotli),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    /// Name Nix uses for the `compression` store setting
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Xz => "xz",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+            Self::Bzip2 => "bzip2",
+            Self::Brotli => "br",
+            Self::None => "none",
+        }
+    }
+
+    /// Levels accepted by `compression-level`, or None if the algorithm has none
+    pub fn level_range(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            Self::Xz => Some(0..=9),
+            Self::Zstd => Some(1..=19),
+            Self::Gzip | Self::Bzip2 => Some(1..=9),
+            Self::Brotli => Some(0..=11),
+            Self::None => None,
+        }
+    }
+}
+
+fn deserialize_compression<'de, D>(deserializer: D) -> Result<Option<CompressionAlgo>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let algo = CompressionAlgo::parse(&value);
+    if algo.is_none() {
+        warn!(
+            "⚠️ Unsupported cache compression '{}', using the Nix default (xz)",
+            value
+        );
+    }
+    Ok(algo)
 }
 
 #[derive(Debug, Clone)]
@@ -71,9 +248,55 @@ impl CacheConfig {
         3600 // 1 hour - large systems (40GB+) need more time. Increase to 7200+ if needed.
     }
 
+    fn default_circuit_breaker_threshold() -> u32 {
+        5
+    }
+
+    fn default_circuit_breaker_cooldown_seconds() -> u64 {
+        300
+    }
+
+    fn default_dedup_check_timeout_seconds() -> u64 {
+        30
+    }
+
+    /// The cache type in effect: Cachix whenever `push_to` is a
+    /// `cachix://` URI, else `cache_type`
+    pub fn effective_cache_type(&self) -> CacheType {
+        match &self.push_to {
+            Some(push_to) if push_to.starts_with(CACHIX_SCHEME) => CacheType::Cachix,
+            _ => self.cache_type,
+        }
+    }
+
+    /// Cachix cache named by `push_to`, with or without the `cachix://` scheme
+    pub fn cachix_cache_name(&self) -> Option<&str> {
+        let push_to = self.push_to.as_deref()?;
+        let name = push_to.strip_prefix(CACHIX_SCHEME).unwrap_or(push_to);
+        let name = name.trim_end_matches('/');
+        (!name.is_empty()).then_some(name)
+    }
+
+    /// Why `store_path` must not be pushed given its closure size, or `None`
+    /// if it is within `max_push_closure_size_bytes`
+    pub fn push_size_error(&self, store_path: &str, closure_size: u64) -> Option<String> {
+        let max = self.max_push_closure_size_bytes?;
+        (closure_size > max).then(|| {
+            format!(
+                "{} exceeds max size: closure is {} bytes, max_push_closure_size_bytes is {}",
+                store_path, closure_size, max
+            )
+        })
+    }
+
     /// Optional signing step. If `signing_key` is set, run this BEFORE `cache_command`.
     /// Equivalent to: nix store sign --recursive --key-file <key> <store_path>
+    ///
+    /// Cachix signs with its own key, so nothing is signed locally for it.
     pub fn sign_command(&self, store_path: &str) -> Option<CacheCommand> {
+        if self.effective_cache_type() == CacheType::Cachix {
+            return None;
+        }
         let key_path = self.signing_key.as_ref()?;
         Some(CacheCommand {
             command: "nix".to_string(),
@@ -90,26 +313,115 @@ impl CacheConfig {
 
     /// Returns the command and arguments for cache operations (the COPY step).
     pub fn cache_command(&self, store_path: &str) -> Option<CacheCommand> {
-        match self.cache_type {
+        match self.effective_cache_type() {
             CacheType::S3 => self.s3_cache_command(store_path),
             CacheType::Attic => self.attic_cache_command(store_path),
             CacheType::Http | CacheType::Nix => self.nix_cache_command(store_path),
+            CacheType::SshNg => self.ssh_ng_cache_command(store_path),
+            CacheType::Cachix => self.cachix_cache_command(store_path),
+        }
+    }
+
+    /// Value for NIX_SSHOPTS when pushing to an SSH store, if any options apply
+    pub fn nix_sshopts(&self) -> Option<String> {
+        if self.effective_cache_type() != CacheType::SshNg {
+            return None;
+        }
+
+        let mut opts = self.ssh_options.clone();
+        if let Some(known_hosts) = &self.ssh_known_hosts_path {
+            opts.push("-o".to_string());
+            opts.push(format!("UserKnownHostsFile={}", known_hosts));
+        }
+
+        if opts.is_empty() {
+            None
+        } else {
+            Some(opts.join(" "))
+        }
+    }
+
+    /// Store URI that `nix` can query for a push destination, or None when
+    /// the destination isn't a Nix store (Attic pushes go through its own CLI)
+    pub fn destination_store_uri(&self, destination: &str) -> Option<String> {
+        match self.effective_cache_type() {
+            CacheType::Attic => None,
+            CacheType::SshNg => Some(self.with_ssh_key(destination)),
+            CacheType::Cachix => {
+                let name = destination
+                    .strip_prefix(CACHIX_SCHEME)
+                    .unwrap_or(destination);
+                Some(format!("https://{}.cachix.org", name.trim_end_matches('/')))
+            }
+            CacheType::S3 | CacheType::Http | CacheType::Nix => Some(destination.to_string()),
         }
     }
 
+    /// `store_uri` with the configured compression settings added, unless
+    /// the URI already sets them
+    fn with_compression(&self, store_uri: &str) -> String {
+        let Some(algo) = self.compression else {
+            return store_uri.to_string();
+        };
+        if store_uri.contains("compression=") {
+            return store_uri.to_string();
+        }
+
+        let mut params = vec![format!("compression={}", algo.as_str())];
+        if let (Some(level), Some(range)) = (self.compression_level, algo.level_range()) {
+            let level = level.clamp(*range.start(), *range.end());
+            params.push(format!("compression-level={}", level));
+        }
+
+        let sep = if store_uri.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", store_uri, sep, params.join("&"))
+    }
+
+    fn with_ssh_key(&self, store_uri: &str) -> String {
+        match &self.ssh_key {
+            Some(key) if !store_uri.contains("ssh-key=") => {
+                let sep = if store_uri.contains('?') { '&' } else { '?' };
+                format!("{}{}ssh-key={}", store_uri, sep, key)
+            }
+            _ => store_uri.to_string(),
+        }
+    }
+
+    /// Settings of the destination `push_to` names, if it is configured
+    pub fn destination(&self) -> Option<&CacheDestination> {
+        let push_to = self.push_to.as_deref()?;
+        self.destinations.iter().find(|d| d.name == push_to)
+    }
+
+    /// Attic cache to push to: the destination's own, else `attic_cache_name`
+    pub fn effective_attic_cache_name(&self) -> Option<&str> {
+        self.destination()
+            .and_then(|d| d.cache_name.as_deref())
+            .or(self.attic_cache_name.as_deref())
+    }
+
+    /// This config with `push_to` replaced by a job's own destination, if it has one
+    pub fn for_destination(&self, destination: Option<&str>) -> CacheConfig {
+        let mut cfg = self.clone();
+        if let Some(destination) = destination {
+            cfg.push_to = Some(destination.to_string());
+        }
+        cfg
+    }
+
     /// Legacy: still returns args only.
     pub fn copy_command_args(&self, store_path: &str) -> Option<Vec<String>> {
         self.cache_command(store_path).map(|cmd| cmd.args)
     }
 
     fn attic_cache_command(&self, store_path: &str) -> Option<CacheCommand> {
-        let cache_name = self.attic_cache_name.as_ref()?;
+        let cache_name = self.effective_attic_cache_name()?;
 
         // Build args with cache name at args[1] (cache.rs expects this position)
         // Flags should come after the positional arguments to avoid conflicts
         let mut args = vec![
             "push".to_string(),
-            cache_name.clone(),
+            cache_name.to_string(),
             store_path.to_string(),
         ];
 
@@ -125,16 +437,29 @@ impl CacheConfig {
         })
     }
 
+    fn cachix_cache_command(&self, store_path: &str) -> Option<CacheCommand> {
+        let cache_name = self.cachix_cache_name()?;
+        Some(CacheCommand {
+            command: "cachix".to_string(),
+            args: vec![
+                "push".to_string(),
+                cache_name.to_string(),
+                store_path.to_string(),
+            ],
+        })
+    }
+
     fn s3_cache_command(&self, store_path: &str) -> Option<CacheCommand> {
         let push_to = self.push_to.as_ref()?;
-        let mut args = vec!["copy".to_string(), "--to".to_string(), push_to.clone()];
+        let mut args = vec![
+            "copy".to_string(),
+            "--to".to_string(),
+            self.with_compression(push_to),
+        ];
 
         if self.force_repush {
             args.push("--refresh".to_string());
         }
-        if let Some(compression) = &self.compression {
-            args.extend(["--compression".to_string(), compression.clone()]);
-        }
 
         // args.extend(["--parallel".to_string(), self.parallel_uploads.to_string()]);
         args.push(store_path.to_string());
@@ -147,14 +472,15 @@ impl CacheConfig {
 
     fn nix_cache_command(&self, store_path: &str) -> Option<CacheCommand> {
         let push_to = self.push_to.as_ref()?;
-        let mut args = vec!["copy".to_string(), "--to".to_string(), push_to.clone()];
+        let mut args = vec![
+            "copy".to_string(),
+            "--to".to_string(),
+            self.with_compression(push_to),
+        ];
 
         if self.force_repush {
             args.push("--refresh".to_string());
         }
-        if let Some(compression) = &self.compression {
-            args.extend(["--compression".to_string(), compression.clone()]);
-        }
         // args.extend(["--parallel".to_string(), self.parallel_uploads.to_string()]);
         args.push(store_path.to_string());
 
@@ -164,6 +490,23 @@ impl CacheConfig {
         })
     }
 
+    fn ssh_ng_cache_command(&self, store_path: &str) -> Option<CacheCommand> {
+        let push_to = self.push_to.as_ref()?;
+        let store_uri = self.with_ssh_key(push_to);
+
+        let mut args = vec!["copy".to_string(), "--to".to_string(), store_uri];
+
+        if self.force_repush {
+            args.push("--refresh".to_string());
+        }
+        args.push(store_path.to_string());
+
+        Some(CacheCommand {
+            command: "nix".to_string(),
+            args,
+        })
+    }
+
     pub fn should_push(&self, target_name: &str) -> bool {
         if !self.push_after_build {
             return false;
@@ -183,6 +526,7 @@ impl Default for CacheConfig {
             push_after_build: false,
             signing_key: None,
             compression: None,
+            compression_level: None,
             push_filter: None,
             parallel_uploads: Self::default_parallel_uploads(),
             s3_region: None,
@@ -191,12 +535,96 @@ impl Default for CacheConfig {
             attic_cache_name: None,
             attic_ignore_upstream_cache_filter: true, // Fixed typo
             attic_jobs: 5,                            // the same as the attic default
+            ssh_options: Vec::new(),
+            ssh_key: None,
+            ssh_known_hosts_path: None,
             max_retries: 3,
             retry_delay_seconds: 5,
             poll_interval: Self::default_poll_interval(),
             push_timeout_seconds: Self::default_push_timeout_seconds(),
             force_repush: false,
             require_sigs: true,
+            circuit_breaker_threshold: Self::default_circuit_breaker_threshold(),
+            circuit_breaker_cooldown_seconds: Self::default_circuit_breaker_cooldown_seconds(),
+            dedup_check_timeout_seconds: Self::default_dedup_check_timeout_seconds(),
+            destinations: Vec::new(),
+            push_backlog_high_water: 0,
+            push_backlog_low_water: 0,
+            max_push_closure_size_bytes: None,
+            push_outputs: HashMap::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_paths_are_not_pushed() {
+        let mut cfg = CacheConfig::default();
+        assert_eq!(cfg.push_size_error("/nix/store/aaa-big", u64::MAX), None);
+
+        cfg.max_push_closure_size_bytes = Some(1024);
+        assert_eq!(cfg.push_size_error("/nix/store/aaa-big", 1024), None);
+        let error = cfg.push_size_error("/nix/store/aaa-big", 1025).unwrap();
+        assert!(error.starts_with("/nix/store/aaa-big exceeds max size"));
+    }
+
+    #[test]
+    fn compression_is_added_to_the_store_uri() {
+        let mut cfg = CacheConfig {
+            cache_type: CacheType::S3,
+            push_to: Some("s3://cache?region=eu-west-1".to_string()),
+            compression: CompressionAlgo::parse("zstd"),
+            compression_level: Some(3),
+            ..Default::default()
+        };
+        let args = cfg.copy_command_args("/nix/store/aaa-hello").unwrap();
+        assert_eq!(
+            args[2],
+            "s3://cache?region=eu-west-1&compression=zstd&compression-level=3"
+        );
+
+        cfg.compression_level = Some(40);
+        assert!(
+            cfg.with_compression("s3://cache")
+                .ends_with("compression-level=19")
+        );
+
+        cfg.compression = CompressionAlgo::parse("none");
+        assert_eq!(
+            cfg.with_compression("file:///srv/cache"),
+            "file:///srv/cache?compression=none"
+        );
+
+        assert_eq!(CompressionAlgo::parse("lz5"), None);
+    }
+
+    #[test]
+    fn cachix_push_to_selects_cachix() {
+        let cfg = CacheConfig {
+            push_to: Some("cachix://mycache".to_string()),
+            signing_key: Some("/run/keys/cache.sec".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cfg.effective_cache_type(), CacheType::Cachix);
+        assert_eq!(cfg.cachix_cache_name(), Some("mycache"));
+
+        let cmd = cfg.cache_command("/nix/store/aaa-hello").unwrap();
+        assert_eq!(cmd.command, "cachix");
+        assert_eq!(cmd.args, ["push", "mycache", "/nix/store/aaa-hello"]);
+        assert!(cfg.sign_command("/nix/store/aaa-hello").is_none());
+        assert_eq!(
+            cfg.destination_store_uri("cachix://mycache").as_deref(),
+            Some("https://mycache.cachix.org")
+        );
+
+        let named = CacheConfig {
+            cache_type: CacheType::Cachix,
+            push_to: Some("othercache".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(named.cachix_cache_name(), Some("othercache"));
+    }
+}
@@ -28,6 +28,75 @@ pub struct DeploymentConfig {
     pub cache_type: CacheType,
     /// Attic cache name (used when cache_type is Attic)
     pub attic_cache_name: Option<String>,
+
+    /// When copying a store path from cache fails, build the NixOS
+    /// configuration that produces it from its flake instead of failing the
+    /// deployment (off by default)
+    #[serde(default)]
+    pub allow_local_build_fallback: bool,
+
+    /// Free space the nix store filesystem must keep before a cache copy
+    #[serde(default = "default_min_free_store_bytes")]
+    pub min_free_store_bytes: u64,
+    /// Run `nix-collect-garbage` to make room when the store is short on space
+    /// instead of failing the deployment straight away
+    #[serde(default)]
+    pub allow_garbage_collection: bool,
+
+    /// Most systems auto_latest moves to a new target at once; the rest wait
+    /// until those report running it. 0 means no limit.
+    #[serde(default)]
+    pub max_concurrent_deployments: usize,
+
+    /// Let the agent replace itself with a newer agent build offered by the
+    /// forge, restarting its service through a transient systemd unit
+    #[serde(default)]
+    pub allow_self_update: bool,
+
+    /// Hostnames (`*` and `?` globs) the auto_latest manager leaves alone,
+    /// e.g. while a host is under maintenance. Their stored policy is kept.
+    #[serde(default)]
+    pub auto_latest_excludes: Vec<String>,
+
+    /// Attempts at copying a target from the cache before giving up. Failures
+    /// that retrying cannot fix (auth errors, path not in the cache) stop at
+    /// the first attempt.
+    #[serde(default = "default_cache_copy_max_retries")]
+    pub cache_copy_max_retries: u32,
+
+    /// Log what a deployment would do (copy, verify, activate, agent update)
+    /// without doing any of it, e.g. to try new agent settings on a canary
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// Prefetch copies the agent runs at once. Deployments always run one at
+    /// a time and don't wait on prefetches.
+    #[serde(default = "default_max_concurrent_copies")]
+    pub max_concurrent_copies: usize,
+
+    /// Copy the upcoming target the forge announces (a newer build the host
+    /// hasn't been moved to yet) ahead of time, so deploying it later
+    /// doesn't wait on the copy
+    #[serde(default)]
+    pub prefetch_next_target: bool,
+
+    /// Kill switch: the forge changes no system's desired target, whether
+    /// by auto_latest, a pin or a promotion. Can also be tripped at runtime
+    /// with `POST /deployments/pause`.
+    #[serde(default)]
+    pub paused: bool,
+}
+
+fn default_min_free_store_bytes() -> u64 {
+    2 * 1024 * 1024 * 1024 // 2 GiB
+}
+
+fn default_cache_copy_max_retries() -> u32 {
+    3
+}
+
+fn default_max_concurrent_copies() -> usize {
+    1
 }
 
 impl Default for DeploymentConfig {
@@ -48,6 +117,17 @@ impl Default for DeploymentConfig {
             require_sigs: true,
             cache_type: CacheType::Nix,
             attic_cache_name: None,
+            allow_local_build_fallback: false,
+            min_free_store_bytes: default_min_free_store_bytes(),
+            allow_garbage_collection: false,
+            max_concurrent_deployments: 0,
+            allow_self_update: false,
+            auto_latest_excludes: Vec::new(),
+            cache_copy_max_retries: default_cache_copy_max_retries(),
+            dry_run: false,
+            max_concurrent_copies: default_max_concurrent_copies(),
+            prefetch_next_target: false,
+            paused: false,
         }
     }
 }
@@ -6,4 +6,26 @@ pub struct EnvironmentConfig {
     pub is_active: bool,
     pub risk_profile: String,
     pub compliance_level: String,
+    /// Cache destination for derivations of this environment's systems,
+    /// overriding `[cache].push_to`
+    #[serde(default)]
+    pub cache_push_to: Option<String>,
+}
+
+impl EnvironmentConfig {
+    /// Production environments: named like one, or with a HIGH or CRITICAL
+    /// risk profile
+    pub fn is_production(&self) -> bool {
+        is_production_name(&self.name)
+            || ["HIGH", "CRITICAL"]
+                .iter()
+                .any(|risk| self.risk_profile.eq_ignore_ascii_case(risk))
+    }
+}
+
+/// Whether an environment name marks production
+pub fn is_production_name(name: &str) -> bool {
+    ["prod", "production"]
+        .iter()
+        .any(|prod| name.eq_ignore_ascii_case(prod))
 }
@@ -6,8 +6,11 @@ mod database;
 pub mod deployment;
 mod environment;
 mod flakes;
+mod notifications;
 mod server;
 mod system;
+mod telemetry;
+mod validation;
 mod vulnix;
 
 pub use agent::*;
@@ -18,8 +21,11 @@ pub use database::*;
 pub use deployment::*;
 pub use environment::*;
 pub use flakes::*;
+pub use notifications::*;
 pub use server::*;
 pub use system::*;
+pub use telemetry::*;
+pub use validation::*;
 pub use vulnix::*;
 
 use crate::models::systems::System;
@@ -32,10 +38,11 @@ use anyhow::{Context, Result};
 use config::Config;
 use serde::Deserialize;
 use sqlx::{PgPool, postgres::PgPoolOptions};
+use std::collections::HashMap;
 use std::env;
 use std::time::Duration;
 use tokio_postgres::NoTls;
-use tracing::debug;
+use tracing::{debug, warn};
 
 mod duration_serde {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -81,6 +88,19 @@ pub struct CrystalForgeConfig {
     pub auth: AuthConfig,
     #[serde(default)]
     pub deployment: DeploymentConfig,
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    /// Upper bound of the random delay before each background loop of the
+    /// server and builder starts, so processes restarted together don't hit
+    /// the database in lockstep. Default: 10s
+    #[serde(default = "default_startup_jitter", with = "humantime_serde")]
+    pub startup_jitter: Duration,
+}
+
+fn default_startup_jitter() -> Duration {
+    Duration::from_secs(10)
 }
 
 impl Default for CrystalForgeConfig {
@@ -97,6 +117,9 @@ impl Default for CrystalForgeConfig {
             cache: CacheConfig::default(),
             auth: AuthConfig::default(),
             deployment: DeploymentConfig::default(),
+            notifications: NotificationConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            startup_jitter: default_startup_jitter(),
         }
     }
 }
@@ -134,6 +157,81 @@ impl CrystalForgeConfig {
         &self.auth
     }
 
+    /// Valid per-system build options keyed by hostname.
+    /// Systems whose options fail validation are logged and left on the global config.
+    pub fn system_build_options(&self) -> HashMap<String, NixBuildOptions> {
+        self.systems
+            .iter()
+            .filter_map(|system| {
+                let options = system.nix_build_options.as_ref()?;
+                match options.validate() {
+                    Ok(()) => Some((system.hostname.clone(), options.clone())),
+                    Err(e) => {
+                        warn!("Ignoring nix_build_options for {}: {}", system.hostname, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Per-environment cache destinations keyed by environment name
+    pub fn environment_cache_destinations(&self) -> HashMap<String, String> {
+        self.environments
+            .iter()
+            .filter_map(|env| Some((env.name.clone(), env.cache_push_to.clone()?)))
+            .collect()
+    }
+
+    /// Working-tree reference to evaluate watched flake `flake_name` from,
+    /// when it is marked `allow_dirty`. `Err` when it is marked but its
+    /// `repo_url` is not a local path or one of its systems is in a
+    /// production environment.
+    pub fn dirty_flake_ref(&self, flake_name: &str) -> Result<Option<String>, String> {
+        let Some(flake) = self
+            .flakes
+            .watched
+            .iter()
+            .find(|flake| flake.name == flake_name && flake.allow_dirty)
+        else {
+            return Ok(None);
+        };
+
+        let production = self
+            .systems
+            .iter()
+            .filter(|system| system.flake_name.as_deref() == Some(flake_name))
+            .find(|system| {
+                is_production_name(&system.environment)
+                    || self
+                        .environments
+                        .iter()
+                        .any(|env| env.name == system.environment && env.is_production())
+            });
+        if let Some(system) = production {
+            return Err(format!(
+                "system '{}' is in production environment '{}'",
+                system.hostname, system.environment
+            ));
+        }
+
+        flake.working_tree_ref().map(Some).ok_or_else(|| {
+            format!(
+                "repo_url '{}' is not an absolute local path",
+                flake.repo_url
+            )
+        })
+    }
+
+    /// Hostnames of systems that are only ever served from the binary cache
+    pub fn cache_only_systems(&self) -> Vec<String> {
+        self.systems
+            .iter()
+            .filter(|system| system.cache_only)
+            .map(|system| system.hostname.clone())
+            .collect()
+    }
+
     pub fn load() -> Result<Self> {
         let config_path = env::var("CRYSTAL_FORGE_CONFIG")
             .unwrap_or_else(|_| "/var/lib/crystal_forge/config.toml".to_string());
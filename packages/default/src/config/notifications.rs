@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+/// Events that can trigger an outgoing notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    BuildFailed,
+    DeployFailed,
+    CveCritical,
+}
+
+/// Where failure notifications are sent and which events trigger them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    /// Webhook URL (e.g. a Slack incoming webhook); unset disables notifications
+    pub url: Option<String>,
+    /// Events that are sent to `url`
+    pub events: Vec<NotificationEvent>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            events: vec![
+                NotificationEvent::BuildFailed,
+                NotificationEvent::DeployFailed,
+                NotificationEvent::CveCritical,
+            ],
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::NixBuildOptions;
 use serde::Deserialize;
 #[derive(Debug, Deserialize, Clone)]
 pub struct SystemConfig {
@@ -7,4 +8,12 @@ pub struct SystemConfig {
     pub flake_name: Option<String>, // just the flake name reference
     pub deployment_policy: String,  // Will be converted to/from DeploymentPolicy enum
     pub desired_target: Option<String>,
+    /// Build tuning for this system, layered over the global `[build]` options
+    #[serde(default)]
+    pub nix_build_options: Option<NixBuildOptions>,
+    /// Never build this system locally: if its store path goes missing and
+    /// the cache push fails, flag it for manual attention instead of
+    /// rebuilding it
+    #[serde(default)]
+    pub cache_only: bool,
 }
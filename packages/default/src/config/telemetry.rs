@@ -0,0 +1,14 @@
+use serde::Deserialize;
+
+/// OpenTelemetry trace export. Only takes effect in binaries built with the
+/// `otel` feature.
+///
+/// This section is loaded from `[telemetry]` in `config.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// OTLP gRPC endpoint, e.g. `http://jaeger:4317`; unset disables export
+    pub otlp_endpoint: Option<String>,
+    /// Service name reported with spans; defaults to the binary name
+    pub service_name: Option<String>,
+}
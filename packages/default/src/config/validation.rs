@@ -0,0 +1,342 @@
+use super::{CacheType, CrystalForgeConfig};
+use crate::models::public_key::PublicKey;
+use crate::models::systems::DeploymentPolicy;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fmt;
+
+/// A problem found by [`CrystalForgeConfig::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Where the problem is, e.g. `systems[web01].environment`
+    pub field: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl CrystalForgeConfig {
+    /// Statically check the configuration without touching the database.
+    ///
+    /// Every system must reference a watched flake and a configured
+    /// environment, and the cache and build sections must be usable. All
+    /// problems are returned at once; an empty list means the config is valid.
+    pub fn validate(&self) -> Result<Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        let flake_names = unique_names(
+            self.flakes.watched.iter().map(|f| f.name.as_str()),
+            "flakes.watched",
+            &mut errors,
+        );
+        let environment_names = unique_names(
+            self.environments.iter().map(|e| e.name.as_str()),
+            "environments",
+            &mut errors,
+        );
+        unique_names(
+            self.systems.iter().map(|s| s.hostname.as_str()),
+            "systems",
+            &mut errors,
+        );
+
+        for system in &self.systems {
+            let field = |name: &str| format!("systems[{}].{}", system.hostname, name);
+
+            if !environment_names.contains(system.environment.as_str()) {
+                errors.push(ValidationError::new(
+                    field("environment"),
+                    format!(
+                        "environment '{}' is not defined in environments",
+                        system.environment
+                    ),
+                ));
+            }
+            if let Some(flake_name) = &system.flake_name
+                && !flake_names.contains(flake_name.as_str())
+            {
+                errors.push(ValidationError::new(
+                    field("flake_name"),
+                    format!("flake '{}' is not in flakes.watched", flake_name),
+                ));
+            }
+            if let Err(e) = system.deployment_policy.parse::<DeploymentPolicy>() {
+                errors.push(ValidationError::new(
+                    field("deployment_policy"),
+                    e.to_string(),
+                ));
+            }
+            if let Err(e) = PublicKey::from_base64(&system.public_key, &system.hostname) {
+                errors.push(ValidationError::new(field("public_key"), e.to_string()));
+            }
+            if let Some(options) = &system.nix_build_options
+                && let Err(e) = options.validate()
+            {
+                errors.push(ValidationError::new(field("nix_build_options"), e));
+            }
+        }
+
+        for flake in &self.flakes.watched {
+            if let Err(e) = self.dirty_flake_ref(&flake.name) {
+                errors.push(ValidationError::new(
+                    format!("flakes.watched[{}].allow_dirty", flake.name),
+                    e,
+                ));
+            }
+        }
+
+        self.validate_cache(&mut errors);
+
+        if let Err(e) = self.build.validate_reservation_timing() {
+            errors.push(ValidationError::new("build", e));
+        }
+        if let Err(e) = self.build.validate_priority() {
+            errors.push(ValidationError::new("build", e));
+        }
+        if let Err(e) = self.build.validate_redact_patterns() {
+            errors.push(ValidationError::new("build.redact_patterns", e));
+        }
+        if let Err(e) = self.build.validate_substituters() {
+            errors.push(ValidationError::new("build.substituters", e));
+        }
+        if let Err(e) = self.build.validate_labels() {
+            errors.push(ValidationError::new("build.label_rules", e));
+        }
+        if let Err(e) = self.build.validate_post_build_hook() {
+            errors.push(ValidationError::new("build.post_build_hook", e));
+        }
+        for (field, url) in [
+            (
+                "build.stuck_worker_webhook",
+                &self.build.stuck_worker_webhook,
+            ),
+            ("notifications.url", &self.notifications.url),
+        ] {
+            if let Some(url) = url
+                && !(url.starts_with("http://") || url.starts_with("https://"))
+            {
+                errors.push(ValidationError::new(
+                    field,
+                    format!("'{}' is not an http(s) URL", url),
+                ));
+            }
+        }
+
+        Ok(errors)
+    }
+
+    fn validate_cache(&self, errors: &mut Vec<ValidationError>) {
+        let cache = &self.cache;
+
+        match cache.effective_cache_type() {
+            CacheType::Attic => {
+                if cache.push_after_build && cache.effective_attic_cache_name().is_none() {
+                    errors.push(ValidationError::new(
+                        "cache.attic_cache_name",
+                        "required when pushing to an Attic cache",
+                    ));
+                }
+                if cache.attic_cache_name.is_none() {
+                    for destination in &cache.destinations {
+                        if destination.cache_name.is_none() {
+                            errors.push(ValidationError::new(
+                                "cache.destinations",
+                                format!(
+                                    "'{}' needs a cache_name when cache.attic_cache_name is unset",
+                                    destination.name
+                                ),
+                            ));
+                        }
+                    }
+                }
+            }
+            CacheType::SshNg => {
+                if let Some(push_to) = &cache.push_to
+                    && !(push_to.starts_with("ssh-ng://") || push_to.starts_with("ssh://"))
+                {
+                    errors.push(ValidationError::new(
+                        "cache.push_to",
+                        format!("'{}' is not an ssh-ng:// or ssh:// store URI", push_to),
+                    ));
+                }
+            }
+            CacheType::Cachix => {
+                if cache.push_after_build && cache.cachix_cache_name().is_none() {
+                    errors.push(ValidationError::new(
+                        "cache.push_to",
+                        "must name the Cachix cache, e.g. cachix://mycache",
+                    ));
+                }
+            }
+            CacheType::S3 | CacheType::Http | CacheType::Nix => {}
+        }
+
+        if cache.push_after_build && cache.push_to.is_none() {
+            errors.push(ValidationError::new(
+                "cache.push_to",
+                "required when push_after_build is enabled",
+            ));
+        }
+        unique_names(
+            cache.destinations.iter().map(|d| d.name.as_str()),
+            "cache.destinations",
+            errors,
+        );
+        for destination in &cache.destinations {
+            if destination.name.is_empty() {
+                errors.push(ValidationError::new(
+                    "cache.destinations",
+                    "every destination needs a name",
+                ));
+            }
+            if destination.token.is_some() && destination.token_file.is_some() {
+                errors.push(ValidationError::new(
+                    "cache.destinations",
+                    format!("'{}' sets both token and token_file", destination.name),
+                ));
+            }
+        }
+        if cache.push_backlog_high_water > 0
+            && cache.push_backlog_low_water >= cache.push_backlog_high_water
+        {
+            errors.push(ValidationError::new(
+                "cache.push_backlog_low_water",
+                "must be below push_backlog_high_water",
+            ));
+        }
+        if cache.parallel_uploads == 0 {
+            errors.push(ValidationError::new(
+                "cache.parallel_uploads",
+                "must be at least 1",
+            ));
+        }
+        if let Some(algo) = cache.compression {
+            if matches!(
+                cache.effective_cache_type(),
+                CacheType::Attic | CacheType::SshNg | CacheType::Cachix
+            ) {
+                errors.push(ValidationError::new(
+                    "cache.compression",
+                    "only applies to binary cache destinations (S3, HTTP, file)",
+                ));
+            }
+            if let Some(level) = cache.compression_level
+                && !algo
+                    .level_range()
+                    .is_some_and(|range| range.contains(&level))
+            {
+                errors.push(ValidationError::new(
+                    "cache.compression_level",
+                    format!("{} is not a valid level for {}", level, algo.as_str()),
+                ));
+            }
+        }
+    }
+}
+
+/// Collect names into a set, reporting duplicates under `section`
+fn unique_names<'a>(
+    names: impl Iterator<Item = &'a str>,
+    section: &str,
+    errors: &mut Vec<ValidationError>,
+) -> HashSet<&'a str> {
+    let mut seen = HashSet::new();
+    for name in names {
+        if !seen.insert(name) {
+            errors.push(ValidationError::new(
+                section,
+                format!("'{}' is defined more than once", name),
+            ));
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EnvironmentConfig, SystemConfig, WatchedFlake};
+
+    fn system(hostname: &str, environment: &str, flake_name: Option<&str>) -> SystemConfig {
+        SystemConfig {
+            hostname: hostname.to_string(),
+            public_key: "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string(),
+            environment: environment.to_string(),
+            flake_name: flake_name.map(str::to_string),
+            deployment_policy: "manual".to_string(),
+            desired_target: None,
+            nix_build_options: None,
+            cache_only: false,
+        }
+    }
+
+    #[test]
+    fn reports_every_dangling_reference() {
+        let mut cfg = CrystalForgeConfig::default().with_environments(vec![EnvironmentConfig {
+            name: "prod".to_string(),
+            description: String::new(),
+            is_active: true,
+            risk_profile: "low".to_string(),
+            compliance_level: "none".to_string(),
+            cache_push_to: None,
+        }]);
+        cfg.systems = vec![
+            system("ok", "prod", None),
+            system("bad-env", "staging", None),
+            system("bad-flake", "prod", Some("missing")),
+        ];
+
+        let fields: Vec<String> = cfg
+            .validate()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.field)
+            .collect();
+
+        assert_eq!(
+            fields,
+            vec![
+                "systems[bad-env].environment".to_string(),
+                "systems[bad-flake].flake_name".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn dirty_flakes_are_refused_for_production_systems() {
+        let mut cfg = CrystalForgeConfig::default();
+        cfg.flakes.watched = vec![WatchedFlake {
+            name: "infra".to_string(),
+            repo_url: "/home/dev/infra".to_string(),
+            auto_poll: false,
+            initial_commit_depth: 5,
+            auth: None,
+            eval_filter: Default::default(),
+            allow_dirty: true,
+        }];
+        cfg.systems = vec![system("dev01", "dev", Some("infra"))];
+        assert_eq!(
+            cfg.dirty_flake_ref("infra"),
+            Ok(Some("path:/home/dev/infra".to_string()))
+        );
+
+        cfg.systems.push(system("web01", "prod", Some("infra")));
+        assert!(cfg.dirty_flake_ref("infra").is_err());
+
+        cfg.flakes.watched[0].allow_dirty = false;
+        assert_eq!(cfg.dirty_flake_ref("infra"), Ok(None));
+    }
+}
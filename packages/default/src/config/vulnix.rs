@@ -14,6 +14,36 @@ pub struct VulnixConfig {
     /// Interval in seconds between checking for new build jobs
     #[serde(with = "humantime_serde")]
     pub poll_interval: Duration,
+    /// Number of derivations scanned at the same time
+    pub max_concurrent_scans: usize,
+    /// Record a software bill of materials for every scanned derivation
+    pub generate_sbom: bool,
+    /// Document format of the recorded SBOMs
+    pub sbom_format: SbomFormat,
+    /// Also scan every derivation the scanned one was built from, not only
+    /// its runtime closure
+    pub scan_build_inputs: bool,
+}
+
+/// SBOM document standard
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SbomFormat {
+    /// CycloneDX 1.5 JSON
+    #[default]
+    CycloneDx,
+    /// SPDX 2.3 JSON
+    Spdx,
+}
+
+impl SbomFormat {
+    /// Name stored alongside the document
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SbomFormat::CycloneDx => "cyclonedx",
+            SbomFormat::Spdx => "spdx",
+        }
+    }
 }
 
 impl Default for VulnixConfig {
@@ -25,6 +55,10 @@ impl Default for VulnixConfig {
             extra_args: vec![],
             whitelist_path: None,
             poll_interval: Duration::from_secs(60),
+            max_concurrent_scans: 1,
+            generate_sbom: true,
+            sbom_format: SbomFormat::default(),
+            scan_build_inputs: false,
         }
     }
 }
@@ -0,0 +1,250 @@
+//! Schema setup run by every binary that owns a database pool, and retries
+//! for transactions Postgres aborts under contention and for calls that lose
+//! their connection while the database restarts or fails over.
+
+use crate::queries::derivations::EvaluationStatus;
+use anyhow::{Context, Result, bail};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Attempts made by [`retry_transaction`] before giving up
+pub const TRANSACTION_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry of an aborted transaction; doubled for each
+/// further attempt, plus jitter so the colliding transactions don't meet again
+const TRANSACTION_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Attempts made by [`retry_connection`] before giving up
+pub const CONNECTION_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry of a call that lost its connection; doubled
+/// for each further attempt, so the retries span a typical failover
+const CONNECTION_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Apply pending migrations, then check that the `derivation_statuses` rows
+/// match the status ids the code uses. Safe to run on every startup.
+pub async fn migrate(pool: &PgPool) -> Result<()> {
+    sqlx::migrate!("./migrations")
+        .run(pool)
+        .await
+        .context("running database migrations")?;
+
+    let rows: Vec<(i32, String)> = sqlx::query_as("SELECT id, name FROM derivation_statuses")
+        .fetch_all(pool)
+        .await
+        .context("reading derivation_statuses")?;
+
+    let problems = status_seed_problems(&rows);
+    if !problems.is_empty() {
+        bail!(
+            "derivation_statuses does not match this build of crystal-forge: {}",
+            problems.join("; ")
+        );
+    }
+
+    info!("✅ Database schema is up to date");
+    Ok(())
+}
+
+/// Ways the seeded status rows disagree with the ids the code expects
+fn status_seed_problems(rows: &[(i32, String)]) -> Vec<String> {
+    let seeded: HashMap<i32, &str> = rows.iter().map(|(id, name)| (*id, name.as_str())).collect();
+
+    EvaluationStatus::ALL
+        .iter()
+        .map(|status| (status.as_id(), status.db_name()))
+        .filter_map(|(id, name)| match seeded.get(&id) {
+            None => Some(format!("status {} ({}) is missing", id, name)),
+            Some(actual) if *actual != name => Some(format!(
+                "status {} is '{}', expected '{}'",
+                id, actual, name
+            )),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+/// Whether a SQLSTATE means the transaction was aborted by a deadlock
+/// (`40P01`) or a serialization failure (`40001`) and can simply be rerun
+fn is_retryable_sqlstate(code: &str) -> bool {
+    matches!(code, "40P01" | "40001")
+}
+
+/// Whether `err` comes from a transaction Postgres aborted as a deadlock
+/// victim or serialization failure
+pub fn is_retryable(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<sqlx::Error>())
+        .any(|e| match e {
+            sqlx::Error::Database(db) => db.code().is_some_and(|c| is_retryable_sqlstate(&c)),
+            _ => false,
+        })
+}
+
+/// Whether a SQLSTATE means the server dropped or refused the connection:
+/// class `08` (connection exception) or the server shutting down or still
+/// starting (`57P01`, `57P02`, `57P03`)
+fn is_connection_sqlstate(code: &str) -> bool {
+    code.starts_with("08") || matches!(code, "57P01" | "57P02" | "57P03")
+}
+
+/// Whether `err` comes from losing or failing to get a database connection
+/// rather than from the query itself, so rerunning it may succeed
+pub fn is_connection_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<sqlx::Error>())
+        .any(|e| match e {
+            sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut => true,
+            sqlx::Error::Database(db) => db.code().is_some_and(|c| is_connection_sqlstate(&c)),
+            _ => false,
+        })
+}
+
+/// Run a transaction, rerunning it up to [`TRANSACTION_ATTEMPTS`] times in
+/// total while it fails with a deadlock or serialization error. `run` must
+/// open and commit its own transaction so every attempt starts clean.
+pub async fn retry_transaction<T, F, Fut>(what: &str, run: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    retry_while(
+        what,
+        "aborted",
+        TRANSACTION_ATTEMPTS,
+        TRANSACTION_RETRY_DELAY,
+        is_retryable,
+        run,
+    )
+    .await
+}
+
+/// Run a database call, rerunning it up to [`CONNECTION_ATTEMPTS`] times in
+/// total with growing delays while it fails on the connection (pool
+/// exhausted, database restarting). Query errors are returned at once.
+pub async fn retry_connection<T, F, Fut>(what: &str, run: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    retry_while(
+        what,
+        "lost its database connection",
+        CONNECTION_ATTEMPTS,
+        CONNECTION_RETRY_DELAY,
+        is_connection_error,
+        run,
+    )
+    .await
+}
+
+async fn retry_while<T, F, Fut>(
+    what: &str,
+    failure: &str,
+    attempts: u32,
+    base_delay: Duration,
+    retryable: fn(&anyhow::Error) -> bool,
+    mut run: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match run().await {
+            Err(e) if attempt < attempts && retryable(&e) => {
+                let delay =
+                    base_delay * 2u32.pow(attempt - 1) + crate::shutdown::jitter(base_delay);
+                warn!(
+                    "🔁 {} {} (attempt {}/{}), retrying in {:?}: {}",
+                    what, failure, attempt, attempts, delay, e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Migrated pool for tests that need a database, from
+/// `CF_TEST_DATABASE_URL`. `None` when it isn't set, so those tests pass
+/// without a database.
+#[cfg(test)]
+pub(crate) async fn test_pool() -> Option<PgPool> {
+    let url = std::env::var("CF_TEST_DATABASE_URL").ok()?;
+    let pool = PgPool::connect(&url)
+        .await
+        .expect("connecting to CF_TEST_DATABASE_URL");
+    migrate(&pool).await.expect("migrating the test database");
+    Some(pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_seed_problems_reports_missing_and_renamed_rows() {
+        let mut rows: Vec<(i32, String)> = EvaluationStatus::ALL
+            .iter()
+            .map(|status| (status.as_id(), status.db_name().to_string()))
+            .collect();
+        rows.push((1, "pending".to_string()));
+        assert!(status_seed_problems(&rows).is_empty());
+
+        rows.retain(|(id, _)| *id != EvaluationStatus::CachePushed.as_id());
+        rows[0].1 = "waiting".to_string();
+        let problems = status_seed_problems(&rows);
+        assert_eq!(
+            problems,
+            vec![
+                "status 3 is 'waiting', expected 'dry-run-pending'".to_string(),
+                "status 14 (cache-pushed) is missing".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_deadlocks_and_serialization_failures_are_retried() {
+        assert!(is_retryable_sqlstate("40P01"));
+        assert!(is_retryable_sqlstate("40001"));
+        assert!(!is_retryable_sqlstate("23505"));
+        assert!(!is_retryable(&anyhow::anyhow!("deadlock detected")));
+        assert!(!is_retryable(&anyhow::Error::new(sqlx::Error::RowNotFound)));
+    }
+
+    #[test]
+    fn connection_errors_are_told_apart_from_query_errors() {
+        assert!(is_connection_sqlstate("08006"));
+        assert!(is_connection_sqlstate("57P01"));
+        assert!(!is_connection_sqlstate("40P01"));
+        assert!(!is_connection_sqlstate("42P01"));
+        assert!(is_connection_error(&anyhow::Error::new(
+            sqlx::Error::PoolTimedOut
+        )));
+        assert!(is_connection_error(
+            &anyhow::Error::new(sqlx::Error::Io(std::io::ErrorKind::ConnectionReset.into()))
+                .context("claiming work")
+        ));
+        assert!(!is_connection_error(&anyhow::Error::new(
+            sqlx::Error::RowNotFound
+        )));
+    }
+
+    #[tokio::test]
+    async fn retry_transaction_stops_on_other_errors() {
+        let mut calls = 0;
+        let result: Result<()> = retry_transaction("test", || {
+            calls += 1;
+            async { Err(anyhow::anyhow!("unique violation")) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
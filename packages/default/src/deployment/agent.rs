@@ -1,9 +1,12 @@
 use crate::handlers::agent::heartbeat::LogResponse;
 use crate::config::{CacheType, deployment::DeploymentConfig};
+use crate::derivations::utils::{get_nar_hash, get_nar_size, nar_hashes_match};
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::Semaphore;
 use tracing::{debug, error, info, warn};
@@ -11,8 +14,43 @@ use tracing::{debug, error, info, warn};
 // Note: This module requires readlink_path() to be in scope
 // readlink_path should be imported from the agent module where it's defined
 
+/// systemd service the agent runs as
+const AGENT_SERVICE: &str = "crystal-forge-agent.service";
+/// Runtime drop-in directory for [`AGENT_SERVICE`]; cleared on reboot
+const AGENT_DROPIN_DIR: &str = "/run/systemd/system/crystal-forge-agent.service.d";
+
+/// Remembers how the last deployment reached this host until the system it
+/// switched to is reported. Lives in /run so it survives the agent restart
+/// that activation triggers, but not a reboot.
+const DEPLOYMENT_SOURCE_FILE: &str = "/run/crystal-forge/deployment-source";
+
+/// Where a deployed system closure came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeploymentSource {
+    Cache,
+    LocalBuild,
+}
+
+impl DeploymentSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeploymentSource::Cache => "cache",
+            DeploymentSource::LocalBuild => "local_build",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "cache" => Some(DeploymentSource::Cache),
+            "local_build" => Some(DeploymentSource::LocalBuild),
+            _ => None,
+        }
+    }
+}
+
 /// Result of a deployment operation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
 pub enum DeploymentResult {
     NoDeploymentNeeded,
     AlreadyOnTarget,
@@ -27,6 +65,10 @@ pub enum DeploymentResult {
         error: String,
         desired_target: String,
     },
+    /// Dry-run mode: the deployment was planned and logged but not applied
+    DryRun {
+        desired_target: String,
+    },
 }
 
 impl DeploymentResult {
@@ -38,6 +80,7 @@ impl DeploymentResult {
                 | DeploymentResult::SuccessFromCache { .. }
                 | DeploymentResult::SuccessLocalBuild
                 | DeploymentResult::Started { .. }
+                | DeploymentResult::DryRun { .. }
         )
     }
 
@@ -60,6 +103,22 @@ impl DeploymentResult {
             } => {
                 format!("Deployment failed for {}: {}", desired_target, error)
             }
+            DeploymentResult::DryRun { desired_target } => {
+                format!("Dry run: would deploy {}", desired_target)
+            }
+        }
+    }
+
+    /// Name of the variant, as stored in `deployment_results.result`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeploymentResult::NoDeploymentNeeded => "no_deployment_needed",
+            DeploymentResult::AlreadyOnTarget => "already_on_target",
+            DeploymentResult::SuccessFromCache { .. } => "success_from_cache",
+            DeploymentResult::SuccessLocalBuild => "success_local_build",
+            DeploymentResult::Started { .. } => "started",
+            DeploymentResult::Failed { .. } => "failed",
+            DeploymentResult::DryRun { .. } => "dry_run",
         }
     }
 
@@ -73,19 +132,48 @@ impl DeploymentResult {
     }
 }
 
+/// The agent's last deployment result, sent to the forge with each heartbeat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentReport {
+    /// Target the forge asked for when the result came about
+    pub target: Option<String>,
+    pub result: DeploymentResult,
+}
+
+impl DeploymentReport {
+    /// The error of a failed deployment
+    pub fn error(&self) -> Option<&str> {
+        match &self.result {
+            DeploymentResult::Failed { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
 /// Agent deployment manager handles applying deployments from server
+#[derive(Clone)]
 pub struct AgentDeploymentManager {
     config: DeploymentConfig,
     current_target: Option<String>,
+    /// Held while a deployment or agent self-update runs, so only one
+    /// touches the system at a time
     deployment_lock: Arc<Semaphore>,
+    /// Permits for background prefetch copies, `max_concurrent_copies` of
+    /// them
+    prefetch_permits: Arc<Semaphore>,
+    /// Store paths being prefetched in the background
+    prefetching: Arc<Mutex<HashSet<String>>>,
 }
 
 impl AgentDeploymentManager {
     pub fn new(config: DeploymentConfig) -> Self {
+        let permits = config.max_concurrent_copies.max(1);
         Self {
             config,
             current_target: None,
             deployment_lock: Arc::new(Semaphore::new(1)),
+            prefetch_permits: Arc::new(Semaphore::new(permits)),
+            prefetching: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
@@ -108,6 +196,10 @@ impl AgentDeploymentManager {
     ) -> Result<DeploymentResult> {
         debug!("Processing heartbeat response");
 
+        if let Some(upcoming) = response.prefetch_target.as_deref() {
+            self.start_prefetch(upcoming, response.desired_target.as_deref());
+        }
+
         let Some(desired_target) = response.desired_target else {
             debug!("No desired target in heartbeat response");
             return Ok(DeploymentResult::NoDeploymentNeeded);
@@ -128,7 +220,18 @@ impl AgentDeploymentManager {
         debug!("Current system: {}", actual_current);
         debug!("Desired system: {}", desired_target);
 
-        match self.execute_deployment(&desired_target).await {
+        match self
+            .execute_deployment(
+                &desired_target,
+                response.expected_nar_hash.as_deref(),
+                response.flake_target.as_deref(),
+            )
+            .await
+        {
+            Ok(result @ DeploymentResult::DryRun { .. }) => {
+                info!("🧪 Dry run finished, nothing was changed");
+                Ok(result)
+            }
             Ok(result) => {
                 info!("Deployment completed successfully");
                 self.current_target = Some(desired_target.to_string());
@@ -144,15 +247,68 @@ impl AgentDeploymentManager {
         }
     }
 
-    async fn execute_deployment(&self, target: &str) -> Result<DeploymentResult> {
+    /// Copy the upcoming target from the cache in the background, so that
+    /// deploying it later finds it in the store already. Prefetches don't
+    /// wait on deployments; at most `max_concurrent_copies` run at once.
+    fn start_prefetch(&self, store_path: &str, desired_target: Option<&str>) {
+        if !self.config.prefetch_next_target || self.config.dry_run {
+            return;
+        }
+        let Some(cache_url) = self.config.cache_url.clone() else {
+            return;
+        };
+        if !store_path.starts_with("/nix/store/")
+            || desired_target == Some(store_path)
+            || Path::new(store_path).exists()
+        {
+            return;
+        }
+        {
+            let mut in_flight = self.prefetching.lock().unwrap();
+            if !in_flight.insert(store_path.to_string()) {
+                debug!("Prefetch of {} already running", store_path);
+                return;
+            }
+        }
+
+        let manager = self.clone();
+        let store_path = store_path.to_string();
+        tokio::spawn(async move {
+            let result = async {
+                let _permit = manager.prefetch_permits.acquire().await?;
+                info!("📥 Prefetching upcoming target {}", store_path);
+                manager.ensure_store_space(&cache_url, &store_path).await?;
+                manager
+                    .copy_from_cache_with_retry(&cache_url, &store_path)
+                    .await
+            }
+            .await;
+
+            match result {
+                Ok(()) => info!("📥 Prefetched upcoming target {}", store_path),
+                Err(e) => warn!("⚠️ Prefetch of {} failed: {:#}", store_path, e),
+            }
+            manager.prefetching.lock().unwrap().remove(&store_path);
+        });
+    }
+
+    async fn execute_deployment(
+        &self,
+        target: &str,
+        expected_nar_hash: Option<&str>,
+        flake_target: Option<&str>,
+    ) -> Result<DeploymentResult> {
         let _permit = self.deployment_lock.acquire().await?;
 
         info!("Starting deployment execution for: {}", target);
 
         let is_store_path = target.starts_with("/nix/store/");
 
-        // Store paths REQUIRE cache to be configured
-        if is_store_path && self.config.cache_url.is_none() {
+        // Store paths REQUIRE cache to be configured (unless we may build locally)
+        if is_store_path
+            && self.config.cache_url.is_none()
+            && !self.config.allow_local_build_fallback
+        {
             anyhow::bail!(
                 "Cannot deploy store path without cache configured. Target: {}",
                 target
@@ -162,9 +318,23 @@ impl AgentDeploymentManager {
         let start_time = std::time::Instant::now();
 
         let result = if is_store_path {
-            // Store paths: deploy from cache
-            let cache_url = self.config.cache_url.as_ref().unwrap(); // Safe because we checked above
-            self.deploy_store_path_from_cache(target, cache_url).await?
+            match self.config.cache_url.as_ref() {
+                // Store paths: deploy from cache
+                Some(cache_url) => {
+                    self.deploy_store_path_from_cache(
+                        target,
+                        cache_url,
+                        expected_nar_hash,
+                        flake_target,
+                    )
+                    .await?
+                }
+                // No cache configured but local builds allowed
+                None => {
+                    self.deploy_store_path_from_local_build(target, flake_target)
+                        .await?
+                }
+            }
         } else {
             anyhow::bail!(
                 "This is not a store path we don't know how to handle it! Target: {}",
@@ -184,6 +354,8 @@ impl AgentDeploymentManager {
         &self,
         store_path: &str,
         cache_url: &str,
+        expected_nar_hash: Option<&str>,
+        flake_target: Option<&str>,
     ) -> Result<DeploymentResult> {
         info!("Deploying store path from cache: {}", store_path);
         info!("Cache type: {:?}", self.config.cache_type);
@@ -197,29 +369,249 @@ impl AgentDeploymentManager {
         // For all cache types, use cache_url directly
         let binary_cache_url = cache_url.to_string();
 
-        // Step 1: Copy from cache with retry logic
+        // Step 1: Make sure the copy won't run the store out of space
+        if self.config.dry_run {
+            info!("🧪 Dry run: skipping free space check for {}", store_path);
+        } else {
+            self.ensure_store_space(cache_url, store_path).await?;
+        }
+
+        // Step 2: Copy from cache with retry logic
         info!("Starting cache copy with retry logic...");
-        self.copy_from_cache_with_retry(&binary_cache_url, store_path)
-            .await?;
+        if let Err(copy_err) = self
+            .copy_from_cache_with_retry(&binary_cache_url, store_path)
+            .await
+        {
+            if !self.config.allow_local_build_fallback {
+                return Err(copy_err);
+            }
+
+            warn!(
+                "Cache copy failed ({:#}), falling back to local build of {}",
+                copy_err, store_path
+            );
+            return self
+                .deploy_store_path_from_local_build(store_path, flake_target)
+                .await
+                .with_context(|| format!("Cache copy failed ({:#})", copy_err));
+        }
 
-        // Step 2: Activate the configuration using systemd-run
+        // Step 3: Make sure the copy is intact and is what the forge built
+        if self.config.dry_run {
+            info!("🧪 Dry run: skipping verification of {}", store_path);
+        } else {
+            self.verify_store_path(store_path, expected_nar_hash)
+                .await?;
+        }
+
+        // Step 4: Activate the configuration using systemd-run
         info!("Activating configuration via systemd-run...");
+        if !self.config.dry_run {
+            remember_deployment_source(store_path, DeploymentSource::Cache);
+        }
         self.activate_configuration(store_path, &unit_name).await?;
 
+        if self.config.dry_run {
+            return Ok(DeploymentResult::DryRun {
+                desired_target: store_path.to_string(),
+            });
+        }
         info!("Deployment detached to systemd unit: {}", unit_name);
         Ok(DeploymentResult::Started { unit_name })
     }
 
+    /// Fail fast if the nix store filesystem has less free space than the
+    /// configured minimum or the target's NAR size, garbage collecting first
+    /// when that is allowed
+    async fn ensure_store_space(&self, cache_url: &str, store_path: &str) -> Result<()> {
+        let nar_size = match get_nar_size(Some(cache_url), store_path).await {
+            Ok(size) => size,
+            Err(e) => {
+                debug!("Could not read narSize of {} from cache: {}", store_path, e);
+                0
+            }
+        };
+        let required = self.config.min_free_store_bytes.max(nar_size);
+
+        let mut free = nix_store_free_bytes()?;
+        if free >= required {
+            return Ok(());
+        }
+
+        if self.config.allow_garbage_collection {
+            warn!(
+                "Only {} bytes free in /nix/store (need {}), collecting garbage",
+                free, required
+            );
+            let output = tokio::process::Command::new("nix-collect-garbage")
+                .output()
+                .await
+                .context("Failed to spawn nix-collect-garbage")?;
+            if !output.status.success() {
+                warn!(
+                    "nix-collect-garbage failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            free = nix_store_free_bytes()?;
+            if free >= required {
+                info!(
+                    "Garbage collection freed enough space ({} bytes free)",
+                    free
+                );
+                return Ok(());
+            }
+        }
+
+        anyhow::bail!(
+            "Insufficient disk space for {}: {} bytes free in /nix/store, need {}",
+            store_path,
+            free,
+            required
+        );
+    }
+
+    /// Check the copied store path against its recorded NAR hash with
+    /// `nix store verify`, then against the hash the forge recorded at build
+    /// time (when the server sent one)
+    async fn verify_store_path(
+        &self,
+        store_path: &str,
+        expected_nar_hash: Option<&str>,
+    ) -> Result<()> {
+        use tokio::process::Command as TokioCommand;
+
+        info!("Verifying contents of {}", store_path);
+        let output = TokioCommand::new("nix")
+            .args(["store", "verify", "--no-trust", store_path])
+            .output()
+            .await
+            .context("Failed to spawn nix store verify")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Store path {} failed verification after cache copy: {}",
+                store_path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let Some(expected) = expected_nar_hash else {
+            debug!("No expected NAR hash from server, skipping hash comparison");
+            return Ok(());
+        };
+
+        let actual = get_nar_hash(store_path).await?;
+        if !nar_hashes_match(expected, &actual) {
+            anyhow::bail!(
+                "NAR hash mismatch for {}: expected {}, got {}",
+                store_path,
+                expected,
+                actual
+            );
+        }
+
+        info!("Verified NAR hash of {}", store_path);
+        Ok(())
+    }
+
+    /// Build the store path from its flake on this machine and activate it
+    async fn deploy_store_path_from_local_build(
+        &self,
+        store_path: &str,
+        flake_target: Option<&str>,
+    ) -> Result<DeploymentResult> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let unit_name = format!("crystal-forge-deploy-{}", timestamp);
+
+        if self.config.dry_run {
+            info!("🧪 Dry run: would build {} locally", store_path);
+        } else {
+            self.build_store_path_locally(store_path, flake_target)
+                .await?;
+        }
+
+        info!("Activating locally built configuration via systemd-run...");
+        if !self.config.dry_run {
+            remember_deployment_source(store_path, DeploymentSource::LocalBuild);
+        }
+        self.activate_configuration(store_path, &unit_name).await?;
+
+        if self.config.dry_run {
+            return Ok(DeploymentResult::DryRun {
+                desired_target: store_path.to_string(),
+            });
+        }
+
+        info!("Deployment detached to systemd unit: {}", unit_name);
+        Ok(DeploymentResult::SuccessLocalBuild)
+    }
+
+    /// Build `flake_target`, the NixOS configuration the forge evaluated to
+    /// `store_path`, and check that it produced that path
+    async fn build_store_path_locally(
+        &self,
+        store_path: &str,
+        flake_target: Option<&str>,
+    ) -> Result<()> {
+        use tokio::process::Command as TokioCommand;
+
+        let Some(flake_target) = flake_target else {
+            anyhow::bail!(
+                "Cannot build {} locally: the forge did not say which flake produces it",
+                store_path
+            );
+        };
+
+        info!("Building {} locally from {}", store_path, flake_target);
+
+        let build_timeout = Duration::from_secs(self.config.deployment_timeout_minutes * 60);
+        let output = tokio::time::timeout(
+            build_timeout,
+            TokioCommand::new("nix")
+                .args(["build", "--no-link", "--print-out-paths", flake_target])
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await
+        .with_context(|| format!("Local build timed out after {:?}", build_timeout))?
+        .context("Failed to spawn nix build")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Local build of {} failed: {}",
+                flake_target,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let outputs = String::from_utf8_lossy(&output.stdout);
+        if !outputs.lines().any(|line| line.trim() == store_path) {
+            anyhow::bail!(
+                "Local build of {} did not produce {} (got: {})",
+                flake_target,
+                store_path,
+                outputs.trim()
+            );
+        }
+
+        info!("Successfully built {} locally", store_path);
+        Ok(())
+    }
+
     async fn copy_from_cache_with_retry(&self, cache_url: &str, store_path: &str) -> Result<()> {
-        const MAX_RETRIES: u32 = 3;
         const BASE_RETRY_DELAY: Duration = Duration::from_secs(5);
+        const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+        let max_retries = self.config.cache_copy_max_retries.max(1);
 
-        for attempt in 1..=MAX_RETRIES {
+        for attempt in 1..=max_retries {
             // Progressive retry strategies:
             // Attempt 1: normal copy
             // Attempt 2: add --refresh to bypass stale cache metadata
-            // Attempt 3: clear local nix cache directory, then retry
-            let use_refresh = attempt == 2;
+            // Attempt 3+: clear local nix cache directory, then retry
+            let use_refresh = attempt >= 2;
 
             match self
                 .copy_from_cache(cache_url, store_path, use_refresh)
@@ -232,8 +624,25 @@ impl AgentDeploymentManager {
                     );
                     return Ok(());
                 }
-                Err(e) if attempt < MAX_RETRIES => {
-                    let retry_delay = BASE_RETRY_DELAY.mul_f64(2_f64.powi((attempt - 1) as i32));
+                Err(e)
+                    if !should_retry_copy(
+                        classify_copy_error(&format!("{:#}", e)),
+                        use_refresh,
+                    ) =>
+                {
+                    error!(
+                        "Cache copy of {} failed with a non-retryable error: {}",
+                        store_path, e
+                    );
+                    return Err(e).context(format!(
+                        "Failed to copy {} from cache (not retrying)",
+                        store_path
+                    ));
+                }
+                Err(e) if attempt < max_retries => {
+                    let retry_delay = BASE_RETRY_DELAY
+                        .mul_f64(2_f64.powi((attempt - 1).min(6) as i32))
+                        .min(MAX_RETRY_DELAY);
                     warn!(
                         "Cache copy attempt {} failed: {}. Retrying in {:.1}s...",
                         attempt,
@@ -251,10 +660,10 @@ impl AgentDeploymentManager {
                     tokio::time::sleep(retry_delay).await;
                 }
                 Err(e) => {
-                    error!("Cache copy failed after {} attempts: {}", MAX_RETRIES, e);
+                    error!("Cache copy failed after {} attempts: {}", max_retries, e);
                     return Err(e).context(format!(
                         "Failed to copy {} from cache after {} retries",
-                        store_path, MAX_RETRIES
+                        store_path, max_retries
                     ));
                 }
             }
@@ -262,7 +671,7 @@ impl AgentDeploymentManager {
 
         Err(anyhow::anyhow!(
             "Cache copy exhausted all {} retries",
-            MAX_RETRIES
+            max_retries
         ))
     }
 
@@ -328,10 +737,12 @@ impl AgentDeploymentManager {
 
         copy_args.push(store_path.to_string());
 
-        debug!(
-            "Executing: nix {}",
-            shell_join(&copy_args.iter().map(|s| s.as_str()).collect::<Vec<_>>())
-        );
+        let command = shell_join(&copy_args.iter().map(|s| s.as_str()).collect::<Vec<_>>());
+        if self.config.dry_run {
+            info!("🧪 Dry run: would run nix {}", command);
+            return Ok(());
+        }
+        debug!("Executing: nix {}", command);
 
         let copy_result = tokio::time::timeout(
             Duration::from_secs(copy_timeout),
@@ -443,6 +854,14 @@ impl AgentDeploymentManager {
     async fn activate_configuration(&self, store_path: &str, unit_name: &str) -> Result<()> {
         let switch_script = format!("{}/bin/switch-to-configuration", store_path);
 
+        if self.config.dry_run {
+            info!(
+                "🧪 Dry run: would run systemd-run --unit {} -- {} switch",
+                unit_name, switch_script
+            );
+            return Ok(());
+        }
+
         // Verify the script exists
         if !std::path::Path::new(&switch_script).exists() {
             anyhow::bail!(
@@ -485,11 +904,163 @@ impl AgentDeploymentManager {
         Ok(())
     }
 
+    /// Switch the agent to the build at `agent_path` when self-update is
+    /// allowed and it is not the build already running.
+    ///
+    /// The new agent is copied from cache and verified, then a transient
+    /// systemd unit points the service at it with a runtime drop-in and
+    /// restarts it. The drop-in lives under /run, so a reboot returns to the
+    /// agent of the active system configuration. Returns whether an update
+    /// was started.
+    pub async fn apply_agent_update(&self, agent_path: &str) -> Result<bool> {
+        if !self.config.allow_self_update {
+            debug!(
+                "Agent update to {} offered but self-update is disabled",
+                agent_path
+            );
+            return Ok(false);
+        }
+
+        let exe = std::env::current_exe().context("Failed to locate the running agent")?;
+        let exe = std::fs::canonicalize(&exe).unwrap_or(exe);
+        if store_path_of(&exe).as_deref() == Some(agent_path) {
+            debug!("Agent already running {}", agent_path);
+            return Ok(false);
+        }
+
+        if self.config.dry_run {
+            info!(
+                "🧪 Dry run: would update agent from {} to {}",
+                exe.display(),
+                agent_path
+            );
+            return Ok(false);
+        }
+
+        let _permit = self.deployment_lock.acquire().await?;
+
+        let Some(cache_url) = self.config.cache_url.as_deref() else {
+            anyhow::bail!(
+                "Cannot update agent to {} without cache configured",
+                agent_path
+            );
+        };
+
+        info!("Updating agent from {} to {}", exe.display(), agent_path);
+        self.copy_from_cache_with_retry(cache_url, agent_path)
+            .await?;
+        self.verify_store_path(agent_path, None).await?;
+
+        let agent_bin = format!("{}/bin/agent", agent_path);
+        if !Path::new(&agent_bin).exists() {
+            anyhow::bail!("Agent binary not found at: {}", agent_bin);
+        }
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let unit_name = format!("crystal-forge-agent-update-{}", timestamp);
+        let config_path = std::env::var("CRYSTAL_FORGE_CONFIG").ok();
+        let script = agent_update_script(&agent_bin, config_path.as_deref());
+
+        let run_args = [
+            "--unit",
+            &unit_name,
+            "--no-block",
+            "--collect",
+            "--",
+            "/bin/sh",
+            "-c",
+            &script,
+        ];
+        debug!("Executing: systemd-run {}", shell_join(&run_args));
+
+        let output = Command::new("systemd-run")
+            .args(run_args)
+            .output()
+            .context("Failed to spawn systemd-run process")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "systemd-run failed with exit code {:?}: {}",
+                output.status.code(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        info!("Agent update detached to systemd unit: {}", unit_name);
+        Ok(true)
+    }
+
     pub fn update_current_target(&mut self, target: Option<String>) {
         self.current_target = target;
     }
 }
 
+/// `/nix/store/<hash>-<name>` prefix of a path inside the nix store
+fn store_path_of(path: &Path) -> Option<String> {
+    let rest = path.strip_prefix("/nix/store").ok()?;
+    let entry = rest.components().next()?;
+    Some(Path::new("/nix/store").join(entry).to_str()?.to_string())
+}
+
+/// Why a `nix copy --from` failed, as far as its error output tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyError {
+    /// The cache rejected our credentials; retrying cannot help
+    Auth,
+    /// The cache does not have the path (or our metadata says so)
+    Missing,
+    /// Anything else: resets, timeouts, 5xx and other network blips
+    Transient,
+}
+
+fn classify_copy_error(error: &str) -> CopyError {
+    const AUTH: &[&str] = &[
+        "HTTP error 401",
+        "HTTP error 403",
+        "Unauthorized",
+        "Forbidden",
+    ];
+    const MISSING: &[&str] = &[
+        "HTTP error 404",
+        "does not exist in binary cache",
+        "is not available in binary cache",
+        "is not valid",
+    ];
+    if AUTH.iter().any(|pattern| error.contains(pattern)) {
+        CopyError::Auth
+    } else if MISSING.iter().any(|pattern| error.contains(pattern)) {
+        CopyError::Missing
+    } else {
+        CopyError::Transient
+    }
+}
+
+/// Whether another copy attempt could succeed. A missing path gets one
+/// `--refresh` attempt in case nix cached a stale negative lookup.
+fn should_retry_copy(error: CopyError, refreshed: bool) -> bool {
+    match error {
+        CopyError::Auth => false,
+        CopyError::Missing => !refreshed,
+        CopyError::Transient => true,
+    }
+}
+
+/// Shell script that points [`AGENT_SERVICE`] at `agent_bin` via a runtime
+/// drop-in and restarts it
+fn agent_update_script(agent_bin: &str, config_path: Option<&str>) -> String {
+    let mut dropin = format!("[Service]\nExecStart=\nExecStart={}\n", agent_bin);
+    if let Some(config) = config_path {
+        dropin.push_str(&format!("Environment=CRYSTAL_FORGE_CONFIG={}\n", config));
+    }
+    format!(
+        "mkdir -p {dir} && printf '%s' {dropin} > {dir}/self-update.conf && systemctl daemon-reload && systemctl restart {service}",
+        dir = AGENT_DROPIN_DIR,
+        dropin = shell_quote(&dropin),
+        service = AGENT_SERVICE,
+    )
+}
+
 fn shell_quote(s: &str) -> String {
     // Simple POSIX single-quote: ' -> '\''  (ends, escaped quote, resumes)
     if s.is_empty() {
@@ -512,7 +1083,138 @@ fn shell_join(args: &[&str]) -> String {
         .join(" ")
 }
 
+/// Record that `store_path` is being deployed from `source`. Failures only
+/// cost the cache-hit statistics, so they are logged and ignored.
+fn remember_deployment_source(store_path: &str, source: DeploymentSource) {
+    let path = Path::new(DEPLOYMENT_SOURCE_FILE);
+    let result = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(path, format!("{} {}\n", source.as_str(), store_path)));
+    if let Err(e) = result {
+        warn!(
+            "Failed to record deployment source for {}: {}",
+            store_path, e
+        );
+    }
+}
+
+/// How `current_system` was deployed, if it is the system the last
+/// deployment switched to. The record is consumed so only the first report
+/// of the new system counts as the deployment.
+pub fn take_deployment_source(current_system: &str) -> Option<DeploymentSource> {
+    let contents = std::fs::read_to_string(DEPLOYMENT_SOURCE_FILE).ok()?;
+    let source = parse_deployment_source(&contents, current_system)?;
+    if let Err(e) = std::fs::remove_file(DEPLOYMENT_SOURCE_FILE) {
+        warn!("Failed to clear {}: {}", DEPLOYMENT_SOURCE_FILE, e);
+    }
+    Some(source)
+}
+
+fn parse_deployment_source(contents: &str, current_system: &str) -> Option<DeploymentSource> {
+    let (source, store_path) = contents.trim().split_once(' ')?;
+    (store_path == current_system.trim_end_matches('/'))
+        .then(|| DeploymentSource::parse(source))
+        .flatten()
+}
+
 /// Reads a symlink and returns its target as a `PathBuf`.
 pub fn readlink_path(path: &str) -> Result<PathBuf> {
     Ok(PathBuf::from(nix::fcntl::readlink(path)?))
 }
+
+/// Bytes available to unprivileged users on the filesystem holding /nix/store
+pub fn nix_store_free_bytes() -> Result<u64> {
+    let stat = nix::sys::statvfs::statvfs("/nix/store").context("statvfs /nix/store failed")?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deployment_report_names_its_result() {
+        let report = DeploymentReport {
+            target: Some("/nix/store/abc123-nixos-system-web01".to_string()),
+            result: DeploymentResult::Failed {
+                error: "copy failed".to_string(),
+                desired_target: "/nix/store/abc123-nixos-system-web01".to_string(),
+            },
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["result"]["result"], report.result.kind());
+        assert_eq!(report.error(), Some("copy failed"));
+
+        let parsed: DeploymentReport = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.result.kind(), "failed");
+    }
+
+    #[test]
+    fn store_path_of_strips_to_store_entry() {
+        assert_eq!(
+            store_path_of(Path::new("/nix/store/abc123-agent/bin/agent")).as_deref(),
+            Some("/nix/store/abc123-agent")
+        );
+        assert_eq!(store_path_of(Path::new("/usr/bin/agent")), None);
+        assert_eq!(store_path_of(Path::new("/nix/store")), None);
+    }
+
+    #[test]
+    fn deployment_source_only_matches_the_deployed_system() {
+        let contents = "cache /nix/store/abc123-nixos-system-web01\n";
+        assert_eq!(
+            parse_deployment_source(contents, "/nix/store/abc123-nixos-system-web01"),
+            Some(DeploymentSource::Cache)
+        );
+        assert_eq!(
+            parse_deployment_source(contents, "/nix/store/def456-nixos-system-web01"),
+            None
+        );
+        assert_eq!(
+            parse_deployment_source("local_build /nix/store/abc123-x", "/nix/store/abc123-x"),
+            Some(DeploymentSource::LocalBuild)
+        );
+        assert_eq!(
+            parse_deployment_source("bogus /nix/store/abc123-x", "/nix/store/abc123-x"),
+            None
+        );
+    }
+
+    #[test]
+    fn copy_errors_are_classified_for_retry() {
+        let auth = classify_copy_error(
+            "nix copy failed: error: unable to download 'https://cache/abc.narinfo': HTTP error 401",
+        );
+        assert_eq!(auth, CopyError::Auth);
+        assert!(!should_retry_copy(auth, false));
+
+        let missing = classify_copy_error(
+            "nix copy failed: error: path '/nix/store/abc-system' is not valid",
+        );
+        assert_eq!(missing, CopyError::Missing);
+        assert!(should_retry_copy(missing, false));
+        assert!(!should_retry_copy(missing, true));
+
+        let reset = classify_copy_error(
+            "nix copy failed: error: unable to download 'https://cache/nar/x': Connection reset by peer (56)",
+        );
+        assert_eq!(reset, CopyError::Transient);
+        assert!(should_retry_copy(reset, true));
+        assert_eq!(
+            classify_copy_error("Cache copy timed out after 3600 seconds (1h 0m)."),
+            CopyError::Transient
+        );
+    }
+
+    #[test]
+    fn agent_update_script_overrides_exec_start() {
+        let script = agent_update_script(
+            "/nix/store/abc123-agent/bin/agent",
+            Some("/etc/crystal-forge/agent.toml"),
+        );
+        assert!(script.contains("ExecStart=\nExecStart=/nix/store/abc123-agent/bin/agent\n"));
+        assert!(script.contains("Environment=CRYSTAL_FORGE_CONFIG=/etc/crystal-forge/agent.toml"));
+        assert!(script.ends_with("systemctl restart crystal-forge-agent.service"));
+    }
+}
@@ -1,11 +1,16 @@
 use crate::config::CrystalForgeConfig;
 use crate::models::systems::DeploymentPolicy;
-use crate::queries::deployment::{get_systems_with_auto_latest_policy, update_desired_target};
+use crate::queries::deployment::{
+    deployments_paused, get_in_flight_deployments, get_systems_with_auto_latest_policy,
+    update_desired_target,
+};
 use crate::queries::derivations::get_latest_deployable_targets_for_flake_hosts;
+use crate::shutdown::{self, ShutdownRx};
 use anyhow::{Context, Result};
+use serde::Serialize;
 use sqlx::PgPool;
-use std::collections::HashMap;
-use tokio::time::{Instant, sleep};
+use std::collections::{HashMap, HashSet};
+use tokio::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 pub mod agent;
 pub use agent::*;
@@ -23,23 +28,35 @@ impl DeploymentPolicyManager {
 
     /// Main deployment policy management loop
     /// Only processes systems with auto_latest policy - manual/pinned policies don't need automatic updates
-    pub async fn run(&self) -> Result<()> {
+    pub async fn run(&self, mut shutdown: ShutdownRx) -> Result<()> {
         let interval = self.config.deployment.deployment_poll_interval;
         info!(
             "🚀 Starting deployment policy manager (poll interval: {:?})",
             interval
         );
+        if shutdown::jittered_start(self.config.startup_jitter, &mut shutdown).await {
+            return Ok(());
+        }
 
         loop {
             let start_time = Instant::now();
 
+            if self.is_paused().await {
+                warn!("⏸️ Deployments paused, not changing any desired targets");
+                if shutdown::sleep_or_shutdown(interval, &mut shutdown).await {
+                    break;
+                }
+                continue;
+            }
+
             match self.update_auto_latest_policies().await {
                 Ok(stats) => {
                     let elapsed = start_time.elapsed();
                     info!(
-                        "✅ Policy update completed: {} systems checked, {} updated ({:.2}s)",
+                        "✅ Policy update completed: {} systems checked, {} updated, {} deferred ({:.2}s)",
                         stats.systems_checked,
                         stats.systems_updated,
+                        stats.systems_deferred,
                         elapsed.as_secs_f64()
                     );
                 }
@@ -48,13 +65,75 @@ impl DeploymentPolicyManager {
                 }
             }
 
-            sleep(interval).await;
+            if shutdown::sleep_or_shutdown(interval, &mut shutdown).await {
+                break;
+            }
         }
+
+        info!("🛑 Deployment policy manager stopped");
+        Ok(())
+    }
+
+    /// The desired_target changes the next auto_latest pass would make,
+    /// without writing any of them
+    pub async fn preview(&self) -> Result<Vec<PlannedChange>> {
+        let (changes, _) = self.plan_auto_latest_changes().await?;
+        Ok(changes)
     }
 
     /// Update desired_target for all systems with auto_latest policy
     async fn update_auto_latest_policies(&self) -> Result<PolicyUpdateStats> {
+        let (changes, mut stats) = self.plan_auto_latest_changes().await?;
+
+        for change in changes {
+            // The kill switch may be tripped part way through a pass
+            if self.is_paused().await {
+                warn!(
+                    "⏸️ Deployments paused, leaving {} at {:?}",
+                    change.hostname,
+                    change.current_target.as_deref()
+                );
+                stats.systems_deferred += 1;
+                continue;
+            }
+            if let Err(e) =
+                update_desired_target(&self.pool, &change.hostname, Some(&change.new_target)).await
+            {
+                error!(
+                    "Failed to set desired_target for {} -> {}: {:#}",
+                    change.hostname, change.new_target, e
+                );
+            } else {
+                info!(
+                    "📋 Updated desired target for {}: {:?} -> {}",
+                    change.hostname,
+                    change.current_target.as_deref(),
+                    change.new_target
+                );
+                stats.systems_updated += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Whether the kill switch is tripped in the config or the database.
+    /// Counts as paused when the database can't be asked.
+    async fn is_paused(&self) -> bool {
+        if self.config.deployment.paused {
+            return true;
+        }
+        deployments_paused(&self.pool).await.unwrap_or_else(|e| {
+            error!("❌ Could not check whether deployments are paused: {:#}", e);
+            true
+        })
+    }
+
+    /// Work out which auto_latest systems should move to a new target,
+    /// honouring excludes and the rollout limit
+    async fn plan_auto_latest_changes(&self) -> Result<(Vec<PlannedChange>, PolicyUpdateStats)> {
         let mut stats = PolicyUpdateStats::default();
+        let mut changes = Vec::new();
 
         // Get all systems with auto_latest policy
         let auto_latest_systems = get_systems_with_auto_latest_policy(&self.pool)
@@ -63,9 +142,25 @@ impl DeploymentPolicyManager {
 
         stats.systems_checked = auto_latest_systems.len();
 
+        let excludes = &self.config.deployment.auto_latest_excludes;
+        let (excluded, auto_latest_systems): (Vec<_>, Vec<_>) =
+            auto_latest_systems.into_iter().partition(|system| {
+                excludes
+                    .iter()
+                    .any(|pattern| glob_matches(pattern, &system.hostname))
+            });
+        if !excluded.is_empty() {
+            let hostnames: Vec<&str> = excluded.iter().map(|s| s.hostname.as_str()).collect();
+            info!(
+                "⏸️ Skipping {} excluded auto_latest systems: {}",
+                hostnames.len(),
+                hostnames.join(", ")
+            );
+        }
+
         if auto_latest_systems.is_empty() {
             debug!("No systems with auto_latest policy found");
-            return Ok(stats);
+            return Ok((changes, stats));
         }
 
         // Group systems by flake_id to batch flake queries
@@ -81,45 +176,70 @@ impl DeploymentPolicyManager {
             }
         }
 
+        let limit = self.config.deployment.max_concurrent_deployments;
+        let in_flight = if limit > 0 {
+            // A host silent for longer than a deployment may take isn't
+            // deploying anymore
+            let last_seen_within =
+                Duration::from_secs(self.config.deployment.deployment_timeout_minutes * 60);
+            get_in_flight_deployments(&self.pool, last_seen_within)
+                .await
+                .context("Failed to fetch in-flight deployments")?
+        } else {
+            Vec::new()
+        };
+        let mut rollout = RolloutLimiter::new(limit, in_flight);
+
         // Process each flake
         for (flake_id, systems) in systems_by_flake {
-            match self.update_flake_systems_to_latest(flake_id, systems).await {
-                Ok(updated_count) => {
-                    stats.systems_updated += updated_count;
-                }
+            match self
+                .plan_flake_systems_to_latest(flake_id, systems, &mut rollout)
+                .await
+            {
+                Ok(flake_changes) => changes.extend(flake_changes),
                 Err(e) => {
                     error!("Failed to update systems for flake {}: {:#}", flake_id, e);
                 }
             }
         }
 
-        Ok(stats)
+        stats.systems_deferred = rollout.deferred;
+        Ok((changes, stats))
     }
 
-    /// Update all systems using a specific flake to the latest successful derivation
-    async fn update_flake_systems_to_latest(
+    /// Plan moving all systems using a specific flake to the latest successful derivation
+    async fn plan_flake_systems_to_latest(
         &self,
         flake_id: i32,
         systems: Vec<crate::models::systems::System>,
-    ) -> Result<usize> {
+        rollout: &mut RolloutLimiter,
+    ) -> Result<Vec<PlannedChange>> {
         use std::collections::HashMap;
 
+        let mut changes = Vec::new();
         if systems.is_empty() {
-            return Ok(0);
+            return Ok(changes);
         }
 
         // Collect hostnames we’re responsible for
         let hostnames: Vec<String> = systems.iter().map(|s| s.hostname.clone()).collect();
 
-        // Fetch per-host latest deployable targets for the latest commit
+        // Fetch per-host latest deployable targets. Only targets from the
+        // latest commit are applied; older ones mean the host's newer builds
+        // haven't succeeded, which is reported instead of silently held.
         let per_host =
             get_latest_deployable_targets_for_flake_hosts(&self.pool, flake_id, &hostnames).await?;
-        let latest_by_host: HashMap<_, _> = per_host
-            .into_iter()
-            .filter_map(|h| h.store_path.map(|t| (h.hostname, t)))
-            .collect();
-
-        let mut updated_count = 0;
+        let mut latest_by_host = HashMap::new();
+        for host in per_host {
+            if host.commits_behind_head > 0 {
+                warn!(
+                    "⏳ Host {} can't advance: its newest deployable target is {} commits behind HEAD",
+                    host.hostname, host.commits_behind_head
+                );
+            } else if let Some(target) = host.store_path {
+                latest_by_host.insert(host.hostname, target);
+            }
+        }
 
         for system in systems {
             // Defensive: ensure auto-latest
@@ -151,47 +271,157 @@ impl DeploymentPolicyManager {
                 continue;
             }
 
-            if let Err(e) =
-                update_desired_target(&self.pool, &system.hostname, Some(latest_target_for_host))
-                    .await
-            {
-                error!(
-                    "Failed to set desired_target for {} -> {}: {:#}",
-                    system.hostname, latest_target_for_host, e
-                );
-            } else {
-                info!(
-                    "📋 Updated desired target for {}: {:?} -> {}",
+            if !rollout.try_start(&system.hostname) {
+                debug!(
+                    "Deferring {}: {} deployments already in flight",
                     system.hostname,
-                    system.desired_target.as_deref(),
-                    latest_target_for_host
+                    rollout.in_flight.len()
                 );
-                updated_count += 1;
+                continue;
             }
+
+            changes.push(PlannedChange {
+                new_target: latest_target_for_host.clone(),
+                hostname: system.hostname,
+                flake_id,
+                current_target: system.desired_target,
+            });
         }
 
-        Ok(updated_count)
+        Ok(changes)
+    }
+}
+
+/// Match `text` against a glob where `*` is any run of characters and `?`
+/// any single character
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
     }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// A desired_target change the auto_latest manager intends to make
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedChange {
+    pub hostname: String,
+    pub flake_id: i32,
+    pub current_target: Option<String>,
+    pub new_target: String,
 }
 
 #[derive(Default)]
 struct PolicyUpdateStats {
     systems_checked: usize,
     systems_updated: usize,
+    systems_deferred: usize,
+}
+
+/// Caps how many systems are moving to a new target at the same time so a
+/// large rollout does not have the whole fleet pulling from the cache at once
+struct RolloutLimiter {
+    /// 0 means unlimited
+    limit: usize,
+    /// Systems whose desired target differs from what they last reported
+    in_flight: HashSet<String>,
+    deferred: usize,
+}
+
+impl RolloutLimiter {
+    fn new(limit: usize, in_flight: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            limit,
+            in_flight: in_flight.into_iter().collect(),
+            deferred: 0,
+        }
+    }
+
+    /// Whether `hostname` may be given a new target now. Systems already in
+    /// flight can always be retargeted since they hold a slot anyway.
+    fn try_start(&mut self, hostname: &str) -> bool {
+        if self.limit == 0 || self.in_flight.contains(hostname) {
+            return true;
+        }
+        if self.in_flight.len() >= self.limit {
+            self.deferred += 1;
+            return false;
+        }
+        self.in_flight.insert(hostname.to_string());
+        true
+    }
 }
 
 /// Spawn the deployment policy manager as a background task
 pub async fn spawn_deployment_policy_manager(
     config: CrystalForgeConfig,
     pool: PgPool,
+    shutdown: ShutdownRx,
 ) -> Result<tokio::task::JoinHandle<()>> {
     let manager = DeploymentPolicyManager::new(config, pool);
 
     let handle = tokio::spawn(async move {
-        if let Err(e) = manager.run().await {
+        if let Err(e) = manager.run(shutdown).await {
             error!("💥 Deployment policy manager crashed: {:#}", e);
         }
     });
 
     Ok(handle)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollout_limiter_holds_back_systems_beyond_limit() {
+        let mut rollout = RolloutLimiter::new(2, vec!["web01".to_string()]);
+
+        assert!(rollout.try_start("web01"));
+        assert!(rollout.try_start("web02"));
+        assert!(!rollout.try_start("web03"));
+        assert!(!rollout.try_start("web04"));
+        // Already in flight, so no new slot is needed
+        assert!(rollout.try_start("web02"));
+        assert_eq!(rollout.deferred, 2);
+
+        let mut unlimited = RolloutLimiter::new(0, Vec::new());
+        assert!((0..10).all(|i| unlimited.try_start(&format!("host{i}"))));
+    }
+
+    #[test]
+    fn glob_matches_hostnames() {
+        assert!(glob_matches("db01", "db01"));
+        assert!(glob_matches("db*", "db01"));
+        assert!(glob_matches("*-staging", "web01-staging"));
+        assert!(glob_matches("web0?", "web07"));
+        assert!(glob_matches("*", "anything"));
+        assert!(glob_matches("a*b*c", "axxbyyc"));
+        assert!(!glob_matches("db*", "web01"));
+        assert!(!glob_matches("web0?", "web10"));
+        assert!(!glob_matches("db01", "db011"));
+    }
+}
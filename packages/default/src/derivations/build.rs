@@ -1,8 +1,13 @@
+use super::BuildCancelled;
 use super::Derivation;
+use super::HashMismatch;
+use super::phase::PhaseTracker;
+use super::progress::{self, BuildProgress};
 use super::utils::*;
 use crate::builder::get_gc_root_path;
 use crate::config::BuildConfig;
 use crate::config::CacheConfig;
+use crate::log::redact::redact;
 use anyhow::Context;
 use anyhow::{Result, anyhow, bail};
 use sqlx::PgPool;
@@ -39,9 +44,9 @@ impl Derivation {
             let mut scoped = Command::new("systemd-run");
             scoped.args(["--scope", "--collect", "--quiet"]);
             apply_systemd_props_for_scope(build_config, &mut scoped);
+            scoped.arg("--").args(build_config.priority_wrapper());
             // IMPORTANT: add-root + indirect before the drv
             scoped.args([
-                "--",
                 "nix-store",
                 "--realise",
                 "--add-root",
@@ -52,7 +57,7 @@ impl Derivation {
             scoped
         } else {
             info!("  → Using direct nix-store for {}", drv_path);
-            let mut direct = Command::new("nix-store");
+            let mut direct = build_config.prioritized_command("nix-store");
             direct.args([
                 "--realise",
                 "--add-root",
@@ -69,12 +74,17 @@ impl Derivation {
         info!("  → About to spawn command for {}", drv_path);
 
         // Try to run with systemd
-        match Self::run_streaming_build(cmd, drv_path, self.id, pool).await {
+        let status_interval = build_config.status_write_interval;
+        match Self::run_streaming_build(cmd, drv_path, self.id, pool, status_interval).await {
             Ok(output_path) => {
                 info!("✅ Build succeeded: {}", output_path);
                 Ok(output_path)
             }
-            Err(e) if build_config.should_use_systemd() && Self::is_systemd_error(&e) => {
+            Err(e)
+                if build_config.should_use_systemd()
+                    && Self::is_systemd_error(&e)
+                    && e.downcast_ref::<HashMismatch>().is_none() =>
+            {
                 warn!(
                     "⚠️  Systemd scope creation failed, falling back to direct execution: {}",
                     e
@@ -207,6 +217,7 @@ impl Derivation {
         drv_path: &str,
         derivation_id: i32,
         pool: &PgPool,
+        status_interval: Duration,
     ) -> Result<String> {
         let start_time = Instant::now();
         info!("  → Spawning build process for {}", drv_path);
@@ -234,7 +245,10 @@ impl Derivation {
         let mut stderr_reader = BufReader::new(stderr).lines();
 
         let mut heartbeat_interval = interval(Duration::from_secs(5));
+        let mut status_writes = StatusWriteLimiter::new(status_interval);
         let mut current_target: Option<String> = None;
+        let mut phases = PhaseTracker::default();
+        let mut hash_mismatch: Option<HashMismatch> = None;
 
         let pool_clone = pool.clone();
         let mut last_output = Instant::now();
@@ -246,12 +260,25 @@ impl Derivation {
                     match line_result {
                         Ok(Some(line)) => {
                             last_output = Instant::now();
+                            let line = redact(&line).into_owned();
                             info!("build stdout: {}", line);
 
-                            // Try to extract current build target from output
-                            if line.contains("building '") || line.contains("copying path '") {
+                            // Try to extract current build target and phase from output
+                            let phase_changed = phases.observe(&line, Instant::now());
+                            let new_target =
+                                line.contains("building '") || line.contains("copying path '");
+                            if new_target {
                                 current_target = Some(line.clone());
                             }
+                            if new_target || phase_changed {
+                                progress::publish(BuildProgress::running(
+                                    derivation_id,
+                                    start_time.elapsed().as_secs() as i32,
+                                    current_target.as_deref(),
+                                    0,
+                                    phases.current(),
+                                ));
+                            }
                         }
                         Ok(None) => break,
                         Err(e) => {
@@ -266,12 +293,26 @@ impl Derivation {
                     match line_result {
                         Ok(Some(line)) => {
                             last_output = Instant::now();
+                            let line = redact(&line).into_owned();
                             debug!("build stderr: {}", line);
+                            HashMismatch::observe(&mut hash_mismatch, &line);
 
-                            // Try to extract current build target from error output
-                            if line.contains("building '") || line.contains("copying path '") {
+                            // Try to extract current build target and phase from error output
+                            let phase_changed = phases.observe(&line, Instant::now());
+                            let new_target =
+                                line.contains("building '") || line.contains("copying path '");
+                            if new_target {
                                 current_target = Some(line.clone());
                             }
+                            if new_target || phase_changed {
+                                progress::publish(BuildProgress::running(
+                                    derivation_id,
+                                    start_time.elapsed().as_secs() as i32,
+                                    current_target.as_deref(),
+                                    0,
+                                    phases.current(),
+                                ));
+                            }
                         }
                         Ok(None) => {},
                         Err(e) => {
@@ -280,19 +321,49 @@ impl Derivation {
                     }
                 }
 
-                // Periodic heartbeat updates to database
+                // Periodic heartbeat updates to database, at most one per
+                // status interval with the latest progress
                 _ = heartbeat_interval.tick() => {
                     let elapsed = start_time.elapsed().as_secs() as i32;
                     let last_activity = last_output.elapsed().as_secs() as i32;
-
-                    if let Err(e) = Self::update_build_heartbeat(
+                    let running = BuildProgress::running(
+                        derivation_id,
+                        elapsed,
+                        current_target.as_deref(),
+                        last_activity,
+                        phases.current(),
+                    );
+                    // Progress is written, announced to other instances and
+                    // cancellation picked up once per status interval; this
+                    // process sees every tick
+                    let write_due = status_writes.try_acquire(Instant::now());
+                    if write_due
+                        && let Err(e) = progress::notify(&pool_clone, &running).await
+                    {
+                        debug!("Failed to announce build progress: {}", e);
+                    }
+                    progress::publish(running);
+                    if !write_due {
+                        continue;
+                    }
+                    let cancelled = Self::update_build_heartbeat(
                         &pool_clone,
                         derivation_id,
                         elapsed,
                         current_target.as_deref(),
                         last_activity,
-                    ).await {
-                        warn!("Failed to update build heartbeat: {}", e);
+                        &phases,
+                    ).await;
+                    match cancelled {
+                        Ok(true) => {
+                            warn!("🛑 Cancellation requested for {}, killing build", drv_path);
+                            if let Err(e) = child.kill().await {
+                                error!("Failed to kill cancelled build {}: {}", drv_path, e);
+                            }
+                            return Err(BuildCancelled.into());
+                        }
+                        Ok(false) => {}
+                        Err(e) => warn!("Failed to update build heartbeat: {}", e),
                     }
                 }
             }
@@ -302,6 +373,11 @@ impl Derivation {
         let status = child.wait().await?;
 
         if !status.success() {
+            // Retrying can't fix a wrong fixed-output hash, so report it as
+            // such instead of as a generic failure
+            if let Some(mismatch) = hash_mismatch {
+                return Err(mismatch.into());
+            }
             let exit_code = status.code().unwrap_or(-1);
             bail!("Build failed for {} with exit code {}", drv_path, exit_code);
         }
@@ -311,33 +387,42 @@ impl Derivation {
         Ok(store_path)
     }
 
-    /// Update the database with build progress information
+    /// Update the database with build progress information.
+    /// Callers rate limit this through [`StatusWriteLimiter`].
+    /// Returns `true` if cancellation of the current build attempt was requested.
     async fn update_build_heartbeat(
         pool: &PgPool,
         derivation_id: i32,
         elapsed_seconds: i32,
         current_target: Option<&str>,
         last_activity_seconds: i32,
-    ) -> Result<()> {
-        sqlx::query!(
+        phases: &PhaseTracker,
+    ) -> Result<bool> {
+        let phase_seconds = serde_json::to_value(phases.seconds(Instant::now()))?;
+        let cancelled = sqlx::query_scalar::<_, bool>(
             r#"
             UPDATE derivations
             SET 
                 build_elapsed_seconds = $1,
                 build_current_target = $2,
                 build_last_activity_seconds = $3,
-                build_last_heartbeat = NOW()
+                build_last_heartbeat = NOW(),
+                build_phase = $5,
+                build_phase_seconds = $6
             WHERE id = $4
+            RETURNING COALESCE(cancellation_requested_at >= started_at, false)
             "#,
-            elapsed_seconds,
-            current_target,
-            last_activity_seconds,
-            derivation_id
         )
-        .execute(pool)
+        .bind(elapsed_seconds)
+        .bind(current_target)
+        .bind(last_activity_seconds)
+        .bind(derivation_id)
+        .bind(phases.current().map(|phase| phase.as_str()))
+        .bind(phase_seconds)
+        .fetch_optional(pool)
         .await?;
 
-        Ok(())
+        Ok(cancelled.unwrap_or(false))
     }
 
     /// Fallback: build directly without systemd isolation
@@ -352,34 +437,25 @@ impl Derivation {
             drv_path
         );
 
-        let mut cmd = Command::new("nix-store");
+        let mut cmd = build_config.prioritized_command("nix-store");
         cmd.args(["--realise", drv_path]);
         cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
 
         build_config.apply_to_command(&mut cmd);
 
-        Self::run_streaming_build(cmd, drv_path, self.id, pool).await
+        Self::run_streaming_build(
+            cmd,
+            drv_path,
+            self.id,
+            pool,
+            build_config.status_write_interval,
+        )
+        .await
     }
 
-    /// Resolve a .drv path to its output store path
+    /// Resolve a .drv path to its default output store path
     async fn resolve_store_path_from_drv(drv_path: &str) -> Result<String> {
-        let output = Command::new("nix-store")
-            .args(["--query", "--outputs", drv_path])
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("Failed to get store path for {}: {}", drv_path, stderr);
-        }
-
-        let store_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-
-        if store_path.is_empty() {
-            bail!("Got empty store path for {}", drv_path);
-        }
-
-        Ok(store_path)
+        Self::resolve_drv_to_store_path(drv_path, None).await
     }
 
     /// Check if an error is a systemd-specific error (for fallback logic)
@@ -391,3 +467,50 @@ impl Derivation {
             || error_str.contains("failed to create")
     }
 }
+
+/// Spaces out build progress writes so large worker pools don't turn every
+/// heartbeat tick into an UPDATE
+struct StatusWriteLimiter {
+    min_interval: Duration,
+    last_write: Option<Instant>,
+}
+
+impl StatusWriteLimiter {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_write: None,
+        }
+    }
+
+    /// Whether a write may happen at `now`; the first one always may
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        if let Some(last) = self.last_write
+            && now.saturating_duration_since(last) < self.min_interval
+        {
+            return false;
+        }
+        self.last_write = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_writes_are_spaced_by_the_interval() {
+        let start = Instant::now();
+        let mut limiter = StatusWriteLimiter::new(Duration::from_secs(30));
+        assert!(limiter.try_acquire(start));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(5)));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(29)));
+        assert!(limiter.try_acquire(start + Duration::from_secs(30)));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(35)));
+
+        let mut unlimited = StatusWriteLimiter::new(Duration::ZERO);
+        assert!(unlimited.try_acquire(start));
+        assert!(unlimited.try_acquire(start));
+    }
+}
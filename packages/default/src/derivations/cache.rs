@@ -1,9 +1,12 @@
 use super::Derivation;
 use super::utils::*;
-use crate::config::{BuildConfig, CacheConfig};
+use crate::config::{BuildConfig, CacheConfig, CacheDestination, CacheType};
+use crate::log::redact::redact;
 use anyhow::bail;
 use anyhow::{Context, Result};
+use serde::Serialize;
 use sqlx::PgPool;
+use std::collections::HashSet;
 use std::process::Stdio;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::time::{Duration, sleep};
@@ -100,7 +103,7 @@ impl Derivation {
         // Resolve .drv -> store path if needed
         let store_path = if path.ends_with(".drv") {
             info!("Resolving derivation path to store path: {}", path);
-            Self::resolve_drv_to_store_path(path).await?
+            Self::resolve_drv_to_store_path(path, None).await?
         } else {
             path.to_string()
         };
@@ -121,11 +124,11 @@ impl Derivation {
         if effective_command == "attic"
             && effective_args.first().map(|s| s.as_str()) == Some("push")
         {
-            let endpoint = std::env::var("ATTIC_SERVER_URL")
-                .context("ATTIC_SERVER_URL not set (e.g. http://atticCache:8080)")?;
-            let token = std::env::var("ATTIC_TOKEN")
-                .context("ATTIC_TOKEN not set (provide a token with push permission)")?;
-            let remote = std::env::var("ATTIC_REMOTE_NAME").unwrap_or_else(|_| "local".to_string());
+            let AtticCredentials {
+                remote,
+                endpoint,
+                token,
+            } = attic_credentials(cache_config)?;
 
             // Ensure remote:repo format in arg[1]
             if effective_args.len() >= 2 && !effective_args[1].contains(':') {
@@ -179,7 +182,7 @@ impl Derivation {
                         warn!(
                             "Preflight 'attic cache info {}' failed: {}",
                             &effective_args[1],
-                            String::from_utf8_lossy(&out.stderr).trim()
+                            redact(String::from_utf8_lossy(&out.stderr).trim())
                         );
                     }
                 }
@@ -207,6 +210,7 @@ impl Derivation {
                     .await
                     .context("Failed to run 'attic push'")?;
                 let stderr = String::from_utf8_lossy(&output.stderr);
+                let stderr = redact(&stderr);
                 let trimmed = stderr.trim();
 
                 // ---- If unauthorized, redo login once and retry
@@ -217,9 +221,6 @@ impl Derivation {
                     warn!("Attic push returned 401; clearing login cache and retrying once...");
                     clear_attic_logged(&remote);
 
-                    // Re-login with current env
-                    let endpoint = std::env::var("ATTIC_SERVER_URL")?;
-                    let token = std::env::var("ATTIC_TOKEN")?;
                     ensure_attic_login(&remote, &endpoint, &token).await?;
 
                     // Retry push with streaming
@@ -243,6 +244,7 @@ impl Derivation {
                 // If we get here, there was an error
                 if !output.status.success() {
                     let stderr = String::from_utf8_lossy(&output.stderr);
+                    let stderr = redact(&stderr);
                     error!("attic (direct) failed: {}", stderr.trim());
                     anyhow::bail!("attic failed (direct): {}", stderr.trim());
                 }
@@ -253,12 +255,43 @@ impl Derivation {
         }
         // --- End Attic special-case ----------------------------------------------------------
 
+        // Cachix signs server-side and authenticates with its own token, so
+        // it runs directly rather than in a systemd scope
+        if effective_command == "cachix" {
+            let mut cmd = Command::new("cachix");
+            cmd.args(&effective_args);
+            apply_cache_env_to_command(&mut cmd);
+            if let Some(token) = cachix_token(cache_config) {
+                ensure_cachix_auth(&token).await?;
+                cmd.env("CACHIX_AUTH_TOKEN", token);
+            } else {
+                warn!("No Cachix token configured and CACHIX_AUTH_TOKEN not set, pushing anyway");
+            }
+
+            info!(
+                "Pushing {} to cache... ({} {})",
+                store_path,
+                effective_command,
+                effective_args.join(" ")
+            );
+            if !run_cache_command_streaming(cmd, "cachix push").await? {
+                anyhow::bail!("cachix push to {} failed", effective_args[1]);
+            }
+
+            info!("Successfully pushed {} to cache (cachix)", store_path);
+            return Ok(());
+        }
+
         // Non-Attic tools (e.g. `nix copy --to ...`)
         if build_config.should_use_systemd() {
             let mut scoped = Command::new("systemd-run");
             scoped.args(["--scope", "--collect", "--quiet"]);
             apply_systemd_props_for_scope(build_config, &mut scoped);
             apply_cache_env(&mut scoped);
+            if let Some(sshopts) = cache_config.nix_sshopts() {
+                scoped.arg("--setenv");
+                scoped.arg(format!("NIX_SSHOPTS={sshopts}"));
+            }
             scoped
                 .arg("--")
                 .arg(&effective_command)
@@ -291,6 +324,9 @@ impl Derivation {
 
         build_config.apply_to_command(&mut cmd);
         apply_cache_env_to_command(&mut cmd);
+        if let Some(sshopts) = cache_config.nix_sshopts() {
+            cmd.env("NIX_SSHOPTS", sshopts);
+        }
 
         let success = run_cache_command_streaming(cmd, &effective_command).await?;
         if !success {
@@ -325,7 +361,7 @@ async fn run_cache_command_streaming(
             line_result = stdout_reader.next_line() => {
                 match line_result {
                     Ok(Some(line)) => {
-                        info!("cache stdout: {}", line);
+                        info!("cache stdout: {}", redact(&line));
                     }
                     Ok(None) => break,
                     Err(e) => {
@@ -338,7 +374,7 @@ async fn run_cache_command_streaming(
             line_result = stderr_reader.next_line() => {
                 match line_result {
                     Ok(Some(line)) => {
-                        debug!("cache stderr: {}", line);
+                        debug!("cache stderr: {}", redact(&line));
                     }
                     Ok(None) => {},
                     Err(e) => {
@@ -353,6 +389,388 @@ async fn run_cache_command_streaming(
     Ok(status.success())
 }
 
+/// Ask the store at `store_uri` which of `store_paths` it already holds.
+///
+/// Uses a single batched `nix path-info --json` call. Paths the store doesn't
+/// have are simply absent from the result; the caller bounds the runtime.
+pub async fn paths_present_in_store(
+    store_uri: &str,
+    store_paths: &[String],
+    cache_config: &CacheConfig,
+) -> Result<HashSet<String>> {
+    if store_paths.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let mut cmd = tokio::process::Command::new("nix");
+    cmd.args(["path-info", "--json", "--store", store_uri]);
+    cmd.args(store_paths);
+    apply_cache_env_to_command(&mut cmd);
+    if let Some(sshopts) = cache_config.nix_sshopts() {
+        cmd.env("NIX_SSHOPTS", sshopts);
+    }
+    cmd.stdin(Stdio::null());
+    cmd.kill_on_drop(true);
+
+    let output = cmd
+        .output()
+        .await
+        .context("failed to run 'nix path-info'")?;
+
+    // Missing paths make path-info exit non-zero, but the valid ones are
+    // still reported on stdout, so only give up if there is nothing to parse
+    if output.stdout.is_empty() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() && !stderr.contains("is not valid") {
+            bail!("nix path-info failed: {}", stderr.trim());
+        }
+        return Ok(HashSet::new());
+    }
+
+    parse_valid_path_info(&output.stdout)
+}
+
+/// Extract the valid paths from `nix path-info --json` output.
+///
+/// Handles both the older array form (`[{"path": ..., "valid": false}]`) and
+/// the newer object form keyed by path (`{"/nix/store/...": null}`).
+fn parse_valid_path_info(stdout: &[u8]) -> Result<HashSet<String>> {
+    let json: serde_json::Value =
+        serde_json::from_slice(stdout).context("invalid JSON from 'nix path-info'")?;
+
+    let valid = match json {
+        serde_json::Value::Array(entries) => entries
+            .into_iter()
+            .filter(|entry| entry.get("valid").and_then(|v| v.as_bool()) != Some(false))
+            .filter_map(|entry| entry.get("path")?.as_str().map(str::to_string))
+            .collect(),
+        serde_json::Value::Object(entries) => entries
+            .into_iter()
+            .filter(|(_, info)| !info.is_null())
+            .map(|(path, _)| path)
+            .collect(),
+        _ => HashSet::new(),
+    };
+
+    Ok(valid)
+}
+
+/// Result of probing a cache destination without uploading anything
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheHealth {
+    pub destination: String,
+    /// The destination answered at all
+    pub reachable: bool,
+    /// The destination accepted our credentials
+    pub authenticated: bool,
+    /// Whether `nix` accepts the configured signing key; `None` when no key
+    /// is configured (or Attic, which signs server-side)
+    pub signing_key_accepted: Option<bool>,
+    /// Public half of the signing key, to compare with the destination's
+    /// trusted keys
+    pub signing_public_key: Option<String>,
+    pub errors: Vec<String>,
+}
+
+impl CacheHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.reachable && self.authenticated && self.signing_key_accepted != Some(false)
+    }
+}
+
+/// How a failed probe of a destination should be reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProbeFailure {
+    /// The destination answered but refused our credentials
+    Unauthorized,
+    /// Anything else, most likely the destination couldn't be reached
+    Unreachable,
+}
+
+fn classify_probe_failure(stderr: &str) -> ProbeFailure {
+    const AUTH_MARKERS: &[&str] = &[
+        "Unauthorized",
+        "401",
+        "403",
+        "Forbidden",
+        "invalid token",
+        "AccessDenied",
+        "InvalidAccessKeyId",
+        "SignatureDoesNotMatch",
+        "Permission denied",
+    ];
+    if AUTH_MARKERS.iter().any(|marker| stderr.contains(marker)) {
+        ProbeFailure::Unauthorized
+    } else {
+        ProbeFailure::Unreachable
+    }
+}
+
+/// Check that the configured cache destination is reachable, accepts our
+/// credentials and that the signing key is usable, without pushing anything.
+///
+/// Attic destinations are probed with `attic cache info`, Cachix caches
+/// through the Cachix API, everything else with
+/// `nix store ping --store <dest>`.
+pub async fn verify_cache_destination(cache_config: &CacheConfig) -> Result<CacheHealth> {
+    let mut health = match cache_config.effective_cache_type() {
+        CacheType::Attic => probe_attic_cache(cache_config).await?,
+        CacheType::Cachix => probe_cachix_cache(cache_config).await?,
+        _ => {
+            let Some(destination) = cache_config.push_to.as_deref() else {
+                bail!("cache.push_to is not configured");
+            };
+            let store_uri = cache_config
+                .destination_store_uri(destination)
+                .unwrap_or_else(|| destination.to_string());
+            probe_nix_store(destination, &store_uri, cache_config).await
+        }
+    };
+
+    if let Some(key_file) = &cache_config.signing_key {
+        match signing_public_key(key_file).await {
+            Ok(public_key) => {
+                health.signing_key_accepted = Some(true);
+                health.signing_public_key = Some(public_key);
+            }
+            Err(e) => {
+                health.signing_key_accepted = Some(false);
+                health
+                    .errors
+                    .push(format!("signing key {}: {:#}", key_file, e));
+            }
+        }
+    }
+
+    Ok(health)
+}
+
+async fn probe_nix_store(
+    destination: &str,
+    store_uri: &str,
+    cache_config: &CacheConfig,
+) -> CacheHealth {
+    let mut health = CacheHealth {
+        destination: destination.to_string(),
+        ..Default::default()
+    };
+
+    let mut cmd = tokio::process::Command::new("nix");
+    cmd.args(["store", "ping", "--store", store_uri]);
+    apply_cache_env_to_command(&mut cmd);
+    if let Some(sshopts) = cache_config.nix_sshopts() {
+        cmd.env("NIX_SSHOPTS", sshopts);
+    }
+    cmd.stdin(Stdio::null());
+    cmd.kill_on_drop(true);
+
+    record_probe(&mut health, "nix store ping", cmd.output().await);
+    health
+}
+
+async fn probe_attic_cache(cache_config: &CacheConfig) -> Result<CacheHealth> {
+    let Some(cache_name) = cache_config.effective_attic_cache_name() else {
+        bail!("cache.attic_cache_name is not configured");
+    };
+    let AtticCredentials {
+        remote,
+        endpoint,
+        token,
+    } = attic_credentials(cache_config)?;
+    let cache = if cache_name.contains(':') {
+        cache_name.to_string()
+    } else {
+        format!("{}:{}", remote, cache_name)
+    };
+
+    let mut health = CacheHealth {
+        destination: format!("{} ({})", cache, endpoint),
+        ..Default::default()
+    };
+
+    // Always log in again so a token edited in config is what gets checked
+    clear_attic_logged(&remote);
+    if let Err(e) = ensure_attic_login(&remote, &endpoint, &token).await {
+        health.errors.push(format!("{:#}", e));
+        return Ok(health);
+    }
+
+    let mut cmd = tokio::process::Command::new("attic");
+    cmd.args(["cache", "info", &cache]);
+    cmd.env("HOME", "/var/lib/crystal-forge");
+    cmd.env("XDG_CONFIG_HOME", "/var/lib/crystal-forge/.config");
+    apply_cache_env_to_command(&mut cmd);
+    cmd.stdin(Stdio::null());
+    cmd.kill_on_drop(true);
+
+    record_probe(&mut health, "attic cache info", cmd.output().await);
+    Ok(health)
+}
+
+/// Cachix API describing one cache; answers 401/403 for a token that
+/// can't access it
+const CACHIX_API: &str = "https://app.cachix.org/api/v1/cache";
+
+async fn probe_cachix_cache(cache_config: &CacheConfig) -> Result<CacheHealth> {
+    let Some(cache_name) = cache_config.cachix_cache_name() else {
+        bail!("cache.push_to does not name a Cachix cache");
+    };
+    let mut health = CacheHealth {
+        destination: format!("cachix://{}", cache_name),
+        ..Default::default()
+    };
+
+    let mut request = reqwest::Client::new().get(format!("{}/{}", CACHIX_API, cache_name));
+    if let Some(token) = cachix_token(cache_config) {
+        request = request.bearer_auth(token);
+    }
+    match request.send().await {
+        Ok(resp) if resp.status().is_success() => {
+            health.reachable = true;
+            health.authenticated = true;
+        }
+        Ok(resp) => {
+            let status = resp.status();
+            health.reachable = true;
+            health
+                .errors
+                .push(format!("Cachix API answered {} for {}", status, cache_name));
+        }
+        Err(e) => health.errors.push(format!("Cachix API unreachable: {}", e)),
+    }
+    Ok(health)
+}
+
+fn record_probe(
+    health: &mut CacheHealth,
+    what: &str,
+    output: std::io::Result<std::process::Output>,
+) {
+    match output {
+        Ok(out) if out.status.success() => {
+            health.reachable = true;
+            health.authenticated = true;
+        }
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            if classify_probe_failure(&stderr) == ProbeFailure::Unauthorized {
+                health.reachable = true;
+            }
+            health
+                .errors
+                .push(format!("{} failed: {}", what, stderr.trim()));
+        }
+        Err(e) => health.errors.push(format!("failed to run {}: {}", what, e)),
+    }
+}
+
+/// Derive the public key from a secret key file, which fails if `nix`
+/// wouldn't accept the key for `nix store sign`
+async fn signing_public_key(key_file: &str) -> Result<String> {
+    let secret = tokio::fs::read(key_file)
+        .await
+        .context("failed to read key file")?;
+
+    let mut cmd = tokio::process::Command::new("nix");
+    cmd.args(["key", "convert-secret-to-public"]);
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    cmd.kill_on_drop(true);
+
+    let mut child = cmd
+        .spawn()
+        .context("failed to run 'nix key convert-secret-to-public'")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        use tokio::io::AsyncWriteExt;
+        stdin.write_all(&secret).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Remote name, server and token the Attic client logs in with
+struct AtticCredentials {
+    remote: String,
+    endpoint: String,
+    token: String,
+}
+
+/// Credentials for the destination in `cache_config.push_to`. Anything the
+/// destination doesn't set, or every field when it isn't a configured
+/// destination, comes from `ATTIC_SERVER_URL`, `ATTIC_TOKEN` and
+/// `ATTIC_REMOTE_NAME`.
+fn attic_credentials(cache_config: &CacheConfig) -> Result<AtticCredentials> {
+    resolve_attic_credentials(cache_config.destination(), |name| std::env::var(name).ok())
+}
+
+fn resolve_attic_credentials(
+    destination: Option<&CacheDestination>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<AtticCredentials> {
+    let endpoint = destination
+        .and_then(|d| d.endpoint.clone())
+        .or_else(|| env("ATTIC_SERVER_URL"))
+        .context("no Attic endpoint configured and ATTIC_SERVER_URL not set (e.g. http://atticCache:8080)")?;
+    let token = destination
+        .and_then(|d| d.resolve_token())
+        .or_else(|| env("ATTIC_TOKEN"))
+        .context("no Attic token configured and ATTIC_TOKEN not set (provide a token with push permission)")?;
+    let remote = match destination {
+        Some(d) => d.remote.clone().unwrap_or_else(|| d.name.clone()),
+        None => env("ATTIC_REMOTE_NAME").unwrap_or_else(|| DEFAULT_ATTIC_REMOTE.to_string()),
+    };
+    Ok(AtticCredentials {
+        remote,
+        endpoint,
+        token,
+    })
+}
+
+/// Cachix auth token for the destination in `cache_config.push_to`, else
+/// `CACHIX_AUTH_TOKEN`
+fn cachix_token(cache_config: &CacheConfig) -> Option<String> {
+    cache_config
+        .destination()
+        .and_then(|d| d.resolve_token())
+        .or_else(|| std::env::var("CACHIX_AUTH_TOKEN").ok())
+}
+
+/// Store `token` with `cachix authtoken` so the client is authenticated
+/// even for commands that don't see `CACHIX_AUTH_TOKEN`. Runs once per
+/// token and process.
+async fn ensure_cachix_auth(token: &str) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    if is_cachix_token_configured(token) {
+        return Ok(());
+    }
+
+    let mut cmd = tokio::process::Command::new("cachix");
+    cmd.args(["authtoken", "--stdin"]);
+    apply_cache_env_to_command(&mut cmd);
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let mut child = cmd.spawn().context("failed to run 'cachix authtoken'")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(token.as_bytes()).await?;
+    }
+    let out = child.wait_with_output().await?;
+    if !out.status.success() {
+        bail!(
+            "cachix authtoken failed: {}",
+            redact(String::from_utf8_lossy(&out.stderr).trim())
+        );
+    }
+
+    mark_cachix_token_configured(token);
+    Ok(())
+}
+
 /// Log into Attic so the remote is available to the client.
 /// Always runs *directly* and writes config under /var/lib/crystal-forge.
 async fn ensure_attic_login(remote: &str, endpoint: &str, token: &str) -> anyhow::Result<()> {
@@ -389,3 +807,80 @@ async fn ensure_attic_login(remote: &str, endpoint: &str, token: &str) -> anyhow
     mark_attic_logged(remote);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_valid_path_info_formats() {
+        let legacy = br#"[
+            {"path": "/nix/store/aaa-hello", "narSize": 1},
+            {"path": "/nix/store/bbb-missing", "valid": false}
+        ]"#;
+        let valid = parse_valid_path_info(legacy).unwrap();
+        assert!(valid.contains("/nix/store/aaa-hello"));
+        assert!(!valid.contains("/nix/store/bbb-missing"));
+
+        let keyed = br#"{
+            "/nix/store/aaa-hello": {"narSize": 1},
+            "/nix/store/bbb-missing": null
+        }"#;
+        let valid = parse_valid_path_info(keyed).unwrap();
+        assert_eq!(valid.len(), 1);
+        assert!(valid.contains("/nix/store/aaa-hello"));
+    }
+
+    #[test]
+    fn test_attic_credentials_prefer_the_destination() {
+        let env = |name: &str| match name {
+            "ATTIC_SERVER_URL" => Some("http://env-attic:8080".to_string()),
+            "ATTIC_TOKEN" => Some("env-token".to_string()),
+            _ => None,
+        };
+
+        let creds = resolve_attic_credentials(None, env).unwrap();
+        assert_eq!(creds.remote, DEFAULT_ATTIC_REMOTE);
+        assert_eq!(creds.endpoint, "http://env-attic:8080");
+        assert_eq!(creds.token, "env-token");
+
+        let destination = CacheDestination {
+            name: "attic-b".to_string(),
+            endpoint: Some("https://attic-b.example".to_string()),
+            token: Some("b-token".to_string()),
+            ..Default::default()
+        };
+        let creds = resolve_attic_credentials(Some(&destination), env).unwrap();
+        assert_eq!(creds.remote, "attic-b");
+        assert_eq!(creds.endpoint, "https://attic-b.example");
+        assert_eq!(creds.token, "b-token");
+
+        let partial = CacheDestination {
+            name: "attic-c".to_string(),
+            remote: Some("c".to_string()),
+            ..Default::default()
+        };
+        let creds = resolve_attic_credentials(Some(&partial), env).unwrap();
+        assert_eq!(creds.remote, "c");
+        assert_eq!(creds.endpoint, "http://env-attic:8080");
+        assert_eq!(creds.token, "env-token");
+
+        assert!(resolve_attic_credentials(Some(&partial), |_| None).is_err());
+    }
+
+    #[test]
+    fn test_classify_probe_failure() {
+        assert_eq!(
+            classify_probe_failure("error: HTTP error 401 (Unauthorized)"),
+            ProbeFailure::Unauthorized
+        );
+        assert_eq!(
+            classify_probe_failure("AccessDenied: Access Denied"),
+            ProbeFailure::Unauthorized
+        );
+        assert_eq!(
+            classify_probe_failure("error: cannot connect to 'cache.example': Connection refused"),
+            ProbeFailure::Unreachable
+        );
+    }
+}
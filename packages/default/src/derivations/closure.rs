@@ -0,0 +1,218 @@
+//! Closures of derivations and built store paths.
+//!
+//! The build closure of a `.drv` is found by walking its `inputDrvs` through
+//! the installed [`Evaluator`](super::Evaluator). The runtime closure of a
+//! built path comes from its store references, each mapped back to the
+//! derivation that produced it.
+
+use super::evaluator::get_evaluator;
+use crate::config::BuildConfig;
+use anyhow::{Result, bail};
+use futures::StreamExt;
+use std::collections::HashSet;
+use tokio::process::Command;
+use tracing::warn;
+
+/// Most derivations [`list_transitive_input_drvs`] will collect for one root
+pub const MAX_TRANSITIVE_INPUT_DRVS: usize = 20_000;
+
+/// Input derivations of a single `.drv`, asked of the installed [`Evaluator`]
+pub async fn list_immediate_input_drvs(
+    drv_path: &str,
+    build_config: &BuildConfig,
+) -> Result<Vec<String>> {
+    get_evaluator().list_inputs(drv_path, build_config).await
+}
+
+/// Every input derivation reachable from `drv_path` within `depth` levels,
+/// in breadth-first order and without `drv_path` itself. Each level is
+/// queried with up to `concurrency` `nix derivation show` calls at a time,
+/// and the walk stops growing once [`MAX_TRANSITIVE_INPUT_DRVS`] have been
+/// found. Inputs that cannot be shown are logged and skipped; only a failure
+/// on `drv_path` itself is an error.
+pub async fn list_transitive_input_drvs(
+    drv_path: &str,
+    depth: usize,
+    concurrency: usize,
+    build_config: &BuildConfig,
+) -> Result<Vec<String>> {
+    walk_input_drvs(
+        drv_path,
+        depth,
+        concurrency,
+        MAX_TRANSITIVE_INPUT_DRVS,
+        |path| async move { list_immediate_input_drvs(&path, build_config).await },
+    )
+    .await
+}
+
+async fn walk_input_drvs<F, Fut>(
+    root: &str,
+    depth: usize,
+    concurrency: usize,
+    max_nodes: usize,
+    list_inputs: F,
+) -> Result<Vec<String>>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<Vec<String>>>,
+{
+    let mut seen = HashSet::from([root.to_string()]);
+    let mut found = Vec::new();
+    let mut frontier = vec![root.to_string()];
+
+    for _ in 0..depth {
+        if frontier.is_empty() {
+            break;
+        }
+
+        let results = futures::stream::iter(frontier.drain(..))
+            .map(|path| {
+                let inputs = list_inputs(path.clone());
+                async move { (path, inputs.await) }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        for (path, inputs) in results {
+            let inputs = match inputs {
+                Ok(inputs) => inputs,
+                Err(e) if path == root => return Err(e),
+                Err(e) => {
+                    warn!("⚠️ Skipping inputs of {}: {}", path, e);
+                    continue;
+                }
+            };
+            for input in inputs {
+                if found.len() >= max_nodes {
+                    warn!(
+                        "⚠️ Input graph of {} exceeds {} derivations, truncating",
+                        root, max_nodes
+                    );
+                    return Ok(found);
+                }
+                // The seen set also keeps a malformed cyclic graph finite
+                if seen.insert(input.clone()) {
+                    found.push(input.clone());
+                    frontier.push(input);
+                }
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+/// `nix derivation show` calls in flight when walking a whole build closure
+const BUILD_CLOSURE_CONCURRENCY: usize = 8;
+
+/// Store paths passed to a single `nix-store --query` call
+const PATHS_PER_QUERY: usize = 256;
+
+/// Every derivation in the build closure of `derivation_path`, excluding
+/// itself
+pub async fn get_closure_derivation_paths(
+    derivation_path: &str,
+    build_config: &BuildConfig,
+) -> Result<Vec<String>> {
+    list_transitive_input_drvs(
+        derivation_path,
+        usize::MAX,
+        BUILD_CLOSURE_CONCURRENCY,
+        build_config,
+    )
+    .await
+}
+
+/// Derivations that produced the runtime closure of `store_path`: every path
+/// it references, directly or not, mapped to its deriver. Paths without a
+/// known deriver are left out, and so is the deriver of `store_path` itself.
+pub async fn runtime_closure_derivations(store_path: &str) -> Result<Vec<String>> {
+    let root = [store_path.to_string()];
+    let mut seen: HashSet<String> = nix_store_query("--deriver", &root)
+        .await?
+        .into_iter()
+        .collect();
+
+    let references: Vec<String> = nix_store_query("--requisites", &root)
+        .await?
+        .into_iter()
+        .filter(|path| path != store_path)
+        .collect();
+
+    let mut derivers = Vec::new();
+    for chunk in references.chunks(PATHS_PER_QUERY) {
+        for deriver in nix_store_query("--deriver", chunk).await? {
+            // Unknown derivers are reported as `unknown-deriver`
+            if deriver.ends_with(".drv") && seen.insert(deriver.clone()) {
+                derivers.push(deriver);
+            }
+        }
+    }
+    Ok(derivers)
+}
+
+/// Output lines of `nix-store --query <flag> <paths>`
+async fn nix_store_query(flag: &str, paths: &[String]) -> Result<Vec<String>> {
+    let output = Command::new("nix-store")
+        .args(["--query", flag])
+        .args(paths)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!(
+            "nix-store --query {} failed: {}",
+            flag,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[tokio::test]
+    async fn transitive_inputs_are_deduplicated_and_bounded() {
+        let graph: std::collections::HashMap<&str, Vec<&str>> = [
+            ("root", vec!["a", "b"]),
+            ("a", vec!["c", "b"]),
+            ("b", vec!["c"]),
+            ("c", vec!["a", "d"]),
+            ("d", vec!["e"]),
+        ]
+        .into();
+        let list = |path: String| {
+            let inputs = graph.get(path.as_str()).cloned();
+            async move {
+                inputs
+                    .map(|inputs| inputs.into_iter().map(String::from).collect())
+                    .ok_or_else(|| anyhow!("no such derivation: {}", path))
+            }
+        };
+
+        let all = walk_input_drvs("root", 10, 2, 100, list).await.unwrap();
+        let mut sorted = all.clone();
+        sorted.sort();
+        assert_eq!(sorted, ["a", "b", "c", "d", "e"]);
+
+        let shallow = walk_input_drvs("root", 2, 2, 100, list).await.unwrap();
+        assert_eq!(shallow.len(), 3);
+        assert!(shallow.contains(&"c".to_string()));
+
+        let capped = walk_input_drvs("root", 10, 2, 2, list).await.unwrap();
+        assert_eq!(capped.len(), 2);
+
+        assert!(walk_input_drvs("missing", 3, 2, 100, list).await.is_err());
+    }
+}
@@ -1,15 +1,23 @@
 use super::Derivation;
+use super::evaluator::get_evaluator;
 use crate::models::commits::Commit;
 use crate::config::BuildConfig;
-use crate::derivations::utils::get_store_path_from_drv;
+use crate::derivations::utils::{
+    build_flake_attr_target, get_store_path_from_drv, system_arch_from_output_name,
+    validate_flake_attr_path,
+};
 use crate::models::flakes::Flake;
-use crate::queries::derivations::insert_derivation_with_target;
+use crate::queries::derivations::{
+    EvaluationStatus, get_pending_flake_attr_derivations, insert_derivation_with_target,
+    set_derivation_required_labels, set_derivation_system_arch, update_derivation_status,
+};
 use anyhow::{Context, Result, anyhow, bail};
 use serde::Deserialize;
 use sqlx::PgPool;
-use std::process::Stdio;
+use std::process::{Output, Stdio};
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
+use tokio::time::{Duration, sleep, timeout};
 use tracing::{debug, error, info, warn};
 
 #[derive(Debug)]
@@ -44,9 +52,13 @@ impl Derivation {
         self.store_path = Some(resolved);
         Ok(())
     }
-    /// Resolve a .drv path to its output store path(s)
+    /// Resolve a .drv path to the store path of one of its outputs,
+    /// [`DEFAULT_OUTPUT`] unless `output_name` says otherwise
     /// TODO: Replace this everywhere its called with resolve_store_path
-    pub async fn resolve_drv_to_store_path(drv_path: &str) -> Result<String> {
+    pub async fn resolve_drv_to_store_path(
+        drv_path: &str,
+        output_name: Option<&str>,
+    ) -> Result<String> {
         if !drv_path.ends_with(".drv") {
             // Already a store path, return as-is
             return Ok(drv_path.to_string());
@@ -70,38 +82,51 @@ impl Derivation {
             anyhow::bail!("No output paths found for derivation: {}", drv_path);
         }
 
-        // Return the first output path (typically there's only one)
-        Ok(store_paths[0].to_string())
+        let wanted = output_name.unwrap_or(DEFAULT_OUTPUT);
+        match select_output(drv_path, &store_paths, wanted) {
+            Some(path) => Ok(path.to_string()),
+            // Derivations without an `out` output: keep the old behaviour
+            None if output_name.is_none() => Ok(store_paths[0].to_string()),
+            None => anyhow::bail!(
+                "{} has no output '{}' (outputs: {})",
+                drv_path,
+                wanted,
+                store_paths.join(", ")
+            ),
+        }
     }
 }
 
-/// Resolve a .drv path to its output store path(s) - static version
-pub async fn resolve_drv_to_store_path_static(drv_path: &str) -> Result<String> {
-    if !drv_path.ends_with(".drv") {
-        // Already a store path, return as-is
-        return Ok(drv_path.to_string());
-    }
-
-    let output = Command::new("nix-store")
-        .args(["--query", "--outputs", drv_path])
-        .output()
-        .await
-        .context("Failed to execute nix-store --query --outputs")?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("nix-store --query --outputs failed: {}", stderr.trim());
-    }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let store_paths: Vec<&str> = stdout.trim().lines().collect();
-
-    if store_paths.is_empty() {
-        anyhow::bail!("No output paths found for derivation: {}", drv_path);
-    }
+/// Output used when none is asked for
+pub const DEFAULT_OUTPUT: &str = "out";
+
+/// Pick the path of output `output_name` from `nix-store --query --outputs`.
+/// Nix names the `out` path after the derivation and every other output
+/// `<name>-<output>`, so the name can be matched exactly.
+fn select_output<'a>(
+    drv_path: &str,
+    store_paths: &[&'a str],
+    output_name: &str,
+) -> Option<&'a str> {
+    let store_name = |path: &str| {
+        let file = path.rsplit('/').next()?;
+        file.split_once('-').map(|(_, name)| name.to_string())
+    };
+    let drv_name = store_name(drv_path)?.strip_suffix(".drv")?.to_string();
+    let expected = if output_name == DEFAULT_OUTPUT {
+        drv_name
+    } else {
+        format!("{}-{}", drv_name, output_name)
+    };
+    store_paths
+        .iter()
+        .copied()
+        .find(|path| store_name(path).as_deref() == Some(expected.as_str()))
+}
 
-    // Return the first output path (typically there's only one)
-    Ok(store_paths[0].to_string())
+/// Resolve a .drv path to its default output store path - static version
+pub async fn resolve_drv_to_store_path_static(drv_path: &str) -> Result<String> {
+    Derivation::resolve_drv_to_store_path(drv_path, None).await
 }
 
 /// Parse derivation paths from nix build stderr output (legacy function, prefer eval_main_drv_path)
@@ -160,25 +185,70 @@ pub fn parse_derivation_paths(stderr: &str, flake_target: &str) -> Result<(Strin
     Ok((main, deps))
 }
 
+/// Why [`parse_derivation_path_verbose`] could not split a derivation name
+/// into pname and version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseIssue {
+    /// Not a `/nix/store/<hash>-<name>.drv` path
+    NotADerivation,
+    /// Nothing follows the store hash
+    NoDash,
+    /// The name has no version-looking suffix; the whole name is the pname
+    NoVersion { pname: String },
+    /// The name ends in an empty segment (e.g. `foo-`)
+    UnrecognizedPattern,
+}
+
+impl ParseIssue {
+    /// Short stable label for logs and counts
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseIssue::NotADerivation => "not-a-derivation",
+            ParseIssue::NoDash => "no-dash",
+            ParseIssue::NoVersion { .. } => "no-version",
+            ParseIssue::UnrecognizedPattern => "unrecognized-pattern",
+        }
+    }
+}
+
 /// Parse derivation path to extract package information
+///
+/// Thin wrapper over [`parse_derivation_path_verbose`] that falls back to
+/// the whole name as pname when no version can be found.
 pub fn parse_derivation_path(drv_path: &str) -> Option<PackageInfo> {
+    match parse_derivation_path_verbose(drv_path) {
+        Ok(info) => Some(info),
+        Err(ParseIssue::NoVersion { pname }) => Some(PackageInfo {
+            pname: Some(pname),
+            version: None,
+        }),
+        Err(_) => None,
+    }
+}
+
+/// Parse derivation path into pname and version, reporting why the
+/// heuristic failed instead of guessing
+pub fn parse_derivation_path_verbose(drv_path: &str) -> Result<PackageInfo, ParseIssue> {
     // Derivation paths look like: /nix/store/hash-name-version.drv
-    let filename = drv_path.split('/').last()?.strip_suffix(".drv")?;
+    let filename = drv_path
+        .split('/')
+        .next_back()
+        .and_then(|name| name.strip_suffix(".drv"))
+        .ok_or(ParseIssue::NotADerivation)?;
 
     // Split by first dash to separate hash from name-version
-    let (_, name_version) = filename.split_once('-')?;
+    let (_, name_version) = filename.split_once('-').ok_or(ParseIssue::NoDash)?;
 
     // Handle NixOS system derivations specifically
-    if name_version.starts_with("nixos-system-") {
+    if let Some(system_part) = name_version.strip_prefix("nixos-system-") {
         // Pattern: nixos-system-HOSTNAME-VERSION
         // Example: nixos-system-aws-test-25.05.20250802.b6bab62
-        let system_part = name_version.strip_prefix("nixos-system-")?;
 
         // Find the last dash to separate hostname from version
         if let Some((hostname, version)) = system_part.rsplit_once('-') {
             // Check if the last part looks like a version (contains dots or digits)
             if version.chars().any(|c| c.is_ascii_digit() || c == '.') {
-                return Some(PackageInfo {
+                return Ok(PackageInfo {
                     pname: Some(format!("nixos-system-{}", hostname)),
                     version: Some(version.to_string()),
                 });
@@ -186,9 +256,8 @@ pub fn parse_derivation_path(drv_path: &str) -> Option<PackageInfo> {
         }
 
         // If we can't parse version, use the whole system part as pname
-        return Some(PackageInfo {
-            pname: Some(format!("{}", system_part)),
-            version: None,
+        return Err(ParseIssue::NoVersion {
+            pname: system_part.to_string(),
         });
     }
 
@@ -197,8 +266,12 @@ pub fn parse_derivation_path(drv_path: &str) -> Option<PackageInfo> {
     // This is heuristic - Nix derivation naming isn't perfectly consistent
     if let Some((name, version)) = name_version.rsplit_once('-') {
         // Check if the last part looks like a version (starts with digit)
-        if version.chars().next()?.is_ascii_digit() {
-            return Some(PackageInfo {
+        let first = version
+            .chars()
+            .next()
+            .ok_or(ParseIssue::UnrecognizedPattern)?;
+        if first.is_ascii_digit() {
+            return Ok(PackageInfo {
                 pname: Some(name.to_string()),
                 version: Some(version.to_string()),
             });
@@ -206,12 +279,221 @@ pub fn parse_derivation_path(drv_path: &str) -> Option<PackageInfo> {
     }
 
     // If we can't parse version, use the whole name_version as pname
-    Some(PackageInfo {
-        pname: Some(name_version.to_string()),
-        version: None,
+    Err(ParseIssue::NoVersion {
+        pname: name_version.to_string(),
     })
 }
 
+/// Queue a flake attribute at `commit` for building.
+///
+/// The attribute is stored as a `flake_attr` derivation in DryRunPending;
+/// [`evaluate_pending_flake_attrs`] then evaluates it to its .drv so a build
+/// worker picks it up. Asking again for something already queued, built or
+/// building returns the existing row unchanged; failed attempts are queued
+/// again.
+pub async fn queue_flake_attr_build(
+    pool: &PgPool,
+    commit: &Commit,
+    flake: &Flake,
+    attr_path: &str,
+    build_config: &BuildConfig,
+) -> Result<Derivation> {
+    validate_flake_attr_path(attr_path)?;
+    let target = build_flake_attr_target(&flake.repo_url, &commit.git_commit_hash, attr_path);
+
+    let derivation = insert_derivation_with_target(
+        pool,
+        Some(commit),
+        attr_path,
+        "flake_attr",
+        Some(&target),
+        None,
+    )
+    .await?;
+    if let Some(system_arch) = system_arch_from_output_name(attr_path) {
+        set_derivation_system_arch(pool, derivation.id, &system_arch).await?;
+    }
+    let labels = build_config.required_labels(attr_path);
+    if !labels.is_empty() {
+        set_derivation_required_labels(pool, derivation.id, &labels).await?;
+    }
+
+    let failed = [
+        EvaluationStatus::DryRunFailed,
+        EvaluationStatus::BuildFailed,
+    ]
+    .iter()
+    .any(|status| status.as_id() == derivation.status_id);
+    if failed {
+        info!(
+            "🔁 Queued {} at {} again after a failed attempt",
+            attr_path, commit.git_commit_hash
+        );
+        return update_derivation_status(
+            pool,
+            derivation.id,
+            EvaluationStatus::DryRunPending,
+            None,
+            None,
+            None,
+        )
+        .await;
+    }
+
+    if derivation.status_id == EvaluationStatus::DryRunPending.as_id() {
+        info!(
+            "📥 Queued {} at {} for evaluation",
+            attr_path, commit.git_commit_hash
+        );
+    } else {
+        info!(
+            "{} at {} is already queued or built (status {})",
+            attr_path, commit.git_commit_hash, derivation.status_id
+        );
+    }
+    Ok(derivation)
+}
+
+/// Evaluate the `flake_attr` derivations [`queue_flake_attr_build`] queued,
+/// moving each to DryRunComplete with its .drv, or to DryRunFailed
+pub async fn evaluate_pending_flake_attrs(pool: &PgPool, build_config: &BuildConfig) -> Result<()> {
+    for derivation in get_pending_flake_attr_derivations(pool).await? {
+        let Some(target) = derivation.derivation_target.as_deref() else {
+            continue;
+        };
+
+        info!("🔍 Evaluating {}", target);
+        let eval = get_evaluator().eval_main_drv(target, build_config);
+        let eval_result = match timeout(Duration::from_secs(300), eval).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!(
+                "nix eval timed out for {}",
+                derivation.derivation_name
+            )),
+        };
+
+        match eval_result {
+            Ok(drv_path) => {
+                update_derivation_status(
+                    pool,
+                    derivation.id,
+                    EvaluationStatus::DryRunComplete,
+                    Some(&drv_path),
+                    None,
+                    None,
+                )
+                .await?;
+                info!(
+                    "✅ Queued {} for build: {}",
+                    derivation.derivation_name, drv_path
+                );
+            }
+            Err(e) => {
+                warn!(
+                    "❌ Failed to evaluate {}: {:#}",
+                    derivation.derivation_name, e
+                );
+                update_derivation_status(
+                    pool,
+                    derivation.id,
+                    EvaluationStatus::DryRunFailed,
+                    None,
+                    Some(&format!("{:#}", e)),
+                    None,
+                )
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Attempts made by [`retry_transient_nix`] before giving up on a transient error
+const NIX_EVAL_MAX_ATTEMPTS: u32 = 3;
+/// Delay before the first retry; doubled for each further attempt
+const NIX_EVAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Whether `nix eval` stderr points at a hiccup worth retrying (substituter
+/// or daemon connection drops, lock contention) rather than a real
+/// evaluation error.
+pub fn is_transient_nix_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    [
+        "unexpected end-of-file",
+        "temporarily unavailable",
+        "timed out waiting for lock",
+        "waiting for lock",
+        "database is locked",
+        "connection reset by peer",
+        "broken pipe",
+    ]
+    .iter()
+    .any(|pattern| stderr.contains(pattern))
+}
+
+/// Run `attempt` again with backoff while it fails with a transient error,
+/// as told by the stderr `failure_stderr` returns for a failed outcome. The
+/// outcome of the last attempt is returned either way.
+pub(crate) async fn retry_transient_nix<T, F, Fut>(
+    what: &str,
+    mut attempt: F,
+    failure_stderr: impl Fn(&T) -> Option<String>,
+) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let mut attempt_number = 1;
+    loop {
+        let outcome = attempt().await;
+        let stderr = match failure_stderr(&outcome) {
+            Some(stderr)
+                if attempt_number < NIX_EVAL_MAX_ATTEMPTS && is_transient_nix_error(&stderr) =>
+            {
+                stderr
+            }
+            _ => return outcome,
+        };
+
+        let delay = NIX_EVAL_RETRY_DELAY * 2u32.pow(attempt_number - 1);
+        warn!(
+            "⚠️ {} hit a transient error (attempt {}/{}), retrying in {:?}: {}",
+            what,
+            attempt_number,
+            NIX_EVAL_MAX_ATTEMPTS,
+            delay,
+            stderr.trim()
+        );
+        sleep(delay).await;
+        attempt_number += 1;
+    }
+}
+
+/// Run `nix <args>`, retrying with backoff while it fails with a transient
+/// error. The output of the last attempt is returned either way.
+pub(super) async fn run_nix_eval(
+    args: &[&str],
+    build_config: &BuildConfig,
+) -> std::io::Result<Output> {
+    retry_transient_nix(
+        "nix eval",
+        || async {
+            let mut cmd = Command::new("nix");
+            cmd.args(args);
+            build_config.apply_to_command(&mut cmd);
+            cmd.output().await
+        },
+        |output| match output {
+            Ok(output) if !output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stderr).into_owned())
+            }
+            _ => None,
+        },
+    )
+    .await
+}
+
 // ============================================================================
 // OPTIONAL: Add these helper functions to eval.rs if needed
 // ============================================================================
@@ -239,11 +521,7 @@ pub async fn is_cf_agent_enabled(flake_target: &str, build_config: &BuildConfig)
         flake_url, system_name
     );
 
-    let mut cmd = Command::new("nix");
-    cmd.args(["eval", "--json", "--expr", &eval_expr]);
-    build_config.apply_to_command(&mut cmd);
-
-    match cmd.output().await {
+    match run_nix_eval(&["eval", "--json", "--expr", &eval_expr], build_config).await {
         Ok(output) if output.status.success() => {
             let json_str = String::from_utf8_lossy(&output.stdout);
             match serde_json::from_str::<serde_json::Value>(&json_str) {
@@ -297,3 +575,67 @@ pub fn parse_input_drvs_from_json(json_str: &str) -> Result<Vec<String>> {
 
     Ok(deps)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_output_matches_by_name() {
+        let drv = "/nix/store/aaaa-hello-2.12.drv";
+        let outputs = [
+            "/nix/store/bbbb-hello-2.12-bin",
+            "/nix/store/cccc-hello-2.12",
+            "/nix/store/dddd-hello-2.12-dev",
+        ];
+        assert_eq!(
+            select_output(drv, &outputs, "out"),
+            Some("/nix/store/cccc-hello-2.12")
+        );
+        assert_eq!(
+            select_output(drv, &outputs, "bin"),
+            Some("/nix/store/bbbb-hello-2.12-bin")
+        );
+        assert_eq!(select_output(drv, &outputs, "man"), None);
+    }
+
+    #[test]
+    fn verbose_parse_reports_why_a_name_did_not_split() {
+        let info = parse_derivation_path_verbose("/nix/store/abc123-openssl-3.0.14.drv").unwrap();
+        assert_eq!(info.pname.as_deref(), Some("openssl"));
+        assert_eq!(info.version.as_deref(), Some("3.0.14"));
+
+        let system = parse_derivation_path_verbose(
+            "/nix/store/abc123-nixos-system-web01-25.05.20250802.b6bab62.drv",
+        )
+        .unwrap();
+        assert_eq!(system.pname.as_deref(), Some("nixos-system-web01"));
+
+        assert_eq!(
+            parse_derivation_path_verbose("/nix/store/abc123-etc.drv").unwrap_err(),
+            ParseIssue::NoVersion {
+                pname: "etc".to_string()
+            }
+        );
+        assert_eq!(
+            parse_derivation_path_verbose("/nix/store/abc123.drv").unwrap_err(),
+            ParseIssue::NoDash
+        );
+        assert_eq!(
+            parse_derivation_path_verbose("/nix/store/abc123-source").unwrap_err(),
+            ParseIssue::NotADerivation
+        );
+        assert_eq!(
+            parse_derivation_path_verbose("/nix/store/abc123-foo-.drv")
+                .unwrap_err()
+                .kind(),
+            "unrecognized-pattern"
+        );
+
+        // The wrapper keeps falling back to the whole name
+        let etc = parse_derivation_path("/nix/store/abc123-etc.drv").unwrap();
+        assert_eq!(etc.pname.as_deref(), Some("etc"));
+        assert!(etc.version.is_none());
+        assert!(parse_derivation_path("/nix/store/abc123-foo-.drv").is_none());
+    }
+}
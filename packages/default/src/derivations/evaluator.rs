@@ -0,0 +1,142 @@
+//! Evaluation backend. Everything that asks nix what a flake contains goes
+//! through an [`Evaluator`], so the `nix` CLI can be swapped for a remote
+//! evaluation service or a stub in tests.
+
+use super::eval::{parse_input_drvs_from_json, run_nix_eval};
+use crate::config::{BuildConfig, apply_flake_auth};
+use anyhow::{Context, Result, anyhow, bail};
+use futures::future::BoxFuture;
+use std::sync::{Arc, OnceLock};
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+
+/// Something that can evaluate flakes and inspect derivations
+pub trait Evaluator: Send + Sync {
+    /// `.drv` path a flake reference (`flake#attr`) evaluates to
+    fn eval_main_drv<'a>(
+        &'a self,
+        target: &'a str,
+        build_config: &'a BuildConfig,
+    ) -> BoxFuture<'a, Result<String>>;
+
+    /// Input derivations of a single `.drv`
+    fn list_inputs<'a>(
+        &'a self,
+        drv_path: &'a str,
+        build_config: &'a BuildConfig,
+    ) -> BoxFuture<'a, Result<Vec<String>>>;
+
+    /// Names of the `nixosConfigurations` a flake defines
+    fn discover_systems<'a>(&'a self, flake_uri: &'a str) -> BoxFuture<'a, Result<Vec<String>>>;
+}
+
+/// The default backend: `nix eval`, `nix derivation show` and
+/// `nix flake show` run on this host
+pub struct NixEvaluator;
+
+impl Evaluator for NixEvaluator {
+    fn eval_main_drv<'a>(
+        &'a self,
+        target: &'a str,
+        build_config: &'a BuildConfig,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let drv_attr = format!("{target}.drvPath");
+            let output = run_nix_eval(&["eval", "--raw", drv_attr.as_str()], build_config)
+                .await
+                .map_err(|e| anyhow!("failed to run nix eval: {}", e))?;
+            if !output.status.success() {
+                bail!(
+                    "nix eval failed for {}: {}",
+                    target,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+
+            let drv_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !drv_path.ends_with(".drv") {
+                bail!("{} did not evaluate to a derivation", target);
+            }
+            Ok(drv_path)
+        })
+    }
+
+    fn list_inputs<'a>(
+        &'a self,
+        drv_path: &'a str,
+        build_config: &'a BuildConfig,
+    ) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let output = run_nix_eval(&["derivation", "show", drv_path], build_config)
+                .await
+                .context("Failed to execute nix derivation show")?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                bail!("nix derivation show {} failed: {}", drv_path, stderr.trim());
+            }
+
+            parse_input_drvs_from_json(&String::from_utf8_lossy(&output.stdout))
+        })
+    }
+
+    fn discover_systems<'a>(&'a self, flake_uri: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut show = Command::new("nix");
+            show.args(["flake", "show", "--json", flake_uri]);
+            apply_flake_auth(&mut show);
+            let output = timeout(Duration::from_secs(300), show.output()).await??;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                bail!("nix flake show failed: {}", stderr.trim());
+            }
+
+            nixos_configurations(&serde_json::from_slice(&output.stdout)?)
+        })
+    }
+}
+
+/// Configuration names under `nixosConfigurations` in `nix flake show --json`
+fn nixos_configurations(flake_json: &serde_json::Value) -> Result<Vec<String>> {
+    Ok(flake_json["nixosConfigurations"]
+        .as_object()
+        .context("missing nixosConfigurations")?
+        .keys()
+        .cloned()
+        .collect())
+}
+
+static EVALUATOR: OnceLock<Arc<dyn Evaluator>> = OnceLock::new();
+
+/// The process-wide evaluator; [`NixEvaluator`] unless [`set_evaluator`]
+/// installed another one first
+pub fn get_evaluator() -> &'static Arc<dyn Evaluator> {
+    EVALUATOR.get_or_init(|| Arc::new(NixEvaluator))
+}
+
+/// Install the evaluator used by the free evaluation functions. Fails once
+/// an evaluator is in use, so call it at startup.
+pub fn set_evaluator(evaluator: Arc<dyn Evaluator>) -> Result<()> {
+    EVALUATOR
+        .set(evaluator)
+        .map_err(|_| anyhow!("an evaluator is already installed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nixos_configurations_come_from_flake_show() {
+        let show = serde_json::json!({
+            "nixosConfigurations": { "web01": { "type": "nixos-configuration" }, "db01": {} },
+            "packages": { "x86_64-linux": { "hello": {} } }
+        });
+        let mut systems = nixos_configurations(&show).unwrap();
+        systems.sort();
+        assert_eq!(systems, ["db01", "web01"]);
+
+        assert!(nixos_configurations(&serde_json::json!({ "packages": {} })).is_err());
+    }
+}
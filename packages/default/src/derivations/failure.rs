@@ -0,0 +1,258 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Coarse classification of why a build failed, derived from the error text
+/// so failures can be aggregated (see `queries::derivations::failure_breakdown`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildFailureKind {
+    Network,
+    HashMismatch,
+    OutOfDiskSpace,
+    Timeout,
+    EvalError,
+    /// Killed on request, see [`BuildCancelled`]
+    Cancelled,
+    Unknown,
+}
+
+impl BuildFailureKind {
+    /// Classify a failure by scanning the error message / captured stderr.
+    /// More specific causes are checked first; anything unrecognised is `Unknown`.
+    pub fn classify(message: &str) -> Self {
+        let msg = message.to_lowercase();
+        let has = |needles: &[&str]| needles.iter().any(|n| msg.contains(n));
+
+        if has(&["hash mismatch", "got:    sha256", "specified: sha256"]) {
+            BuildFailureKind::HashMismatch
+        } else if has(&["no space left on device", "disk quota exceeded"]) {
+            BuildFailureKind::OutOfDiskSpace
+        } else if has(&[
+            "build timed out",
+            "timed out after",
+            "exceeded the maximum allowed time",
+            "silent for",
+        ]) {
+            BuildFailureKind::Timeout
+        } else if has(&[
+            "unable to download",
+            "could not resolve host",
+            "couldn't resolve host",
+            "name or service not known",
+            "temporary failure in name resolution",
+            "connection refused",
+            "connection reset",
+            "network is unreachable",
+            "no route to host",
+            "ssl connect error",
+            "http error",
+        ]) {
+            BuildFailureKind::Network
+        } else if has(&[
+            "evaluation aborted",
+            "undefined variable",
+            "infinite recursion",
+            "while evaluating",
+            "does not provide attribute",
+            "attribute '",
+            "syntax error",
+        ]) {
+            BuildFailureKind::EvalError
+        } else {
+            BuildFailureKind::Unknown
+        }
+    }
+
+    /// Whether building again can succeed. A hash mismatch fails the same
+    /// way every time until the fixed-output hash is fixed, and a cancelled
+    /// build must not come back on its own.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            BuildFailureKind::HashMismatch | BuildFailureKind::Cancelled
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildFailureKind::Network => "network",
+            BuildFailureKind::HashMismatch => "hash_mismatch",
+            BuildFailureKind::OutOfDiskSpace => "out_of_disk_space",
+            BuildFailureKind::Timeout => "timeout",
+            BuildFailureKind::EvalError => "eval_error",
+            BuildFailureKind::Cancelled => "cancelled",
+            BuildFailureKind::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for BuildFailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for BuildFailureKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "network" => Ok(BuildFailureKind::Network),
+            "hash_mismatch" => Ok(BuildFailureKind::HashMismatch),
+            "out_of_disk_space" => Ok(BuildFailureKind::OutOfDiskSpace),
+            "timeout" => Ok(BuildFailureKind::Timeout),
+            "eval_error" => Ok(BuildFailureKind::EvalError),
+            "cancelled" => Ok(BuildFailureKind::Cancelled),
+            "unknown" => Ok(BuildFailureKind::Unknown),
+            _ => Err(anyhow::anyhow!("Invalid build failure kind: {}", s)),
+        }
+    }
+}
+
+/// A fixed-output derivation whose fetched content didn't match its
+/// declared hash, as reported in nix build output
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashMismatch {
+    /// The fixed-output `.drv` that failed
+    pub derivation: String,
+    /// Hash the derivation declares
+    pub specified: Option<String>,
+    /// Hash of what was actually fetched
+    pub got: Option<String>,
+}
+
+impl HashMismatch {
+    /// Feed one line of build stderr. Nix reports the mismatch on one line
+    /// and the `specified:` and `got:` hashes on the lines after it.
+    pub fn observe(current: &mut Option<Self>, line: &str) {
+        let line = line.trim();
+        if let Some((_, rest)) = line.split_once("hash mismatch in fixed-output derivation") {
+            *current = Some(Self {
+                derivation: rest
+                    .trim()
+                    .trim_end_matches(':')
+                    .trim_matches('\'')
+                    .to_string(),
+                ..Default::default()
+            });
+            return;
+        }
+
+        let Some(mismatch) = current.as_mut() else {
+            return;
+        };
+        if let Some(hash) = line
+            .strip_prefix("specified:")
+            .or_else(|| line.strip_prefix("wanted:"))
+        {
+            mismatch.specified = Some(hash.trim().to_string());
+        } else if let Some(hash) = line.strip_prefix("got:") {
+            mismatch.got = Some(hash.trim().to_string());
+        }
+    }
+}
+
+impl fmt::Display for HashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hash mismatch in fixed-output derivation '{}': specified {}, got {}",
+            self.derivation,
+            self.specified.as_deref().unwrap_or("unknown"),
+            self.got.as_deref().unwrap_or("unknown")
+        )
+    }
+}
+
+impl std::error::Error for HashMismatch {}
+
+/// A build that was killed because its cancellation was requested
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuildCancelled;
+
+impl fmt::Display for BuildCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl std::error::Error for BuildCancelled {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_known_failures() {
+        assert_eq!(
+            BuildFailureKind::classify(
+                "error: hash mismatch in fixed-output derivation '/nix/store/abc-src.drv'"
+            ),
+            BuildFailureKind::HashMismatch
+        );
+        assert_eq!(
+            BuildFailureKind::classify("error: writing to file: No space left on device"),
+            BuildFailureKind::OutOfDiskSpace
+        );
+        assert_eq!(
+            BuildFailureKind::classify("Build timed out after 7200.0s (limit: 7200.0s)"),
+            BuildFailureKind::Timeout
+        );
+        assert_eq!(
+            BuildFailureKind::classify(
+                "error: unable to download 'https://example.org/x.tar.gz': Couldn't resolve host name"
+            ),
+            BuildFailureKind::Network
+        );
+        assert_eq!(
+            BuildFailureKind::classify("error: undefined variable 'pkgs'"),
+            BuildFailureKind::EvalError
+        );
+        assert_eq!(
+            BuildFailureKind::classify("builder for '/nix/store/x.drv' failed with exit code 2"),
+            BuildFailureKind::Unknown
+        );
+    }
+
+    #[test]
+    fn test_hash_mismatch_from_build_output() {
+        let mut mismatch = None;
+        for line in [
+            "building '/nix/store/abc-src.drv'...",
+            "error: hash mismatch in fixed-output derivation '/nix/store/abc-src.drv':",
+            "         specified: sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+            "            got:    sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB=",
+            "error: 1 dependencies of derivation '/nix/store/def-app.drv' failed to build",
+        ] {
+            HashMismatch::observe(&mut mismatch, line);
+        }
+
+        let mismatch = mismatch.unwrap();
+        assert_eq!(mismatch.derivation, "/nix/store/abc-src.drv");
+        assert_eq!(
+            mismatch.got.as_deref(),
+            Some("sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB=")
+        );
+
+        let message = mismatch.to_string();
+        assert!(message.contains("specified sha256-AAAA"));
+        let kind = BuildFailureKind::classify(&message);
+        assert_eq!(kind, BuildFailureKind::HashMismatch);
+        assert!(!kind.is_retryable());
+    }
+
+    #[test]
+    fn test_failure_kind_round_trip() {
+        for kind in [
+            BuildFailureKind::Network,
+            BuildFailureKind::HashMismatch,
+            BuildFailureKind::OutOfDiskSpace,
+            BuildFailureKind::Timeout,
+            BuildFailureKind::EvalError,
+            BuildFailureKind::Cancelled,
+            BuildFailureKind::Unknown,
+        ] {
+            assert_eq!(kind.as_str().parse::<BuildFailureKind>().unwrap(), kind);
+        }
+    }
+}
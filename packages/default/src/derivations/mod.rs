@@ -6,12 +6,20 @@ use tracing::error;
 // Core model definitions
 pub mod build;
 pub mod cache;
+pub mod closure;
 pub mod eval;
+pub mod evaluator;
+pub mod failure;
+pub mod phase;
+pub mod progress;
 pub mod utils;
 
 // Re-export everything for backward compatibility
 pub use build::*;
+pub use closure::*;
 pub use eval::*;
+pub use evaluator::{Evaluator, NixEvaluator, get_evaluator, set_evaluator};
+pub use failure::{BuildCancelled, BuildFailureKind, HashMismatch};
 pub use utils::*;
 
 #[derive(Debug, FromRow, Serialize, Deserialize)]
@@ -53,6 +61,10 @@ pub enum DerivationType {
     NixOS,
     #[sqlx(rename = "package")]
     Package,
+    /// Any other flake output (packages, checks, devShells, ...), addressed by
+    /// the attribute path in `derivation_name`
+    #[sqlx(rename = "flake_attr")]
+    FlakeAttr,
 }
 
 // Status information from the derivation_statuses table
@@ -72,6 +84,7 @@ impl From<String> for DerivationType {
         match s.as_str() {
             "nixos" => DerivationType::NixOS,
             "package" => DerivationType::Package,
+            "flake_attr" => DerivationType::FlakeAttr,
             _ => {
                 // Log the error but provide a default instead of panicking
                 error!(
@@ -89,6 +102,7 @@ impl ToString for DerivationType {
         match self {
             DerivationType::NixOS => "nixos".into(),
             DerivationType::Package => "package".into(),
+            DerivationType::FlakeAttr => "flake_attr".into(),
         }
     }
 }
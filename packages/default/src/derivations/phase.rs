@@ -0,0 +1,194 @@
+//! Phases of a running build, recognised from nix and stdenv log output.
+//!
+//! A build of a closure runs many derivations one after another; time spent
+//! in each phase is summed over all of them, so a build that sits in
+//! `download` for most of its run points at a slow or unreachable
+//! substituter rather than a slow compile.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokio::time::{Duration, Instant};
+
+/// Coarse phase of a build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildPhase {
+    /// Fetching substitutes from a binary cache
+    Download,
+    Unpack,
+    Configure,
+    /// Includes running tests
+    Build,
+    Install,
+    /// Fixup and post-install checks
+    Fixup,
+}
+
+impl BuildPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Download => "download",
+            Self::Unpack => "unpack",
+            Self::Configure => "configure",
+            Self::Build => "build",
+            Self::Install => "install",
+            Self::Fixup => "fixup",
+        }
+    }
+
+    /// Phase that `line` of build output announces, if any. Understands
+    /// `Running phase: <name>` from current stdenv, the older stdenv messages
+    /// (`unpacking sources`, `configuring`, ...) and nix's substitution
+    /// messages. Lines may carry a `<drv name>> ` prefix as `nix build -L`
+    /// prints them.
+    pub fn from_line(line: &str) -> Option<Self> {
+        let line = strip_log_prefix(line.trim());
+
+        if let Some(name) = line.strip_prefix("Running phase: ") {
+            return Self::from_stdenv_phase(name.trim());
+        }
+        if (line.starts_with("copying path '") && line.contains("' from '"))
+            || line.contains(" will be fetched")
+        {
+            return Some(Self::Download);
+        }
+
+        match line {
+            "unpacking sources" | "patching sources" => Some(Self::Unpack),
+            "configuring" => Some(Self::Configure),
+            "building" | "running tests" => Some(Self::Build),
+            "installing" => Some(Self::Install),
+            "post-installation fixup" => Some(Self::Fixup),
+            _ => None,
+        }
+    }
+
+    fn from_stdenv_phase(name: &str) -> Option<Self> {
+        match name {
+            "unpackPhase" | "patchPhase" => Some(Self::Unpack),
+            "updateAutotoolsGnuConfigScriptsPhase" | "configurePhase" => Some(Self::Configure),
+            "buildPhase" | "checkPhase" => Some(Self::Build),
+            "installPhase" => Some(Self::Install),
+            "fixupPhase" | "installCheckPhase" | "distPhase" => Some(Self::Fixup),
+            _ => None,
+        }
+    }
+}
+
+/// `nix build -L` prefixes builder output with the derivation name
+fn strip_log_prefix(line: &str) -> &str {
+    match line.split_once("> ") {
+        Some((name, rest)) if !name.is_empty() && !name.contains(' ') => rest,
+        _ => line,
+    }
+}
+
+/// Whether `line` is nix starting the build of another derivation
+fn starts_derivation(line: &str) -> bool {
+    line.trim_start().starts_with("building '")
+}
+
+/// Follows the phase of one build and how long it spent in each
+#[derive(Debug, Default)]
+pub struct PhaseTracker {
+    current: Option<(BuildPhase, Instant)>,
+    finished: BTreeMap<BuildPhase, Duration>,
+}
+
+impl PhaseTracker {
+    /// Follow a line of build output seen at `now`. Returns `true` when the
+    /// current phase changed. Starting another derivation ends the current
+    /// phase without starting a new one.
+    pub fn observe(&mut self, line: &str, now: Instant) -> bool {
+        let phase = BuildPhase::from_line(line);
+        if phase.is_none() && !starts_derivation(line) {
+            return false;
+        }
+        if phase == self.current() {
+            return false;
+        }
+
+        if let Some((previous, started)) = self.current.take() {
+            *self.finished.entry(previous).or_default() += now.duration_since(started);
+        }
+        self.current = phase.map(|phase| (phase, now));
+        true
+    }
+
+    /// Phase the build is in right now
+    pub fn current(&self) -> Option<BuildPhase> {
+        self.current.map(|(phase, _)| phase)
+    }
+
+    /// Whole seconds spent in each phase so far, the current one included
+    pub fn seconds(&self, now: Instant) -> BTreeMap<BuildPhase, u64> {
+        let mut totals = self.finished.clone();
+        if let Some((phase, started)) = self.current {
+            *totals.entry(phase).or_default() += now.duration_since(started);
+        }
+        totals
+            .into_iter()
+            .map(|(phase, duration)| (phase, duration.as_secs()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_stdenv_and_substitution_lines() {
+        assert_eq!(
+            BuildPhase::from_line("Running phase: unpackPhase"),
+            Some(BuildPhase::Unpack)
+        );
+        assert_eq!(
+            BuildPhase::from_line("hello> Running phase: checkPhase"),
+            Some(BuildPhase::Build)
+        );
+        assert_eq!(
+            BuildPhase::from_line("post-installation fixup"),
+            Some(BuildPhase::Fixup)
+        );
+        assert_eq!(
+            BuildPhase::from_line(
+                "copying path '/nix/store/abc-glibc-2.39' from 'https://cache.nixos.org'..."
+            ),
+            Some(BuildPhase::Download)
+        );
+        assert_eq!(BuildPhase::from_line("Running phase: shellHook"), None);
+        assert_eq!(
+            BuildPhase::from_line("building '/nix/store/abc.drv'..."),
+            None
+        );
+        assert_eq!(BuildPhase::from_line("gcc -O2 -c main.c"), None);
+    }
+
+    #[test]
+    fn tracker_sums_time_per_phase_across_derivations() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut tracker = PhaseTracker::default();
+
+        assert!(tracker.observe("these 3 paths will be fetched (1.2 MiB download)", at(0)));
+        assert!(!tracker.observe("copying path '/nix/store/a' from 'https://c'...", at(4)));
+        assert!(tracker.observe("building '/nix/store/x.drv'...", at(10)));
+        assert_eq!(tracker.current(), None);
+        assert!(tracker.observe("Running phase: buildPhase", at(12)));
+        assert!(!tracker.observe("make[1]: Entering directory", at(20)));
+        assert!(tracker.observe("building '/nix/store/y.drv'...", at(40)));
+        assert!(tracker.observe("Running phase: buildPhase", at(41)));
+        assert!(tracker.observe("Running phase: installPhase", at(51)));
+
+        let seconds = tracker.seconds(at(53));
+        assert_eq!(tracker.current(), Some(BuildPhase::Install));
+        assert_eq!(seconds[&BuildPhase::Download], 10);
+        assert_eq!(seconds[&BuildPhase::Build], 38);
+        assert_eq!(seconds[&BuildPhase::Install], 2);
+        assert_eq!(
+            serde_json::to_value(&seconds).unwrap(),
+            serde_json::json!({ "download": 10, "build": 38, "install": 2 })
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Live progress of builds, for streaming to dashboards.
+//!
+//! Builds running in-process publish progress to a broadcast channel on
+//! every heartbeat. Builders also announce it through a Postgres
+//! notification, at most once per status write interval, which
+//! [`forward_notifications`] relays to the channel of the server process. The progress columns the builder writes to the
+//! database remain a fallback, which [`ProgressTracker`] merges in without
+//! letting them overwrite fresher live updates.
+
+use super::phase::BuildPhase;
+use crate::queries::derivations::EvaluationStatus;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// Updates buffered per subscriber; a slow subscriber skips older ones
+const PROGRESS_CHANNEL_CAPACITY: usize = 256;
+
+/// Postgres notification channel builders announce progress on
+const NOTIFY_CHANNEL: &str = "build_progress";
+
+/// Longest current target sent in a notification, well below the 8000 byte
+/// payload limit
+const MAX_NOTIFY_TARGET_LEN: usize = 1024;
+
+/// Where a build currently is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BuildProgress {
+    pub derivation_id: i32,
+    pub status_id: i32,
+    pub status: String,
+    pub elapsed_seconds: Option<i32>,
+    pub current_target: Option<String>,
+    pub last_activity_seconds: Option<i32>,
+    /// [`BuildPhase`] the build is in, e.g. `download` or `build`
+    pub phase: Option<String>,
+}
+
+impl BuildProgress {
+    /// Progress of a build running in this process
+    pub fn running(
+        derivation_id: i32,
+        elapsed_seconds: i32,
+        current_target: Option<&str>,
+        last_activity_seconds: i32,
+        phase: Option<BuildPhase>,
+    ) -> Self {
+        let status = EvaluationStatus::BuildInProgress;
+        Self {
+            derivation_id,
+            status_id: status.as_id(),
+            status: status.db_name().to_string(),
+            elapsed_seconds: Some(elapsed_seconds),
+            current_target: current_target.map(str::to_string),
+            last_activity_seconds: Some(last_activity_seconds),
+            phase: phase.map(|phase| phase.as_str().to_string()),
+        }
+    }
+
+    /// Whether the build is queued or running, i.e. more progress can follow
+    pub fn is_active(&self) -> bool {
+        [
+            EvaluationStatus::DryRunComplete,
+            EvaluationStatus::BuildPending,
+            EvaluationStatus::BuildInProgress,
+        ]
+        .iter()
+        .any(|status| status.as_id() == self.status_id)
+    }
+}
+
+static PROGRESS: OnceLock<broadcast::Sender<BuildProgress>> = OnceLock::new();
+
+fn sender() -> &'static broadcast::Sender<BuildProgress> {
+    PROGRESS.get_or_init(|| broadcast::channel(PROGRESS_CHANNEL_CAPACITY).0)
+}
+
+/// Announce progress of a build running in this process
+pub fn publish(progress: BuildProgress) {
+    // No subscribers is fine, nobody is watching
+    let _ = sender().send(progress);
+}
+
+/// Receive progress of every build running in this process, and of builds
+/// elsewhere once [`forward_notifications`] runs
+pub fn subscribe() -> broadcast::Receiver<BuildProgress> {
+    sender().subscribe()
+}
+
+/// Announce progress of a build to every process listening on the database
+pub async fn notify(pool: &PgPool, progress: &BuildProgress) -> Result<()> {
+    let mut progress = progress.clone();
+    if let Some(target) = progress.current_target.as_mut()
+        && target.len() > MAX_NOTIFY_TARGET_LEN
+    {
+        let mut end = MAX_NOTIFY_TARGET_LEN;
+        while !target.is_char_boundary(end) {
+            end -= 1;
+        }
+        target.truncate(end);
+    }
+
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(NOTIFY_CHANNEL)
+        .bind(serde_json::to_string(&progress)?)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Relay progress notifications from builders to [`subscribe`]rs in this
+/// process, reconnecting whenever the listener fails
+pub async fn forward_notifications(pool: PgPool) {
+    loop {
+        if let Err(e) = listen(&pool).await {
+            warn!("⚠️ Build progress listener failed, reconnecting: {}", e);
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+async fn listen(pool: &PgPool) -> Result<()> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(NOTIFY_CHANNEL).await?;
+    loop {
+        let notification = listener.recv().await?;
+        match serde_json::from_str(notification.payload()) {
+            Ok(progress) => publish(progress),
+            Err(e) => debug!("Ignoring malformed build progress notification: {}", e),
+        }
+    }
+}
+
+/// Decides which updates of one build are worth sending to a client
+#[derive(Debug, Default)]
+pub struct ProgressTracker {
+    /// Live updates have arrived, so database rows only matter for the
+    /// final status
+    live: bool,
+    last: Option<BuildProgress>,
+}
+
+impl ProgressTracker {
+    /// The update to send, if `update` tells the client anything new.
+    /// `live` marks updates from the broadcast channel rather than the
+    /// database.
+    pub fn accept(&mut self, update: BuildProgress, live: bool) -> Option<BuildProgress> {
+        if live {
+            self.live = true;
+        } else if self.live && update.is_active() {
+            return None;
+        }
+        if self.last.as_ref() == Some(&update) {
+            return None;
+        }
+        self.last = Some(update.clone());
+        Some(update)
+    }
+
+    /// Whether the last update sent was final
+    pub fn is_finished(&self) -> bool {
+        self.last.as_ref().is_some_and(|last| !last.is_active())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_database_rows_do_not_replace_live_updates() {
+        let mut tracker = ProgressTracker::default();
+        let db_row = BuildProgress::running(1, 30, Some("building 'a'"), 2, None);
+
+        assert_eq!(tracker.accept(db_row.clone(), false), Some(db_row.clone()));
+        assert_eq!(tracker.accept(db_row.clone(), false), None);
+
+        let live = BuildProgress::running(1, 42, Some("building 'b'"), 0, Some(BuildPhase::Build));
+        assert_eq!(tracker.accept(live.clone(), true), Some(live));
+        assert_eq!(tracker.accept(db_row.clone(), false), None);
+        assert!(!tracker.is_finished());
+
+        let done = BuildProgress {
+            status_id: EvaluationStatus::BuildComplete.as_id(),
+            status: EvaluationStatus::BuildComplete.db_name().to_string(),
+            ..db_row
+        };
+        assert_eq!(tracker.accept(done.clone(), false), Some(done));
+        assert!(tracker.is_finished());
+    }
+
+    #[tokio::test]
+    async fn notifications_reach_subscribers() {
+        let Some(pool) = crate::db::test_pool().await else {
+            return;
+        };
+        let mut updates = subscribe();
+        let forwarder = tokio::spawn(forward_notifications(pool.clone()));
+        let sent = BuildProgress::running(-851, 7, Some(&"x".repeat(10_000)), 1, None);
+
+        let received = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                // The listener may not be up yet, so keep announcing
+                notify(&pool, &sent).await.unwrap();
+                if let Ok(Ok(update)) =
+                    tokio::time::timeout(Duration::from_millis(200), updates.recv()).await
+                    && update.derivation_id == sent.derivation_id
+                {
+                    return update;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        forwarder.abort();
+
+        assert_eq!(received.elapsed_seconds, Some(7));
+        assert_eq!(
+            received.current_target.map(|target| target.len()),
+            Some(MAX_NOTIFY_TARGET_LEN)
+        );
+    }
+}
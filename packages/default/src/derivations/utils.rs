@@ -1,5 +1,6 @@
-use crate::config::BuildConfig;
-use anyhow::Result;
+use super::closure::get_closure_derivation_paths;
+use crate::config::{BuildConfig, SCOPE_UNSUPPORTED_PROPERTIES};
+use anyhow::{Result, bail};
 use std::collections::HashSet;
 use std::sync::{Mutex, OnceLock};
 use tokio::process::Command;
@@ -11,6 +12,7 @@ pub const CACHE_ENV_ALLOWLIST: &[&str] = &[
     "XDG_CONFIG_HOME",
     "ATTIC_SERVER_URL",
     "ATTIC_TOKEN",
+    "CACHIX_AUTH_TOKEN",
     "AWS_ACCESS_KEY_ID",
     "AWS_SECRET_ACCESS_KEY",
     "AWS_SESSION_TOKEN",
@@ -28,6 +30,9 @@ pub const CACHE_ENV_ALLOWLIST: &[&str] = &[
     "NO_PROXY",
     "no_proxy",
     "NIX_CONFIG",
+    // ssh-ng:// stores
+    "SSH_AUTH_SOCK",
+    "NIX_SSHOPTS",
 ];
 
 pub const DEFAULT_ATTIC_REMOTE: &str = "local";
@@ -50,6 +55,19 @@ pub fn clear_attic_logged(remote: &str) {
     set.lock().unwrap().remove(remote);
 }
 
+// Token last handed to `cachix authtoken` in this process
+static CACHIX_CONFIGURED_TOKEN: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+
+pub fn mark_cachix_token_configured(token: &str) {
+    let configured = CACHIX_CONFIGURED_TOKEN.get_or_init(|| Mutex::new(None));
+    *configured.lock().unwrap() = Some(token.to_string());
+}
+
+pub fn is_cachix_token_configured(token: &str) -> bool {
+    let configured = CACHIX_CONFIGURED_TOKEN.get_or_init(|| Mutex::new(None));
+    configured.lock().unwrap().as_deref() == Some(token)
+}
+
 pub fn debug_attic_environment() {
     debug!("=== Attic Environment Debug ===");
     debug!("HOME: {:?}", std::env::var("HOME"));
@@ -136,7 +154,12 @@ pub fn apply_systemd_props_for_scope(build: &BuildConfig, cmd: &mut tokio::proce
             "Device",
             "IPAccounting",
         ];
-        if OK.iter().any(|pre| p.starts_with(pre)) {
+        if SCOPE_UNSUPPORTED_PROPERTIES
+            .iter()
+            .any(|pre| p.starts_with(pre))
+        {
+            warn!("⚠️ Ignoring systemd property {} (not valid for scopes)", p);
+        } else if OK.iter().any(|pre| p.starts_with(pre)) {
             cmd.args(["--property", p]);
         }
         // intentionally ignore service-only props like Environment=, Restart=, WorkingDirectory= …
@@ -199,9 +222,12 @@ pub fn apply_cache_env(scoped: &mut Command) {
 // Flake reference building helpers
 // ============================================================================
 
-/// Build the base flake reference (git+url?rev=hash)
+/// Build the base flake reference (git+url?rev=hash). `path:` references
+/// (an `allow_dirty` working tree) are used as they are, without a rev.
 pub fn build_flake_reference(repo_url: &str, commit_hash: &str) -> String {
-    if repo_url.starts_with("git+") {
+    if repo_url.starts_with("path:") {
+        repo_url.to_string()
+    } else if repo_url.starts_with("git+") {
         if repo_url.contains("?rev=") {
             repo_url.to_string()
         } else {
@@ -222,8 +248,55 @@ pub fn build_agent_target(repo_url: &str, commit_hash: &str, system_name: &str)
 
 /// Build flake target for evaluation (nix path-info compatible)
 pub fn build_evaluation_target(repo_url: &str, commit_hash: &str, system_name: &str) -> String {
+    build_flake_attr_target(
+        repo_url,
+        commit_hash,
+        &format!("nixosConfigurations.{system_name}.config.system.build.toplevel"),
+    )
+}
+
+/// Nix systems recognised in flake output names and attribute paths
+const NIX_SYSTEMS: &[&str] = &[
+    "x86_64-linux",
+    "aarch64-linux",
+    "i686-linux",
+    "armv7l-linux",
+    "riscv64-linux",
+    "x86_64-darwin",
+    "aarch64-darwin",
+];
+
+/// Nix system a flake output builds for, judged from its name: a full system
+/// (`packages.aarch64-linux.hello`) or a bare architecture (`web01-aarch64`,
+/// taken to be Linux). `None` when the name doesn't say.
+pub fn system_arch_from_output_name(name: &str) -> Option<String> {
+    if let Some(system) = NIX_SYSTEMS.iter().find(|system| name.contains(*system)) {
+        return Some(system.to_string());
+    }
+    ["x86_64", "aarch64", "riscv64"]
+        .iter()
+        .find(|arch| name.contains(*arch))
+        .map(|arch| format!("{}-linux", arch))
+}
+
+/// Build flake target for an arbitrary attribute, e.g. `checks.x86_64-linux.integration`
+pub fn build_flake_attr_target(repo_url: &str, commit_hash: &str, attr_path: &str) -> String {
     let flake_ref = build_flake_reference(repo_url, commit_hash);
-    format!("{flake_ref}#nixosConfigurations.{system_name}.config.system.build.toplevel")
+    format!("{flake_ref}#{attr_path}")
+}
+
+/// Reject attribute paths that aren't plain dotted identifiers
+pub fn validate_flake_attr_path(attr_path: &str) -> Result<()> {
+    let valid_segment = |segment: &str| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+' | '\''))
+    };
+    if !attr_path.split('.').all(valid_segment) {
+        bail!("Invalid flake attribute path: {:?}", attr_path);
+    }
+    Ok(())
 }
 
 // ============================================================================
@@ -236,23 +309,7 @@ pub async fn get_complete_closure(
     build_config: &BuildConfig,
 ) -> Result<Vec<(String, bool)>> {
     // Return (drv_path, is_built)
-    let output = Command::new("nix")
-        .args(["path-info", "--derivation", "--recursive", derivation_path])
-        .output()
-        .await?;
-
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to get closure: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
-
-    let drv_paths: Vec<String> = String::from_utf8(output.stdout)?
-        .lines()
-        .filter(|line| !line.is_empty() && *line != derivation_path)
-        .map(|s| s.to_string())
-        .collect();
+    let drv_paths = get_closure_derivation_paths(derivation_path, build_config).await?;
 
     // Check which ones are already built in the local store
     let mut closure = Vec::new();
@@ -289,31 +346,36 @@ pub async fn get_store_path_from_drv(drv_path: &str) -> Result<String> {
     Ok(String::from_utf8(output.stdout)?.trim().to_string())
 }
 
-/// Enhanced version that gets closure with cache status in one pass
-pub async fn get_complete_closure_with_cache_status(
-    derivation_path: &str,
-    build_config: &BuildConfig,
-) -> Result<Vec<(String, String, bool)>> {
-    // Returns (drv_path, store_path, is_built)
-
-    // Get all derivations in closure
-    let output = Command::new("nix")
-        .args(["path-info", "--derivation", "--recursive", derivation_path])
+/// Whether `store_path` is one of the outputs `nix-store --query --outputs`
+/// lists for `drv_path`
+pub async fn is_output_of(drv_path: &str, store_path: &str) -> Result<bool> {
+    let output = Command::new("nix-store")
+        .args(["--query", "--outputs", drv_path])
         .output()
         .await?;
 
     if !output.status.success() {
         anyhow::bail!(
-            "Failed to get closure: {}",
-            String::from_utf8_lossy(&output.stderr)
+            "Failed to query outputs of {}: {}",
+            drv_path,
+            String::from_utf8_lossy(&output.stderr).trim()
         );
     }
 
-    let drv_paths: Vec<String> = String::from_utf8(output.stdout)?
+    Ok(String::from_utf8_lossy(&output.stdout)
         .lines()
-        .filter(|line| !line.is_empty() && *line != derivation_path)
-        .map(|s| s.to_string())
-        .collect();
+        .any(|line| line.trim() == store_path))
+}
+
+/// Enhanced version that gets closure with cache status in one pass
+pub async fn get_complete_closure_with_cache_status(
+    derivation_path: &str,
+    build_config: &BuildConfig,
+) -> Result<Vec<(String, String, bool)>> {
+    // Returns (drv_path, store_path, is_built)
+
+    // Get all derivations in closure
+    let drv_paths = get_closure_derivation_paths(derivation_path, build_config).await?;
 
     info!(
         "🔍 Checking build status for {} derivations...",
@@ -360,3 +422,215 @@ pub async fn get_store_path_and_build_status(drv_path: &str) -> Result<(String,
 
     Ok((store_path, is_built))
 }
+
+/// Read the NAR hash the local store records for `store_path`
+pub async fn get_nar_hash(store_path: &str) -> Result<String> {
+    let info = path_info_json(None, store_path, &[]).await?;
+    match nar_hash_from_path_info(&info, store_path) {
+        Some(hash) => Ok(hash),
+        None => bail!("nix path-info reported no narHash for {}", store_path),
+    }
+}
+
+/// NAR size of `store_path` in `store` (a binary cache URL), or in the local
+/// store when `store` is `None`
+pub async fn get_nar_size(store: Option<&str>, store_path: &str) -> Result<u64> {
+    let info = path_info_json(store, store_path, &[]).await?;
+    match path_info_entry(&info, store_path)
+        .and_then(|entry| entry.get("narSize"))
+        .and_then(|size| size.as_u64())
+    {
+        Some(size) => Ok(size),
+        None => bail!("nix path-info reported no narSize for {}", store_path),
+    }
+}
+
+/// Total NAR size of `store_path` and everything it references, in the
+/// local store
+pub async fn get_closure_size(store_path: &str) -> Result<u64> {
+    let info = path_info_json(None, store_path, &["--closure-size"]).await?;
+    match path_info_entry(&info, store_path)
+        .and_then(|entry| entry.get("closureSize"))
+        .and_then(|size| size.as_u64())
+    {
+        Some(size) => Ok(size),
+        None => bail!("nix path-info reported no closureSize for {}", store_path),
+    }
+}
+
+async fn path_info_json(
+    store: Option<&str>,
+    store_path: &str,
+    extra_args: &[&str],
+) -> Result<serde_json::Value> {
+    let mut cmd = Command::new("nix");
+    cmd.args(["path-info", "--json"]).args(extra_args);
+    if let Some(store) = store {
+        cmd.args(["--store", store]);
+    }
+    let output = cmd.arg(store_path).output().await?;
+
+    if !output.status.success() {
+        bail!(
+            "nix path-info failed for {}: {}",
+            store_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Find the entry for `store_path` in `nix path-info --json` output, which is
+/// an array of objects on older Nix and an object keyed by store path on newer Nix
+fn path_info_entry<'a>(
+    info: &'a serde_json::Value,
+    store_path: &str,
+) -> Option<&'a serde_json::Value> {
+    match info {
+        serde_json::Value::Array(entries) => entries
+            .iter()
+            .find(|e| e.get("path").and_then(|p| p.as_str()) == Some(store_path))
+            .or_else(|| entries.first()),
+        serde_json::Value::Object(map) => map.get(store_path),
+        _ => None,
+    }
+}
+
+fn nar_hash_from_path_info(info: &serde_json::Value, store_path: &str) -> Option<String> {
+    path_info_entry(info, store_path)?
+        .get("narHash")
+        .and_then(|h| h.as_str())
+        .map(str::to_string)
+}
+
+/// Compare two NAR hashes that may be in different notations: the older
+/// `sha256:<nix base32>` form or the SRI `sha256-<base64>` form
+pub fn nar_hashes_match(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (nar_hash_digest(a), nar_hash_digest(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Alphabet of Nix's base32 encoding (no e, o, u, t)
+const NIX32_ALPHABET: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// Raw digest bytes of a sha256 NAR hash in any notation Nix prints
+fn nar_hash_digest(hash: &str) -> Option<Vec<u8>> {
+    use base64::Engine;
+
+    if let Some(sri) = hash.strip_prefix("sha256-") {
+        return base64::engine::general_purpose::STANDARD.decode(sri).ok();
+    }
+    let encoded = hash.strip_prefix("sha256:").unwrap_or(hash);
+    if !encoded.is_ascii() {
+        return None;
+    }
+    match encoded.len() {
+        52 => nix32_decode(encoded, 32),
+        64 => (0..64)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16).ok())
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Decode Nix's base32, which emits the digest's bits least significant first
+fn nix32_decode(encoded: &str, size: usize) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; size];
+    for (n, c) in encoded.bytes().rev().enumerate() {
+        let digit = NIX32_ALPHABET.iter().position(|&a| a == c)? as u16;
+        let bit = n * 5;
+        let (i, j) = (bit / 8, bit % 8);
+        bytes[i] |= (digit << j) as u8;
+        let carry = (digit >> (8 - j)) as u8;
+        if i + 1 < size {
+            bytes[i + 1] |= carry;
+        } else if carry != 0 {
+            return None;
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nix32_encode(bytes: &[u8]) -> String {
+        let len = (bytes.len() * 8).div_ceil(5);
+        (0..len)
+            .rev()
+            .map(|n| {
+                let bit = n * 5;
+                let (i, j) = (bit / 8, bit % 8);
+                let low = (bytes[i] as u16) >> j;
+                let high = bytes.get(i + 1).map_or(0, |&b| (b as u16) << (8 - j));
+                NIX32_ALPHABET[((low | high) & 0x1f) as usize] as char
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nar_hashes_match_across_notations() {
+        use base64::Engine;
+
+        let digest: Vec<u8> = (0u8..32).map(|b| b.wrapping_mul(37)).collect();
+        let nix32 = format!("sha256:{}", nix32_encode(&digest));
+        let sri = format!(
+            "sha256-{}",
+            base64::engine::general_purpose::STANDARD.encode(&digest)
+        );
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+
+        assert!(nar_hashes_match(&nix32, &sri));
+        assert!(nar_hashes_match(&sri, &format!("sha256:{}", hex)));
+
+        let mut other = digest.clone();
+        other[0] ^= 1;
+        let other_sri = format!(
+            "sha256-{}",
+            base64::engine::general_purpose::STANDARD.encode(&other)
+        );
+        assert!(!nar_hashes_match(&nix32, &other_sri));
+    }
+
+    #[test]
+    fn nar_hash_from_both_path_info_formats() {
+        let path = "/nix/store/abc-nixos-system";
+        let old = serde_json::json!([{ "path": path, "narHash": "sha256:old" }]);
+        let new = serde_json::json!({ path: { "narHash": "sha256-new=" } });
+
+        assert_eq!(
+            nar_hash_from_path_info(&old, path).as_deref(),
+            Some("sha256:old")
+        );
+        assert_eq!(
+            nar_hash_from_path_info(&new, path).as_deref(),
+            Some("sha256-new=")
+        );
+        assert_eq!(nar_hash_from_path_info(&new, "/nix/store/other"), None);
+    }
+
+    #[test]
+    fn system_arch_comes_from_output_name() {
+        assert_eq!(
+            system_arch_from_output_name("packages.aarch64-darwin.hello").as_deref(),
+            Some("aarch64-darwin")
+        );
+        assert_eq!(
+            system_arch_from_output_name("web01-aarch64").as_deref(),
+            Some("aarch64-linux")
+        );
+        assert_eq!(
+            system_arch_from_output_name("web01_x86_64").as_deref(),
+            Some("x86_64-linux")
+        );
+        assert_eq!(system_arch_from_output_name("web01"), None);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::config;
+use crate::config::apply_flake_auth;
 use crate::models::commits::Commit;
 use crate::queries::commits::{flake_has_commits, flake_last_commit, insert_commit};
 use anyhow::{Context, Result, bail};
@@ -11,7 +12,14 @@ pub async fn fetch_and_insert_latest_commit(
     repo_url: &str,
     branch: &str,
 ) -> Result<Option<String>> {
-    let commits = get_commits_with_timestamps(repo_url, branch, Some(1), None).await?;
+    let commits = get_commits_with_timestamps(
+        repo_url,
+        branch,
+        Some(1),
+        None,
+        &config::EvalFilter::default(),
+    )
+    .await?;
 
     let (commit_hash, timestamp) = commits
         .into_iter()
@@ -27,14 +35,16 @@ pub async fn fetch_and_insert_latest_commit(
     Ok(Some(commit_hash))
 }
 
-/// Fetch up to N recent commits from a git repository and insert them into the database
+/// Fetch up to N recent commits from a git repository and insert the ones
+/// `filter` admits into the database
 pub async fn fetch_and_insert_recent_commits(
     pool: &PgPool,
     repo_url: &str,
     branch: &str,
     limit: Option<usize>,
+    filter: &config::EvalFilter,
 ) -> Result<Vec<String>> {
-    let commits = get_commits_with_timestamps(repo_url, branch, limit, None).await?;
+    let commits = get_commits_with_timestamps(repo_url, branch, limit, None, filter).await?;
 
     let mut inserted = Vec::new();
     for (hash, timestamp) in commits {
@@ -66,6 +76,15 @@ pub async fn initialize_flake_commits(
             continue;
         }
 
+        if !flake.eval_filter.may_admit(&flake.branch()) {
+            debug!(
+                "⏭️ Skipping {} (branch {} excluded by eval_filter)",
+                flake.name,
+                flake.branch()
+            );
+            continue;
+        }
+
         // Check if this flake already has commits
         match flake_has_commits(pool, &flake.repo_url).await {
             Ok(true) => {
@@ -86,6 +105,7 @@ pub async fn initialize_flake_commits(
             &flake.repo_url,
             &flake.branch(),
             Some(flake.initial_commit_depth),
+            &flake.eval_filter,
         )
         .await
         {
@@ -127,6 +147,15 @@ pub async fn sync_all_watched_flakes_commits(
             continue;
         }
 
+        if !flake.eval_filter.may_admit(&flake.branch()) {
+            debug!(
+                "⭐️ Skipping {} (branch {} excluded by eval_filter)",
+                flake.name,
+                flake.branch()
+            );
+            continue;
+        }
+
         info!("🔗 Syncing commits for flake: {}", flake.name);
 
         // Check if flake has commits first
@@ -140,6 +169,7 @@ pub async fn sync_all_watched_flakes_commits(
                             &flake.repo_url,
                             &flake.branch(),
                             &last_commit,
+                            &flake.eval_filter,
                         )
                         .await
                         {
@@ -172,6 +202,7 @@ pub async fn sync_all_watched_flakes_commits(
                     &flake.repo_url,
                     &flake.branch(),
                     Some(flake.initial_commit_depth),
+                    &flake.eval_filter,
                 )
                 .await
                 {
@@ -196,7 +227,7 @@ pub async fn sync_all_watched_flakes_commits(
     Ok(())
 }
 
-fn normalize_repo_url_for_git(repo_url: &str) -> String {
+pub(crate) fn normalize_repo_url_for_git(repo_url: &str) -> String {
     let base_url = if let Some(stripped) = repo_url.strip_prefix("git+") {
         stripped
     } else if repo_url.starts_with("github:") {
@@ -217,12 +248,94 @@ fn normalize_repo_url_for_git(repo_url: &str) -> String {
     }
 }
 
-/// Get commits with timestamps, optionally since a specific commit
+/// Fresh checkout holding some commits of one repository, fetched with a
+/// single `git fetch` so their signatures can be checked one by one
+pub struct SignatureCheckout {
+    dir: tempfile::TempDir,
+}
+
+impl SignatureCheckout {
+    /// Fetch `commit_hashes` from `repo_url` into a new checkout. Fails if
+    /// any of them could not be fetched.
+    pub async fn fetch(repo_url: &str, commit_hashes: &[&str]) -> Result<Self> {
+        let git_url = normalize_repo_url_for_git(repo_url);
+        let dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+
+        let init = tokio::process::Command::new("git")
+            .args(["init", "--quiet", "."])
+            .current_dir(dir.path())
+            .output()
+            .await
+            .context("Failed to spawn git init")?;
+        if !init.status.success() {
+            bail!(
+                "git init failed: {}",
+                String::from_utf8_lossy(&init.stderr).trim()
+            );
+        }
+
+        let mut fetch = tokio::process::Command::new("git");
+        apply_flake_auth(&mut fetch);
+        let fetch = fetch
+            .args(["fetch", "--quiet", "--depth", "1", &git_url])
+            .args(commit_hashes)
+            .current_dir(dir.path())
+            .output()
+            .await
+            .context("Failed to spawn git fetch")?;
+        if !fetch.status.success() {
+            bail!(
+                "git fetch of {} from {} failed: {}",
+                commit_hashes.join(", "),
+                repo_url,
+                String::from_utf8_lossy(&fetch.stderr).trim()
+            );
+        }
+
+        Ok(Self { dir })
+    }
+
+    /// Check the signature of a fetched commit with `git verify-commit`.
+    ///
+    /// The outer `Result` fails when the check itself could not run; the
+    /// inner one carries the reason a signature was rejected.
+    pub async fn verify(
+        &self,
+        commit_hash: &str,
+        allowed_signers_file: Option<&str>,
+    ) -> Result<std::result::Result<(), String>> {
+        let mut verify = tokio::process::Command::new("git");
+        if let Some(signers) = allowed_signers_file {
+            verify.args(["-c", &format!("gpg.ssh.allowedSignersFile={}", signers)]);
+        }
+        let verify = verify
+            .args(["verify-commit", commit_hash])
+            .current_dir(self.dir.path())
+            .output()
+            .await
+            .context("Failed to spawn git verify-commit")?;
+
+        if verify.status.success() {
+            return Ok(Ok(()));
+        }
+
+        let stderr = String::from_utf8_lossy(&verify.stderr);
+        let reason = match stderr.trim() {
+            "" => "commit is not signed".to_string(),
+            detail => detail.to_string(),
+        };
+        Ok(Err(reason))
+    }
+}
+
+/// Get commits with timestamps, optionally since a specific commit, keeping
+/// only the ones `filter` admits
 async fn get_commits_with_timestamps(
     repo_url: &str,
     branch: &str,
     limit: Option<usize>,
     since_commit: Option<&str>,
+    filter: &config::EvalFilter,
 ) -> Result<Vec<(String, chrono::DateTime<chrono::Utc>)>> {
     let git_url = normalize_repo_url_for_git(repo_url);
     let temp_dir = tempfile::tempdir().context("Failed to create temporary directory")?;
@@ -230,7 +343,9 @@ async fn get_commits_with_timestamps(
 
     // Clone
     let depth = limit.unwrap_or(10).to_string();
-    let clone_output = tokio::process::Command::new("git")
+    let mut clone = tokio::process::Command::new("git");
+    apply_flake_auth(&mut clone);
+    let clone_output = clone
         .args(&[
             "clone",
             "--depth",
@@ -250,8 +365,21 @@ async fn get_commits_with_timestamps(
         bail!("Git clone failed for {}: {}", repo_url, stderr);
     }
 
+    // Commits skipped by the filter are never inserted, so the last known
+    // commit can fall out of the shallow clone; list the latest ones instead
+    let since_commit = match since_commit {
+        Some(since) if !has_commit(clone_path, since).await => {
+            debug!(
+                "{} is outside the fetched history of {}, listing the latest commits",
+                since, repo_url
+            );
+            None
+        }
+        other => other,
+    };
+
     // Build git log args
-    let mut args = vec!["log", "--format=%H|%cI"];
+    let mut args = vec!["log", "--format=%H|%cI|%D"];
 
     // Add range if since_commit provided
     let range;
@@ -278,23 +406,83 @@ async fn get_commits_with_timestamps(
     }
 
     let stdout = String::from_utf8(log_output.stdout)?;
-    let commits: Result<Vec<_>> = stdout
-        .lines()
-        .filter(|line| !line.trim().is_empty())
-        .map(|line| {
-            let parts: Vec<&str> = line.split('|').collect();
-            if parts.len() != 2 {
-                bail!("Invalid git log format: {}", line);
-            }
-            let hash = parts[0].trim().to_string();
-            let timestamp = chrono::DateTime::parse_from_rfc3339(parts[1].trim())
-                .context("Failed to parse timestamp")?
-                .with_timezone(&chrono::Utc);
-            Ok((hash, timestamp))
+    let mut commits = Vec::new();
+    for line in stdout.lines().filter(|line| !line.trim().is_empty()) {
+        let (hash, timestamp, tags) = parse_log_line(line)?;
+        if !filter.admits_ref(branch, &tags) {
+            debug!("⏭️ Skipping {} (not on an admitted branch or tag)", hash);
+            continue;
+        }
+        if filter.filters_paths()
+            && !filter.admits_changes(&changed_files(clone_path, &hash).await?)
+        {
+            debug!("⏭️ Skipping {} (no admitted paths changed)", hash);
+            continue;
+        }
+        commits.push((hash, timestamp));
+    }
+
+    Ok(commits)
+}
+
+/// Split a `%H|%cI|%D` git log line into hash, commit time and tag names
+fn parse_log_line(line: &str) -> Result<(String, chrono::DateTime<chrono::Utc>, Vec<String>)> {
+    let parts: Vec<&str> = line.splitn(3, '|').collect();
+    if parts.len() < 2 {
+        bail!("Invalid git log format: {}", line);
+    }
+    let hash = parts[0].trim().to_string();
+    let timestamp = chrono::DateTime::parse_from_rfc3339(parts[1].trim())
+        .context("Failed to parse timestamp")?
+        .with_timezone(&chrono::Utc);
+    let tags = parts
+        .get(2)
+        .map(|refs| {
+            refs.split(", ")
+                .filter_map(|r| r.trim().strip_prefix("tag: "))
+                .map(str::to_string)
+                .collect()
         })
-        .collect();
+        .unwrap_or_default();
+    Ok((hash, timestamp, tags))
+}
 
-    commits
+/// Whether `commit` exists in the clone at `repo_path`
+async fn has_commit(repo_path: &std::path::Path, commit: &str) -> bool {
+    tokio::process::Command::new("git")
+        .args(["cat-file", "-e", &format!("{}^{{commit}}", commit)])
+        .current_dir(repo_path)
+        .output()
+        .await
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Files changed by `commit`; everything in the tree for a shallow boundary
+async fn changed_files(repo_path: &std::path::Path, commit: &str) -> Result<Vec<String>> {
+    let output = tokio::process::Command::new("git")
+        .args([
+            "diff-tree",
+            "--no-commit-id",
+            "--name-only",
+            "-r",
+            "--root",
+            commit,
+        ])
+        .current_dir(repo_path)
+        .output()
+        .await
+        .context("Failed to spawn git diff-tree")?;
+    if !output.status.success() {
+        bail!(
+            "git diff-tree {} failed: {}",
+            commit,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect())
 }
 
 /// Fetch and insert all new commits since a given commit hash
@@ -303,12 +491,14 @@ pub async fn fetch_and_insert_commits_since(
     repo_url: &str,
     branch: &str,
     since_commit: &Commit,
+    filter: &config::EvalFilter,
 ) -> Result<Vec<String>> {
     let commits = get_commits_with_timestamps(
         repo_url,
         branch,
         Some(50),
         Some(&since_commit.git_commit_hash),
+        filter,
     )
     .await?;
 
@@ -338,3 +528,73 @@ pub async fn fetch_and_insert_commits_since(
     );
     Ok(inserted)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_log_line_reads_tags_from_decorations() {
+        let (hash, timestamp, tags) =
+            parse_log_line("abc123|2025-01-02T03:04:05+00:00|HEAD -> main, tag: v1.2, tag: prod")
+                .unwrap();
+        assert_eq!(hash, "abc123");
+        assert_eq!(timestamp.to_rfc3339(), "2025-01-02T03:04:05+00:00");
+        assert_eq!(tags, ["v1.2", "prod"]);
+
+        let (_, _, tags) = parse_log_line("abc123|2025-01-02T03:04:05+00:00|").unwrap();
+        assert!(tags.is_empty());
+        assert!(parse_log_line("abc123").is_err());
+    }
+
+    /// Repository with `count` unsigned commits, and their hashes
+    fn unsigned_repo(count: usize) -> (tempfile::TempDir, Vec<String>) {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(repo.path())
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        git(&["init", "--quiet", "."]);
+        let hashes = (0..count)
+            .map(|i| {
+                git(&[
+                    "commit",
+                    "--quiet",
+                    "--allow-empty",
+                    "-m",
+                    &format!("commit {i}"),
+                ]);
+                git(&["rev-parse", "HEAD"])
+            })
+            .collect();
+        (repo, hashes)
+    }
+
+    #[tokio::test]
+    async fn unsigned_commits_are_rejected_from_one_fetch() {
+        let (repo, hashes) = unsigned_repo(2);
+        let repo_url = repo.path().to_str().unwrap();
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+
+        let checkout = SignatureCheckout::fetch(repo_url, &hashes).await.unwrap();
+        for hash in hashes {
+            let outcome = checkout.verify(hash, None).await.unwrap();
+            assert!(outcome.is_err(), "unsigned commit {hash} was accepted");
+        }
+    }
+
+    #[tokio::test]
+    async fn fetching_an_unknown_commit_is_an_error() {
+        let (repo, _) = unsigned_repo(1);
+        let missing = "0123456789abcdef0123456789abcdef01234567";
+
+        let fetched = SignatureCheckout::fetch(repo.path().to_str().unwrap(), &[missing]).await;
+        assert!(fetched.is_err());
+    }
+}
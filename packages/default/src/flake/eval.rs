@@ -1,10 +1,10 @@
+use crate::derivations::get_evaluator;
 use crate::models::commits::Commit;
 use crate::queries::commits::increment_commit_list_attempt_count;
-use anyhow::{Context, Result};
+use anyhow::Result;
 use sqlx::PgPool;
 use std::path::Path;
 use tokio::process::Command;
-use tokio::time::{Duration, timeout};
 use tracing::{debug, error};
 
 /// Returns the list of NixOS configurations defined in a flake at a given Git commit.
@@ -63,35 +63,20 @@ pub async fn list_nixos_configurations_from_commit(
         }
     }
 
-    let output = timeout(
-        Duration::from_secs(300),
-        Command::new("nix")
-            .args(["flake", "show", "--json", &flake_uri])
-            .output(),
-    )
-    .await??;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        error!("❌ nix flake show failed for {flake_uri}: {stderr}");
-        match increment_commit_list_attempt_count(&pool, &commit).await {
-            Ok(_) => tracing::debug!(
-                "✅ Incremented attempt count for commit: {}",
-                commit.git_commit_hash
-            ),
-            Err(inc_err) => tracing::error!("❌ Failed to increment attempt count: {inc_err}"),
+    let nixos_configs = match get_evaluator().discover_systems(&flake_uri).await {
+        Ok(configs) => configs,
+        Err(e) => {
+            error!("❌ Listing systems failed for {flake_uri}: {e:#}");
+            match increment_commit_list_attempt_count(&pool, &commit).await {
+                Ok(_) => tracing::debug!(
+                    "✅ Incremented attempt count for commit: {}",
+                    commit.git_commit_hash
+                ),
+                Err(inc_err) => tracing::error!("❌ Failed to increment attempt count: {inc_err}"),
+            }
+            return Err(e);
         }
-        anyhow::bail!("nix flake show failed: {}", stderr.trim());
-    }
-
-    let flake_json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
-
-    let nixos_configs = flake_json["nixosConfigurations"]
-        .as_object()
-        .context("missing nixosConfigurations")?
-        .keys()
-        .cloned()
-        .collect::<Vec<String>>();
+    };
 
     debug!("✅ nixosConfigurations: {:?}", nixos_configs);
     Ok(nixos_configs)
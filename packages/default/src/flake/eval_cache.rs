@@ -0,0 +1,143 @@
+//! Evaluation cache: commits whose flake.lock, evaluated sources and
+//! system set are identical produce the same derivations, so a commit whose
+//! inputs match an earlier fully evaluated commit copies its derivations
+//! instead of running nix-eval-jobs again.
+
+use crate::deployment::glob_matches;
+use crate::flake::commits::normalize_repo_url_for_git;
+use crate::flake::incremental::{IncrementalPlan, git};
+use crate::models::commits::Commit;
+use crate::queries::commits::get_cached_evaluation;
+use crate::queries::derivations::get_commit_system_names;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::info;
+
+/// Key identifying the evaluation inputs of `commit_hash`: the files of its
+/// tree that are not matched by `ignore` (flake.lock among them) and the
+/// names of the systems expected from it
+pub async fn evaluation_input_key(
+    repo_url: &str,
+    commit_hash: &str,
+    ignore: &[String],
+    systems: &[String],
+) -> Result<String> {
+    let git_url = normalize_repo_url_for_git(repo_url);
+    let temp_dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+    let checkout = temp_dir.path();
+
+    git(checkout, &["init", "--quiet", "."]).await?;
+    git(
+        checkout,
+        &["fetch", "--quiet", "--depth", "1", &git_url, commit_hash],
+    )
+    .await?;
+    let tree = git(checkout, &["ls-tree", "-r", "--full-tree", commit_hash]).await?;
+
+    Ok(input_key(&tree, ignore, systems))
+}
+
+/// Hash of `git ls-tree -r` output, leaving out files matched by `ignore`,
+/// together with the sorted system names
+pub fn input_key(ls_tree: &str, ignore: &[String], systems: &[String]) -> String {
+    let mut hasher = Sha256::new();
+
+    // Lines are `<mode> <type> <object>\t<path>` and already sorted by path
+    for line in ls_tree.lines() {
+        let Some((_, path)) = line.split_once('\t') else {
+            continue;
+        };
+        if ignore.iter().any(|pattern| glob_matches(pattern, path)) {
+            continue;
+        }
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    let mut systems: Vec<&str> = systems.iter().map(String::as_str).collect();
+    systems.sort_unstable();
+    systems.dedup();
+    hasher.update(b"\0");
+    hasher.update(systems.join(",").as_bytes());
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Plan copying every system of the commit cached under `input_key` to
+/// `commit`, or `None` if no other commit of the flake has that key.
+/// Systems that did not produce a derivation there are evaluated again.
+pub async fn plan_cached_evaluation(
+    pool: &PgPool,
+    commit: &Commit,
+    input_key: &str,
+) -> Result<Option<IncrementalPlan>> {
+    let Some(cached) = get_cached_evaluation(pool, commit.flake_id, input_key).await? else {
+        return Ok(None);
+    };
+    if cached.id == commit.id {
+        return Ok(None);
+    }
+
+    let systems = get_commit_system_names(pool, cached.id).await?;
+    if systems.is_empty() {
+        return Ok(None);
+    }
+    let (carry_forward, evaluate): (Vec<_>, Vec<_>) =
+        systems.into_iter().partition(|(_, has_path)| *has_path);
+    let plan = IncrementalPlan {
+        previous: cached,
+        evaluate: evaluate.into_iter().map(|(name, _)| name).collect(),
+        carry_forward: carry_forward.into_iter().map(|(name, _)| name).collect(),
+    };
+
+    info!(
+        "♻️ Evaluation inputs of {} match {}, reusing {} systems and evaluating {}",
+        commit.git_commit_hash,
+        plan.previous.git_commit_hash,
+        plan.carry_forward.len(),
+        plan.evaluate.len()
+    );
+
+    Ok(Some(plan))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    const TREE: &str = "\
+100644 blob 1111111111111111111111111111111111111111\tREADME.md
+100644 blob 2222222222222222222222222222222222222222\tflake.lock
+100644 blob 3333333333333333333333333333333333333333\tflake.nix
+100644 blob 4444444444444444444444444444444444444444\thosts/web01.nix
+";
+
+    #[test]
+    fn key_ignores_docs_but_tracks_lock_sources_and_systems() {
+        let ignore = strings(&["*.md", "docs/*"]);
+        let systems = strings(&["web01", "db01"]);
+        let key = input_key(TREE, &ignore, &systems);
+
+        let docs_only = TREE.replace("1111", "aaaa") + "100644 blob 5555\tdocs/setup.md\n";
+        assert_eq!(input_key(&docs_only, &ignore, &systems), key);
+        assert_eq!(
+            input_key(TREE, &ignore, &strings(&["db01", "web01", "web01"])),
+            key
+        );
+
+        assert_ne!(
+            input_key(&TREE.replace("2222", "bbbb"), &ignore, &systems),
+            key
+        );
+        assert_ne!(
+            input_key(&TREE.replace("4444", "dddd"), &ignore, &systems),
+            key
+        );
+        assert_ne!(input_key(TREE, &ignore, &strings(&["web01"])), key);
+    }
+}
@@ -0,0 +1,176 @@
+//! Incremental evaluation: work out which nixosConfigurations a commit can
+//! have changed so the rest can be carried over from the previous commit.
+
+use crate::config::apply_flake_auth;
+use crate::flake::commits::normalize_repo_url_for_git;
+use crate::models::commits::Commit;
+use crate::queries::commits::get_previous_evaluated_commit;
+use crate::queries::derivations::get_commit_system_names;
+use anyhow::{Context, Result, bail};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::path::Path;
+use tracing::{debug, info};
+
+/// Which systems of a commit need evaluating and which can be copied from
+/// the previous evaluated commit
+#[derive(Debug)]
+pub struct IncrementalPlan {
+    pub previous: Commit,
+    pub evaluate: Vec<String>,
+    pub carry_forward: Vec<String>,
+}
+
+/// Plan an incremental evaluation of `commit`, or `None` when a full
+/// evaluation is required (no previous evaluated commit, or a change that
+/// cannot be attributed to individual systems).
+pub async fn plan_incremental_evaluation(
+    pool: &PgPool,
+    commit: &Commit,
+    repo_url: &str,
+) -> Result<Option<IncrementalPlan>> {
+    let Some(previous) = get_previous_evaluated_commit(pool, commit).await? else {
+        debug!(
+            "No previously evaluated commit before {}, evaluating everything",
+            commit.git_commit_hash
+        );
+        return Ok(None);
+    };
+
+    let systems = get_commit_system_names(pool, previous.id).await?;
+    if systems.is_empty() {
+        return Ok(None);
+    }
+    let names: Vec<String> = systems.iter().map(|(name, _)| name.clone()).collect();
+
+    let changed =
+        changed_files(repo_url, &previous.git_commit_hash, &commit.git_commit_hash).await?;
+    let Some(affected) = affected_systems(&changed, &names) else {
+        debug!(
+            "Changes in {} are not specific to a system, evaluating everything",
+            commit.git_commit_hash
+        );
+        return Ok(None);
+    };
+
+    // Systems that never produced a derivation have nothing to copy
+    let (evaluate, carry_forward): (Vec<_>, Vec<_>) = systems
+        .into_iter()
+        .partition(|(name, has_path)| affected.contains(name) || !has_path);
+    let plan = IncrementalPlan {
+        previous,
+        evaluate: evaluate.into_iter().map(|(name, _)| name).collect(),
+        carry_forward: carry_forward.into_iter().map(|(name, _)| name).collect(),
+    };
+
+    info!(
+        "🔍 Incremental evaluation of {}: {} changed files, {} systems to evaluate, {} carried from {}",
+        commit.git_commit_hash,
+        changed.len(),
+        plan.evaluate.len(),
+        plan.carry_forward.len(),
+        plan.previous.git_commit_hash
+    );
+
+    Ok(Some(plan))
+}
+
+/// Files changed between two commits of a flake, as reported by
+/// `git diff --name-only`
+pub async fn changed_files(repo_url: &str, from_hash: &str, to_hash: &str) -> Result<Vec<String>> {
+    let git_url = normalize_repo_url_for_git(repo_url);
+    let temp_dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+    let checkout = temp_dir.path();
+
+    git(checkout, &["init", "--quiet", "."]).await?;
+    git(
+        checkout,
+        &[
+            "fetch", "--quiet", "--depth", "1", &git_url, from_hash, to_hash,
+        ],
+    )
+    .await?;
+    let diff = git(checkout, &["diff", "--name-only", from_hash, to_hash]).await?;
+
+    Ok(diff
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+pub(crate) async fn git(checkout: &Path, args: &[&str]) -> Result<String> {
+    let mut cmd = tokio::process::Command::new("git");
+    apply_flake_auth(&mut cmd);
+    let output = cmd
+        .args(args)
+        .current_dir(checkout)
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn git {}", args[0]))?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Systems affected by a set of changed files.
+///
+/// A file belongs to a system when one of its path components, or the stem
+/// of a `.nix` file, equals the system name (e.g. `hosts/web01/default.nix`
+/// or `machines/web01.nix`). Returns `None` if any file cannot be attributed
+/// to a system, since it may be shared by all of them and a full evaluation
+/// is needed.
+pub fn affected_systems(changed: &[String], systems: &[String]) -> Option<HashSet<String>> {
+    let mut affected = HashSet::new();
+
+    for file in changed {
+        let mut owners = file
+            .split('/')
+            .map(|component| component.strip_suffix(".nix").unwrap_or(component))
+            .filter(|component| systems.iter().any(|system| system == component))
+            .peekable();
+        owners.peek()?;
+        affected.extend(owners.map(str::to_string));
+    }
+
+    Some(affected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn attributes_files_to_systems_or_requires_full_eval() {
+        let systems = strings(&["web01", "db01", "cache"]);
+
+        let affected = affected_systems(
+            &strings(&["hosts/web01/default.nix", "machines/db01.nix"]),
+            &systems,
+        )
+        .unwrap();
+        assert_eq!(affected, HashSet::from(["web01".into(), "db01".into()]));
+
+        assert_eq!(affected_systems(&[], &systems), Some(HashSet::new()));
+        assert!(affected_systems(&strings(&["flake.lock"]), &systems).is_none());
+        assert!(
+            affected_systems(
+                &strings(&["hosts/web01.nix", "modules/common.nix"]),
+                &systems
+            )
+            .is_none()
+        );
+        // Substrings do not count
+        assert!(affected_systems(&strings(&["hosts/web01-old.nix"]), &systems).is_none());
+    }
+}
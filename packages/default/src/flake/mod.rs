@@ -1,2 +1,4 @@
 pub mod commits;
 pub mod eval;
+pub mod eval_cache;
+pub mod incremental;
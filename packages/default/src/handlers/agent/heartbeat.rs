@@ -2,8 +2,14 @@ use crate::handlers::agent_request::{
     CFState, authenticate_agent_request, deserialize_system_state_versioned,
 };
 use crate::models::agent_heartbeats::AgentHeartbeat;
-use crate::queries::systems::get_desired_target_by_hostname;
+use crate::models::systems::System;
+use crate::queries::deployment::{record_deployment_outcome, record_deployment_result};
+use crate::queries::derivations::{
+    get_latest_deployable_targets_for_flake_hosts, get_store_path_origin,
+};
+use crate::queries::systems::{get_agent_update_for_hostname, get_by_hostname};
 use crate::queries::{agent_heartbeat::insert_agent_heartbeat, system_states::insert_system_state};
+use anyhow::Result;
 use axum::response::Response;
 use axum::{
     body::Bytes,
@@ -14,11 +20,25 @@ use axum::{
 use serde::Deserialize;
 use serde::Serialize;
 use sqlx::PgPool;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 #[derive(Serialize, Deserialize)]
 pub struct LogResponse {
     pub desired_target: Option<String>,
+    /// NAR hash the forge recorded when it built `desired_target`
+    #[serde(default)]
+    pub expected_nar_hash: Option<String>,
+    /// Flake target that builds `desired_target`, for agents allowed to
+    /// build it themselves when no cache has it
+    #[serde(default)]
+    pub flake_target: Option<String>,
+    /// Store path of a newer agent build the agent may switch itself to
+    #[serde(default)]
+    pub agent_update: Option<String>,
+    /// Newer target the host will be moved to once the rollout allows, which
+    /// the agent may copy ahead of time
+    #[serde(default)]
+    pub prefetch_target: Option<String>,
 }
 /// Handles the `/current-system` POST route.
 /// Verifies the body signature using headers, parses the payload, and
@@ -50,6 +70,15 @@ pub async fn log(
         agent_request.system.hostname, payload
     );
 
+    if let Some(report) = &payload.last_deployment
+        && let Err(e) = record_deployment_result(&pool, &payload.hostname, report).await
+    {
+        warn!(
+            "⚠️ Failed to record deployment result of {}: {e:?}",
+            payload.hostname
+        );
+    }
+
     match AgentHeartbeat::from_system_state_if_heartbeat(&payload, &pool).await {
         Ok(heartbeat) => {
             // This is a heartbeat - insert to heartbeats table
@@ -61,6 +90,20 @@ pub async fn log(
         }
         Err(_state_change_reason) => {
             info!("🔍 Heartbeat became state change: {}", _state_change_reason);
+            if payload.is_deployment()
+                && let Err(e) = record_deployment_outcome(
+                    &pool,
+                    &payload.hostname,
+                    payload.store_path.as_deref(),
+                )
+                .await
+            {
+                warn!(
+                    "⚠️ Failed to record deployment of {}: {e:?}",
+                    payload.hostname
+                );
+            }
+
             // State changed - insert full state record
             if let Err(e) = insert_system_state(&pool, &payload, version_compatible).await {
                 debug!("❌ failed to insert system state: {e:?}");
@@ -70,17 +113,54 @@ pub async fn log(
         }
     }
 
-    // Fetch desired target for this system
-    let desired_target =
-        match get_desired_target_by_hostname(&pool, &agent_request.system.hostname).await {
-            Ok(target) => target,
+    // Fetch this system for its desired target
+    let system = match get_by_hostname(&pool, &agent_request.system.hostname).await {
+        Ok(system) => system,
+        Err(e) => {
+            debug!("❌ Failed to fetch desired target: {e:?}");
+            None // Continue with None if query fails
+        }
+    };
+    let desired_target = system.as_ref().and_then(|s| s.desired_target.clone());
+
+    let origin = match desired_target.as_deref() {
+        Some(target) => get_store_path_origin(&pool, target)
+            .await
+            .unwrap_or_else(|e| {
+                debug!("❌ Failed to fetch origin of desired target: {e:?}");
+                None
+            }),
+        None => None,
+    };
+    let (expected_nar_hash, flake_target) = match origin {
+        Some(origin) => (origin.nar_hash, origin.flake_target),
+        None => (None, None),
+    };
+
+    let agent_update =
+        match get_agent_update_for_hostname(&pool, &agent_request.system.hostname).await {
+            Ok(update) => update,
             Err(e) => {
-                debug!("❌ Failed to fetch desired target: {e:?}");
-                None // Continue with None if query fails
+                debug!("❌ Failed to fetch agent update: {e:?}");
+                None
             }
         };
 
-    let response = LogResponse { desired_target };
+    let prefetch_target = match system.as_ref().filter(|_| payload.wants_prefetch) {
+        Some(system) => upcoming_target(&pool, system).await.unwrap_or_else(|e| {
+            debug!("❌ Failed to fetch upcoming target: {e:?}");
+            None
+        }),
+        None => None,
+    };
+
+    let response = LogResponse {
+        desired_target,
+        expected_nar_hash,
+        flake_target,
+        agent_update,
+        prefetch_target,
+    };
 
     // Return JSON response with appropriate status
     let status = if version_compatible {
@@ -91,3 +171,27 @@ pub async fn log(
 
     (status, axum::Json(response)).into_response()
 }
+
+/// The target auto_latest will move `hostname` to next, when the newest
+/// deployable build differs from its desired target (e.g. while the rollout
+/// limit holds the host back)
+async fn upcoming_target(pool: &PgPool, system: &System) -> Result<Option<String>> {
+    let Some(flake_id) = system
+        .flake_id
+        .filter(|_| system.is_auto_deployment_enabled())
+    else {
+        return Ok(None);
+    };
+
+    let latest = get_latest_deployable_targets_for_flake_hosts(
+        pool,
+        flake_id,
+        std::slice::from_ref(&system.hostname),
+    )
+    .await?;
+    Ok(latest
+        .into_iter()
+        .filter(|target| target.commits_behind_head == 0)
+        .find_map(|target| target.store_path)
+        .filter(|path| system.desired_target.as_deref() != Some(path.as_str())))
+}
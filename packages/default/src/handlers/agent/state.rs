@@ -1,5 +1,6 @@
 use crate::handlers::agent_request::deserialize_system_state_versioned;
 use crate::handlers::agent_request::{CFState, authenticate_agent_request};
+use crate::queries::deployment::record_deployment_outcome;
 use crate::queries::system_states::insert_system_state;
 use axum::{
     body::Bytes,
@@ -8,7 +9,7 @@ use axum::{
     response::IntoResponse,
 };
 use sqlx::PgPool;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 /// Handles the `/current-system` POST route.
 /// Verifies the body signature using headers, parses the payload, and
@@ -40,6 +41,16 @@ pub async fn update(
         agent_request.system.hostname, payload
     );
 
+    if payload.is_deployment()
+        && let Err(e) =
+            record_deployment_outcome(&pool, &payload.hostname, payload.store_path.as_deref()).await
+    {
+        warn!(
+            "⚠️ Failed to record deployment of {}: {e:?}",
+            payload.hostname
+        );
+    }
+
     // Insert with compatibility flag
     if let Err(e) = insert_system_state(&pool, &payload, version_compatible).await {
         debug!("❌ failed to insert into DB: {e:?}");
@@ -1,3 +1,5 @@
+use crate::config::CrystalForgeConfig;
+use crate::handlers::health::CacheProbe;
 use crate::models::{system_states::SystemState, system_states::SystemStateV1, systems::System};
 use crate::queries::systems::get_by_hostname;
 use anyhow::Result;
@@ -8,6 +10,7 @@ use bytes::Bytes; // Add this import
 use ed25519_dalek::Signature;
 use ed25519_dalek::Verifier;
 use sqlx::PgPool;
+use std::sync::Arc;
 
 pub struct VerifiedAgentRequest {
     pub key_id: String,
@@ -71,16 +74,28 @@ pub async fn authenticate_agent_request(
 #[derive(Clone)]
 pub struct CFState {
     pub pool: PgPool,
+    /// Configuration the server was started with
+    pub config: Arc<CrystalForgeConfig>,
+    /// Last cache destination probe of `/readyz`
+    pub cache_probe: CacheProbe,
 }
 
 impl CFState {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn new(pool: PgPool, config: CrystalForgeConfig) -> Self {
+        Self {
+            pool,
+            config: Arc::new(config),
+            cache_probe: CacheProbe::default(),
+        }
     }
 
     pub fn pool(&self) -> &PgPool {
         &self.pool
     }
+
+    pub fn config(&self) -> &CrystalForgeConfig {
+        &self.config
+    }
 }
 
 impl FromRef<CFState> for PgPool {
@@ -0,0 +1,108 @@
+use crate::queries::commits::{get_commit_by_hash, mark_skip, unskip};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::error;
+
+#[derive(Debug, Deserialize)]
+pub struct SkipRequest {
+    /// Who is skipping the commit, kept for auditing
+    pub by: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnskipRequest {
+    /// Who is unskipping the commit, kept for auditing
+    pub by: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Handles `POST /commits/:hash/skip`.
+/// Marks a pending or dead-lettered commit as never to be evaluated.
+pub async fn skip(
+    State(pool): State<PgPool>,
+    Path(hash): Path<String>,
+    Json(request): Json<SkipRequest>,
+) -> Response {
+    if request.by.trim().is_empty() || request.reason.trim().is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "both `by` and `reason` are required" })),
+        )
+            .into_response();
+    }
+
+    let commit_id = match commit_id(&pool, &hash).await {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+    match mark_skip(&pool, commit_id, &request.by, &request.reason).await {
+        Ok(true) => Json(json!({ "commit": hash, "status": "skipped" })).into_response(),
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": format!("commit {} is not pending or eval_failed", hash)
+            })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to skip commit {}: {}", hash, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Handles `POST /commits/:hash/unskip`.
+/// Puts a skipped commit back in the evaluation queue.
+pub async fn unskip_commit(
+    State(pool): State<PgPool>,
+    Path(hash): Path<String>,
+    Json(request): Json<UnskipRequest>,
+) -> Response {
+    if request.by.trim().is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "`by` is required" })),
+        )
+            .into_response();
+    }
+
+    let commit_id = match commit_id(&pool, &hash).await {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+    match unskip(&pool, commit_id, &request.by, request.reason.as_deref()).await {
+        Ok(true) => Json(json!({ "commit": hash, "status": "pending" })).into_response(),
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("commit {} is not skipped", hash) })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to unskip commit {}: {}", hash, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn commit_id(pool: &PgPool, hash: &str) -> Result<i32, Response> {
+    match get_commit_by_hash(pool, hash).await {
+        Ok(commit) => Ok(commit.id),
+        Err(e) if matches!(e.downcast_ref(), Some(sqlx::Error::RowNotFound)) => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("unknown commit {}", hash) })),
+        )
+            .into_response()),
+        Err(e) => {
+            error!("❌ Failed to load commit {}: {}", hash, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
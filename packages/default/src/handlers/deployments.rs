@@ -0,0 +1,68 @@
+use crate::config::CrystalForgeConfig;
+use crate::deployment::DeploymentPolicyManager;
+use crate::handlers::agent_request::CFState;
+use crate::queries::deployment::{deployments_paused, pause_deployments, resume_deployments};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::error;
+
+/// Handles `GET /deployments/auto-latest/preview`.
+/// Runs the auto_latest planning pass without writing anything and returns
+/// the desired_target changes it would make right now.
+pub async fn preview_auto_latest(State(state): State<CFState>) -> Response {
+    let manager = DeploymentPolicyManager::new(state.config().clone(), state.pool.clone());
+    match manager.preview().await {
+        Ok(changes) => Json(json!({ "changes": changes })).into_response(),
+        Err(e) => {
+            error!("❌ Failed to preview auto_latest changes: {:#}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Handles `GET /deployments/pause`.
+/// Reports whether the deployment kill switch is tripped, in the config or
+/// at runtime.
+pub async fn pause_status(State(pool): State<PgPool>) -> Response {
+    let configured = CrystalForgeConfig::load()
+        .map(|cfg| cfg.deployment.paused)
+        .unwrap_or(false);
+    match deployments_paused(&pool).await {
+        Ok(paused) => Json(json!({ "paused": paused || configured, "configured": configured }))
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to read deployment pause flag: {:#}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Handles `POST /deployments/pause`.
+/// Trips the kill switch so no system's desired target is changed.
+pub async fn pause(State(pool): State<PgPool>) -> Response {
+    match pause_deployments(&pool).await {
+        Ok(()) => Json(json!({ "paused": true })).into_response(),
+        Err(e) => {
+            error!("❌ Failed to pause deployments: {:#}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Handles `POST /deployments/resume`.
+/// Releases the runtime kill switch; `deployment.paused` in the config still
+/// applies.
+pub async fn resume(State(pool): State<PgPool>) -> Response {
+    match resume_deployments(&pool).await {
+        Ok(()) => Json(json!({ "paused": false })).into_response(),
+        Err(e) => {
+            error!("❌ Failed to resume deployments: {:#}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use crate::derivations::eval::queue_flake_attr_build;
+use crate::derivations::progress::{self, BuildProgress, ProgressTracker};
+use crate::derivations::utils::validate_flake_attr_path;
+use crate::handlers::agent_request::CFState;
+use crate::queries::commits::get_commit_by_hash;
+use crate::queries::derivations::{
+    DEFAULT_RESCHEDULE_MAX_ROWS, EvaluationStatus, RescheduleFilter, RescheduleLimitExceeded,
+    export_dependency_graph, get_build_progress, get_by_commit_hash, request_cancellation,
+    reschedule,
+};
+use crate::queries::sboms::get_sbom;
+use axum::{
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Json, Response},
+};
+use futures::Stream;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, warn};
+
+/// How often a progress stream re-reads the database, in case a builder's
+/// notifications don't arrive
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Handles `GET /commits/:hash/derivations`.
+/// Returns every derivation evaluated for the commit with its status, so CI
+/// can gate on all systems building green.
+pub async fn by_commit_hash(State(pool): State<PgPool>, Path(hash): Path<String>) -> Response {
+    match get_by_commit_hash(&pool, &hash).await {
+        Ok(Some(derivations)) => {
+            let total = derivations.len();
+            let succeeded = derivations.iter().filter(|d| d.is_success).count();
+            let failed = derivations
+                .iter()
+                .filter(|d| d.is_terminal && !d.is_success)
+                .count();
+
+            Json(json!({
+                "git_commit_hash": hash,
+                "summary": {
+                    "total": total,
+                    "succeeded": succeeded,
+                    "failed": failed,
+                    "pending": total - succeeded - failed,
+                    "all_succeeded": total > 0 && succeeded == total,
+                },
+                "derivations": derivations,
+            }))
+            .into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("unknown commit {}", hash) })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to load derivations for commit {}: {}", hash, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BuildAttrRequest {
+    /// Flake attribute path, e.g. `checks.x86_64-linux.integration`
+    pub attr: String,
+}
+
+/// Handles `POST /commits/:hash/builds`.
+/// Queues a flake attribute at the commit; the commit evaluation loop
+/// evaluates it and a build worker builds it. Poll
+/// `GET /commits/:hash/derivations` for the result.
+pub async fn queue_attr_build(
+    State(state): State<CFState>,
+    Path(hash): Path<String>,
+    Json(request): Json<BuildAttrRequest>,
+) -> Response {
+    let pool = state.pool.clone();
+    if let Err(e) = validate_flake_attr_path(&request.attr) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response();
+    }
+
+    let commit = match get_commit_by_hash(&pool, &hash).await {
+        Ok(commit) => commit,
+        Err(e) if matches!(e.downcast_ref(), Some(sqlx::Error::RowNotFound)) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": format!("unknown commit {}", hash) })),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            error!("❌ Failed to load commit {}: {}", hash, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let flake = match commit.get_flake(&pool).await {
+        Ok(flake) => flake,
+        Err(e) => {
+            error!("❌ Failed to load flake for commit {}: {}", hash, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let build_config = &state.config().build;
+    match queue_flake_attr_build(&pool, &commit, &flake, &request.attr, build_config).await {
+        Ok(derivation) => (
+            StatusCode::ACCEPTED,
+            Json(json!({ "derivation": derivation })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to queue {} at {}: {:#}", request.attr, hash, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Handles `POST /derivations/:id/cancel`.
+/// Queued builds are cancelled immediately; in-progress builds are killed by
+/// their worker within about five seconds.
+pub async fn cancel_build(State(pool): State<PgPool>, Path(id): Path<i32>) -> Response {
+    match request_cancellation(&pool, id).await {
+        Ok(true) => (
+            StatusCode::ACCEPTED,
+            Json(json!({ "derivation_id": id, "cancellation_requested": true })),
+        )
+            .into_response(),
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("derivation {} is not queued or building", id) })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to cancel build for derivation {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RescheduleRequest {
+    #[serde(default)]
+    pub flake_id: Option<i32>,
+    /// Hash of the commit whose derivations to reschedule
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// Status name as in `derivation_statuses`, e.g. `build-pending`;
+    /// without one every non-terminal derivation matches
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub max_rows: Option<i64>,
+}
+
+/// Handles `POST /derivations/reschedule`.
+/// Moves the matching derivations to the front of the queue, e.g. one
+/// flake's stalled builds after an infrastructure fix. Refuses with 409 when
+/// more than `max_rows` match.
+pub async fn reschedule_derivations(
+    State(pool): State<PgPool>,
+    Json(request): Json<RescheduleRequest>,
+) -> Response {
+    let status = match request.status.as_deref() {
+        None => None,
+        Some(name) => match EvaluationStatus::ALL
+            .into_iter()
+            .find(|status| status.db_name() == name)
+        {
+            Some(status) => Some(status),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": format!("unknown status {}", name) })),
+                )
+                    .into_response();
+            }
+        },
+    };
+
+    let commit_id = match request.commit.as_deref() {
+        None => None,
+        Some(hash) => match get_commit_by_hash(&pool, hash).await {
+            Ok(commit) => Some(commit.id),
+            Err(e) if matches!(e.downcast_ref(), Some(sqlx::Error::RowNotFound)) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({ "error": format!("unknown commit {}", hash) })),
+                )
+                    .into_response();
+            }
+            Err(e) => {
+                error!("❌ Failed to load commit {}: {}", hash, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        },
+    };
+
+    let filter = RescheduleFilter {
+        flake_id: request.flake_id,
+        commit_id,
+        status,
+        max_rows: request.max_rows.unwrap_or(DEFAULT_RESCHEDULE_MAX_ROWS),
+    };
+    match reschedule(&pool, &filter).await {
+        Ok(count) => Json(json!({ "rescheduled": count })).into_response(),
+        Err(e) if e.downcast_ref::<RescheduleLimitExceeded>().is_some() => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to reschedule derivations: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Handles `GET /derivations/:id/sbom`.
+/// Returns the SBOM document recorded when the derivation was last CVE
+/// scanned, in the format it was generated in.
+pub async fn sbom(State(pool): State<PgPool>, Path(id): Path<i32>) -> Response {
+    match get_sbom(&pool, id).await {
+        Ok(Some(sbom)) => Json(sbom.document).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("no SBOM recorded for derivation {}", id) })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to load SBOM for derivation {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    #[default]
+    Json,
+    Dot,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphQuery {
+    #[serde(default)]
+    pub format: GraphFormat,
+}
+
+/// Handles `GET /commits/:hash/graph?format=json|dot`.
+/// Exports the commit's derivations and everything they depend on, with the
+/// build status of each, as JSON or a Graphviz digraph.
+pub async fn dependency_graph(
+    State(pool): State<PgPool>,
+    Path(hash): Path<String>,
+    Query(query): Query<GraphQuery>,
+) -> Response {
+    let commit = match get_commit_by_hash(&pool, &hash).await {
+        Ok(commit) => commit,
+        Err(e) if matches!(e.downcast_ref(), Some(sqlx::Error::RowNotFound)) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": format!("unknown commit {}", hash) })),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            error!("❌ Failed to load commit {}: {}", hash, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match export_dependency_graph(&pool, commit.id).await {
+        Ok(graph) => match query.format {
+            GraphFormat::Json => Json(graph).into_response(),
+            GraphFormat::Dot => (
+                [(header::CONTENT_TYPE, "text/vnd.graphviz")],
+                graph.to_dot(),
+            )
+                .into_response(),
+        },
+        Err(e) => {
+            error!("❌ Failed to export dependency graph for {}: {}", hash, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Handles `GET /builds/:id/progress`.
+/// Server-Sent Events stream of `progress` events carrying the build's
+/// status, current target and elapsed time, ending once the build finishes.
+/// Updates arrive with every build heartbeat, a few seconds apart, through
+/// Postgres notifications from the builder; the database is polled as a
+/// fallback.
+pub async fn build_progress(State(pool): State<PgPool>, Path(id): Path<i32>) -> Response {
+    // Subscribe before reading the initial state so no live update is missed
+    let updates = progress::subscribe();
+    match get_build_progress(&pool, id).await {
+        Ok(Some(initial)) => Sse::new(progress_events(pool, id, initial, updates))
+            .keep_alive(KeepAlive::default())
+            .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("unknown derivation {}", id) })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("❌ Failed to load build progress for {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+struct ProgressStream {
+    pool: PgPool,
+    derivation_id: i32,
+    updates: broadcast::Receiver<BuildProgress>,
+    poll: tokio::time::Interval,
+    tracker: ProgressTracker,
+    initial: Option<BuildProgress>,
+}
+
+impl ProgressStream {
+    /// The next update worth sending, or `None` once the build is over
+    async fn next(&mut self) -> Option<BuildProgress> {
+        if let Some(initial) = self.initial.take() {
+            return self.tracker.accept(initial, false);
+        }
+        while !self.tracker.is_finished() {
+            let (update, live) = tokio::select! {
+                update = self.updates.recv() => match update {
+                    Ok(update) if update.derivation_id == self.derivation_id => (update, true),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                _ = self.poll.tick() => {
+                    match get_build_progress(&self.pool, self.derivation_id).await {
+                        Ok(Some(update)) => (update, false),
+                        Ok(None) => return None,
+                        Err(e) => {
+                            warn!(
+                                "⚠️ Failed to poll build progress for {}: {}",
+                                self.derivation_id, e
+                            );
+                            continue;
+                        }
+                    }
+                }
+            };
+            if let Some(update) = self.tracker.accept(update, live) {
+                return Some(update);
+            }
+        }
+        None
+    }
+}
+
+fn progress_events(
+    pool: PgPool,
+    derivation_id: i32,
+    initial: BuildProgress,
+    updates: broadcast::Receiver<BuildProgress>,
+) -> impl Stream<Item = Result<Event, axum::Error>> {
+    let mut poll = tokio::time::interval(PROGRESS_POLL_INTERVAL);
+    poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let stream = ProgressStream {
+        pool,
+        derivation_id,
+        updates,
+        poll,
+        tracker: ProgressTracker::default(),
+        initial: Some(initial),
+    };
+
+    futures::stream::unfold(stream, |mut stream| async move {
+        let update = stream.next().await?;
+        let event = Event::default().event("progress").json_data(&update);
+        Some((event, stream))
+    })
+}
@@ -0,0 +1,223 @@
+use crate::config::CacheConfig;
+use crate::derivations::cache::{CacheHealth, verify_cache_destination};
+use crate::handlers::agent_request::CFState;
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::Serialize;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use std::future::Future;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant, timeout};
+
+/// Longest a single readiness check may take before it counts as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a cache destination probe answers `/readyz` before the cache is
+/// probed again
+const CACHE_PROBE_TTL: Duration = Duration::from_secs(30);
+
+/// Result of checking one subsystem
+#[derive(Debug, Clone, Serialize)]
+pub struct SubsystemHealth {
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SubsystemHealth {
+    fn healthy(detail: Option<String>) -> Self {
+        Self {
+            healthy: true,
+            detail,
+            error: None,
+        }
+    }
+
+    fn unhealthy(error: String) -> Self {
+        Self {
+            healthy: false,
+            detail: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Body of `GET /readyz`
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub database: SubsystemHealth,
+    pub nix: SubsystemHealth,
+    /// `None` when no cache destination is configured
+    pub cache: Option<CacheHealth>,
+}
+
+impl Readiness {
+    pub fn new(
+        database: SubsystemHealth,
+        nix: SubsystemHealth,
+        cache: Option<CacheHealth>,
+    ) -> Self {
+        let ready =
+            database.healthy && nix.healthy && cache.as_ref().is_none_or(CacheHealth::is_healthy);
+        Self {
+            ready,
+            database,
+            nix,
+            cache,
+        }
+    }
+}
+
+/// Handles `GET /healthz`.
+/// Liveness only: answers as long as the process can serve requests.
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "alive" }))
+}
+
+/// Handles `GET /readyz`.
+/// Checks the database, `nix` and the configured cache destination, and
+/// answers 503 if any of them is unusable.
+pub async fn readyz(State(state): State<CFState>) -> (StatusCode, Json<Readiness>) {
+    let (database, nix, cache) = tokio::join!(
+        check_database(state.pool()),
+        check_nix(),
+        state.cache_probe.check(&state.config().cache),
+    );
+    let readiness = Readiness::new(database, nix, cache);
+
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+async fn with_timeout<T>(check: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(format!("timed out after {:?}", CHECK_TIMEOUT)))
+}
+
+async fn check_database(pool: &PgPool) -> SubsystemHealth {
+    let result = with_timeout(async {
+        sqlx::query("SELECT 1")
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(_) => SubsystemHealth::healthy(None),
+        Err(e) => SubsystemHealth::unhealthy(e),
+    }
+}
+
+async fn check_nix() -> SubsystemHealth {
+    let result = with_timeout(async {
+        let output = tokio::process::Command::new("nix")
+            .arg("--version")
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| format!("failed to run nix: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "nix --version failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    })
+    .await;
+
+    match result {
+        Ok(version) => SubsystemHealth::healthy(Some(version)),
+        Err(e) => SubsystemHealth::unhealthy(e),
+    }
+}
+
+/// Result of the last cache destination probe, shared by `/readyz` requests
+/// so frequent polling doesn't probe the cache every time
+#[derive(Clone, Default)]
+pub struct CacheProbe {
+    last: Arc<Mutex<Option<ProbeResult>>>,
+}
+
+struct ProbeResult {
+    probed_at: Instant,
+    health: Option<CacheHealth>,
+}
+
+impl CacheProbe {
+    /// Last probe result if younger than [`CACHE_PROBE_TTL`], otherwise a
+    /// fresh probe. Concurrent callers wait for the same probe.
+    async fn check(&self, cache_config: &CacheConfig) -> Option<CacheHealth> {
+        let mut last = self.last.lock().await;
+        if let Some(result) = last.as_ref()
+            && result.probed_at.elapsed() < CACHE_PROBE_TTL
+        {
+            return result.health.clone();
+        }
+        let health = check_cache(cache_config).await;
+        *last = Some(ProbeResult {
+            probed_at: Instant::now(),
+            health: health.clone(),
+        });
+        health
+    }
+}
+
+async fn check_cache(cache_config: &CacheConfig) -> Option<CacheHealth> {
+    if cache_config.push_to.is_none() && cache_config.effective_attic_cache_name().is_none() {
+        return None;
+    }
+
+    let result = with_timeout(async {
+        verify_cache_destination(cache_config)
+            .await
+            .map_err(|e| format!("{:#}", e))
+    })
+    .await;
+
+    Some(result.unwrap_or_else(|e| CacheHealth {
+        destination: cache_config.push_to.clone().unwrap_or_default(),
+        errors: vec![e],
+        ..Default::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_only_when_every_configured_subsystem_is_healthy() {
+        let ok = || SubsystemHealth::healthy(None);
+        assert!(Readiness::new(ok(), ok(), None).ready);
+
+        let db_down = SubsystemHealth::unhealthy("connection refused".to_string());
+        assert!(!Readiness::new(db_down, ok(), None).ready);
+
+        let unreachable = CacheHealth {
+            destination: "s3://cache".to_string(),
+            ..Default::default()
+        };
+        assert!(!Readiness::new(ok(), ok(), Some(unreachable)).ready);
+
+        let reachable = CacheHealth {
+            destination: "s3://cache".to_string(),
+            reachable: true,
+            authenticated: true,
+            ..Default::default()
+        };
+        assert!(Readiness::new(ok(), ok(), Some(reachable)).ready);
+    }
+}
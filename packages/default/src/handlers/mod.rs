@@ -1,4 +1,10 @@
 pub mod agent;
 pub mod agent_request;
+pub mod commits;
+pub mod deployments;
+pub mod derivations;
+pub mod health;
+pub mod reservations;
 pub mod status;
 pub mod webhook;
+pub mod workers;
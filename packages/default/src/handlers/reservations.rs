@@ -0,0 +1,49 @@
+use crate::queries::build_reservations::{force_release_reservation, list_active_reservations};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::error;
+
+/// Handles `GET /reservations`.
+/// Lists every build reservation with how long ago its worker last
+/// heartbeated, so a claim held by a dead builder is easy to spot.
+pub async fn list(State(pool): State<PgPool>) -> Response {
+    match list_active_reservations(&pool).await {
+        Ok(reservations) => Json(json!({ "reservations": reservations })).into_response(),
+        Err(e) => {
+            error!("❌ Failed to list build reservations: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Handles `POST /reservations/:derivation_id/release`.
+/// Releases the reservation on a derivation immediately instead of waiting
+/// for its lease to expire; the build is requeued for another worker.
+pub async fn force_release(State(pool): State<PgPool>, Path(derivation_id): Path<i32>) -> Response {
+    match force_release_reservation(&pool, derivation_id).await {
+        Ok(Some(worker_id)) => Json(json!({
+            "derivation_id": derivation_id,
+            "released_from": worker_id,
+        }))
+        .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": format!("derivation {} has no reservation", derivation_id)
+            })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!(
+                "❌ Failed to release reservation on derivation {}: {}",
+                derivation_id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
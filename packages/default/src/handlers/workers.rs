@@ -0,0 +1,9 @@
+use crate::log::{WorkerStatusReport, worker_status_report};
+use axum::response::Json;
+
+/// Handles `GET /workers`.
+/// Reports the workers tracked by this process. Build, CVE and cache workers
+/// are only populated in the process that runs those loops.
+pub async fn list() -> Json<WorkerStatusReport> {
+    Json(worker_status_report().await)
+}
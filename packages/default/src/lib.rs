@@ -1,11 +1,16 @@
 pub mod builder;
 pub mod config;
+pub mod db;
 pub mod deployment;
 pub mod derivations;
 pub mod flake;
 pub mod handlers;
 pub mod log;
 pub mod models;
+pub mod notifications;
 pub mod queries;
+pub mod sbom;
 pub mod server;
+pub mod shutdown;
+pub mod telemetry;
 pub mod vulnix;
@@ -1,3 +1,6 @@
+pub mod redact;
+
+use serde::Serialize;
 use std::sync::Arc;
 use std::sync::OnceLock;
 use tokio::sync::RwLock;
@@ -11,16 +14,19 @@ pub struct WorkerStatus {
     pub state: WorkerState,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum WorkerState {
     Idle,
     Working,
     Sleeping,
+    /// Stopped claiming work because the builder is being drained
+    Draining,
 }
 
 // Global status tracker using OnceLock
 pub static BUILD_WORKER_STATUS: OnceLock<Arc<RwLock<Vec<WorkerStatus>>>> = OnceLock::new();
-pub static CVE_SCAN_STATUS: OnceLock<Arc<RwLock<Option<WorkerStatus>>>> = OnceLock::new();
+pub static CVE_SCAN_STATUS: OnceLock<Arc<RwLock<Vec<WorkerStatus>>>> = OnceLock::new();
 pub static CACHE_PUSH_STATUS: OnceLock<Arc<RwLock<Option<WorkerStatus>>>> = OnceLock::new();
 pub static DRY_RUN_WORKER_STATUS: OnceLock<Arc<RwLock<Vec<WorkerStatus>>>> = OnceLock::new();
 
@@ -32,14 +38,58 @@ pub fn get_build_status() -> &'static Arc<RwLock<Vec<WorkerStatus>>> {
     BUILD_WORKER_STATUS.get_or_init(|| Arc::new(RwLock::new(Vec::new())))
 }
 
-pub fn get_cve_status() -> &'static Arc<RwLock<Option<WorkerStatus>>> {
-    CVE_SCAN_STATUS.get_or_init(|| Arc::new(RwLock::new(None)))
+pub fn get_cve_status() -> &'static Arc<RwLock<Vec<WorkerStatus>>> {
+    CVE_SCAN_STATUS.get_or_init(|| Arc::new(RwLock::new(Vec::new())))
 }
 
 pub fn get_cache_status() -> &'static Arc<RwLock<Option<WorkerStatus>>> {
     CACHE_PUSH_STATUS.get_or_init(|| Arc::new(RwLock::new(None)))
 }
 
+/// Serializable view of a [`WorkerStatus`], with the elapsed time resolved
+/// when the snapshot is taken
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerStatusSnapshot {
+    pub worker_id: usize,
+    pub state: WorkerState,
+    pub current_task: Option<String>,
+    pub elapsed_seconds: Option<u64>,
+}
+
+impl From<&WorkerStatus> for WorkerStatusSnapshot {
+    fn from(status: &WorkerStatus) -> Self {
+        Self {
+            worker_id: status.worker_id,
+            state: status.state,
+            current_task: status.current_task.clone(),
+            elapsed_seconds: status.started_at.map(|t| t.elapsed().as_secs()),
+        }
+    }
+}
+
+/// Status of every worker tracked in this process
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerStatusReport {
+    pub build_workers: Vec<WorkerStatusSnapshot>,
+    pub dry_run_workers: Vec<WorkerStatusSnapshot>,
+    pub cve_scanners: Vec<WorkerStatusSnapshot>,
+    pub cache_pusher: Option<WorkerStatusSnapshot>,
+}
+
+pub async fn worker_status_report() -> WorkerStatusReport {
+    let build_workers = get_build_status().read().await;
+    let dry_run_workers = get_dry_run_status().read().await;
+    let cve_status = get_cve_status().read().await;
+    let cache_status = get_cache_status().read().await;
+
+    WorkerStatusReport {
+        build_workers: build_workers.iter().map(Into::into).collect(),
+        dry_run_workers: dry_run_workers.iter().map(Into::into).collect(),
+        cve_scanners: cve_status.iter().map(Into::into).collect(),
+        cache_pusher: cache_status.as_ref().map(Into::into),
+    }
+}
+
 pub async fn log_builder_worker_status() {
     let build_workers = get_build_status().read().await;
     let dry_run_workers = get_dry_run_status().read().await;
@@ -88,18 +138,22 @@ pub async fn log_builder_worker_status() {
         }
     }
 
-    // CVE scan
-    if let Some(status) = cve_status.as_ref() {
-        match &status.current_task {
+    // CVE scanners
+    info!("CVE Scanners ({} total):", cve_status.len());
+    for scanner in cve_status.iter() {
+        match &scanner.current_task {
             Some(task) => {
-                let elapsed = status
+                let elapsed = scanner
                     .started_at
                     .map(|t| t.elapsed().as_secs())
                     .unwrap_or(0);
-                info!("CVE Scanner: {:?} - {} ({}s)", status.state, task, elapsed);
+                info!(
+                    "  Scanner {}: {:?} - {} ({}s)",
+                    scanner.worker_id, scanner.state, task, elapsed
+                );
             }
             None => {
-                info!("CVE Scanner: {:?}", status.state);
+                info!("  Scanner {}: {:?}", scanner.worker_id, scanner.state);
             }
         }
     }
@@ -0,0 +1,142 @@
+//! Masking of secrets in command output before it is logged or stored.
+//!
+//! Build scripts and cache tools sometimes echo credentials. Every line of
+//! build and cache output, and every error message written to the database,
+//! goes through [`redact`] first.
+
+use crate::config::CrystalForgeConfig;
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// Environment variables whose values are always masked
+pub const SECRET_ENV_VARS: &[&str] = &[
+    "ATTIC_TOKEN",
+    "CACHIX_AUTH_TOKEN",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+];
+
+/// Replacement text for anything redacted
+pub const MASK: &str = "[REDACTED]";
+
+/// Secret values shorter than this are not masked on their own, so an
+/// empty or one-letter variable doesn't mangle every line
+const MIN_SECRET_LEN: usize = 4;
+
+/// Masks known secret values and anything matching configured patterns
+pub struct Redactor {
+    secrets: Vec<String>,
+    assignment: Regex,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// `secrets` are literal values to mask, `patterns` regexes whose whole
+    /// match is masked
+    pub fn new(
+        secrets: impl IntoIterator<Item = String>,
+        patterns: &[String],
+    ) -> Result<Self, regex::Error> {
+        let assignment = Regex::new(&format!(
+            r"\b({})\s*([=:])\s*\S+",
+            SECRET_ENV_VARS.join("|")
+        ))?;
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            secrets: secrets
+                .into_iter()
+                .filter(|secret| secret.len() >= MIN_SECRET_LEN)
+                .collect(),
+            assignment,
+            patterns,
+        })
+    }
+
+    /// `text` with every secret replaced by [`MASK`]
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = Cow::Owned(text.replace(secret.as_str(), MASK));
+            }
+        }
+        if let Cow::Owned(masked) = self.assignment.replace_all(&text, format!("$1$2{MASK}")) {
+            text = Cow::Owned(masked);
+        }
+        for pattern in &self.patterns {
+            if let Cow::Owned(masked) = pattern.replace_all(&text, MASK) {
+                text = Cow::Owned(masked);
+            }
+        }
+        text
+    }
+}
+
+static REDACTOR: OnceLock<Redactor> = OnceLock::new();
+
+/// The process-wide redactor: secret env values of this process and the
+/// cache destination tokens, plus the configured `build.redact_patterns`
+fn redactor() -> &'static Redactor {
+    REDACTOR.get_or_init(|| {
+        let cfg = CrystalForgeConfig::load().ok();
+        let destination_tokens: Vec<String> = cfg
+            .iter()
+            .flat_map(|cfg| &cfg.cache.destinations)
+            .filter_map(|destination| destination.resolve_token())
+            .collect();
+        let secrets = || {
+            SECRET_ENV_VARS
+                .iter()
+                .filter_map(|name| std::env::var(name).ok())
+                .chain(destination_tokens.iter().cloned())
+        };
+        let patterns = cfg.map(|cfg| cfg.build.redact_patterns).unwrap_or_default();
+        Redactor::new(secrets(), &patterns).unwrap_or_else(|e| {
+            warn!("⚠️ Ignoring build.redact_patterns: {}", e);
+            Redactor::new(secrets(), &[]).expect("built-in redaction pattern is valid")
+        })
+    })
+}
+
+/// Mask secrets in a line of output or an error message
+pub fn redact(text: &str) -> Cow<'_, str> {
+    redactor().redact(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_masked() {
+        let redactor = Redactor::new(
+            ["s3cr3t-token".to_string(), "x".to_string()],
+            &[r"ghp_[A-Za-z0-9]+".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            redactor.redact("login with s3cr3t-token failed"),
+            "login with [REDACTED] failed"
+        );
+        assert_eq!(
+            redactor.redact("env: AWS_SECRET_ACCESS_KEY=abc123 HOME=/root"),
+            "env: AWS_SECRET_ACCESS_KEY=[REDACTED] HOME=/root"
+        );
+        assert_eq!(
+            redactor.redact("cloning with ghp_abcDEF123"),
+            "cloning with [REDACTED]"
+        );
+        assert!(matches!(
+            redactor.redact("building 'x.drv'"),
+            Cow::Borrowed("building 'x.drv'")
+        ));
+
+        assert!(Redactor::new(Vec::new(), &["(".to_string()]).is_err());
+    }
+}
@@ -89,6 +89,7 @@ impl AgentHeartbeat {
             && current.network_interfaces == previous.network_interfaces
             && current.primary_mac_address == previous.primary_mac_address
             && current.primary_ip_address == previous.primary_ip_address
+            && current.primary_ipv6_address == previous.primary_ipv6_address
             && current.gateway_ip == previous.gateway_ip
             && current.selinux_status == previous.selinux_status
             && current.tpm_present == previous.tpm_present
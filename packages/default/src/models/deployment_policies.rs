@@ -174,6 +174,33 @@ impl PolicyCheckResult {
 
 /// Build the complete Nix expression for nix-eval-jobs with policy checks
 pub fn build_nix_eval_expression(flake_ref: &str, policies: &[DeploymentPolicy]) -> String {
+    build_nix_eval_expression_for_systems(flake_ref, policies, None)
+}
+
+/// Like [`build_nix_eval_expression`], but when `systems` is given only those
+/// nixosConfigurations are evaluated
+pub fn build_nix_eval_expression_for_systems(
+    flake_ref: &str,
+    policies: &[DeploymentPolicy],
+    systems: Option<&[String]>,
+) -> String {
+    let configs = match systems {
+        None => "flake.nixosConfigurations".to_string(),
+        Some(systems) => {
+            let names = systems
+                .iter()
+                .map(|s| format!("\"{}\"", s.replace('"', "\\\"").replace("${", "\\${")))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!(
+                "builtins.intersectAttrs \
+                 (builtins.listToAttrs (map (name: {{ inherit name; value = null; }}) [ {} ])) \
+                 flake.nixosConfigurations",
+                names
+            )
+        }
+    };
+
     let policy_fields = if policies.is_empty() {
         "        # No policies configured".to_string()
     } else {
@@ -191,7 +218,7 @@ pub fn build_nix_eval_expression(flake_ref: &str, policies: &[DeploymentPolicy])
         r#"
 let
   flake = builtins.getFlake "{}";
-  configs = flake.nixosConfigurations;
+  configs = {};
 in
   builtins.mapAttrs (name: cfg: 
     let
@@ -213,7 +240,7 @@ in
       }}
   ) configs
 "#,
-        flake_ref, policy_fields
+        flake_ref, configs, policy_fields
     )
 }
 
@@ -263,4 +290,15 @@ mod tests {
         assert!(expr.contains("hasRequiredPackages"));
         assert!(expr.contains("services.crystal-forge"));
     }
+
+    #[test]
+    fn test_build_expression_for_selected_systems() {
+        let systems = vec!["web01".to_string(), "db01".to_string()];
+        let expr = build_nix_eval_expression_for_systems("github:user/repo", &[], Some(&systems));
+        assert!(expr.contains("builtins.intersectAttrs"));
+        assert!(expr.contains(r#"[ "web01" "db01" ]"#));
+
+        let all = build_nix_eval_expression("github:user/repo", &[]);
+        assert!(all.contains("configs = flake.nixosConfigurations;"));
+    }
 }
@@ -1,6 +1,7 @@
 use anyhow::{Result, bail};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::collections::HashMap;
 use std::process::Stdio;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
@@ -8,11 +9,13 @@ use tracing::{debug, error, info, warn};
 
 use crate::models::commits::Commit;
 use crate::config::{BuildConfig, ServerConfig};
+use crate::derivations::eval::retry_transient_nix;
+use crate::derivations::utils::{build_flake_reference, system_arch_from_output_name};
 use crate::models::deployment_policies::{
-    DeploymentPolicy, PolicyCheckResult, build_nix_eval_expression,
+    DeploymentPolicy, PolicyCheckResult, build_nix_eval_expression_for_systems,
 };
 use crate::models::flakes::Flake;
-use crate::queries::derivations::{EvaluationStatus, insert_derivation_with_target};
+use crate::queries::derivations::{DerivationInput, EvaluationStatus, batch_insert_derivations};
 
 /// NixEvalJobResult with meta field
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,17 +30,30 @@ pub struct NixEvalJobResult {
     #[serde(rename = "cacheStatus")]
     pub cache_status: Option<String>,
     pub outputs: Option<serde_json::Value>,
+    /// Nix system the derivation is for, e.g. `aarch64-linux`
+    pub system: Option<String>,
 
     /// Meta field (only present with --meta flag)
     /// Contains our policy check results in meta.policies
     pub meta: Option<serde_json::Value>,
 }
 
+/// A nix-eval-jobs result waiting for the batch derivation insert
+struct PendingInsert {
+    input: DerivationInput,
+    has_error: bool,
+    drv_path: Option<String>,
+}
+
 /// Evaluate a flake's nixosConfigurations with nix-eval-jobs and policy checking
 ///
 /// FIXED: Now properly:
 /// 1. Stores derivation_path from nix-eval-jobs
 /// 2. Updates status to DryRunComplete after successful evaluation
+///
+/// `only_systems` restricts evaluation to those nixosConfigurations.
+/// Like `nix eval`, a run that fails with a transient error is retried with
+/// backoff; derivations a failed run already recorded are upserted again.
 pub async fn evaluate_with_nix_eval_jobs(
     pool: &PgPool,
     commit: &Commit,
@@ -45,6 +61,42 @@ pub async fn evaluate_with_nix_eval_jobs(
     repo_url: &str,
     commit_hash: &str,
     target_system: &str,
+    only_systems: Option<&[String]>,
+    build_config: &BuildConfig,
+    server_config: &ServerConfig,
+    policies: &[DeploymentPolicy],
+) -> Result<(Vec<NixEvalJobResult>, Vec<PolicyCheckResult>)> {
+    retry_transient_nix(
+        "nix-eval-jobs",
+        || {
+            run_nix_eval_jobs(
+                pool,
+                commit,
+                flake,
+                repo_url,
+                commit_hash,
+                target_system,
+                only_systems,
+                build_config,
+                server_config,
+                policies,
+            )
+        },
+        |result| result.as_ref().err().map(|e| format!("{:#}", e)),
+    )
+    .await
+}
+
+/// One nix-eval-jobs run of [`evaluate_with_nix_eval_jobs`]
+#[allow(clippy::too_many_arguments)]
+async fn run_nix_eval_jobs(
+    pool: &PgPool,
+    commit: &Commit,
+    flake: &Flake,
+    repo_url: &str,
+    commit_hash: &str,
+    target_system: &str,
+    only_systems: Option<&[String]>,
     build_config: &BuildConfig,
     server_config: &ServerConfig,
     policies: &[DeploymentPolicy],
@@ -52,7 +104,7 @@ pub async fn evaluate_with_nix_eval_jobs(
     let flake_ref = build_flake_reference(repo_url, commit_hash);
 
     // Build ONE Nix expression that includes policy checks
-    let nix_expr = build_nix_eval_expression(&flake_ref, policies);
+    let nix_expr = build_nix_eval_expression_for_systems(&flake_ref, policies, only_systems);
 
     info!(
         "🚀 Running: nix-eval-jobs for {} with {} policies",
@@ -104,8 +156,8 @@ pub async fn evaluate_with_nix_eval_jobs(
     let mut stdout_done = false;
     let mut stderr_done = false;
 
-    // Track successfully evaluated derivations with their .drv paths
-    let mut evaluated_derivations: Vec<(i32, String)> = Vec::new();
+    // Derivations to insert once nix-eval-jobs is done, in one batch
+    let mut pending_inserts: Vec<PendingInsert> = Vec::new();
 
     loop {
         tokio::select! {
@@ -156,7 +208,7 @@ pub async fn evaluate_with_nix_eval_jobs(
                                     debug!("⚠️  No meta field for {}", system_name);
                                 }
 
-                                // Insert derivation with policy check results
+                                // Queue derivation (with policy check results) for the batch insert
                                 if let Some(system_name) = result.attr_path.last() {
                                     let derivation_target = build_agent_target(
                                         &flake.repo_url,
@@ -164,39 +216,20 @@ pub async fn evaluate_with_nix_eval_jobs(
                                         system_name,
                                     );
 
-                                    match insert_derivation_with_target(
-                                        pool,
-                                        Some(commit),
-                                        system_name,
-                                        "nixos",
-                                        Some(&derivation_target),
-                                        cf_agent_enabled,
-                                    ).await {
-                                        Ok(deriv) => {
-                                            debug!("✅ Inserted/updated {} (id={}, CF agent: {:?})",
-                                                system_name, deriv.id, cf_agent_enabled);
-
-                                            // CRITICAL: Track derivations that evaluated successfully
-                                            // Only mark as complete if:
-                                            // 1. No evaluation error
-                                            // 2. Has a valid .drv path
-                                            if !has_error && drv_path.is_some() {
-                                                evaluated_derivations.push((
-                                                    deriv.id,
-                                                    drv_path.clone().unwrap()
-                                                ));
-                                                debug!("📋 Queued {} for DryRunComplete update", system_name);
-                                            } else {
-                                                if has_error {
-                                                    warn!("⚠️  {} has evaluation error, not marking complete", system_name);
-                                                }
-                                                if drv_path.is_none() {
-                                                    warn!("⚠️  {} missing drv_path, not marking complete", system_name);
-                                                }
-                                            }
-                                        }
-                                        Err(e) => warn!("⚠️  Failed to insert {}: {}", system_name, e),
-                                    }
+                                    pending_inserts.push(PendingInsert {
+                                        input: DerivationInput {
+                                            commit_id: Some(commit.id),
+                                            derivation_name: system_name.clone(),
+                                            derivation_type: "nixos".to_string(),
+                                            derivation_target: Some(derivation_target),
+                                            cf_agent_enabled,
+                                            system_arch: result.system.clone().or_else(|| {
+                                                system_arch_from_output_name(system_name)
+                                            }),
+                                        },
+                                        has_error,
+                                        drv_path: drv_path.clone(),
+                                    });
                                 }
 
                                 if result.attr_path.last() == Some(&target_system.to_string()) || target_system == "all" {
@@ -241,6 +274,54 @@ pub async fn evaluate_with_nix_eval_jobs(
         }
     }
 
+    // Track successfully evaluated derivations with their .drv paths
+    let mut evaluated_derivations: Vec<(i32, String)> = Vec::new();
+
+    let inputs: Vec<DerivationInput> = pending_inserts.iter().map(|p| p.input.clone()).collect();
+    match batch_insert_derivations(pool, &inputs).await {
+        Ok(inserted) => {
+            info!("✅ Inserted/updated {} derivations", inserted.len());
+            let ids: HashMap<&str, i32> = inserted
+                .iter()
+                .map(|d| (d.derivation_name.as_str(), d.id))
+                .collect();
+
+            for pending in &pending_inserts {
+                let system_name = &pending.input.derivation_name;
+                let Some(&deriv_id) = ids.get(system_name.as_str()) else {
+                    continue;
+                };
+                debug!(
+                    "✅ Inserted/updated {} (id={}, CF agent: {:?})",
+                    system_name, deriv_id, pending.input.cf_agent_enabled
+                );
+
+                // CRITICAL: Track derivations that evaluated successfully
+                // Only mark as complete if:
+                // 1. No evaluation error
+                // 2. Has a valid .drv path
+                match (&pending.drv_path, pending.has_error) {
+                    (Some(drv_path), false) => {
+                        evaluated_derivations.push((deriv_id, drv_path.clone()));
+                        debug!("📋 Queued {} for DryRunComplete update", system_name);
+                    }
+                    (drv_path, has_error) => {
+                        if has_error {
+                            warn!(
+                                "⚠️  {} has evaluation error, not marking complete",
+                                system_name
+                            );
+                        }
+                        if drv_path.is_none() {
+                            warn!("⚠️  {} missing drv_path, not marking complete", system_name);
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => warn!("⚠️  Failed to insert {} derivations: {}", inputs.len(), e),
+    }
+
     let status = child.wait().await?;
     if !status.success() {
         let stderr_text = stderr_output.join("\n");
@@ -354,19 +435,6 @@ pub async fn evaluate_with_nix_eval_jobs(
     Ok((results, policy_checks))
 }
 
-fn build_flake_reference(repo_url: &str, commit_hash: &str) -> String {
-    if repo_url.starts_with("git+") {
-        if repo_url.contains("?rev=") {
-            repo_url.to_string()
-        } else {
-            format!("{}?rev={}", repo_url, commit_hash)
-        }
-    } else {
-        let separator = if repo_url.contains('?') { "&" } else { "?" };
-        format!("git+{}{separator}rev={}", repo_url, commit_hash)
-    }
-}
-
 fn build_agent_target(repo_url: &str, commit_hash: &str, system_name: &str) -> String {
     let flake_ref = build_flake_reference(repo_url, commit_hash);
     format!("{}#nixosConfigurations.{}", flake_ref, system_name)
@@ -48,17 +48,7 @@ pub fn get_primary_mac() -> Result<String> {
         .map_err(|e| anyhow!("Failed to run ip route: {:?}", e))?;
 
     let route = str::from_utf8(&output.stdout)?;
-    let iface = route
-        .lines()
-        .find(|l| l.contains("default"))
-        .and_then(|l| {
-            l.split_whitespace().find(|w| *w == "dev").and_then(|_| {
-                let parts: Vec<&str> = l.split_whitespace().collect();
-                parts
-                    .get(parts.iter().position(|&w| w == "dev")? + 1)
-                    .copied()
-            })
-        })
+    let iface = default_route_interface(route)
         .ok_or_else(|| anyhow!("Could not determine default interface"))?;
 
     let output = Command::new("cat")
@@ -76,17 +66,7 @@ pub fn get_primary_ip() -> Result<String> {
         .map_err(|e| anyhow!("Failed to run ip route: {:?}", e))?;
 
     let route = str::from_utf8(&output.stdout)?;
-    let iface = route
-        .lines()
-        .find(|l| l.contains("default"))
-        .and_then(|l| {
-            l.split_whitespace().find(|w| *w == "dev").and_then(|_| {
-                let parts: Vec<&str> = l.split_whitespace().collect();
-                parts
-                    .get(parts.iter().position(|&w| w == "dev")? + 1)
-                    .copied()
-            })
-        })
+    let iface = default_route_interface(route)
         .ok_or_else(|| anyhow!("Could not determine default interface"))?;
 
     let output = Command::new("ip")
@@ -109,6 +89,72 @@ pub fn get_primary_ip() -> Result<String> {
     Ok(ip.to_string())
 }
 
+/// Global IPv6 address of the interface holding the IPv6 default route.
+/// On multi-homed hosts this may differ from the IPv4 default interface.
+pub fn get_primary_ipv6() -> Result<String> {
+    let output = Command::new("ip")
+        .arg("-6")
+        .arg("route")
+        .output()
+        .map_err(|e| anyhow!("Failed to run ip -6 route: {:?}", e))?;
+
+    let route = str::from_utf8(&output.stdout)?;
+    let iface = default_route_interface(route)
+        .ok_or_else(|| anyhow!("Could not determine IPv6 default interface"))?;
+
+    let output = Command::new("ip")
+        .arg("-f")
+        .arg("inet6")
+        .arg("addr")
+        .arg("show")
+        .arg("dev")
+        .arg(iface)
+        .arg("scope")
+        .arg("global")
+        .output()
+        .map_err(|e| anyhow!("Failed to get IPv6 address: {:?}", e))?;
+
+    let stdout = str::from_utf8(&output.stdout)?;
+    primary_ipv6_from_addr_show(stdout)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Could not extract IPv6 address"))
+}
+
+/// Interface named by the first default route in `ip route` output
+fn default_route_interface(route: &str) -> Option<&str> {
+    route
+        .lines()
+        .find(|l| l.starts_with("default"))
+        .and_then(|l| {
+            let parts: Vec<&str> = l.split_whitespace().collect();
+            parts
+                .get(parts.iter().position(|&w| w == "dev")? + 1)
+                .copied()
+        })
+}
+
+/// First stable address in `ip -f inet6 addr show` output. Privacy
+/// (temporary) and deprecated addresses rotate, so they are only used when
+/// nothing else is available.
+fn primary_ipv6_from_addr_show(output: &str) -> Option<&str> {
+    let addresses: Vec<(&str, bool)> = output
+        .lines()
+        .map(str::trim_start)
+        .filter(|line| line.starts_with("inet6 "))
+        .filter_map(|line| {
+            let addr = line.split_whitespace().nth(1)?.split('/').next()?;
+            let unstable = line.contains(" temporary") || line.contains(" deprecated");
+            Some((addr, unstable))
+        })
+        .collect();
+
+    addresses
+        .iter()
+        .find(|(_, unstable)| !unstable)
+        .or_else(|| addresses.first())
+        .map(|(addr, _)| *addr)
+}
+
 pub fn get_gateway_ip() -> Result<String> {
     let output = Command::new("ip")
         .arg("route")
@@ -132,3 +178,26 @@ pub fn get_selinux_status() -> Result<String> {
 
     Ok(str::from_utf8(&output.stdout)?.trim().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_default_route_interface() {
+        let route = "2001:db8::/64 dev wlan0 proto ra metric 600\n\
+                     default via fe80::1 dev eth1 proto ra metric 100\n";
+        assert_eq!(default_route_interface(route), Some("eth1"));
+        assert_eq!(default_route_interface("10.0.0.0/24 dev eth0\n"), None);
+    }
+
+    #[test]
+    fn prefers_stable_ipv6_address() {
+        let output = "2: eth1: <BROADCAST,MULTICAST,UP> mtu 1500\n\
+            inet6 2001:db8::abcd/64 scope global temporary dynamic\n\
+               valid_lft 86000sec preferred_lft 14000sec\n\
+            inet6 2001:db8::1/64 scope global dynamic mngtmpaddr\n\
+               valid_lft 86000sec preferred_lft 14000sec\n";
+        assert_eq!(primary_ipv6_from_addr_show(output), Some("2001:db8::1"));
+    }
+}
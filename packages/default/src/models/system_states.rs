@@ -8,9 +8,12 @@ use std::{fs, io::ErrorKind, path::Path};
 use sysinfo::System;
 use tracing::debug;
 
+use crate::deployment::agent::{DeploymentReport, nix_store_free_bytes};
+
 // Import these from your network_interfaces.rs
 use crate::models::network_interfaces::{
-    get_gateway_ip, get_network_interfaces, get_primary_ip, get_primary_mac, get_selinux_status,
+    get_gateway_ip, get_network_interfaces, get_primary_ip, get_primary_ipv6, get_primary_mac,
+    get_selinux_status,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -90,6 +93,18 @@ pub struct SystemState {
     pub hostname: String,
     pub change_reason: String, // Will be converted to/from ChangeReason enum
     pub timestamp: Option<DateTime<Utc>>,
+    /// For cf_deployment reports: "cache" or "local_build"
+    pub deployment_source: Option<String>,
+    /// Outcome of the agent's last deployment attempt; only sent with
+    /// heartbeats and kept in `deployment_results`, not `system_states`
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_deployment: Option<DeploymentReport>,
+    /// Heartbeat-only: the agent prefetches upcoming targets, so the forge
+    /// should look one up for it
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wants_prefetch: bool,
 
     // ───── System Info ─────
     pub store_path: Option<String>,
@@ -99,6 +114,8 @@ pub struct SystemState {
     pub uptime_secs: Option<i64>,
     pub cpu_brand: Option<String>,
     pub cpu_cores: Option<i32>,
+    /// Bytes free on the filesystem holding /nix/store
+    pub nix_store_free_bytes: Option<i64>,
 
     // ───── Hardware IDs ─────
     pub board_serial: Option<String>,
@@ -112,6 +129,8 @@ pub struct SystemState {
     pub network_interfaces: Option<serde_json::Value>,
     pub primary_mac_address: Option<String>,
     pub primary_ip_address: Option<String>,
+    /// Global IPv6 address on the IPv6 default-route interface
+    pub primary_ipv6_address: Option<String>,
     pub gateway_ip: Option<String>,
 
     // ───── Security & Compliance ─────
@@ -147,6 +166,9 @@ impl SystemState {
             hostname: v1.hostname,
             change_reason: Self::map_v1_context(&v1.context),
             timestamp: v1.timestamp,
+            deployment_source: None,
+            last_deployment: None,
+            wants_prefetch: false,
 
             // ───── System Info ─────
             store_path: v1.store_path,
@@ -169,6 +191,8 @@ impl SystemState {
             network_interfaces: v1.network_interfaces,
             primary_mac_address: v1.primary_mac_address,
             primary_ip_address: v1.primary_ip_address,
+            primary_ipv6_address: None,
+            nix_store_free_bytes: None,
             gateway_ip: v1.gateway_ip,
 
             // ───── Security & Compliance ─────
@@ -222,6 +246,9 @@ impl SystemState {
             hostname: hostname.to_string(),
             store_path: Some(store_path.to_string()),
             change_reason: change_reason.to_string(),
+            deployment_source: None,
+            last_deployment: None,
+            wants_prefetch: false,
 
             // Use overrides or sensible test defaults
             os: os_override
@@ -249,6 +276,8 @@ impl SystemState {
             network_interfaces: Some(serde_json::Value::Array(vec![])),
             primary_mac_address: Some("02:00:00:00:00:01".to_string()),
             primary_ip_address: Some("192.168.1.100".to_string()),
+            primary_ipv6_address: Some("fd00::100".to_string()),
+            nix_store_free_bytes: Some(100 * 1024 * 1024 * 1024),
             gateway_ip: Some("192.168.1.1".to_string()),
 
             // Security defaults
@@ -286,6 +315,9 @@ impl SystemState {
         let cpu_brand = sys.cpus().get(0).map(|c| c.brand().to_string());
         let cpu_cores = Some(sys.cpus().len() as i32);
 
+        debug!("🔍 reading nix_store_free_bytes");
+        let nix_store_free_bytes = nix_store_free_bytes().ok().map(|bytes| bytes as i64);
+
         debug!("🔍 reading board_serial");
         let board_serial = read_trimmed("/sys/class/dmi/id/board_serial")?;
         debug!("🔍 reading product_uuid");
@@ -312,6 +344,8 @@ impl SystemState {
         let primary_mac_address = get_primary_mac().ok();
         debug!("🔍 reading primary_ip_address");
         let primary_ip_address = get_primary_ip().ok();
+        debug!("🔍 reading primary_ipv6_address");
+        let primary_ipv6_address = get_primary_ipv6().ok();
         debug!("🔍 reading gateway_ip");
         let gateway_ip = get_gateway_ip().ok();
 
@@ -345,6 +379,9 @@ impl SystemState {
             hostname: hostname.to_string(),
             store_path: Some(store_path.to_string()),
             change_reason: change_reason.to_string(),
+            deployment_source: None,
+            last_deployment: None,
+            wants_prefetch: false,
             os,
             kernel,
             memory_gb,
@@ -360,6 +397,8 @@ impl SystemState {
             network_interfaces,
             primary_mac_address,
             primary_ip_address,
+            primary_ipv6_address,
+            nix_store_free_bytes,
             gateway_ip,
             selinux_status,
             tpm_present,
@@ -0,0 +1,191 @@
+//! Outgoing notifications for failures that need a human: failed builds,
+//! failed deployments and critical CVEs.
+
+use crate::config::{CrystalForgeConfig, NotificationConfig, NotificationEvent};
+use anyhow::{Result, bail};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::{Arc, OnceLock};
+use tracing::warn;
+
+/// Longest error summary sent in a notification
+const MAX_SUMMARY_CHARS: usize = 500;
+
+/// A single failure worth telling someone about
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub event: NotificationEvent,
+    pub derivation_name: String,
+    pub commit: Option<String>,
+    pub summary: String,
+}
+
+impl Notification {
+    pub fn new(
+        event: NotificationEvent,
+        derivation_name: impl Into<String>,
+        commit: Option<String>,
+        error: &str,
+    ) -> Self {
+        Self {
+            event,
+            derivation_name: derivation_name.into(),
+            commit,
+            summary: summarize(error),
+        }
+    }
+
+    /// One-line human readable description, used as the chat message text
+    pub fn text(&self) -> String {
+        let what = match self.event {
+            NotificationEvent::BuildFailed => "Build failed",
+            NotificationEvent::DeployFailed => "Deployment failed",
+            NotificationEvent::CveCritical => "Critical CVEs found",
+        };
+        match &self.commit {
+            Some(commit) => format!(
+                "{} for {} ({}): {}",
+                what,
+                self.derivation_name,
+                commit.chars().take(8).collect::<String>(),
+                self.summary
+            ),
+            None => format!("{} for {}: {}", what, self.derivation_name, self.summary),
+        }
+    }
+}
+
+/// Trim an error down to something that fits in a chat message
+fn summarize(error: &str) -> String {
+    let error = error.trim();
+    if error.chars().count() <= MAX_SUMMARY_CHARS {
+        return error.to_string();
+    }
+    let truncated: String = error.chars().take(MAX_SUMMARY_CHARS).collect();
+    format!("{}…", truncated)
+}
+
+/// Something that can deliver a [`Notification`]
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<()>>;
+}
+
+/// POSTs notifications as JSON to a webhook. The `text` field makes the
+/// payload directly usable as a Slack incoming webhook message.
+pub struct WebhookNotifier {
+    url: String,
+    events: HashSet<NotificationEvent>,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    /// Build a notifier from config, or `None` if no URL is configured
+    pub fn from_config(config: &NotificationConfig) -> Option<Self> {
+        let url = config.url.clone()?;
+        Some(Self {
+            url,
+            events: config.events.iter().copied().collect(),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn payload(notification: &Notification) -> serde_json::Value {
+        serde_json::json!({
+            "text": notification.text(),
+            "event": notification.event,
+            "derivation_name": notification.derivation_name,
+            "commit": notification.commit,
+            "summary": notification.summary,
+        })
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if !self.events.contains(&notification.event) {
+                return Ok(());
+            }
+
+            let resp = self
+                .client
+                .post(&self.url)
+                .json(&Self::payload(notification))
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                bail!("webhook responded with {}", resp.status());
+            }
+            Ok(())
+        })
+    }
+}
+
+static NOTIFIER: OnceLock<Option<Arc<dyn Notifier>>> = OnceLock::new();
+
+/// The process-wide notifier, built from the loaded config on first use.
+/// A config that fails to load is not remembered, so the next call tries
+/// again instead of disabling notifications for the life of the process.
+pub fn get_notifier() -> Option<&'static Arc<dyn Notifier>> {
+    if let Some(notifier) = NOTIFIER.get() {
+        return notifier.as_ref();
+    }
+
+    let config = match CrystalForgeConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("⚠️ Failed to load config for notifications: {}", e);
+            return None;
+        }
+    };
+    let notifier = WebhookNotifier::from_config(&config.notifications)
+        .map(|notifier| Arc::new(notifier) as Arc<dyn Notifier>);
+    NOTIFIER.get_or_init(|| notifier).as_ref()
+}
+
+/// Send a notification if notifications are configured. Delivery failures
+/// are logged and otherwise ignored so alerting never breaks the caller.
+pub async fn notify(notification: Notification) {
+    let Some(notifier) = get_notifier() else {
+        return;
+    };
+    if let Err(e) = notifier.notify(&notification).await {
+        warn!(
+            "⚠️ Failed to send {:?} notification for {}: {}",
+            notification.event, notification.derivation_name, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_carries_name_commit_and_summary() {
+        let notification = Notification::new(
+            NotificationEvent::BuildFailed,
+            "web01",
+            Some("0123456789abcdef".to_string()),
+            &format!(
+                "  builder for '/nix/store/x.drv' failed\n{}",
+                "x".repeat(600)
+            ),
+        );
+
+        assert!(notification.summary.starts_with("builder for"));
+        assert_eq!(notification.summary.chars().count(), MAX_SUMMARY_CHARS + 1);
+
+        let payload = WebhookNotifier::payload(&notification);
+        assert_eq!(payload["event"], "build_failed");
+        assert_eq!(payload["derivation_name"], "web01");
+        assert_eq!(payload["commit"], "0123456789abcdef");
+        assert!(
+            payload["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("Build failed for web01 (01234567): builder for")
+        );
+    }
+}
@@ -1,5 +1,6 @@
+use crate::config::SchedulingMode;
 use crate::derivations::Derivation;
-use crate::queries::derivations::EvaluationStatus;
+use crate::queries::derivations::{EvaluationStatus, lock_derivation};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -52,6 +53,35 @@ pub struct QueueStatus {
     pub has_stale_workers: bool,
 }
 
+/// A running build that has gone quiet for longer than the watchdog threshold
+#[derive(Debug, Serialize)]
+pub struct StuckBuild {
+    pub derivation_id: i32,
+    pub derivation_name: String,
+    pub worker_id: String,
+    pub started_at: Option<DateTime<Utc>>,
+    pub build_current_target: Option<String>,
+    /// Phase the build was last seen in, e.g. `download`
+    pub build_phase: Option<String>,
+    pub build_last_heartbeat: Option<DateTime<Utc>>,
+    /// Seconds since the build last produced output, including the time since
+    /// its last heartbeat so a worker that stopped heartbeating is caught too
+    pub quiet_seconds: i64,
+}
+
+/// A reservation currently held by a worker, for operators
+#[derive(Debug, Serialize)]
+pub struct ActiveReservation {
+    pub worker_id: String,
+    pub derivation_id: i32,
+    pub derivation_name: String,
+    pub status_id: i32,
+    pub reserved_at: DateTime<Utc>,
+    pub heartbeat_at: DateTime<Utc>,
+    /// Seconds since the worker last heartbeated this reservation
+    pub heartbeat_age_seconds: i64,
+}
+
 /// Create a new build reservation
 pub async fn create_reservation(
     pool: &PgPool,
@@ -108,6 +138,119 @@ where
     Ok(())
 }
 
+/// Release a reservation without finishing the build (e.g. on shutdown)
+///
+/// The derivation goes back to dry-run-complete so another worker can pick it
+/// up immediately, and the aborted attempt is not counted against it.
+pub async fn release_reservation(pool: &PgPool, worker_id: &str, derivation_id: i32) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    lock_derivation(&mut *tx, derivation_id).await?;
+    delete_reservation(&mut *tx, worker_id, derivation_id).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE derivations
+        SET status_id = $1,
+            started_at = NULL,
+            attempt_count = GREATEST(COALESCE(attempt_count, 0) - 1, 0)
+        WHERE id = $2
+          AND status_id = $3
+        "#,
+        EvaluationStatus::DryRunComplete.as_id(),
+        derivation_id,
+        EvaluationStatus::BuildInProgress.as_id()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    info!(
+        "Worker {} released reservation on derivation {}",
+        worker_id, derivation_id
+    );
+
+    Ok(())
+}
+
+/// Every reservation currently held, stalest heartbeat first
+pub async fn list_active_reservations(pool: &PgPool) -> Result<Vec<ActiveReservation>> {
+    let reservations = sqlx::query_as!(
+        ActiveReservation,
+        r#"
+        SELECT
+            br.worker_id,
+            br.derivation_id,
+            d.derivation_name,
+            d.status_id,
+            br.reserved_at,
+            br.heartbeat_at,
+            EXTRACT(EPOCH FROM NOW() - br.heartbeat_at)::bigint AS "heartbeat_age_seconds!"
+        FROM build_reservations br
+        JOIN derivations d ON d.id = br.derivation_id
+        ORDER BY br.heartbeat_at ASC, br.id ASC
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(reservations)
+}
+
+/// Drop whatever reservation is held on `derivation_id`, regardless of which
+/// worker holds it, e.g. when that worker's host is dead and waiting for the
+/// lease to expire isn't acceptable.
+///
+/// An in-progress derivation goes back to dry-run-complete so another worker
+/// can claim it. Returns the worker that held the reservation, or `None` if
+/// there was none.
+pub async fn force_release_reservation(
+    pool: &PgPool,
+    derivation_id: i32,
+) -> Result<Option<String>> {
+    let mut tx = pool.begin().await?;
+
+    lock_derivation(&mut *tx, derivation_id).await?;
+    let worker_id = sqlx::query_scalar!(
+        r#"
+        DELETE FROM build_reservations
+        WHERE derivation_id = $1
+        RETURNING worker_id
+        "#,
+        derivation_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if worker_id.is_some() {
+        sqlx::query!(
+            r#"
+            UPDATE derivations
+            SET status_id = $1, started_at = NULL
+            WHERE id = $2
+              AND status_id = $3
+            "#,
+            EvaluationStatus::DryRunComplete.as_id(),
+            derivation_id,
+            EvaluationStatus::BuildInProgress.as_id()
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    if let Some(worker_id) = &worker_id {
+        warn!(
+            "Force-released reservation of worker {} on derivation {}",
+            worker_id, derivation_id
+        );
+    }
+
+    Ok(worker_id)
+}
+
 /// Update heartbeat for a worker's reservations
 pub async fn update_heartbeat(pool: &PgPool, worker_id: &str) -> Result<u64> {
     let result = sqlx::query!(
@@ -178,87 +321,151 @@ pub async fn cleanup_stale_reservations(
 /// - Still being evaluated (DryRunPending, DryRunInProgress)
 /// - Already building (BuildInProgress)
 /// - Failed (DryRunFailed, BuildFailed)
-pub async fn claim_next_derivation(pool: &PgPool, worker_id: &str) -> Result<Option<Derivation>> {
+///
+/// Ordering comes from view_buildable_derivations: packages shared by several
+/// queued systems are handed out before the systems themselves, and a system
+/// is not offered while one of its dependencies is being built.
+///
+/// With [`SchedulingMode::Fair`] the commit with the fewest reservations goes
+/// first, so a large new commit can't starve an older commit's last builds.
+///
+/// Only derivations for one of `systems` are claimed. A package without a
+/// recorded system inherits the one of the system that needs it; derivations
+/// with no known system at all go to any builder.
+///
+/// A derivation whose last attempt failed on `hostname` is only handed back
+/// to that host once it has nothing else to claim, so a retry after a
+/// machine-local failure usually lands on another builder.
+///
+/// Derivations with required labels are only claimed when all of them are
+/// among `labels`; unlabeled derivations go to any builder.
+pub async fn claim_next_derivation(
+    pool: &PgPool,
+    worker_id: &str,
+    hostname: &str,
+    scheduling: SchedulingMode,
+    systems: &[String],
+    labels: &[String],
+) -> Result<Option<Derivation>> {
     let mut tx = pool.begin().await?;
 
     // 1) Use the view query directly within the transaction to get correct ordering
-    let buildable = sqlx::query_as!(
-        BuildableDerivation,
-        r#"
-        SELECT 
-            id as "id!",
-            derivation_name as "derivation_name!",
-            derivation_type as "derivation_type!",
-            derivation_path,
-            status_id as "status_id!",
-            nixos_id,
-            nixos_commit_ts,
-            active_workers,
-            queue_position
-        FROM view_buildable_derivations
-        ORDER BY queue_position
-        LIMIT 1
-        "#
-    )
-    .fetch_optional(&mut *tx)
-    .await?;
+    let buildable = match scheduling {
+        SchedulingMode::Fifo => {
+            sqlx::query_as!(
+                BuildableDerivation,
+                r#"
+                SELECT
+                    v.id AS "id!", v.derivation_name AS "derivation_name!",
+                    v.derivation_type AS "derivation_type!", v.derivation_path,
+                    v.status_id AS "status_id!", v.nixos_id, v.nixos_commit_ts, v.active_workers,
+                    v.queue_position
+                FROM view_buildable_derivations v
+                JOIN derivations d ON d.id = v.id
+                LEFT JOIN derivations n ON n.id = v.nixos_id
+                WHERE (COALESCE(d.system_arch, n.system_arch) IS NULL
+                       OR COALESCE(d.system_arch, n.system_arch) = ANY($1))
+                  AND d.required_labels <@ $3::text[]
+                ORDER BY d.failed_on_host IS NOT DISTINCT FROM $2, v.queue_position
+                LIMIT 1
+                "#,
+                systems,
+                hostname,
+                labels
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+        }
+        SchedulingMode::Fair => {
+            sqlx::query_as!(
+                BuildableDerivation,
+                r#"
+                WITH active_per_commit AS (
+                    SELECT n.commit_id, COUNT(*) AS active
+                    FROM build_reservations br
+                    JOIN derivations n ON n.id = br.nixos_derivation_id
+                    GROUP BY n.commit_id
+                )
+                SELECT
+                    v.id AS "id!", v.derivation_name AS "derivation_name!",
+                    v.derivation_type AS "derivation_type!", v.derivation_path,
+                    v.status_id AS "status_id!", v.nixos_id, v.nixos_commit_ts,
+                    COALESCE(a.active, 0) AS active_workers, v.queue_position
+                FROM view_buildable_derivations v
+                JOIN derivations d ON d.id = v.id
+                LEFT JOIN derivations n ON n.id = v.nixos_id
+                LEFT JOIN active_per_commit a ON a.commit_id = n.commit_id
+                WHERE (COALESCE(d.system_arch, n.system_arch) IS NULL
+                       OR COALESCE(d.system_arch, n.system_arch) = ANY($1))
+                  AND d.required_labels <@ $3::text[]
+                ORDER BY
+                    d.failed_on_host IS NOT DISTINCT FROM $2,
+                    COALESCE(a.active, 0),
+                    v.queue_position
+                LIMIT 1
+                "#,
+                systems,
+                hostname,
+                labels
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+        }
+    };
 
     let Some(buildable) = buildable else {
         tx.rollback().await?;
         return Ok(None);
     };
 
-    // 2) Atomically try to reserve it
-    let reserved = sqlx::query!(
+    // 2) Start the build and reserve it in one statement, so no crash can
+    // leave a reservation on a pending row or an in-progress row without
+    // one. Progress left over from an earlier attempt is reset, so the
+    // attempt doesn't look stuck before its first build heartbeat.
+    let claimed = sqlx::query_scalar!(
         r#"
+        WITH started AS (
+            UPDATE derivations
+            SET
+                status_id = $1,
+                started_at = NOW(),
+                attempt_count = COALESCE(attempt_count, 0) + 1,
+                build_elapsed_seconds = 0,
+                build_current_target = NULL,
+                build_last_activity_seconds = 0,
+                build_last_heartbeat = NOW(),
+                build_phase = NULL,
+                build_phase_seconds = NULL
+            WHERE id = $2
+              AND status_id IN ($3, $4)
+              AND NOT EXISTS (
+                  SELECT 1 FROM build_reservations WHERE derivation_id = $2
+              )
+            RETURNING id
+        )
         INSERT INTO build_reservations (worker_id, derivation_id, nixos_derivation_id)
-        VALUES ($1, $2, $3)
+        SELECT $5, id, $6 FROM started
         ON CONFLICT (derivation_id) DO NOTHING
         RETURNING derivation_id
         "#,
-        worker_id,
-        buildable.id,
-        buildable.nixos_id,
-    )
-    .fetch_optional(&mut *tx)
-    .await?;
-
-    if reserved.is_none() {
-        tx.rollback().await?;
-        return Ok(None);
-    }
-
-    // 3) Update status to BuildInProgress
-    let updated = sqlx::query!(
-        r#"
-        UPDATE derivations
-        SET 
-            status_id = $1,
-            started_at = NOW(),
-            attempt_count = COALESCE(attempt_count, 0) + 1
-        WHERE id = $2
-          AND status_id IN ($3, $4)
-        "#,
         EvaluationStatus::BuildInProgress.as_id(),
         buildable.id,
         EvaluationStatus::DryRunComplete.as_id(),
         EvaluationStatus::BuildPending.as_id(),
+        worker_id,
+        buildable.nixos_id
     )
-    .execute(&mut *tx)
+    .fetch_optional(&mut *tx)
     .await?;
 
-    if updated.rows_affected() != 1 {
-        sqlx::query!(
-            r#"DELETE FROM build_reservations WHERE derivation_id = $1"#,
-            buildable.id
-        )
-        .execute(&mut *tx)
-        .await?;
+    if claimed.is_none() {
+        // Another worker got there first; undo a status change whose
+        // reservation lost the race
         tx.rollback().await?;
         return Ok(None);
     }
 
-    // 4) Fetch the full Derivation record
+    // 3) Fetch the full Derivation record
     let derivation = sqlx::query_as!(
         Derivation,
         r#"
@@ -389,3 +596,52 @@ pub async fn get_active_worker_count(pool: &PgPool) -> Result<i64> {
 
     Ok(count)
 }
+
+/// Find in-progress builds held by workers matching `worker_pattern` (a SQL
+/// `LIKE` pattern) that have been quiet for at least `threshold_seconds`
+pub async fn find_stuck_builds(
+    pool: &PgPool,
+    worker_pattern: &str,
+    threshold_seconds: i64,
+) -> Result<Vec<StuckBuild>> {
+    let stuck = sqlx::query_as!(
+        StuckBuild,
+        r#"
+        SELECT
+            derivation_id AS "derivation_id!",
+            derivation_name AS "derivation_name!",
+            worker_id AS "worker_id!",
+            started_at,
+            build_current_target,
+            build_phase,
+            build_last_heartbeat,
+            quiet_seconds AS "quiet_seconds!"
+        FROM (
+            SELECT
+                d.id AS derivation_id,
+                d.derivation_name,
+                br.worker_id,
+                d.started_at,
+                d.build_current_target,
+                d.build_phase,
+                d.build_last_heartbeat,
+                (COALESCE(d.build_last_activity_seconds, 0)
+                    + EXTRACT(EPOCH FROM NOW() - COALESCE(d.build_last_heartbeat, d.started_at, br.reserved_at)))::bigint
+                    AS quiet_seconds
+            FROM derivations d
+            JOIN build_reservations br ON br.derivation_id = d.id
+            WHERE d.status_id = $1
+              AND br.worker_id LIKE $2
+        ) builds
+        WHERE quiet_seconds >= $3
+        ORDER BY quiet_seconds DESC
+        "#,
+        EvaluationStatus::BuildInProgress.as_id(),
+        worker_pattern,
+        threshold_seconds
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(stuck)
+}
@@ -1,8 +1,10 @@
 use crate::builder::remove_gc_root;
+use crate::log::redact::redact;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use sqlx::{FromRow, PgPool};
-use tracing::{debug, warn};
+use std::collections::HashMap;
+use tracing::{debug, info, warn};
 
 #[derive(Debug, FromRow, Clone)]
 pub struct CachePushJob {
@@ -18,6 +20,8 @@ pub struct CachePushJob {
     pub push_size_bytes: Option<i64>,
     pub push_duration_ms: Option<i32>,
     pub cache_destination: Option<String>,
+    /// Derivation output to push; `None` means `out`
+    pub output_name: Option<String>,
 }
 
 /// Get derivations that need cache pushing (build-complete status)
@@ -65,14 +69,18 @@ pub async fn create_cache_push_job(
     derivation_id: i32,
     store_path: &str,
     cache_destination: Option<&str>,
+    output_name: Option<&str>,
 ) -> Result<i32> {
-    // First, try to find an existing pending or in-progress job
+    // First, try to find an existing pending or in-progress job for this destination
     if let Some(existing_job_id) = sqlx::query_scalar!(
         r#"
         SELECT id FROM cache_push_jobs 
         WHERE derivation_id = $1 AND status IN ('pending', 'in_progress')
+          AND cache_destination IS NOT DISTINCT FROM $2
+        LIMIT 1
         "#,
-        derivation_id
+        derivation_id,
+        cache_destination
     )
     .fetch_optional(pool)
     .await?
@@ -89,10 +97,12 @@ pub async fn create_cache_push_job(
         r#"
         SELECT id FROM cache_push_jobs 
         WHERE derivation_id = $1 AND status = 'failed' AND attempts < 5
+          AND cache_destination IS NOT DISTINCT FROM $2
         ORDER BY scheduled_at DESC
         LIMIT 1
         "#,
-        derivation_id
+        derivation_id,
+        cache_destination
     )
     .fetch_optional(pool)
     .await?
@@ -104,12 +114,15 @@ pub async fn create_cache_push_job(
             SET status = 'pending', 
                 store_path = $2,
                 cache_destination = $3,
+                output_name = $4,
+                priority = cache_push_priority(derivation_id),
                 scheduled_at = NOW()
             WHERE id = $1
             "#,
             failed_job_id,
             store_path,
-            cache_destination
+            cache_destination,
+            output_name
         )
         .execute(pool)
         .await?;
@@ -125,13 +138,14 @@ pub async fn create_cache_push_job(
     let job_id = sqlx::query_scalar!(
         r#"
         INSERT INTO cache_push_jobs (
-            derivation_id, store_path, cache_destination, status
-        ) VALUES ($1, $2, $3, 'pending')
+            derivation_id, store_path, cache_destination, output_name, status, priority
+        ) VALUES ($1, $2, $3, $4, 'pending', cache_push_priority($1))
         RETURNING id
         "#,
         derivation_id,
         store_path,
-        cache_destination
+        cache_destination,
+        output_name
     )
     .fetch_one(pool)
     .await?;
@@ -143,6 +157,52 @@ pub async fn create_cache_push_job(
     Ok(job_id)
 }
 
+/// Split an environment → destination map into parallel arrays, bound as
+/// `UNNEST($2::text[], $3::text[])` by the destination queries
+pub(crate) fn environment_destination_arrays(
+    environment_destinations: &HashMap<String, String>,
+) -> (Vec<String>, Vec<String>) {
+    environment_destinations
+        .iter()
+        .map(|(env, dest)| (env.clone(), dest.clone()))
+        .unzip()
+}
+
+/// Cache destinations a derivation should be pushed to, based on the
+/// environments of the systems it belongs to. Environments without an
+/// override, and derivations without an environment, use
+/// `default_destination`.
+pub async fn cache_destinations_for_derivation(
+    pool: &PgPool,
+    derivation_id: i32,
+    default_destination: &str,
+    environment_destinations: &HashMap<String, String>,
+) -> Result<Vec<String>> {
+    let (env_names, destinations) = environment_destination_arrays(environment_destinations);
+    let resolved = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT COALESCE(ed.destination, $1) AS "destination!"
+        FROM view_derivation_environments de
+        LEFT JOIN UNNEST($2::text[], $3::text[]) AS ed(env_name, destination)
+            ON ed.env_name = de.environment_name
+        WHERE de.derivation_id = $4
+        ORDER BY 1
+        "#,
+        default_destination,
+        &env_names,
+        &destinations,
+        derivation_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if resolved.is_empty() {
+        Ok(vec![default_destination.to_string()])
+    } else {
+        Ok(resolved)
+    }
+}
+
 /// Mark cache push job as in progress
 pub async fn mark_cache_push_in_progress(pool: &PgPool, job_id: i32) -> Result<()> {
     sqlx::query!(
@@ -213,6 +273,8 @@ pub async fn mark_cache_push_completed(
 
 /// Mark cache push job as failed with exponential backoff
 pub async fn mark_cache_push_failed(pool: &PgPool, job_id: i32, error_message: &str) -> Result<()> {
+    let error_message = &*redact(error_message);
+
     // Get current attempt count to calculate retry delay
     let attempts =
         sqlx::query_scalar!("SELECT attempts FROM cache_push_jobs WHERE id = $1", job_id)
@@ -275,6 +337,64 @@ pub async fn mark_cache_push_failed(pool: &PgPool, job_id: i32, error_message: &
     Ok(())
 }
 
+/// Fail a cache push job for good, without retries, leaving it for an
+/// operator to review (e.g. a path too large to push)
+pub async fn mark_cache_push_rejected(pool: &PgPool, job_id: i32, reason: &str) -> Result<()> {
+    let reason = &*redact(reason);
+
+    sqlx::query!(
+        r#"
+        UPDATE cache_push_jobs 
+        SET 
+            status = 'permanently_failed',
+            completed_at = NOW(),
+            error_message = $2,
+            retry_after = NULL
+        WHERE id = $1
+        "#,
+        job_id,
+        reason
+    )
+    .execute(pool)
+    .await?;
+
+    warn!("🚫 Rejected cache push job {}: {}", job_id, reason);
+    Ok(())
+}
+
+/// Defer a cache push job without consuming an attempt.
+///
+/// Used when the destination's circuit breaker is open: the job is parked
+/// until `defer_seconds` from now and then picked up like a pending job.
+pub async fn mark_cache_push_deferred(
+    pool: &PgPool,
+    job_id: i32,
+    defer_seconds: u64,
+    reason: &str,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE cache_push_jobs 
+        SET 
+            status = 'deferred',
+            error_message = $2,
+            retry_after = NOW() + make_interval(secs => $3)
+        WHERE id = $1
+        "#,
+        job_id,
+        reason,
+        defer_seconds as f64
+    )
+    .execute(pool)
+    .await?;
+
+    debug!(
+        "Deferred cache push job {} for {}s: {}",
+        job_id, defer_seconds, reason
+    );
+    Ok(())
+}
+
 /// Update derivation status to cache-pushed
 pub async fn mark_derivation_cache_pushed(pool: &PgPool, derivation_id: i32) -> Result<()> {
     sqlx::query!(
@@ -292,8 +412,10 @@ pub async fn mark_derivation_cache_pushed(pool: &PgPool, derivation_id: i32) ->
     Ok(())
 }
 
-/// Get pending cache push jobs, including failed jobs ready for retry
-/// Prioritizes jobs from newest commits first
+/// Get pending cache push jobs, including failed jobs ready for retry and
+/// deferred jobs whose destination cooldown has elapsed.
+/// Toplevels of systems waiting to deploy come first (the job's `priority`),
+/// then jobs from the newest commits
 pub async fn get_pending_cache_push_jobs(
     pool: &PgPool,
     limit: Option<i32>,
@@ -304,18 +426,20 @@ pub async fn get_pending_cache_push_jobs(
         SELECT 
             cpj.id, cpj.derivation_id, cpj.status, cpj.store_path, cpj.scheduled_at, cpj.started_at, 
             cpj.completed_at, cpj.attempts, cpj.error_message, cpj.push_size_bytes, 
-            cpj.push_duration_ms, cpj.cache_destination
+            cpj.push_duration_ms, cpj.cache_destination, cpj.output_name
         FROM cache_push_jobs cpj
         JOIN derivations d ON d.id = cpj.derivation_id
         JOIN commits c ON c.id = d.commit_id
         WHERE 
             (cpj.status = 'pending')
             OR 
-            (cpj.status = 'failed' AND cpj.retry_after IS NOT NULL AND cpj.retry_after <= NOW())
+            (cpj.status IN ('failed', 'deferred') AND cpj.retry_after IS NOT NULL AND cpj.retry_after <= NOW())
         ORDER BY 
+            cpj.priority DESC,
             CASE 
                 WHEN cpj.status = 'pending' THEN 0
-                WHEN cpj.status = 'failed' THEN 1
+                WHEN cpj.status = 'deferred' THEN 1
+                WHEN cpj.status = 'failed' THEN 2
             END,
             c.commit_timestamp DESC,
             d.completed_at ASC NULLS LAST
@@ -333,6 +457,23 @@ pub async fn get_pending_cache_push_jobs(
     Ok(jobs)
 }
 
+/// Cache push jobs still waiting to reach their destination: pending,
+/// running, deferred, or failed with a retry scheduled
+pub async fn count_cache_push_backlog(pool: &PgPool) -> Result<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM cache_push_jobs
+        WHERE status IN ('pending', 'in_progress', 'deferred')
+           OR (status = 'failed' AND retry_after IS NOT NULL)
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
 pub async fn cleanup_stale_cache_push_jobs(pool: &PgPool, timeout_minutes: i32) -> Result<()> {
     // Only clean up jobs that are truly stuck in 'in_progress' state
     // Don't touch 'failed' jobs that are waiting for retry
@@ -364,3 +505,153 @@ pub async fn cleanup_stale_cache_push_jobs(pool: &PgPool, timeout_minutes: i32)
 
     Ok(())
 }
+
+/// Requeue cache pushes for build-complete derivations that never made it
+/// into `destination`, e.g. after a cache outage exhausted their retries.
+///
+/// Only derivations whose store path still exists in the local store are
+/// requeued; anything garbage-collected since needs a rebuild instead.
+/// Failed jobs for the destination are reset with a fresh attempt budget,
+/// and derivations without one get a new pending job. Returns the number of
+/// derivations requeued.
+pub async fn requeue_missing_from_cache(pool: &PgPool, destination: &str) -> Result<usize> {
+    use crate::queries::derivations::EvaluationStatus;
+
+    let candidates = sqlx::query!(
+        r#"
+        SELECT d.id, d.store_path AS "store_path!"
+        FROM derivations d
+        WHERE d.status_id = $1
+          AND d.store_path IS NOT NULL
+          AND NOT EXISTS (
+              SELECT 1 FROM cache_push_jobs cpj
+              WHERE cpj.derivation_id = d.id
+                AND cpj.cache_destination = $2
+                AND cpj.status IN ('completed', 'pending', 'in_progress', 'deferred')
+          )
+        ORDER BY d.completed_at DESC NULLS LAST
+        "#,
+        EvaluationStatus::BuildComplete.as_id(),
+        destination
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut requeued = 0;
+    let mut missing_locally = 0;
+
+    for candidate in candidates {
+        let (derivation_id, store_path) = (candidate.id, candidate.store_path);
+        if !tokio::fs::try_exists(&store_path).await.unwrap_or(false) {
+            missing_locally += 1;
+            continue;
+        }
+
+        let mut tx = pool.begin().await?;
+        let reset = sqlx::query!(
+            r#"
+            UPDATE cache_push_jobs
+            SET status = 'pending',
+                attempts = 0,
+                store_path = $2,
+                priority = cache_push_priority(derivation_id),
+                error_message = NULL,
+                retry_after = NULL,
+                started_at = NULL,
+                completed_at = NULL,
+                scheduled_at = NOW()
+            WHERE id = (
+                SELECT id FROM cache_push_jobs
+                WHERE derivation_id = $1
+                  AND cache_destination = $3
+                  AND status IN ('failed', 'permanently_failed')
+                ORDER BY scheduled_at DESC
+                LIMIT 1
+            )
+            "#,
+            derivation_id,
+            &store_path,
+            destination
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        // A push queued meanwhile (e.g. by a builder) already covers it
+        let queued = if reset == 0 {
+            sqlx::query!(
+                r#"
+                INSERT INTO cache_push_jobs (derivation_id, store_path, cache_destination, status, priority)
+                VALUES ($1, $2, $3, 'pending', cache_push_priority($1))
+                ON CONFLICT DO NOTHING
+                "#,
+                derivation_id,
+                &store_path,
+                destination
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+        } else {
+            reset
+        };
+        tx.commit().await?;
+        if queued > 0 {
+            requeued += 1;
+        }
+    }
+
+    info!(
+        "📤 Requeued {} derivations missing from {} ({} skipped, no longer in local store)",
+        requeued, destination, missing_locally
+    );
+
+    Ok(requeued)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    #[tokio::test]
+    async fn queues_one_pending_job_per_destination() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let derivation_id: i32 = sqlx::query_scalar(
+            r#"
+            INSERT INTO derivations (derivation_type, derivation_name, status_id)
+            VALUES ('package', $1, 10)
+            RETURNING id
+            "#,
+        )
+        .bind(format!("cache-push-test-{}", uuid::Uuid::new_v4()))
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let store_path = "/nix/store/aaa-cache-push-test";
+
+        let dev = create_cache_push_job(&pool, derivation_id, store_path, Some("s3://dev"), None)
+            .await
+            .unwrap();
+        let prod = create_cache_push_job(&pool, derivation_id, store_path, Some("s3://prod"), None)
+            .await
+            .unwrap();
+        assert_ne!(dev, prod);
+        // Queueing a destination again reuses its job
+        let again = create_cache_push_job(&pool, derivation_id, store_path, Some("s3://dev"), None)
+            .await
+            .unwrap();
+        assert_eq!(again, dev);
+
+        let pending: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM cache_push_jobs WHERE derivation_id = $1 AND status = 'pending'",
+        )
+        .bind(derivation_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(pending, 2);
+    }
+}
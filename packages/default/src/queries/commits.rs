@@ -1,7 +1,9 @@
 use crate::models::commits::Commit;
 use crate::models::flakes::Flake;
+use crate::queries::derivations::EvaluationStatus;
 use anyhow::{Context, Result};
 use sqlx::PgPool;
+use std::collections::HashMap;
 use tracing::{debug, error, info, warn};
 
 pub async fn insert_commit(
@@ -45,36 +47,65 @@ pub async fn get_commit_by_id(pool: &PgPool, id: i32) -> Result<Commit> {
     Ok(commit)
 }
 
-pub async fn get_commits_pending_evaluation(pool: &PgPool) -> Result<Vec<Commit>> {
+/// Commits waiting for (re-)evaluation, oldest first so a backlog is caught
+/// up chronologically. At most `per_flake_limit` commits of each flake are
+/// returned (0 for no limit), so a newly onboarded flake with a long history
+/// is worked through in chunks without holding up the others. `flake_id`
+/// restricts the result to one flake. Commits that have used up
+/// `max_attempts` are left alone; see [`reset_eval_failed`].
+pub async fn get_commits_pending_evaluation(
+    pool: &PgPool,
+    max_attempts: i32,
+    flake_id: Option<i32>,
+    per_flake_limit: usize,
+) -> Result<Vec<Commit>> {
+    let limit = (per_flake_limit > 0).then_some(per_flake_limit as i64);
     let rows = sqlx::query_as!(
         Commit,
         r#"
-        SELECT c.id, c.flake_id, c.git_commit_hash, c.commit_timestamp, c.attempt_count
-        FROM commits c
-        LEFT JOIN derivations d ON c.id = d.commit_id
-        WHERE d.commit_id IS NULL
-        AND c.evaluation_status = 'pending'
-        AND COALESCE(c.evaluation_attempt_count, 0) < 5
-        AND (
-            c.evaluation_started_at IS NULL
-            OR (
-                -- Attempts 1-3: retry after 1 minute
-                COALESCE(c.evaluation_attempt_count, 0) < 3 
-                AND c.evaluation_started_at < NOW() - INTERVAL '1 minute'
-            )
-            OR (
-                -- Attempt 4: retry after 1 hour
-                c.evaluation_attempt_count = 3
-                AND c.evaluation_started_at < NOW() - INTERVAL '1 hour'
-            )
-            OR (
-                -- Attempt 5: retry after 2 hours
-                c.evaluation_attempt_count = 4
-                AND c.evaluation_started_at < NOW() - INTERVAL '2 hours'
+        SELECT
+            id AS "id!",
+            flake_id AS "flake_id!",
+            git_commit_hash AS "git_commit_hash!",
+            commit_timestamp AS "commit_timestamp!",
+            attempt_count AS "attempt_count!"
+        FROM (
+            SELECT
+                c.id, c.flake_id, c.git_commit_hash, c.commit_timestamp, c.attempt_count,
+                ROW_NUMBER() OVER (
+                    PARTITION BY c.flake_id ORDER BY c.commit_timestamp ASC, c.id ASC
+                ) AS flake_position
+            FROM commits c
+            LEFT JOIN derivations d ON c.id = d.commit_id
+            WHERE d.commit_id IS NULL
+            AND c.evaluation_status = 'pending'
+            AND COALESCE(c.evaluation_attempt_count, 0) < $1
+            AND ($2::int IS NULL OR c.flake_id = $2)
+            AND (
+                c.evaluation_started_at IS NULL
+                OR (
+                    -- Attempts 1-3: retry after 1 minute
+                    COALESCE(c.evaluation_attempt_count, 0) < 3 
+                    AND c.evaluation_started_at < NOW() - INTERVAL '1 minute'
+                )
+                OR (
+                    -- Attempt 4: retry after 1 hour
+                    c.evaluation_attempt_count = 3
+                    AND c.evaluation_started_at < NOW() - INTERVAL '1 hour'
+                )
+                OR (
+                    -- Attempt 5 and later: retry after 2 hours
+                    c.evaluation_attempt_count >= 4
+                    AND c.evaluation_started_at < NOW() - INTERVAL '2 hours'
+                )
             )
-        )
-        ORDER BY c.commit_timestamp DESC
-        "#
+        ) pending
+        WHERE $3::bigint IS NULL OR flake_position <= $3
+        ORDER BY commit_timestamp ASC, id ASC
+        "#,
+        max_attempts,
+        flake_id,
+        limit
     )
     .fetch_all(pool)
     .await?;
@@ -163,6 +194,55 @@ pub async fn get_commit_distance_from_head(
     Ok(distance)
 }
 
+/// Fetch several commits in one round trip. Unknown ids are skipped.
+pub async fn get_commits_by_ids(pool: &PgPool, ids: &[i32]) -> Result<Vec<Commit>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let commits = sqlx::query_as::<_, Commit>(
+        r#"
+        SELECT id, flake_id, git_commit_hash, commit_timestamp, attempt_count
+        FROM commits
+        WHERE id = ANY($1)
+        "#,
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(commits)
+}
+
+/// Batched form of [`get_commit_distance_from_head`]: commit id -> number of
+/// newer commits on the same flake (0 for HEAD).
+pub async fn get_commit_distances_from_head(
+    pool: &PgPool,
+    ids: &[i32],
+) -> Result<HashMap<i32, i32>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows: Vec<(i32, i32)> = sqlx::query_as(
+        r#"
+        SELECT
+            c.id,
+            (
+                SELECT COUNT(*)
+                FROM commits newer
+                WHERE newer.flake_id = c.flake_id
+                  AND newer.commit_timestamp > c.commit_timestamp
+            )::int AS distance
+        FROM commits c
+        WHERE c.id = ANY($1)
+        "#,
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().collect())
+}
+
 /// Reset commits stuck in 'in_progress' state (from crashed evaluations)
 pub async fn reset_stuck_commit_evaluations(pool: &PgPool) -> Result<()> {
     let reset = sqlx::query!(
@@ -225,28 +305,332 @@ pub async fn mark_commit_evaluation_complete(pool: &PgPool, commit_id: i32) -> R
     Ok(())
 }
 
+/// Move evaluated commits whose derivations have all been built, or pushed
+/// to the cache, to `built`, including commits that had nothing to build.
+/// A `built` commit with a derivation that needs building again (e.g. its
+/// store path was collected) goes back to `complete`.
+///
+/// Returns the hashes of the commits that became `built`.
+pub async fn mark_fully_built_commits(pool: &PgPool) -> Result<Vec<String>> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE commits c
+        SET evaluation_status = 'complete'
+        WHERE c.evaluation_status = 'built'
+          AND EXISTS (
+              SELECT 1
+              FROM derivations d
+              WHERE d.commit_id = c.id
+                AND d.status_id <> $1
+                AND d.status_id <> (SELECT id FROM derivation_statuses WHERE name = 'cache-pushed')
+          )
+        "#,
+    )
+    .bind(EvaluationStatus::BuildComplete.as_id())
+    .execute(&mut *tx)
+    .await?;
+
+    let built = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE commits c
+        SET evaluation_status = 'built'
+        WHERE c.evaluation_status = 'complete'
+          AND NOT EXISTS (
+              SELECT 1
+              FROM derivations d
+              WHERE d.commit_id = c.id
+                AND d.status_id <> $1
+                AND d.status_id <> (SELECT id FROM derivation_statuses WHERE name = 'cache-pushed')
+          )
+        RETURNING c.git_commit_hash
+        "#,
+    )
+    .bind(EvaluationStatus::BuildComplete.as_id())
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(built)
+}
+
 /// Mark commit evaluation as failed (with retry logic)
+///
+/// Once `max_attempts` evaluations have failed the commit is dead-lettered
+/// as `eval_failed`. Returns `true` if that happened on this call.
 pub async fn mark_commit_evaluation_failed(
     pool: &PgPool,
     commit_id: i32,
     error: &str,
-) -> Result<()> {
-    sqlx::query!(
+    max_attempts: i32,
+) -> Result<bool> {
+    let status = sqlx::query_scalar!(
         r#"
         UPDATE commits
         SET 
             evaluation_status = CASE 
-                WHEN COALESCE(evaluation_attempt_count, 0) >= 5 THEN 'failed'
+                WHEN COALESCE(evaluation_attempt_count, 0) >= $3 THEN 'eval_failed'
                 ELSE 'pending'
             END,
             evaluation_error_message = $2
         WHERE id = $1
+        RETURNING evaluation_status
         "#,
         commit_id,
-        error
+        error,
+        max_attempts
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    Ok(status.as_deref() == Some("eval_failed"))
+}
+
+/// Dead-letter a commit as `eval_failed` without evaluating it, e.g. because
+/// its signature did not verify. Retrying would not change the outcome.
+pub async fn reject_commit_evaluation(pool: &PgPool, commit_id: i32, reason: &str) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE commits
+        SET
+            evaluation_status = 'eval_failed',
+            evaluation_error_message = $2
+        WHERE id = $1
+        "#,
+    )
+    .bind(commit_id)
+    .bind(reason)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Put a dead-lettered commit back in the evaluation queue with a fresh
+/// attempt budget. Returns `false` if the commit was not `eval_failed`.
+pub async fn reset_eval_failed(pool: &PgPool, commit_id: i32) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE commits
+        SET
+            evaluation_status = 'pending',
+            evaluation_attempt_count = 0,
+            evaluation_started_at = NULL,
+            evaluation_completed_at = NULL,
+            evaluation_error_message = NULL
+        WHERE id = $1
+          AND evaluation_status = 'eval_failed'
+        "#,
+        commit_id
+    )
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        info!("🔁 Commit {} re-queued for evaluation", commit_id);
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Never evaluate a commit, e.g. a known-broken one in old history, by
+/// moving it to the terminal `skipped` state. Only pending or dead-lettered
+/// commits can be skipped; returns `false` for any other. `skipped_by` and
+/// `reason` are kept in `commit_skip_log`.
+pub async fn mark_skip(
+    pool: &PgPool,
+    commit_id: i32,
+    skipped_by: &str,
+    reason: &str,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query(
+        r#"
+        UPDATE commits
+        SET
+            evaluation_status = 'skipped',
+            evaluation_error_message = $2
+        WHERE id = $1
+          AND evaluation_status IN ('pending', 'eval_failed')
+        "#,
     )
+    .bind(commit_id)
+    .bind(format!("skipped by {}: {}", skipped_by, reason))
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    log_commit_skip(&mut *tx, commit_id, "skip", skipped_by, Some(reason)).await?;
+
+    tx.commit().await?;
+    info!(
+        "⏭️ Commit {} skipped by {}: {}",
+        commit_id, skipped_by, reason
+    );
+    Ok(true)
+}
+
+/// Put a skipped commit back in the evaluation queue with a fresh attempt
+/// budget. Returns `false` if the commit was not `skipped`.
+pub async fn unskip(
+    pool: &PgPool,
+    commit_id: i32,
+    unskipped_by: &str,
+    reason: Option<&str>,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query(
+        r#"
+        UPDATE commits
+        SET
+            evaluation_status = 'pending',
+            evaluation_attempt_count = 0,
+            evaluation_started_at = NULL,
+            evaluation_completed_at = NULL,
+            evaluation_error_message = NULL
+        WHERE id = $1
+          AND evaluation_status = 'skipped'
+        "#,
+    )
+    .bind(commit_id)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    log_commit_skip(&mut *tx, commit_id, "unskip", unskipped_by, reason).await?;
+
+    tx.commit().await?;
+    info!(
+        "🔁 Commit {} unskipped by {} and re-queued for evaluation",
+        commit_id, unskipped_by
+    );
+    Ok(true)
+}
+
+async fn log_commit_skip<'e, E>(
+    executor: E,
+    commit_id: i32,
+    action: &str,
+    actor: &str,
+    reason: Option<&str>,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO commit_skip_log (commit_id, action, actor, reason)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(commit_id)
+    .bind(action)
+    .bind(actor)
+    .bind(reason)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// The most recent commit of the same flake, older than `commit`, whose
+/// evaluation finished. Used as the baseline for incremental evaluation.
+pub async fn get_previous_evaluated_commit(
+    pool: &PgPool,
+    commit: &Commit,
+) -> Result<Option<Commit>> {
+    let previous = sqlx::query_as::<_, Commit>(
+        r#"
+        SELECT id, flake_id, git_commit_hash, commit_timestamp, attempt_count
+        FROM commits
+        WHERE flake_id = $1
+          AND id <> $2
+          AND commit_timestamp < $3
+          AND evaluation_status IN ('complete', 'built')
+        ORDER BY commit_timestamp DESC, id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(commit.flake_id)
+    .bind(commit.id)
+    .bind(commit.commit_timestamp)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(previous)
+}
+
+/// Commit of `flake_id` whose evaluation is cached under `input_key`
+pub async fn get_cached_evaluation(
+    pool: &PgPool,
+    flake_id: i32,
+    input_key: &str,
+) -> Result<Option<Commit>> {
+    let cached = sqlx::query_as::<_, Commit>(
+        r#"
+        SELECT c.id, c.flake_id, c.git_commit_hash, c.commit_timestamp, c.attempt_count
+        FROM evaluation_cache ec
+        JOIN commits c ON c.id = ec.commit_id
+        WHERE ec.flake_id = $1
+          AND ec.input_key = $2
+          AND c.evaluation_status IN ('complete', 'built')
+        "#,
+    )
+    .bind(flake_id)
+    .bind(input_key)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(cached)
+}
+
+/// Remember `commit_id` as the evaluation of `input_key`, replacing an
+/// earlier commit with the same inputs
+pub async fn record_cached_evaluation(
+    pool: &PgPool,
+    flake_id: i32,
+    input_key: &str,
+    commit_id: i32,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO evaluation_cache (flake_id, input_key, commit_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (flake_id, input_key)
+        DO UPDATE SET commit_id = EXCLUDED.commit_id, created_at = NOW()
+        "#,
+    )
+    .bind(flake_id)
+    .bind(input_key)
+    .bind(commit_id)
     .execute(pool)
     .await?;
 
     Ok(())
 }
+
+/// Hash of the commit a derivation was evaluated from, if it belongs to one
+pub async fn get_commit_hash_for_derivation(
+    pool: &PgPool,
+    derivation_id: i32,
+) -> Result<Option<String>> {
+    let hash = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT c.git_commit_hash
+        FROM derivations d
+        JOIN commits c ON c.id = d.commit_id
+        WHERE d.id = $1
+        "#,
+    )
+    .bind(derivation_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(hash)
+}
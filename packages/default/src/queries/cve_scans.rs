@@ -1,12 +1,15 @@
 use crate::derivations::utils::get_store_path_from_drv;
 use crate::derivations::{Derivation, DerivationType};
 use crate::models::cve_scans::{CveScan, ScanStatus};
-use crate::vulnix::vulnix_parser::{VulnixParser, VulnixScanOutput};
+use crate::vulnix::vulnix_parser::{ScanStats, VulnixParser, VulnixScanOutput};
 use anyhow::Result;
 use bigdecimal::BigDecimal;
 use bigdecimal::FromPrimitive;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use sqlx::PgPool;
-use tracing::debug;
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, info};
 use uuid::Uuid;
 
 /// Get derivations that need CVE scanning
@@ -205,19 +208,36 @@ pub async fn mark_cve_scan_failed(
     Ok(())
 }
 
-/// Save complete scan results to database
+/// Save complete scan results to database, returning the actionable
+/// (non-whitelisted) statistics that were recorded
 pub async fn save_scan_results(
     pool: &PgPool,
     scan_id: Uuid,
     vulnix_results: &VulnixScanOutput,
     scan_duration_ms: Option<i32>,
-) -> Result<()> {
-    // Calculate statistics from vulnix results
-    let stats = VulnixParser::calculate_stats(vulnix_results);
-
+) -> Result<ScanStats> {
     // Start a transaction
     let mut tx = pool.begin().await?;
 
+    // Whitelisted CVEs are recorded but left out of the severity counts
+    let whitelist: HashMap<String, CveWhitelistEntry> = sqlx::query_as!(
+        CveWhitelistEntry,
+        r#"
+        SELECT cve_id, reason, expires_at
+        FROM cve_whitelist
+        WHERE expires_at IS NULL OR expires_at > NOW()
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|entry| (entry.cve_id.clone(), entry))
+    .collect();
+    let whitelisted_ids: HashSet<String> = whitelist.keys().cloned().collect();
+
+    // Calculate statistics from vulnix results
+    let stats = VulnixParser::calculate_actionable_stats(vulnix_results, &whitelisted_ids);
+
     // Update the scan record with completion data
     sqlx::query!(
         r#"
@@ -339,21 +359,30 @@ pub async fn save_scan_results(
             .execute(&mut *tx)
             .await?;
 
-            // Insert package vulnerability relationship using derivation_id
+            // Insert package vulnerability relationship using derivation_id.
+            // The whitelist flag is refreshed on every scan so expired
+            // entries surface again.
+            let accepted = whitelist.get(cve_id);
             sqlx::query!(
                 r#"
                 INSERT INTO package_vulnerabilities (
-                    derivation_id, cve_id, detection_method, is_whitelisted
+                    derivation_id, cve_id, detection_method, is_whitelisted,
+                    whitelist_reason, whitelist_expires_at
                 )
-                VALUES ($1, $2, $3, $4)
+                VALUES ($1, $2, $3, $4, $5, $6)
                 ON CONFLICT (derivation_id, cve_id) DO UPDATE SET
                     detection_method = EXCLUDED.detection_method,
+                    is_whitelisted = EXCLUDED.is_whitelisted,
+                    whitelist_reason = EXCLUDED.whitelist_reason,
+                    whitelist_expires_at = EXCLUDED.whitelist_expires_at,
                     updated_at = NOW()
                 "#,
                 package_derivation_id,
                 cve_id,
                 "vulnix",
-                false // Not whitelisted by default
+                accepted.is_some(),
+                accepted.map(|entry| entry.reason.as_str()),
+                accepted.and_then(|entry| entry.expires_at)
             )
             .execute(&mut *tx)
             .await?;
@@ -405,6 +434,50 @@ pub async fn save_scan_results(
     // Commit the transaction
     tx.commit().await?;
 
+    Ok(stats)
+}
+
+/// An accepted CVE risk from the `cve_whitelist` table
+#[derive(Debug, Clone, Serialize)]
+pub struct CveWhitelistEntry {
+    pub cve_id: String,
+    pub reason: String,
+    /// `None` accepts the risk indefinitely
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Whitelist a CVE until `expires_at`. Later scans still record findings for
+/// it but leave them out of the actionable severity counts; once the entry
+/// expires the CVE is counted again. Re-adding an entry replaces it.
+pub async fn add_whitelist(
+    pool: &PgPool,
+    cve_id: &str,
+    reason: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO cve_whitelist (cve_id, reason, expires_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (cve_id) DO UPDATE SET
+            reason = EXCLUDED.reason,
+            expires_at = EXCLUDED.expires_at,
+            updated_at = NOW()
+        "#,
+        cve_id,
+        reason,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+
+    info!(
+        "🛡️ Whitelisted {} until {}: {}",
+        cve_id,
+        expires_at.map_or_else(|| "further notice".to_string(), |t| t.to_rfc3339()),
+        reason
+    );
+
     Ok(())
 }
 
@@ -1,6 +1,14 @@
-use crate::models::systems::System;
-use anyhow::Result;
+use crate::config::CrystalForgeConfig;
+use crate::deployment::agent::DeploymentReport;
+use crate::models::systems::{DeploymentPolicy, System};
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::info;
+use uuid::Uuid;
 
 /// Get all systems that have deployment_policy set to 'auto_latest'
 pub async fn get_systems_with_auto_latest_policy(pool: &PgPool) -> Result<Vec<System>> {
@@ -36,6 +44,11 @@ pub async fn update_desired_target(
     hostname: &str,
     desired_target: Option<&str>,
 ) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    ensure_deployments_not_paused(&mut *tx).await?;
+    record_target_change(&mut *tx, hostname, desired_target, None).await?;
+
     // TODO: Update systems table to have desired store path instead of desired target or have both
     sqlx::query(
         r#"
@@ -46,12 +59,328 @@ pub async fn update_desired_target(
     )
     .bind(desired_target)
     .bind(hostname)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Trip the global kill switch: no system's desired target is changed, by
+/// auto_latest, pins or promotions, until [`resume_deployments`] is called
+pub async fn pause_deployments(pool: &PgPool) -> Result<()> {
+    set_deployments_paused(pool, true).await?;
+    info!("⏸️ Deployments paused");
+    Ok(())
+}
+
+/// Release the global kill switch set by [`pause_deployments`]
+pub async fn resume_deployments(pool: &PgPool) -> Result<()> {
+    set_deployments_paused(pool, false).await?;
+    info!("▶️ Deployments resumed");
+    Ok(())
+}
+
+async fn set_deployments_paused(pool: &PgPool, paused: bool) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO deployment_pause (id, paused, changed_at)
+        VALUES (true, $1, NOW())
+        ON CONFLICT (id) DO UPDATE SET paused = EXCLUDED.paused, changed_at = NOW()
+        "#,
+    )
+    .bind(paused)
     .execute(pool)
     .await?;
+    Ok(())
+}
+
+/// Whether the global kill switch is tripped
+pub async fn deployments_paused(pool: &PgPool) -> Result<bool> {
+    let paused = sqlx::query_scalar::<_, bool>("SELECT paused FROM deployment_pause WHERE id")
+        .fetch_optional(pool)
+        .await?;
+    Ok(paused.unwrap_or(false))
+}
+
+/// Fail if the kill switch is tripped in the config or the database. Run it
+/// in the transaction that changes desired targets: the flag stays locked
+/// until that commits, so a concurrent pause waits instead of being missed.
+async fn ensure_deployments_not_paused<'e, E>(executor: E) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let configured = CrystalForgeConfig::load()
+        .map(|cfg| cfg.deployment.paused)
+        .unwrap_or(false);
+    let paused =
+        sqlx::query_scalar::<_, bool>("SELECT paused FROM deployment_pause WHERE id FOR SHARE")
+            .fetch_optional(executor)
+            .await?
+            .unwrap_or(false);
 
+    if configured || paused {
+        bail!("Deployments are paused, not changing any desired target");
+    }
     Ok(())
 }
 
+/// Every distinct store path some system is meant to be running
+pub async fn get_desired_target_store_paths(pool: &PgPool) -> Result<Vec<String>> {
+    let paths = sqlx::query_scalar(
+        "SELECT DISTINCT desired_target FROM systems WHERE desired_target IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(paths)
+}
+
+/// One entry of the deployment audit trail
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct DeploymentEvent {
+    pub id: i64,
+    pub hostname: String,
+    pub event_type: String,
+    pub old_target: Option<String>,
+    pub new_target: Option<String>,
+    pub policy: Option<String>,
+    pub outcome: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Log that `hostname`'s desired_target is about to change to `new_target`.
+/// Must run before the systems row is updated so the old target is captured;
+/// `policy` defaults to the system's current deployment policy.
+async fn record_target_change<'e, E>(
+    executor: E,
+    hostname: &str,
+    new_target: Option<&str>,
+    policy: Option<&str>,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO deployment_events (hostname, event_type, old_target, new_target, policy)
+        SELECT hostname, 'target_set', desired_target, $2, COALESCE($3, deployment_policy)
+        FROM systems
+        WHERE hostname = $1
+          AND desired_target IS DISTINCT FROM $2
+        "#,
+    )
+    .bind(hostname)
+    .bind(new_target)
+    .bind(policy)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Log that the agent on `hostname` reported deploying `store_path`, and
+/// whether that is the target the forge asked for. Call it before the new
+/// system state is stored so the previously running path is the old target.
+pub async fn record_deployment_outcome(
+    pool: &PgPool,
+    hostname: &str,
+    store_path: Option<&str>,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO deployment_events (hostname, event_type, old_target, new_target, policy, outcome)
+        SELECT
+            s.hostname,
+            'deployed',
+            (
+                SELECT ss.store_path
+                FROM system_states ss
+                WHERE ss.hostname = s.hostname
+                ORDER BY ss.timestamp DESC
+                LIMIT 1
+            ),
+            $2,
+            s.deployment_policy,
+            CASE WHEN s.desired_target = $2 THEN 'succeeded' ELSE 'off_target' END
+        FROM systems s
+        WHERE s.hostname = $1
+        "#,
+    )
+    .bind(hostname)
+    .bind(store_path)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The last deployment result the agent on a host reported, with how far
+/// the host has converged on its desired target
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct LastDeploymentResult {
+    pub hostname: String,
+    pub target: Option<String>,
+    pub result: String,
+    pub error: Option<String>,
+    pub detail: serde_json::Value,
+    pub reported_at: DateTime<Utc>,
+    pub desired_target: Option<String>,
+    pub current_target: Option<String>,
+    /// "converged", "converging" or "failed"; `None` without a desired target
+    pub convergence: Option<String>,
+}
+
+/// Store the last deployment result reported by the agent on `hostname`.
+/// Repeats of the stored result keep their original `reported_at`.
+pub async fn record_deployment_result(
+    pool: &PgPool,
+    hostname: &str,
+    report: &DeploymentReport,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO deployment_results (hostname, target, result, error, detail)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (hostname) DO UPDATE SET
+            target = EXCLUDED.target,
+            result = EXCLUDED.result,
+            error = EXCLUDED.error,
+            detail = EXCLUDED.detail,
+            reported_at = NOW()
+        WHERE (deployment_results.target, deployment_results.detail)
+            IS DISTINCT FROM (EXCLUDED.target, EXCLUDED.detail)
+        "#,
+    )
+    .bind(hostname)
+    .bind(&report.target)
+    .bind(report.result.kind())
+    .bind(report.error())
+    .bind(serde_json::to_value(&report.result)?)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The last deployment result reported for `hostname`, if any
+pub async fn last_deployment_result(
+    pool: &PgPool,
+    hostname: &str,
+) -> Result<Option<LastDeploymentResult>> {
+    let result = sqlx::query_as::<_, LastDeploymentResult>(
+        r#"
+        SELECT
+            dr.hostname,
+            dr.target,
+            dr.result,
+            dr.error,
+            dr.detail,
+            dr.reported_at,
+            v.desired_target,
+            v.current_target,
+            v.convergence
+        FROM deployment_results dr
+        LEFT JOIN view_deployment_convergence v ON v.hostname = dr.hostname
+        WHERE dr.hostname = $1
+        "#,
+    )
+    .bind(hostname)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result)
+}
+
+/// Share of deployments reported within `window` whose system was copied
+/// from the binary cache rather than built on the host. `None` when no
+/// deployment in the window reported where it came from.
+pub async fn deployment_cache_hit_rate(pool: &PgPool, window: Duration) -> Result<Option<f64>> {
+    let rate = sqlx::query_scalar::<_, Option<f64>>(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE deployment_source = 'cache')::float8
+                / NULLIF(COUNT(*), 0)
+        FROM system_states
+        WHERE change_reason = 'cf_deployment'
+          AND deployment_source IS NOT NULL
+          AND timestamp > NOW() - make_interval(secs => $1)
+        "#,
+    )
+    .bind(window.as_secs_f64())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rate)
+}
+
+/// A page of `hostname`'s deployment events, newest first. Pass the smallest
+/// `id` of the previous page as `before_id` to get the next one.
+pub async fn get_deployment_events_for_host(
+    pool: &PgPool,
+    hostname: &str,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<DeploymentEvent>> {
+    let events = sqlx::query_as::<_, DeploymentEvent>(
+        r#"
+        SELECT id, hostname, event_type, old_target, new_target, policy, outcome, created_at
+        FROM deployment_events
+        WHERE hostname = $1
+          AND ($2::bigint IS NULL OR id < $2)
+        ORDER BY id DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(hostname)
+    .bind(before_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(events)
+}
+
+/// Hostnames of active auto_latest systems told to deploy a target they have
+/// not yet reported running, i.e. deployments still in flight. Systems not
+/// heard from (state change or heartbeat) within `last_seen_within` are left
+/// out, so a host that went offline mid-rollout doesn't hold a slot forever.
+pub async fn get_in_flight_deployments(
+    pool: &PgPool,
+    last_seen_within: Duration,
+) -> Result<Vec<String>> {
+    let hostnames = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT s.hostname
+        FROM systems s
+        JOIN LATERAL (
+            SELECT ss.id, ss.timestamp, ss.store_path
+            FROM system_states ss
+            WHERE ss.hostname = s.hostname
+            ORDER BY ss.timestamp DESC
+            LIMIT 1
+        ) current_state ON true
+        LEFT JOIN LATERAL (
+            SELECT MAX(ah.timestamp) AS timestamp
+            FROM agent_heartbeats ah
+            WHERE ah.system_state_id = current_state.id
+        ) heartbeat ON true
+        WHERE s.is_active = true
+          AND s.deployment_policy = 'auto_latest'
+          AND s.desired_target IS NOT NULL
+          AND s.desired_target IS DISTINCT FROM current_state.store_path
+          AND GREATEST(current_state.timestamp, heartbeat.timestamp)
+              >= NOW() - make_interval(secs => $1)
+        ORDER BY s.hostname
+        "#,
+    )
+    .bind(last_seen_within.as_secs_f64())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(hostnames)
+}
+
 /// Update the deployment policy for a system by hostname
 pub async fn update_deployment_policy(pool: &PgPool, hostname: &str, policy: &str) -> Result<()> {
     sqlx::query(
@@ -97,3 +426,457 @@ pub async fn get_systems_by_deployment_policy(pool: &PgPool, policy: &str) -> Re
 
     Ok(systems)
 }
+
+/// Pin a system to the deployable target it had at a specific commit.
+///
+/// Resolves the host's nixos derivation for `commit_hash` on the system's
+/// flake (it must have been pushed to cache, same as auto_latest), sets it as
+/// `desired_target`, and switches the policy to `pinned` so the auto_latest
+/// manager leaves the host alone. Returns the pinned store path. Refused
+/// while deployments are paused.
+pub async fn pin_system_to_commit(
+    pool: &PgPool,
+    hostname: &str,
+    commit_hash: &str,
+) -> Result<String> {
+    let mut tx = pool.begin().await?;
+
+    ensure_deployments_not_paused(&mut *tx).await?;
+
+    let flake_id: Option<i32> = sqlx::query_scalar(
+        r#"
+        SELECT flake_id
+        FROM systems
+        WHERE hostname = $1
+        "#,
+    )
+    .bind(hostname)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| anyhow!("Unknown system {}", hostname))?;
+
+    let flake_id =
+        flake_id.ok_or_else(|| anyhow!("System {} is not associated with a flake", hostname))?;
+
+    let store_path: Option<String> = sqlx::query_scalar(
+        r#"
+        SELECT d.store_path
+        FROM derivations d
+        JOIN commits c ON c.id = d.commit_id
+        JOIN cache_push_jobs cpj
+          ON cpj.derivation_id = d.id
+         AND cpj.status = 'completed'
+        WHERE c.flake_id = $1
+          AND c.git_commit_hash = $2
+          AND d.derivation_type = 'nixos'
+          AND d.derivation_name = $3
+          AND d.store_path IS NOT NULL
+        ORDER BY cpj.completed_at DESC NULLS LAST, d.id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(flake_id)
+    .bind(commit_hash)
+    .bind(hostname)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let store_path = store_path.ok_or_else(|| {
+        anyhow!(
+            "No deployable target for {} at commit {} (not evaluated, built, or cached yet)",
+            hostname,
+            commit_hash
+        )
+    })?;
+
+    let pinned = DeploymentPolicy::Pinned.to_string();
+    record_target_change(&mut *tx, hostname, Some(&store_path), Some(&pinned)).await?;
+
+    sqlx::query(
+        r#"
+        UPDATE systems
+        SET desired_target = $1,
+            deployment_policy = $2,
+            updated_at = NOW()
+        WHERE hostname = $3
+        "#,
+    )
+    .bind(&store_path)
+    .bind(DeploymentPolicy::Pinned.to_string())
+    .bind(hostname)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(store_path)
+}
+
+/// Where the store path a system is running came from
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct DeployedTargetLineage {
+    pub hostname: String,
+    pub store_path: String,
+    /// When the system was first seen running this store path
+    pub deployed_at: DateTime<Utc>,
+    /// Most recent state report from the system
+    pub last_reported_at: DateTime<Utc>,
+    pub derivation_id: Option<i32>,
+    pub derivation_name: Option<String>,
+    pub build_started_at: Option<DateTime<Utc>>,
+    pub build_completed_at: Option<DateTime<Utc>>,
+    pub built_by_host: Option<String>,
+    pub nar_hash: Option<String>,
+    pub commit_hash: Option<String>,
+    pub commit_timestamp: Option<DateTime<Utc>>,
+    pub flake_name: Option<String>,
+    pub repo_url: Option<String>,
+}
+
+/// Resolve the store path `hostname` last reported back to the derivation,
+/// commit and flake that produced it.
+///
+/// Returns `None` if the system has never reported a store path. The build
+/// and source fields are empty when the path wasn't built by this forge.
+pub async fn trace_deployed_target(
+    pool: &PgPool,
+    hostname: &str,
+) -> Result<Option<DeployedTargetLineage>> {
+    let lineage = sqlx::query_as::<_, DeployedTargetLineage>(
+        r#"
+        WITH current AS (
+            SELECT hostname, store_path, timestamp
+            FROM system_states
+            WHERE hostname = $1
+              AND store_path IS NOT NULL
+            ORDER BY timestamp DESC
+            LIMIT 1
+        ),
+        deployed AS (
+            -- First report of the current store path since the system last
+            -- ran something else
+            SELECT MIN(ss.timestamp) AS deployed_at
+            FROM system_states ss, current cur
+            WHERE ss.hostname = cur.hostname
+              AND ss.store_path = cur.store_path
+              AND ss.timestamp > COALESCE(
+                  (
+                      SELECT MAX(prev.timestamp)
+                      FROM system_states prev
+                      WHERE prev.hostname = cur.hostname
+                        AND prev.store_path IS NOT NULL
+                        AND prev.store_path <> cur.store_path
+                  ),
+                  '-infinity'
+              )
+        )
+        SELECT
+            cur.hostname,
+            cur.store_path,
+            dep.deployed_at,
+            cur.timestamp AS last_reported_at,
+            d.id AS derivation_id,
+            d.derivation_name,
+            d.started_at AS build_started_at,
+            d.completed_at AS build_completed_at,
+            d.built_by_host,
+            d.nar_hash,
+            c.git_commit_hash AS commit_hash,
+            c.commit_timestamp,
+            f.name AS flake_name,
+            f.repo_url
+        FROM current cur
+        CROSS JOIN deployed dep
+        LEFT JOIN LATERAL (
+            SELECT *
+            FROM derivations
+            WHERE store_path = cur.store_path
+            ORDER BY completed_at DESC NULLS LAST, id DESC
+            LIMIT 1
+        ) d ON TRUE
+        LEFT JOIN commits c ON c.id = d.commit_id
+        LEFT JOIN flakes f ON f.id = c.flake_id
+        "#,
+    )
+    .bind(hostname)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(lineage)
+}
+
+/// One source -> target assignment made by [`promote_environment`]
+#[derive(Debug, Clone, Serialize)]
+pub struct PromotedSystem {
+    pub source_hostname: String,
+    pub target_hostname: String,
+    pub store_path: String,
+    pub previous_target: Option<String>,
+}
+
+/// Promote what is running in `from_env` to the mapped systems in `to_env`.
+///
+/// For every `source -> target` pair in `hostname_map`, the store path the
+/// source system last reported is set as the target's `desired_target`, and
+/// the target is switched to `pinned` so auto_latest doesn't undo it. Every
+/// source must be on a target that has been pushed to cache; if any pair fails
+/// validation nothing is changed. Each assignment is recorded in
+/// `environment_promotions` under a shared promotion id, which is returned
+/// alongside the assignments. Refused while deployments are paused.
+pub async fn promote_environment(
+    pool: &PgPool,
+    from_env: &str,
+    to_env: &str,
+    hostname_map: &HashMap<String, String>,
+) -> Result<(Uuid, Vec<PromotedSystem>)> {
+    if hostname_map.is_empty() {
+        bail!("No systems to promote from {} to {}", from_env, to_env);
+    }
+
+    let mut tx = pool.begin().await?;
+
+    ensure_deployments_not_paused(&mut *tx).await?;
+
+    let from_env_id: Uuid = sqlx::query_scalar("SELECT id FROM environments WHERE name = $1")
+        .bind(from_env)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| anyhow!("Unknown environment {}", from_env))?;
+    let to_env_id: Uuid = sqlx::query_scalar("SELECT id FROM environments WHERE name = $1")
+        .bind(to_env)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| anyhow!("Unknown environment {}", to_env))?;
+
+    let mut pairs: Vec<(&String, &String)> = hostname_map.iter().collect();
+    pairs.sort();
+
+    let mut promoted = Vec::with_capacity(pairs.len());
+    for (source, target) in pairs {
+        let source_env: Option<Option<Uuid>> =
+            sqlx::query_scalar("SELECT environment_id FROM systems WHERE hostname = $1")
+                .bind(source)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if source_env.flatten() != Some(from_env_id) {
+            bail!(
+                "Source system {} is not in environment {}",
+                source,
+                from_env
+            );
+        }
+
+        let target_row: Option<(Option<Uuid>, Option<String>)> = sqlx::query_as(
+            "SELECT environment_id, desired_target FROM systems WHERE hostname = $1 FOR UPDATE",
+        )
+        .bind(target)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let previous_target = match target_row {
+            Some((Some(env_id), previous)) if env_id == to_env_id => previous,
+            _ => bail!("Target system {} is not in environment {}", target, to_env),
+        };
+
+        let store_path: String = sqlx::query_scalar(
+            r#"
+            SELECT store_path
+            FROM system_states
+            WHERE hostname = $1
+              AND store_path IS NOT NULL
+            ORDER BY timestamp DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(source)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| {
+            anyhow!(
+                "Source system {} has not reported a deployed target",
+                source
+            )
+        })?;
+
+        let cached: bool = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM derivations d
+                JOIN cache_push_jobs cpj
+                  ON cpj.derivation_id = d.id
+                 AND cpj.status = 'completed'
+                WHERE d.store_path = $1
+            )
+            "#,
+        )
+        .bind(&store_path)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !cached {
+            bail!(
+                "Source system {} is running {} which has not been pushed to cache",
+                source,
+                store_path
+            );
+        }
+
+        promoted.push(PromotedSystem {
+            source_hostname: source.clone(),
+            target_hostname: target.clone(),
+            store_path,
+            previous_target,
+        });
+    }
+
+    let promotion_id = Uuid::new_v4();
+    for system in &promoted {
+        record_target_change(
+            &mut *tx,
+            &system.target_hostname,
+            Some(&system.store_path),
+            Some("promotion"),
+        )
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE systems
+            SET desired_target = $1,
+                deployment_policy = $2,
+                updated_at = NOW()
+            WHERE hostname = $3
+            "#,
+        )
+        .bind(&system.store_path)
+        .bind(DeploymentPolicy::Pinned.to_string())
+        .bind(&system.target_hostname)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO environment_promotions (
+                promotion_id, from_environment_id, to_environment_id,
+                source_hostname, target_hostname, store_path, previous_target
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(promotion_id)
+        .bind(from_env_id)
+        .bind(to_env_id)
+        .bind(&system.source_hostname)
+        .bind(&system.target_hostname)
+        .bind(&system.store_path)
+        .bind(&system.previous_target)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "🚀 Promoted {} systems from {} to {} (promotion {})",
+        promoted.len(),
+        from_env,
+        to_env,
+        promotion_id
+    );
+
+    Ok((promotion_id, promoted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    #[tokio::test]
+    async fn silent_systems_are_not_in_flight() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let deploying = format!("in-flight-test-{}", Uuid::new_v4());
+        let silent = format!("in-flight-test-{}", Uuid::new_v4());
+        let manual = format!("in-flight-test-{}", Uuid::new_v4());
+
+        for (hostname, policy, reported) in [
+            (&deploying, "auto_latest", "NOW()"),
+            (&silent, "auto_latest", "NOW() - INTERVAL '2 hours'"),
+            (&manual, "manual", "NOW()"),
+        ] {
+            sqlx::query(
+                r#"
+                INSERT INTO systems (hostname, public_key, derivation, deployment_policy, desired_target)
+                VALUES ($1, 'key', $1, $2, '/nix/store/bbb-system')
+                "#,
+            )
+            .bind(hostname)
+            .bind(policy)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query(&format!(
+                r#"
+                INSERT INTO system_states (hostname, store_path, change_reason, timestamp)
+                VALUES ($1, '/nix/store/aaa-system', 'startup', {reported})
+                "#
+            ))
+            .bind(hostname)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let in_flight = get_in_flight_deployments(&pool, Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert!(in_flight.contains(&deploying));
+        assert!(!in_flight.contains(&silent));
+        assert!(!in_flight.contains(&manual));
+    }
+
+    #[tokio::test]
+    async fn targets_are_not_changed_while_paused() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let hostname = format!("paused-test-{}", Uuid::new_v4());
+        sqlx::query(
+            r#"
+            INSERT INTO systems (hostname, public_key, derivation, desired_target)
+            VALUES ($1, 'key', $1, '/nix/store/aaa-system')
+            "#,
+        )
+        .bind(&hostname)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        pause_deployments(&pool).await.unwrap();
+        let updated = update_desired_target(&pool, &hostname, Some("/nix/store/bbb-system")).await;
+        let pinned = pin_system_to_commit(&pool, &hostname, "0123456789abcdef").await;
+        let promoted = promote_environment(
+            &pool,
+            "staging",
+            "production",
+            &HashMap::from([(hostname.clone(), hostname.clone())]),
+        )
+        .await;
+        resume_deployments(&pool).await.unwrap();
+
+        for error in [
+            updated.unwrap_err(),
+            pinned.unwrap_err(),
+            promoted.unwrap_err(),
+        ] {
+            assert!(error.to_string().contains("paused"), "{error:#}");
+        }
+        let desired: Option<String> =
+            sqlx::query_scalar("SELECT desired_target FROM systems WHERE hostname = $1")
+                .bind(&hostname)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(desired.as_deref(), Some("/nix/store/aaa-system"));
+    }
+}
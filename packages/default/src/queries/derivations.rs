@@ -1,11 +1,19 @@
+use crate::config::BuildConfig;
 use crate::models::commits::Commit;
 // Add this line
-use crate::derivations::{Derivation, DerivationType, build_agent_target, parse_derivation_path};
+use crate::derivations::{
+    BuildCancelled, BuildFailureKind, Derivation, DerivationType, HashMismatch, PackageInfo,
+    ParseIssue, build_agent_target, build_evaluation_target, parse_derivation_path_verbose,
+};
+use crate::log::redact::redact;
+use crate::queries::cache_push::environment_destination_arrays;
 use anyhow::Context;
 use anyhow::Result;
 use anyhow::anyhow;
+use futures::StreamExt;
 use sqlx::PgPool;
 use sqlx::{Executor, Postgres};
+use std::collections::{HashMap, HashSet};
 use tracing::{debug, error, info, warn};
 
 // Status IDs from the derivation_statuses table
@@ -20,13 +28,46 @@ pub enum EvaluationStatus {
     BuildInProgress = 8,
     BuildComplete = 10,
     BuildFailed = 12,
+    /// Built and pushed to the binary cache
+    CachePushed = 14,
+    /// A dependency failed; not claimable until it stops failing
+    Blocked = 15,
 }
 
 impl EvaluationStatus {
+    pub const ALL: [EvaluationStatus; 10] = [
+        EvaluationStatus::DryRunPending,
+        EvaluationStatus::DryRunInProgress,
+        EvaluationStatus::DryRunComplete,
+        EvaluationStatus::DryRunFailed,
+        EvaluationStatus::BuildPending,
+        EvaluationStatus::BuildInProgress,
+        EvaluationStatus::BuildComplete,
+        EvaluationStatus::BuildFailed,
+        EvaluationStatus::CachePushed,
+        EvaluationStatus::Blocked,
+    ];
+
     pub fn as_id(&self) -> i32 {
         self.clone() as i32
     }
 
+    /// `derivation_statuses.name` seeded for this status
+    pub fn db_name(&self) -> &'static str {
+        match self {
+            EvaluationStatus::DryRunPending => "dry-run-pending",
+            EvaluationStatus::DryRunInProgress => "dry-run-inprogress",
+            EvaluationStatus::DryRunComplete => "dry-run-complete",
+            EvaluationStatus::DryRunFailed => "dry-run-failed",
+            EvaluationStatus::BuildPending => "build-pending",
+            EvaluationStatus::BuildInProgress => "build-inprogress",
+            EvaluationStatus::BuildComplete => "build-complete",
+            EvaluationStatus::BuildFailed => "build-failed",
+            EvaluationStatus::CachePushed => "cache-pushed",
+            EvaluationStatus::Blocked => "blocked",
+        }
+    }
+
     pub fn is_terminal(&self) -> bool {
         matches!(
             self,
@@ -34,6 +75,7 @@ impl EvaluationStatus {
                 | EvaluationStatus::DryRunFailed
                 | EvaluationStatus::BuildComplete
                 | EvaluationStatus::BuildFailed
+                | EvaluationStatus::CachePushed
         )
     }
 
@@ -193,6 +235,258 @@ pub async fn insert_derivation_with_target(
     Ok(derivation)
 }
 
+/// `flake_attr` derivations requested through the API that still wait for
+/// their attribute to be evaluated, oldest first
+pub async fn get_pending_flake_attr_derivations(pool: &PgPool) -> Result<Vec<Derivation>> {
+    let derivations = sqlx::query_as!(
+        Derivation,
+        r#"
+        SELECT
+            id,
+            commit_id,
+            derivation_type as "derivation_type: DerivationType",
+            derivation_name,
+            derivation_path,
+            derivation_target,
+            scheduled_at,
+            completed_at,
+            started_at,
+            attempt_count,
+            evaluation_duration_ms,
+            error_message,
+            pname,
+            version,
+            status_id,
+            build_elapsed_seconds,
+            build_current_target,
+            build_last_activity_seconds,
+            build_last_heartbeat,
+            cf_agent_enabled,
+            store_path
+        FROM derivations
+        WHERE derivation_type = 'flake_attr'
+          AND status_id = $1
+          AND derivation_target IS NOT NULL
+        ORDER BY scheduled_at ASC NULLS LAST, id ASC
+        "#,
+        EvaluationStatus::DryRunPending.as_id()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(derivations)
+}
+
+/// One row for [`batch_insert_derivations`]
+#[derive(Debug, Clone)]
+pub struct DerivationInput {
+    pub commit_id: Option<i32>,
+    pub derivation_name: String,
+    pub derivation_type: String,
+    pub derivation_target: Option<String>,
+    pub cf_agent_enabled: Option<bool>,
+    /// Nix system the derivation builds for, when known
+    pub system_arch: Option<String>,
+}
+
+/// Insert or refresh many derivations in one statement.
+///
+/// Same conflict handling as [`insert_derivation_with_target`]: rows already
+/// in a terminal state keep their status and schedule. Inputs repeating the
+/// same (commit, name, type) are collapsed, last one wins. Returned rows are
+/// in no particular order.
+pub async fn batch_insert_derivations(
+    pool: &PgPool,
+    inputs: &[DerivationInput],
+) -> Result<Vec<Derivation>> {
+    let mut seen = HashSet::new();
+    let mut unique: Vec<&DerivationInput> = inputs
+        .iter()
+        .rev()
+        .filter(|input| {
+            seen.insert((
+                input.commit_id,
+                input.derivation_name.as_str(),
+                input.derivation_type.as_str(),
+            ))
+        })
+        .collect();
+    unique.reverse();
+
+    if unique.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let commit_ids: Vec<Option<i32>> = unique.iter().map(|i| i.commit_id).collect();
+    let types: Vec<&str> = unique.iter().map(|i| i.derivation_type.as_str()).collect();
+    let names: Vec<&str> = unique.iter().map(|i| i.derivation_name.as_str()).collect();
+    let targets: Vec<Option<&str>> = unique
+        .iter()
+        .map(|i| i.derivation_target.as_deref())
+        .collect();
+    let cf_agent: Vec<Option<bool>> = unique.iter().map(|i| i.cf_agent_enabled).collect();
+    let system_archs: Vec<Option<&str>> = unique.iter().map(|i| i.system_arch.as_deref()).collect();
+
+    let derivations = sqlx::query_as::<_, Derivation>(
+        r#"
+        INSERT INTO derivations (
+            commit_id,
+            derivation_type,
+            derivation_name,
+            derivation_target,
+            status_id,
+            attempt_count,
+            scheduled_at,
+            cf_agent_enabled,
+            system_arch
+        )
+        SELECT commit_id, derivation_type, derivation_name, derivation_target, $6, 0, NOW(), cf_agent_enabled, system_arch
+        FROM UNNEST($1::int[], $2::text[], $3::text[], $4::text[], $5::bool[], $11::text[])
+            AS input(commit_id, derivation_type, derivation_name, derivation_target, cf_agent_enabled, system_arch)
+        ON CONFLICT (COALESCE(commit_id, -1), derivation_name, derivation_type)
+        DO UPDATE SET
+            -- keep terminal states; otherwise reset
+            status_id = CASE
+                WHEN derivations.status_id IN ($7, $8, $9, $10) THEN derivations.status_id
+                ELSE EXCLUDED.status_id
+            END,
+            -- keep/refresh target if provided
+            derivation_target = COALESCE(EXCLUDED.derivation_target, derivations.derivation_target),
+            system_arch = COALESCE(EXCLUDED.system_arch, derivations.system_arch),
+            -- nudge the scheduler only for non-terminal rows
+            scheduled_at = CASE
+                WHEN derivations.status_id IN ($7, $8, $9, $10) THEN derivations.scheduled_at
+                ELSE NOW()
+            END
+        RETURNING
+            id, commit_id, derivation_type, derivation_name, derivation_path,
+            derivation_target, scheduled_at, completed_at, started_at, attempt_count,
+            evaluation_duration_ms, error_message, pname, version, status_id,
+            build_elapsed_seconds, build_current_target, build_last_activity_seconds,
+            build_last_heartbeat, cf_agent_enabled, store_path
+        "#,
+    )
+    .bind(&commit_ids)
+    .bind(&types)
+    .bind(&names)
+    .bind(&targets)
+    .bind(&cf_agent)
+    .bind(EvaluationStatus::DryRunPending.as_id())
+    // $7..$10 (terminal statuses)
+    .bind(EvaluationStatus::DryRunComplete.as_id())
+    .bind(EvaluationStatus::DryRunFailed.as_id())
+    .bind(EvaluationStatus::BuildComplete.as_id())
+    .bind(EvaluationStatus::BuildFailed.as_id())
+    .bind(&system_archs)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(derivations)
+}
+
+/// Record the nix system a derivation builds for
+pub async fn set_derivation_system_arch(
+    pool: &PgPool,
+    derivation_id: i32,
+    system_arch: &str,
+) -> Result<()> {
+    sqlx::query("UPDATE derivations SET system_arch = $1 WHERE id = $2")
+        .bind(system_arch)
+        .bind(derivation_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// NixOS system derivations recorded for a commit, by name, paired with
+/// whether the system got as far as a derivation path
+pub async fn get_commit_system_names(pool: &PgPool, commit_id: i32) -> Result<Vec<(String, bool)>> {
+    let names = sqlx::query_as::<_, (String, bool)>(
+        r#"
+        SELECT derivation_name, derivation_path IS NOT NULL
+        FROM derivations
+        WHERE commit_id = $1 AND derivation_type = 'nixos'
+        ORDER BY derivation_name
+        "#,
+    )
+    .bind(commit_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(names)
+}
+
+/// Copy the NixOS system derivations named in `names` from `from_commit` to
+/// `to_commit_id` instead of evaluating them again. Built systems keep their
+/// store path; anything else with a derivation path is queued as
+/// dry-run-complete. Systems without a derivation path are not copied.
+///
+/// Returns the names that were carried forward.
+pub async fn carry_forward_derivations(
+    pool: &PgPool,
+    from_commit: &Commit,
+    to_commit: &Commit,
+    names: &[String],
+) -> Result<Vec<String>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let carried = sqlx::query_scalar::<_, String>(
+        r#"
+        INSERT INTO derivations (
+            commit_id,
+            derivation_type,
+            derivation_name,
+            derivation_path,
+            derivation_target,
+            status_id,
+            attempt_count,
+            scheduled_at,
+            completed_at,
+            pname,
+            version,
+            cf_agent_enabled,
+            store_path,
+            nar_hash
+        )
+        SELECT
+            $2,
+            d.derivation_type,
+            d.derivation_name,
+            d.derivation_path,
+            REPLACE(d.derivation_target, $3, $4),
+            CASE WHEN d.status_id = $6 AND d.store_path IS NOT NULL THEN $6 ELSE $7 END,
+            0,
+            NOW(),
+            CASE WHEN d.status_id = $6 AND d.store_path IS NOT NULL THEN d.completed_at END,
+            d.pname,
+            d.version,
+            d.cf_agent_enabled,
+            CASE WHEN d.status_id = $6 THEN d.store_path END,
+            CASE WHEN d.status_id = $6 THEN d.nar_hash END
+        FROM derivations d
+        WHERE d.commit_id = $1
+          AND d.derivation_type = 'nixos'
+          AND d.derivation_name = ANY($5)
+          AND d.derivation_path IS NOT NULL
+        ON CONFLICT DO NOTHING
+        RETURNING derivation_name
+        "#,
+    )
+    .bind(from_commit.id)
+    .bind(to_commit.id)
+    .bind(&from_commit.git_commit_hash)
+    .bind(&to_commit.git_commit_hash)
+    .bind(names)
+    .bind(EvaluationStatus::BuildComplete.as_id())
+    .bind(EvaluationStatus::DryRunComplete.as_id())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(carried)
+}
+
 // Convenience function for the common case with a commit
 pub async fn insert_derivation_for_commit(
     pool: &PgPool,
@@ -1279,6 +1573,111 @@ pub async fn update_scheduled_at(pool: &PgPool) -> Result<()> {
     Ok(())
 }
 
+/// Largest number of derivations [`reschedule`] touches unless told otherwise
+pub const DEFAULT_RESCHEDULE_MAX_ROWS: i64 = 1000;
+
+/// Which derivations [`reschedule`] moves to the front of the queue.
+/// Unset filters match everything; without a status only non-terminal
+/// derivations are rescheduled.
+#[derive(Debug, Clone)]
+pub struct RescheduleFilter {
+    pub flake_id: Option<i32>,
+    pub commit_id: Option<i32>,
+    pub status: Option<EvaluationStatus>,
+    /// Refuse to reschedule anything if more derivations than this match
+    pub max_rows: i64,
+}
+
+impl Default for RescheduleFilter {
+    fn default() -> Self {
+        Self {
+            flake_id: None,
+            commit_id: None,
+            status: None,
+            max_rows: DEFAULT_RESCHEDULE_MAX_ROWS,
+        }
+    }
+}
+
+/// Returned by [`reschedule`] when more derivations match than its filter's
+/// `max_rows` allows
+#[derive(Debug)]
+pub struct RescheduleLimitExceeded {
+    pub max_rows: i64,
+}
+
+impl std::fmt::Display for RescheduleLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "more than {} derivations match; narrow the filter or raise max_rows",
+            self.max_rows
+        )
+    }
+}
+
+impl std::error::Error for RescheduleLimitExceeded {}
+
+/// Set `scheduled_at = NOW()` on the derivations matching `filter`.
+/// Nothing is changed when more than `filter.max_rows` match; the error is
+/// then a [`RescheduleLimitExceeded`]. Returns the number of derivations
+/// rescheduled.
+pub async fn reschedule(pool: &PgPool, filter: &RescheduleFilter) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+
+    let terminal: Vec<i32> = EvaluationStatus::ALL
+        .iter()
+        .filter(|status| status.is_terminal())
+        .map(EvaluationStatus::as_id)
+        .collect();
+    let ids = sqlx::query_scalar!(
+        r#"
+        SELECT d.id
+        FROM derivations d
+        LEFT JOIN commits c ON c.id = d.commit_id
+        WHERE ($1::int IS NULL OR c.flake_id = $1)
+          AND ($2::int IS NULL OR d.commit_id = $2)
+          AND CASE
+                WHEN $3::int IS NULL THEN d.status_id <> ALL($4)
+                ELSE d.status_id = $3
+              END
+        ORDER BY d.id
+        LIMIT $5
+        FOR UPDATE OF d
+        "#,
+        filter.flake_id,
+        filter.commit_id,
+        filter.status.as_ref().map(EvaluationStatus::as_id),
+        &terminal,
+        filter.max_rows.saturating_add(1)
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if ids.len() as i64 > filter.max_rows {
+        tx.rollback().await?;
+        warn!(
+            "⏰ Not rescheduling, too many derivations match {:?}",
+            filter
+        );
+        return Err(RescheduleLimitExceeded {
+            max_rows: filter.max_rows,
+        }
+        .into());
+    }
+
+    let result = sqlx::query!(
+        "UPDATE derivations SET scheduled_at = NOW() WHERE id = ANY($1)",
+        &ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    info!("⏰ Rescheduled {} derivations", result.rows_affected());
+    Ok(result.rows_affected())
+}
+
 /// Increment the number of attempts for failed operations
 pub async fn increment_derivation_attempt_count(
     pool: &PgPool,
@@ -1299,7 +1698,15 @@ pub async fn increment_derivation_attempt_count(
     Ok(())
 }
 
+/// Attempts after which a failed derivation is no longer retried
+const MAX_ATTEMPTS: i32 = 5;
+
 /// Handle derivation failure with proper attempt count logic
+///
+/// Also records a coarse `failure_kind` classified from the error text so
+/// failures can be aggregated with [`failure_breakdown`]. Failures that
+/// can't be fixed by retrying (hash mismatches) and cancelled builds use up
+/// every attempt at once so the derivation is not retried.
 pub async fn handle_derivation_failure<'e, E>(
     executor: E,
     derivation: &Derivation,
@@ -1309,18 +1716,40 @@ pub async fn handle_derivation_failure<'e, E>(
 where
     E: sqlx::Executor<'e, Database = sqlx::Postgres>,
 {
+    let error_message = redact(&format!("{}: {:#}", phase, error)).into_owned();
+    let failure_kind = if error.downcast_ref::<HashMismatch>().is_some() {
+        BuildFailureKind::HashMismatch
+    } else if error.downcast_ref::<BuildCancelled>().is_some() {
+        BuildFailureKind::Cancelled
+    } else {
+        BuildFailureKind::classify(&error_message)
+    };
+    if !failure_kind.is_retryable() {
+        warn!(
+            "🚫 {} failed with {}, not retrying",
+            derivation.derivation_name, failure_kind
+        );
+    }
+
     sqlx::query!(
         r#"
         UPDATE derivations
         SET status_id = $1, 
             error_message = $2,
-            attempt_count = attempt_count + 1,
+            failure_kind = $3,
+            attempt_count = CASE
+                WHEN $5 THEN attempt_count + 1
+                ELSE GREATEST(attempt_count + 1, $6)
+            END,
             completed_at = NOW()
-        WHERE id = $3
+        WHERE id = $4
         "#,
         EvaluationStatus::BuildFailed.as_id(),
-        format!("{}: {}", phase, error),
-        derivation.id
+        error_message,
+        failure_kind.as_str(),
+        derivation.id,
+        failure_kind.is_retryable(),
+        MAX_ATTEMPTS
     )
     .execute(executor)
     .await?;
@@ -1328,7 +1757,110 @@ where
     Ok(())
 }
 
-pub async fn reset_non_terminal_derivations(pool: &PgPool) -> Result<()> {
+/// Move queued derivations with a failed dependency to `Blocked`, so
+/// workers stop claiming builds that cannot succeed. `only_dependents_of`
+/// limits this to the dependents of one derivation. Returns the number of
+/// derivations blocked.
+pub async fn block_derivations_with_failed_dependencies<'e, E>(
+    executor: E,
+    only_dependents_of: Option<i32>,
+) -> Result<u64>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let result = sqlx::query(
+        r#"
+        UPDATE derivations d
+        SET status_id = $1,
+            error_message = 'blocked: dependency ' || failed.derivation_name || ' failed'
+        FROM (
+            SELECT DISTINCT ON (dd.derivation_id) dd.derivation_id, p.derivation_name
+            FROM derivation_dependencies dd
+            JOIN derivations p ON p.id = dd.depends_on_id
+            WHERE p.status_id IN ($2, $3)
+              AND ($4::int IS NULL OR p.id = $4)
+            ORDER BY dd.derivation_id, p.id
+        ) failed
+        WHERE d.id = failed.derivation_id
+          AND d.status_id IN ($5, $6)
+        "#,
+    )
+    .bind(EvaluationStatus::Blocked.as_id())
+    .bind(EvaluationStatus::DryRunFailed.as_id())
+    .bind(EvaluationStatus::BuildFailed.as_id())
+    .bind(only_dependents_of)
+    .bind(EvaluationStatus::DryRunComplete.as_id())
+    .bind(EvaluationStatus::BuildPending.as_id())
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Return blocked derivations whose dependencies no longer include a
+/// failed one to `BuildPending`. Returns the number unblocked.
+pub async fn unblock_derivations(pool: &PgPool) -> Result<u64> {
+    let result = sqlx::query(
+        r#"
+        UPDATE derivations d
+        SET status_id = $1,
+            error_message = NULL,
+            scheduled_at = NOW()
+        WHERE d.status_id = $2
+          AND NOT EXISTS (
+              SELECT 1
+              FROM derivation_dependencies dd
+              JOIN derivations p ON p.id = dd.depends_on_id
+              WHERE dd.derivation_id = d.id
+                AND p.status_id IN ($3, $4)
+          )
+        "#,
+    )
+    .bind(EvaluationStatus::BuildPending.as_id())
+    .bind(EvaluationStatus::Blocked.as_id())
+    .bind(EvaluationStatus::DryRunFailed.as_id())
+    .bind(EvaluationStatus::BuildFailed.as_id())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Number of failed derivations per failure kind
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FailureKindCount {
+    pub failure_kind: String,
+    pub count: i64,
+}
+
+/// Count currently failed derivations grouped by `failure_kind`.
+/// Rows that failed before classification existed are reported as `unknown`.
+pub async fn failure_breakdown(pool: &PgPool) -> Result<Vec<FailureKindCount>> {
+    let rows = sqlx::query_as!(
+        FailureKindCount,
+        r#"
+        SELECT
+            COALESCE(failure_kind, 'unknown') AS "failure_kind!",
+            COUNT(*) AS "count!"
+        FROM derivations
+        WHERE status_id = $1
+        GROUP BY 1
+        ORDER BY 2 DESC, 1
+        "#,
+        EvaluationStatus::BuildFailed.as_id()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn reset_non_terminal_derivations<'a, A>(db: A) -> Result<()>
+where
+    A: sqlx::Acquire<'a, Database = Postgres>,
+{
+    let mut conn = db.acquire().await?;
+
     // First, set derivations to terminal failed states if attempts >= 5
     let terminal_dry_run_result = sqlx::query!(
         r#"
@@ -1340,7 +1872,7 @@ pub async fn reset_non_terminal_derivations(pool: &PgPool) -> Result<()> {
         "#,
         EvaluationStatus::DryRunFailed.as_id() // 6
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     let terminal_build_result = sqlx::query!(
@@ -1353,7 +1885,7 @@ pub async fn reset_non_terminal_derivations(pool: &PgPool) -> Result<()> {
         "#,
         EvaluationStatus::BuildFailed.as_id() // 12
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     // Then, reset derivations that should be retried (attempts < 5)
@@ -1369,7 +1901,7 @@ pub async fn reset_non_terminal_derivations(pool: &PgPool) -> Result<()> {
         EvaluationStatus::DryRunComplete.as_id(), // 5
         EvaluationStatus::BuildComplete.as_id()   // 10
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     let reset_build_result = sqlx::query!(
@@ -1384,7 +1916,7 @@ pub async fn reset_non_terminal_derivations(pool: &PgPool) -> Result<()> {
         EvaluationStatus::DryRunComplete.as_id(), // 5
         EvaluationStatus::BuildComplete.as_id()   // 10
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     let total_terminal =
@@ -1424,6 +1956,22 @@ pub async fn mark_target_build_in_progress(pool: &PgPool, target_id: i32) -> Res
     mark_derivation_build_in_progress(pool, target_id).await
 }
 
+/// Take the row lock on a derivation for the rest of the transaction.
+///
+/// Transactions that also touch the derivation's build reservation lock the
+/// derivation first, so concurrent workers acquire locks in the same order.
+pub async fn lock_derivation<'e, E>(executor: E, derivation_id: i32) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query("SELECT id FROM derivations WHERE id = $1 FOR UPDATE")
+        .bind(derivation_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
 pub async fn mark_target_build_complete<'e, E>(
     executor: E,
     derivation_id: i32,
@@ -1448,37 +1996,246 @@ where
     Ok(())
 }
 
-pub async fn mark_target_failed(
-    pool: &PgPool,
-    target_id: i32,
-    phase: &str,
-    error_message: &str,
-) -> Result<Derivation> {
-    mark_derivation_failed(pool, target_id, phase, error_message).await
-}
-
-/// Discover packages from derivation paths and insert them into the database
-pub async fn discover_and_insert_packages(
+/// Record the NAR hash of a built derivation's store path
+pub async fn set_derivation_nar_hash(
     pool: &PgPool,
-    parent_derivation_id: i32,
-    derivation_paths: &[&str],
+    derivation_id: i32,
+    nar_hash: &str,
 ) -> Result<()> {
-    use tracing::warn;
-
-    if derivation_paths.is_empty() {
-        return Ok(());
-    }
+    sqlx::query("UPDATE derivations SET nar_hash = $2 WHERE id = $1")
+        .bind(derivation_id)
+        .bind(nar_hash)
+        .execute(pool)
+        .await?;
 
-    info!(
-        "🔍 Analyzing {} derivation paths for package information",
-        derivation_paths.len()
-    );
+    Ok(())
+}
 
-    // NEW: Batch collect all valid packages first
-    let mut packages_to_insert = Vec::new();
+/// Record which builder host produced a derivation's store path
+pub async fn set_derivation_built_by_host<'e, E>(
+    executor: E,
+    derivation_id: i32,
+    hostname: &str,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query("UPDATE derivations SET built_by_host = $2 WHERE id = $1")
+        .bind(derivation_id)
+        .bind(hostname)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Build progress of a derivation as last written by its builder, or `None`
+/// if there is no such derivation
+pub async fn get_build_progress(
+    pool: &PgPool,
+    derivation_id: i32,
+) -> Result<Option<crate::derivations::progress::BuildProgress>> {
+    let progress = sqlx::query_as(
+        r#"
+        SELECT
+            d.id AS derivation_id,
+            d.status_id,
+            COALESCE(s.name, 'unknown') AS status,
+            d.build_elapsed_seconds AS elapsed_seconds,
+            d.build_current_target AS current_target,
+            d.build_last_activity_seconds AS last_activity_seconds,
+            d.build_phase AS phase
+        FROM derivations d
+        LEFT JOIN derivation_statuses s ON s.id = d.status_id
+        WHERE d.id = $1
+        "#,
+    )
+    .bind(derivation_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(progress)
+}
+
+/// Record the builder host whose attempt at a derivation just failed, so
+/// [`claim_next_derivation`](crate::queries::build_reservations::claim_next_derivation)
+/// offers the retry to other hosts first
+pub async fn set_derivation_failed_on_host<'e, E>(
+    executor: E,
+    derivation_id: i32,
+    hostname: &str,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query("UPDATE derivations SET failed_on_host = $2 WHERE id = $1")
+        .bind(derivation_id)
+        .bind(hostname)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Completed builds recorded for one builder host
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct HostBuildCount {
+    pub built_by_host: String,
+    pub builds: i64,
+    pub last_completed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A derivation built by a particular host
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct HostBuild {
+    pub derivation_id: i32,
+    pub derivation_name: String,
+    pub store_path: Option<String>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Number of builds each builder host has completed, busiest first
+pub async fn builds_per_host(pool: &PgPool) -> Result<Vec<HostBuildCount>> {
+    let rows = sqlx::query_as::<_, HostBuildCount>(
+        r#"
+        SELECT
+            built_by_host,
+            COUNT(*) AS builds,
+            MAX(completed_at) AS last_completed_at
+        FROM derivations
+        WHERE built_by_host IS NOT NULL
+        GROUP BY built_by_host
+        ORDER BY builds DESC, built_by_host
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Most recent builds produced by `hostname`, newest first
+pub async fn builds_by_host(pool: &PgPool, hostname: &str, limit: i64) -> Result<Vec<HostBuild>> {
+    let rows = sqlx::query_as::<_, HostBuild>(
+        r#"
+        SELECT
+            id AS derivation_id,
+            derivation_name,
+            store_path,
+            completed_at
+        FROM derivations
+        WHERE built_by_host = $1
+        ORDER BY completed_at DESC NULLS LAST, id DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(hostname)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Failed to load builds for host {}", hostname))?;
+
+    Ok(rows)
+}
+
+/// Where a deployable store path came from, as the agent needs it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorePathOrigin {
+    /// NAR hash recorded for the most recent build that produced the path
+    pub nar_hash: Option<String>,
+    /// `nix build` target of the NixOS configuration producing the path,
+    /// for building it on the host when no cache has it
+    pub flake_target: Option<String>,
+}
+
+/// NAR hash and flake target of the most recent build that produced
+/// `store_path`
+pub async fn get_store_path_origin(
+    pool: &PgPool,
+    store_path: &str,
+) -> Result<Option<StorePathOrigin>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            d.nar_hash,
+            d.derivation_name,
+            d.derivation_type,
+            c.git_commit_hash AS "git_commit_hash?",
+            f.repo_url AS "repo_url?"
+        FROM derivations d
+        LEFT JOIN commits c ON c.id = d.commit_id
+        LEFT JOIN flakes f ON f.id = c.flake_id
+        WHERE d.store_path = $1
+        ORDER BY (d.nar_hash IS NOT NULL) DESC, d.completed_at DESC NULLS LAST
+        LIMIT 1
+        "#,
+        store_path
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| {
+        let flake_target = match (row.repo_url, row.git_commit_hash) {
+            (Some(repo_url), Some(commit_hash)) if row.derivation_type == "nixos" => Some(
+                build_evaluation_target(&repo_url, &commit_hash, &row.derivation_name),
+            ),
+            _ => None,
+        };
+        StorePathOrigin {
+            nar_hash: row.nar_hash,
+            flake_target,
+        }
+    }))
+}
+
+pub async fn mark_target_failed(
+    pool: &PgPool,
+    target_id: i32,
+    phase: &str,
+    error_message: &str,
+) -> Result<Derivation> {
+    mark_derivation_failed(pool, target_id, phase, error_message).await
+}
+
+/// Discover packages from derivation paths and insert them into the database
+pub async fn discover_and_insert_packages(
+    pool: &PgPool,
+    parent_derivation_id: i32,
+    derivation_paths: &[&str],
+) -> Result<()> {
+    use tracing::warn;
+
+    if derivation_paths.is_empty() {
+        return Ok(());
+    }
+
+    info!(
+        "🔍 Analyzing {} derivation paths for package information",
+        derivation_paths.len()
+    );
+
+    // NEW: Batch collect all valid packages first
+    let mut packages_to_insert = Vec::new();
+
+    let mut parse_issues: HashMap<&'static str, usize> = HashMap::new();
 
     for &drv_path in derivation_paths {
-        if let Some(package_info) = parse_derivation_path(drv_path) {
+        let parsed = match parse_derivation_path_verbose(drv_path) {
+            Ok(info) => Some(info),
+            Err(issue) => {
+                debug!("🧩 Could not parse {} ({})", drv_path, issue.kind());
+                *parse_issues.entry(issue.kind()).or_default() += 1;
+                match issue {
+                    ParseIssue::NoVersion { pname } => Some(PackageInfo {
+                        pname: Some(pname),
+                        version: None,
+                    }),
+                    _ => None,
+                }
+            }
+        };
+
+        if let Some(package_info) = parsed {
             if drv_path.contains("nixos-system-") {
                 debug!("⏭️ Skipping NixOS system derivation: {}", drv_path);
                 continue;
@@ -1505,6 +2262,12 @@ pub async fn discover_and_insert_packages(
         }
     }
 
+    if !parse_issues.is_empty() {
+        let mut counts: Vec<_> = parse_issues.into_iter().collect();
+        counts.sort();
+        debug!("🧩 Derivation name parse issues: {:?}", counts);
+    }
+
     if packages_to_insert.is_empty() {
         info!("No packages to insert");
         return Ok(());
@@ -1647,9 +2410,14 @@ pub struct HostLatestTarget {
     pub derivation_target: Option<String>,
     pub store_path: Option<String>,
     pub last_cache_completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Commits of the flake newer than the one this target was built from;
+    /// zero when the newest commit is deployable
+    pub commits_behind_head: i64,
 }
 
 // src/db/queries.rs
+/// Newest deployable (built and cache-pushed) target of each host across
+/// the flake's commits, with how many commits behind HEAD it is
 pub async fn get_latest_deployable_targets_for_flake_hosts(
     pool: &PgPool,
     flake_id: i32,
@@ -1662,34 +2430,37 @@ pub async fn get_latest_deployable_targets_for_flake_hosts(
     // NOTE: pass `hostnames` as a TEXT[] (Vec<String>) to $2
     let rows = sqlx::query!(
         r#"
-        WITH latest_commit AS (
-          SELECT id, flake_id, git_commit_hash
+        WITH flake_commits AS (
+          SELECT
+            id,
+            git_commit_hash,
+            commit_timestamp,
+            RANK() OVER (ORDER BY commit_timestamp DESC) - 1 AS commits_behind_head
           FROM commits
           WHERE flake_id = $1
-          ORDER BY commit_timestamp DESC
-          LIMIT 1
         ),
         per_host AS (
           SELECT
             d.derivation_name AS hostname,
             d.id              AS derivation_id,
-            d.derivation_target,
             d.store_path,
             f.repo_url        AS repo_url,
-            lc.git_commit_hash AS commit_hash,
+            fc.git_commit_hash AS commit_hash,
+            fc.commits_behind_head,
             MAX(cpj.completed_at) AS last_cache_completed_at,
             ROW_NUMBER() OVER (
               PARTITION BY d.derivation_name
               ORDER BY
+                fc.commit_timestamp   DESC,
                 MAX(cpj.completed_at) DESC NULLS LAST,
                 MAX(d.completed_at)   DESC NULLS LAST,
-                MAX(d.id)             DESC
+                d.id                  DESC
             ) AS rn
           FROM derivations d
-          JOIN latest_commit lc
-            ON d.commit_id = lc.id
+          JOIN flake_commits fc
+            ON d.commit_id = fc.id
           JOIN flakes f
-            ON lc.flake_id = f.id
+            ON f.id = $1
           JOIN cache_push_jobs cpj
             ON cpj.derivation_id = d.id
            AND cpj.status = 'completed'
@@ -1699,19 +2470,20 @@ pub async fn get_latest_deployable_targets_for_flake_hosts(
           GROUP BY
             d.derivation_name,
             d.id,
-            d.derivation_target,
             d.store_path,
             f.repo_url,
-            lc.git_commit_hash
+            fc.git_commit_hash,
+            fc.commit_timestamp,
+            fc.commits_behind_head
         )
         SELECT
-          hostname,
-          derivation_id,
-          derivation_target,
+          hostname AS "hostname!",
+          derivation_id AS "derivation_id!",
           store_path,
           last_cache_completed_at,
-          repo_url,
-          commit_hash
+          repo_url AS "repo_url!",
+          commit_hash AS "commit_hash!",
+          commits_behind_head AS "commits_behind_head!"
         FROM per_host
         WHERE rn = 1
         "#,
@@ -1723,25 +2495,76 @@ pub async fn get_latest_deployable_targets_for_flake_hosts(
 
     let out = rows
         .into_iter()
-        .map(|r| {
-            let hostname = r.hostname.clone();
-            HostLatestTarget {
-                hostname: hostname,
-                derivation_id: r.derivation_id,
-                store_path: r.store_path,
-                derivation_target: Some(build_agent_target(
-                    &r.repo_url,
-                    &r.commit_hash,
-                    &r.hostname,
-                )),
-                last_cache_completed_at: r.last_cache_completed_at,
-            }
+        .map(|r| HostLatestTarget {
+            derivation_target: Some(build_agent_target(&r.repo_url, &r.commit_hash, &r.hostname)),
+            hostname: r.hostname,
+            derivation_id: r.derivation_id,
+            store_path: r.store_path,
+            last_cache_completed_at: r.last_cache_completed_at,
+            commits_behind_head: r.commits_behind_head,
         })
         .collect();
 
     Ok(out)
 }
 
+/// A derivation at a commit together with its human-readable status
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct CommitDerivationStatus {
+    pub id: i32,
+    pub derivation_name: String,
+    pub derivation_type: String,
+    pub status_id: i32,
+    pub status: String,
+    pub is_terminal: bool,
+    pub is_success: bool,
+    pub store_path: Option<String>,
+    pub error_message: Option<String>,
+}
+
+/// Resolve a commit by its git hash and return every derivation evaluated for it.
+///
+/// Returns `Ok(None)` when no commit with that hash is known, so callers can
+/// tell "unknown commit" apart from "commit with nothing evaluated yet".
+pub async fn get_by_commit_hash(
+    pool: &PgPool,
+    git_commit_hash: &str,
+) -> Result<Option<Vec<CommitDerivationStatus>>> {
+    let commit_ids: Vec<i32> =
+        sqlx::query_scalar("SELECT id FROM commits WHERE git_commit_hash = $1")
+            .bind(git_commit_hash)
+            .fetch_all(pool)
+            .await?;
+
+    if commit_ids.is_empty() {
+        return Ok(None);
+    }
+
+    let rows = sqlx::query_as::<_, CommitDerivationStatus>(
+        r#"
+        SELECT
+            d.id,
+            d.derivation_name,
+            d.derivation_type,
+            d.status_id,
+            ds.name AS status,
+            ds.is_terminal,
+            ds.is_success,
+            d.store_path,
+            d.error_message
+        FROM derivations d
+        JOIN derivation_statuses ds ON ds.id = d.status_id
+        WHERE d.commit_id = ANY($1)
+        ORDER BY d.derivation_type DESC, d.derivation_name, d.id
+        "#,
+    )
+    .bind(&commit_ids)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(rows))
+}
+
 pub async fn mark_derivation_cache_pushed(pool: &PgPool, derivation_id: i32) -> Result<()> {
     sqlx::query!(
         r#"
@@ -1749,7 +2572,7 @@ pub async fn mark_derivation_cache_pushed(pool: &PgPool, derivation_id: i32) ->
         SET status_id = $1
         WHERE id = $2
         "#,
-        14_i32, // cache-pushed status
+        EvaluationStatus::CachePushed.as_id(),
         derivation_id
     )
     .execute(pool)
@@ -1779,27 +2602,39 @@ pub async fn update_cf_agent_enabled(
     Ok(())
 }
 
-/// Batch create cache push jobs for all built derivations missing jobs
-pub async fn batch_queue_cache_jobs(pool: &PgPool, destination: &str) -> Result<usize> {
+/// Batch create cache push jobs for all built derivations missing jobs.
+/// Each derivation is queued for the destination of its systems' environments
+/// (see `view_derivation_environments`), falling back to `default_destination`.
+pub async fn batch_queue_cache_jobs(
+    pool: &PgPool,
+    default_destination: &str,
+    environment_destinations: &HashMap<String, String>,
+) -> Result<usize> {
+    let (env_names, destinations) = environment_destination_arrays(environment_destinations);
     let count = sqlx::query_scalar!(
         r#"
-        INSERT INTO cache_push_jobs (derivation_id, store_path, cache_destination, status)
-        SELECT 
-            d.id,
-            d.store_path,
-            $1,
-            'pending'
-        FROM derivations d
-        WHERE d.status_id = 10  -- build-complete
-            AND d.store_path IS NOT NULL
-            AND NOT EXISTS (
-                SELECT 1 FROM cache_push_jobs cpj 
-                WHERE cpj.derivation_id = d.id
-                AND cpj.cache_destination = $1  -- CHECK SPECIFIC DESTINATION
-            )
+        WITH targets AS (
+            SELECT DISTINCT d.id, d.store_path, COALESCE(ed.destination, $1) AS destination
+            FROM derivations d
+            LEFT JOIN view_derivation_environments de ON de.derivation_id = d.id
+            LEFT JOIN UNNEST($2::text[], $3::text[]) AS ed(env_name, destination)
+                ON ed.env_name = de.environment_name
+            WHERE d.status_id = 10  -- build-complete
+                AND d.store_path IS NOT NULL
+        )
+        INSERT INTO cache_push_jobs (derivation_id, store_path, cache_destination, status, priority)
+        SELECT t.id, t.store_path, t.destination, 'pending', cache_push_priority(t.id)
+        FROM targets t
+        WHERE NOT EXISTS (
+            SELECT 1 FROM cache_push_jobs cpj
+            WHERE cpj.derivation_id = t.id
+            AND cpj.cache_destination = t.destination  -- CHECK SPECIFIC DESTINATION
+        )
         RETURNING id
         "#,
-        destination
+        default_destination,
+        &env_names,
+        &destinations
     )
     .fetch_all(pool)
     .await?
@@ -1812,30 +2647,260 @@ pub async fn batch_queue_cache_jobs(pool: &PgPool, destination: &str) -> Result<
     Ok(count)
 }
 
+/// How many store paths [`find_missing_store_paths`] checks at once
+const STORE_PATH_CHECK_CONCURRENCY: usize = 32;
+
+/// Built derivations [`find_missing_store_paths`] reads per call
+const STORE_PATH_AUDIT_PAGE_SIZE: i64 = 1000;
+
+/// Built derivations with an id above `after_id` whose `store_path` no
+/// longer exists in the local nix store, e.g. because it was garbage
+/// collected. Looks at up to [`STORE_PATH_AUDIT_PAGE_SIZE`] derivations and
+/// also returns the id to continue after, or `None` once all were checked.
+/// Paths that cannot be checked are logged and treated as present.
+pub async fn find_missing_store_paths(
+    pool: &PgPool,
+    after_id: i32,
+) -> Result<(Vec<Derivation>, Option<i32>)> {
+    let built = sqlx::query_as::<_, Derivation>(
+        r#"
+        SELECT
+            id, commit_id, derivation_type, derivation_name, derivation_path,
+            derivation_target, scheduled_at, completed_at, started_at, attempt_count,
+            evaluation_duration_ms, error_message, pname, version, status_id,
+            build_elapsed_seconds, build_current_target, build_last_activity_seconds,
+            build_last_heartbeat, cf_agent_enabled, store_path
+        FROM derivations
+        WHERE status_id = $1
+          AND store_path IS NOT NULL
+          AND id > $2
+        ORDER BY id
+        LIMIT $3
+        "#,
+    )
+    .bind(EvaluationStatus::BuildComplete.as_id())
+    .bind(after_id)
+    .bind(STORE_PATH_AUDIT_PAGE_SIZE)
+    .fetch_all(pool)
+    .await?;
+
+    let next = match built.last() {
+        Some(last) if built.len() as i64 == STORE_PATH_AUDIT_PAGE_SIZE => Some(last.id),
+        _ => None,
+    };
+
+    let missing = futures::stream::iter(built)
+        .map(|derivation| async move {
+            let path = derivation.store_path.as_deref().unwrap_or_default();
+            match tokio::fs::try_exists(path).await {
+                Ok(exists) => (!exists).then_some(derivation),
+                Err(e) => {
+                    warn!("⚠️ Could not check store path {}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .buffer_unordered(STORE_PATH_CHECK_CONCURRENCY)
+        .filter_map(std::future::ready)
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok((missing, next))
+}
+
 /// Reset a derivation back to dry-run-complete status when store path is missing
-pub async fn reset_derivation_for_rebuild(pool: &PgPool, derivation_id: i32) -> Result<()> {
-    sqlx::query!(
+///
+/// Derivations of cache-only systems are never rebuilt locally; returns
+/// `false` and leaves them alone, see [`flag_missing_cache_only_derivation`].
+pub async fn reset_derivation_for_rebuild(pool: &PgPool, derivation_id: i32) -> Result<bool> {
+    let result = sqlx::query!(
         r#"
         UPDATE derivations
         SET 
-            status_id = 5,  -- dry-run-complete
+            status_id = $2,
             store_path = NULL,
             completed_at = NULL,
             error_message = 'Store path was garbage collected, needs rebuild'
         WHERE id = $1
+          AND NOT cache_only
         "#,
-        derivation_id
+        derivation_id,
+        EvaluationStatus::DryRunComplete.as_id()
     )
     .execute(pool)
     .await?;
 
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
     info!(
         "Reset derivation {} to dry-run-complete for rebuild",
         derivation_id
     );
+    Ok(true)
+}
+
+/// Error recorded on a cache-only derivation whose store path is gone
+const CACHE_ONLY_MISSING_MESSAGE: &str =
+    "Store path missing on a cache-only system, needs manual attention";
+
+/// Flag a cache-only derivation whose store path is gone for an operator,
+/// keeping its status and store path. Returns `false` if it was already
+/// flagged.
+pub async fn flag_missing_cache_only_derivation(pool: &PgPool, derivation_id: i32) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE derivations
+        SET error_message = $2
+        WHERE id = $1
+          AND cache_only
+          AND error_message IS DISTINCT FROM $2
+        "#,
+        derivation_id,
+        CACHE_ONLY_MISSING_MESSAGE
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record the builder labels a derivation requires
+pub async fn set_derivation_required_labels(
+    pool: &PgPool,
+    derivation_id: i32,
+    labels: &[String],
+) -> Result<()> {
+    sqlx::query("UPDATE derivations SET required_labels = $2 WHERE id = $1")
+        .bind(derivation_id)
+        .bind(labels)
+        .execute(pool)
+        .await?;
     Ok(())
 }
 
+/// Assign the builder labels [`BuildConfig::label_rules`] require to the
+/// derivations of `commit_id`. Returns how many derivations need labels.
+pub async fn apply_label_rules(
+    pool: &PgPool,
+    commit_id: i32,
+    build_config: &BuildConfig,
+) -> Result<usize> {
+    if build_config.label_rules.is_empty() {
+        return Ok(0);
+    }
+
+    let derivations = sqlx::query_as::<_, (i32, String)>(
+        "SELECT id, derivation_name FROM derivations WHERE commit_id = $1",
+    )
+    .bind(commit_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut labeled = 0;
+    for (id, name) in derivations {
+        let labels = build_config.required_labels(&name);
+        if !labels.is_empty() {
+            set_derivation_required_labels(pool, id, &labels).await?;
+            labeled += 1;
+        }
+    }
+    Ok(labeled)
+}
+
+/// Mark the NixOS derivations of `commit_id` for the `hostnames` that are
+/// only ever served from the binary cache. Returns how many were marked.
+pub async fn mark_cache_only_derivations(
+    pool: &PgPool,
+    commit_id: i32,
+    hostnames: &[String],
+) -> Result<u64> {
+    if hostnames.is_empty() {
+        return Ok(0);
+    }
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE derivations
+        SET cache_only = true
+        WHERE commit_id = $1
+          AND derivation_type = 'nixos'
+          AND derivation_name = ANY($2)
+          AND NOT cache_only
+        "#,
+        commit_id,
+        hostnames
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Request cancellation of a derivation's build.
+///
+/// An in-progress build is flagged and the building worker kills it on its
+/// next heartbeat, marking it failed with "cancelled". A derivation that is
+/// still queued and not yet claimed is marked failed immediately. Either way
+/// every attempt is used up so restarts don't queue it again. Returns
+/// `false` if the derivation is in neither state.
+pub async fn request_cancellation(pool: &PgPool, derivation_id: i32) -> Result<bool> {
+    let flagged = sqlx::query!(
+        r#"
+        UPDATE derivations
+        SET cancellation_requested_at = NOW()
+        WHERE id = $1
+          AND status_id = $2
+        "#,
+        derivation_id,
+        EvaluationStatus::BuildInProgress.as_id()
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if flagged > 0 {
+        info!(
+            "🛑 Cancellation requested for in-progress build {}",
+            derivation_id
+        );
+        return Ok(true);
+    }
+
+    let dequeued = sqlx::query!(
+        r#"
+        UPDATE derivations d
+        SET status_id = $2,
+            error_message = 'build: cancelled',
+            failure_kind = $5,
+            attempt_count = GREATEST(d.attempt_count, $6),
+            cancellation_requested_at = NOW(),
+            completed_at = NOW()
+        WHERE d.id = $1
+          AND d.status_id IN ($3, $4)
+          AND NOT EXISTS (
+              SELECT 1 FROM build_reservations br WHERE br.derivation_id = d.id
+          )
+        "#,
+        derivation_id,
+        EvaluationStatus::BuildFailed.as_id(),
+        EvaluationStatus::DryRunComplete.as_id(),
+        EvaluationStatus::BuildPending.as_id(),
+        BuildFailureKind::Cancelled.as_str(),
+        MAX_ATTEMPTS
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if dequeued > 0 {
+        info!("🛑 Cancelled queued build {}", derivation_id);
+    }
+
+    Ok(dequeued > 0)
+}
+
 pub async fn batch_mark_derivations_complete(
     pool: &PgPool,
     deriv_ids: &[i32],
@@ -2010,3 +3075,600 @@ pub async fn cleanup_partial_derivations(pool: &PgPool) -> Result<()> {
 
     Ok(())
 }
+
+/// A single completed build and how long it took
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BuildDurationSample {
+    pub derivation_id: i32,
+    pub commit_id: Option<i32>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration_seconds: f64,
+}
+
+/// p50/p95 summary over a set of build durations
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct BuildDurationPercentiles {
+    pub samples: usize,
+    pub p50_seconds: f64,
+    pub p95_seconds: f64,
+}
+
+/// Recent completed build durations for a derivation name, newest first.
+///
+/// Prefers `build_elapsed_seconds` as reported by the builder heartbeat and
+/// falls back to `completed_at - started_at` for rows that never got one.
+pub async fn build_duration_history(
+    pool: &PgPool,
+    derivation_name: &str,
+    limit: i64,
+) -> Result<Vec<BuildDurationSample>> {
+    let samples = sqlx::query_as!(
+        BuildDurationSample,
+        r#"
+        SELECT
+            id AS derivation_id,
+            commit_id,
+            completed_at,
+            COALESCE(
+                build_elapsed_seconds::float8,
+                EXTRACT(EPOCH FROM (completed_at - started_at))::float8
+            ) AS "duration_seconds!"
+        FROM derivations
+        WHERE derivation_name = $1
+          AND status_id IN ($2, $3)
+          AND (
+            build_elapsed_seconds IS NOT NULL
+            OR (started_at IS NOT NULL AND completed_at IS NOT NULL)
+          )
+        ORDER BY completed_at DESC NULLS LAST, id DESC
+        LIMIT $4
+        "#,
+        derivation_name,
+        EvaluationStatus::BuildComplete.as_id(),
+        EvaluationStatus::CachePushed.as_id(),
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Failed to load build durations for {}", derivation_name))?;
+
+    Ok(samples)
+}
+
+/// Compute p50/p95 over the given samples using nearest-rank.
+/// Returns `None` when there is nothing to summarize.
+pub fn build_duration_percentiles(
+    samples: &[BuildDurationSample],
+) -> Option<BuildDurationPercentiles> {
+    let mut durations: Vec<f64> = samples
+        .iter()
+        .map(|s| s.duration_seconds)
+        .filter(|d| d.is_finite() && *d >= 0.0)
+        .collect();
+
+    if durations.is_empty() {
+        return None;
+    }
+
+    durations.sort_by(|a, b| a.total_cmp(b));
+
+    let rank = |p: f64| -> f64 {
+        let idx = ((p / 100.0) * durations.len() as f64).ceil() as usize;
+        durations[idx.clamp(1, durations.len()) - 1]
+    };
+
+    Some(BuildDurationPercentiles {
+        samples: durations.len(),
+        p50_seconds: rank(50.0),
+        p95_seconds: rank(95.0),
+    })
+}
+
+/// Page size [`search`] uses when none is given
+pub const DEFAULT_SEARCH_LIMIT: i64 = 50;
+/// Largest page [`search`] returns
+pub const MAX_SEARCH_LIMIT: i64 = 500;
+
+/// Filters and paging for [`search`]; unset filters match everything
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct SearchParams {
+    pub derivation_type: Option<DerivationType>,
+    pub status_id: Option<i32>,
+    pub flake_id: Option<i32>,
+    pub commit_id: Option<i32>,
+    /// Case-insensitive substring of `derivation_name`
+    pub name: Option<String>,
+    pub cf_agent_enabled: Option<bool>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self {
+            derivation_type: None,
+            status_id: None,
+            flake_id: None,
+            commit_id: None,
+            name: None,
+            cf_agent_enabled: None,
+            limit: DEFAULT_SEARCH_LIMIT,
+            offset: 0,
+        }
+    }
+}
+
+impl SearchParams {
+    /// `(limit, offset)` clamped to sane values
+    fn page(&self) -> (i64, i64) {
+        (self.limit.clamp(1, MAX_SEARCH_LIMIT), self.offset.max(0))
+    }
+}
+
+/// One page of [`search`] results and the number of matches overall
+#[derive(Debug, serde::Serialize)]
+pub struct SearchResults {
+    pub derivations: Vec<Derivation>,
+    pub total: i64,
+}
+
+/// List derivations matching `params`, newest first
+pub async fn search(pool: &PgPool, params: &SearchParams) -> Result<SearchResults> {
+    let (limit, offset) = params.page();
+    let derivation_type = params.derivation_type.as_ref().map(ToString::to_string);
+    let name = params.name.as_deref().filter(|name| !name.is_empty());
+
+    // Keep the filter of both queries in sync
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM derivations d
+        LEFT JOIN commits c ON c.id = d.commit_id
+        WHERE ($1::text IS NULL OR d.derivation_type = $1)
+          AND ($2::int IS NULL OR d.status_id = $2)
+          AND ($3::int IS NULL OR c.flake_id = $3)
+          AND ($4::int IS NULL OR d.commit_id = $4)
+          AND ($5::text IS NULL OR strpos(lower(d.derivation_name), lower($5)) > 0)
+          AND ($6::bool IS NULL OR d.cf_agent_enabled = $6)
+        "#,
+        derivation_type,
+        params.status_id,
+        params.flake_id,
+        params.commit_id,
+        name,
+        params.cf_agent_enabled
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let derivations = sqlx::query_as!(
+        Derivation,
+        r#"
+        SELECT
+            d.id, d.commit_id, d.derivation_type AS "derivation_type: DerivationType",
+            d.derivation_name, d.derivation_path, d.derivation_target, d.scheduled_at,
+            d.completed_at, d.started_at, d.attempt_count, d.evaluation_duration_ms,
+            d.error_message, d.pname, d.version, d.status_id, d.build_elapsed_seconds,
+            d.build_current_target, d.build_last_activity_seconds, d.build_last_heartbeat,
+            d.cf_agent_enabled, d.store_path
+        FROM derivations d
+        LEFT JOIN commits c ON c.id = d.commit_id
+        WHERE ($1::text IS NULL OR d.derivation_type = $1)
+          AND ($2::int IS NULL OR d.status_id = $2)
+          AND ($3::int IS NULL OR c.flake_id = $3)
+          AND ($4::int IS NULL OR d.commit_id = $4)
+          AND ($5::text IS NULL OR strpos(lower(d.derivation_name), lower($5)) > 0)
+          AND ($6::bool IS NULL OR d.cf_agent_enabled = $6)
+        ORDER BY d.id DESC
+        LIMIT $7 OFFSET $8
+        "#,
+        derivation_type,
+        params.status_id,
+        params.flake_id,
+        params.commit_id,
+        name,
+        params.cf_agent_enabled,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(SearchResults { derivations, total })
+}
+
+/// A derivation in a [`DependencyGraph`], with its build status
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GraphNode {
+    pub id: i32,
+    pub derivation_name: String,
+    pub derivation_type: String,
+    pub status_id: i32,
+    pub status: String,
+}
+
+/// `derivation_id` depends on `depends_on_id`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GraphEdge {
+    pub derivation_id: i32,
+    pub depends_on_id: i32,
+}
+
+/// The derivations of a commit and everything they depend on
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DependencyGraph {
+    pub commit_id: i32,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl DependencyGraph {
+    /// Graphviz rendering with nodes filled by build status, so failed and
+    /// blocked subtrees stand out. Edges point from a derivation to what it
+    /// depends on.
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph \"commit-{}\" {{\n", self.commit_id);
+        dot.push_str("  rankdir=LR;\n  node [style=filled];\n");
+        for node in &self.nodes {
+            let shape = if node.derivation_type == "nixos" {
+                "box"
+            } else {
+                "ellipse"
+            };
+            dot.push_str(&format!(
+                "  {} [label=\"{}\\n{}\", shape={}, fillcolor=\"{}\"];\n",
+                node.id,
+                dot_escape(&node.derivation_name),
+                dot_escape(&node.status),
+                shape,
+                status_color(node.status_id)
+            ));
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!(
+                "  {} -> {};\n",
+                edge.derivation_id, edge.depends_on_id
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Escape a string for use inside a quoted DOT id or label
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Fill colour of a node in [`DependencyGraph::to_dot`]
+fn status_color(status_id: i32) -> &'static str {
+    match status_id {
+        id if id == EvaluationStatus::BuildFailed.as_id()
+            || id == EvaluationStatus::DryRunFailed.as_id() =>
+        {
+            "#f4a6a6"
+        }
+        id if id == EvaluationStatus::Blocked.as_id() => "#f8cf8f",
+        id if id == EvaluationStatus::BuildInProgress.as_id()
+            || id == EvaluationStatus::DryRunInProgress.as_id() =>
+        {
+            "#fff3a0"
+        }
+        // BuildComplete, and CachePushed after it
+        id if id >= EvaluationStatus::BuildComplete.as_id() => "#b5e3b5",
+        _ => "#e0e0e0",
+    }
+}
+
+/// The dependency graph of a commit: its derivations plus every derivation
+/// reachable through `derivation_dependencies`, with the edges between them
+pub async fn export_dependency_graph(pool: &PgPool, commit_id: i32) -> Result<DependencyGraph> {
+    let nodes = sqlx::query_as!(
+        GraphNode,
+        r#"
+        WITH RECURSIVE reachable(id) AS (
+            SELECT id FROM derivations WHERE commit_id = $1
+            UNION
+            SELECT dd.depends_on_id
+            FROM derivation_dependencies dd
+            JOIN reachable r ON r.id = dd.derivation_id
+        )
+        SELECT
+            d.id AS "id!", d.derivation_name AS "derivation_name!",
+            d.derivation_type AS "derivation_type!", d.status_id AS "status_id!",
+            COALESCE(s.name, 'unknown') AS "status!"
+        FROM reachable r
+        JOIN derivations d ON d.id = r.id
+        LEFT JOIN derivation_statuses s ON s.id = d.status_id
+        ORDER BY d.id
+        "#,
+        commit_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let ids: Vec<i32> = nodes.iter().map(|node| node.id).collect();
+    let edges = sqlx::query_as!(
+        GraphEdge,
+        r#"
+        SELECT derivation_id, depends_on_id
+        FROM derivation_dependencies
+        WHERE derivation_id = ANY($1)
+        ORDER BY derivation_id, depends_on_id
+        "#,
+        &ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(DependencyGraph {
+        commit_id,
+        nodes,
+        edges,
+    })
+}
+
+/// A derivation in both commits of a [`CommitDiff`] whose store path
+/// differs between them
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChangedDerivation {
+    pub derivation_name: String,
+    pub old_store_path: Option<String>,
+    pub new_store_path: Option<String>,
+}
+
+/// Derivations added, removed or changed going from one commit to another
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct CommitDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<ChangedDerivation>,
+}
+
+/// Compare the derivations of `commit_a` with those of `commit_b`, matched
+/// by name. A derivation not yet built in one of the commits counts as
+/// changed, since its store path can't be shown to be the same.
+pub async fn diff_commits(pool: &PgPool, commit_a: i32, commit_b: i32) -> Result<CommitDiff> {
+    let rows = sqlx::query_as::<_, (String, Option<String>, Option<String>, bool, bool)>(
+        r#"
+        WITH a AS (
+            SELECT DISTINCT ON (derivation_name) derivation_name, store_path
+            FROM derivations
+            WHERE commit_id = $1
+            ORDER BY derivation_name, id DESC
+        ),
+        b AS (
+            SELECT DISTINCT ON (derivation_name) derivation_name, store_path
+            FROM derivations
+            WHERE commit_id = $2
+            ORDER BY derivation_name, id DESC
+        )
+        SELECT
+            COALESCE(a.derivation_name, b.derivation_name) AS derivation_name,
+            a.store_path AS old_store_path,
+            b.store_path AS new_store_path,
+            a.derivation_name IS NOT NULL AS in_a,
+            b.derivation_name IS NOT NULL AS in_b
+        FROM a
+        FULL OUTER JOIN b ON b.derivation_name = a.derivation_name
+        WHERE a.derivation_name IS NULL
+           OR b.derivation_name IS NULL
+           OR a.store_path IS DISTINCT FROM b.store_path
+        ORDER BY 1
+        "#,
+    )
+    .bind(commit_a)
+    .bind(commit_b)
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Failed to diff commits {} and {}", commit_a, commit_b))?;
+
+    let mut diff = CommitDiff::default();
+    for (derivation_name, old_store_path, new_store_path, in_a, in_b) in rows {
+        match (in_a, in_b) {
+            (false, _) => diff.added.push(derivation_name),
+            (_, false) => diff.removed.push(derivation_name),
+            _ => diff.changed.push(ChangedDerivation {
+                derivation_name,
+                old_store_path,
+                new_store_path,
+            }),
+        }
+    }
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(secs: f64) -> BuildDurationSample {
+        BuildDurationSample {
+            derivation_id: 0,
+            commit_id: None,
+            completed_at: None,
+            duration_seconds: secs,
+        }
+    }
+
+    #[test]
+    fn test_search_page_is_clamped() {
+        assert_eq!(SearchParams::default().page(), (DEFAULT_SEARCH_LIMIT, 0));
+
+        let params = SearchParams {
+            limit: 10_000,
+            offset: -5,
+            ..Default::default()
+        };
+        assert_eq!(params.page(), (MAX_SEARCH_LIMIT, 0));
+
+        let params = SearchParams {
+            limit: 0,
+            offset: 100,
+            ..Default::default()
+        };
+        assert_eq!(params.page(), (1, 100));
+    }
+
+    #[test]
+    fn test_dependency_graph_to_dot() {
+        let node = |id, name: &str, derivation_type: &str, status: EvaluationStatus| GraphNode {
+            id,
+            derivation_name: name.to_string(),
+            derivation_type: derivation_type.to_string(),
+            status_id: status.as_id(),
+            status: status.db_name().to_string(),
+        };
+        let graph = DependencyGraph {
+            commit_id: 7,
+            nodes: vec![
+                node(1, "web01", "nixos", EvaluationStatus::Blocked),
+                node(
+                    2,
+                    "openssl \"patched\"",
+                    "package",
+                    EvaluationStatus::BuildFailed,
+                ),
+            ],
+            edges: vec![GraphEdge {
+                derivation_id: 1,
+                depends_on_id: 2,
+            }],
+        };
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph \"commit-7\" {"));
+        assert!(dot.contains("  1 [label=\"web01\\nblocked\", shape=box, fillcolor=\"#f8cf8f\"];"));
+        assert!(dot.contains(
+            r##"label="openssl \"patched\"\nbuild-failed", shape=ellipse, fillcolor="#f4a6a6""##
+        ));
+        assert!(dot.contains("  1 -> 2;"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_build_duration_percentiles_empty() {
+        assert_eq!(build_duration_percentiles(&[]), None);
+    }
+
+    #[test]
+    fn test_build_duration_percentiles_nearest_rank() {
+        let samples: Vec<_> = (1..=20).map(|s| sample(s as f64)).rev().collect();
+        let p = build_duration_percentiles(&samples).unwrap();
+        assert_eq!(p.samples, 20);
+        assert_eq!(p.p50_seconds, 10.0);
+        assert_eq!(p.p95_seconds, 19.0);
+    }
+
+    #[test]
+    fn test_build_duration_percentiles_ignores_negative() {
+        let samples = vec![sample(-5.0), sample(30.0)];
+        let p = build_duration_percentiles(&samples).unwrap();
+        assert_eq!(p.samples, 1);
+        assert_eq!(p.p50_seconds, 30.0);
+        assert_eq!(p.p95_seconds, 30.0);
+    }
+
+    #[tokio::test]
+    async fn reschedule_refuses_more_than_max_rows() {
+        let Some(pool) = crate::db::test_pool().await else {
+            return;
+        };
+        let name = format!("reschedule-test-{}", uuid::Uuid::new_v4());
+        let commit_id: i32 = sqlx::query_scalar(
+            r#"
+            WITH flake AS (
+                INSERT INTO flakes (name, repo_url) VALUES ($1, $1) RETURNING id
+            )
+            INSERT INTO commits (flake_id, git_commit_hash, commit_timestamp)
+            SELECT id, $1, NOW() FROM flake
+            RETURNING id
+            "#,
+        )
+        .bind(&name)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        for suffix in ["a", "b"] {
+            sqlx::query(
+                r#"
+                INSERT INTO derivations (commit_id, derivation_type, derivation_name, status_id)
+                VALUES ($1, 'package', $2, $3)
+                "#,
+            )
+            .bind(commit_id)
+            .bind(format!("{}-{}", name, suffix))
+            .bind(EvaluationStatus::BuildPending.as_id())
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let mut filter = RescheduleFilter {
+            commit_id: Some(commit_id),
+            max_rows: 1,
+            ..Default::default()
+        };
+        let err = reschedule(&pool, &filter).await.unwrap_err();
+        assert!(err.downcast_ref::<RescheduleLimitExceeded>().is_some());
+
+        filter.max_rows = 2;
+        assert_eq!(reschedule(&pool, &filter).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn cancelled_builds_are_not_requeued_on_restart() {
+        let Some(pool) = crate::db::test_pool().await else {
+            return;
+        };
+        let name = format!("cancel-test-{}", uuid::Uuid::new_v4());
+        let mut ids = Vec::new();
+        for (suffix, status) in [
+            ("queued", EvaluationStatus::BuildPending),
+            ("running", EvaluationStatus::BuildInProgress),
+        ] {
+            let id: i32 = sqlx::query_scalar(
+                r#"
+                INSERT INTO derivations
+                    (derivation_type, derivation_name, derivation_path, status_id, started_at)
+                VALUES ('package', $1, $2, $3, NOW())
+                RETURNING id
+                "#,
+            )
+            .bind(format!("{}-{}", name, suffix))
+            .bind(format!("/nix/store/{}-{}.drv", name, suffix))
+            .bind(status.as_id())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+
+        // queued: failed right away; running: killed by its worker
+        assert!(request_cancellation(&pool, ids[0]).await.unwrap());
+        assert!(request_cancellation(&pool, ids[1]).await.unwrap());
+        let running = get_derivation_by_id(&pool, ids[1]).await.unwrap();
+        handle_derivation_failure(&pool, &running, "build", &BuildCancelled.into())
+            .await
+            .unwrap();
+
+        // the restart reset touches every derivation, so keep it to a
+        // transaction other tests never see
+        let mut tx = pool.begin().await.unwrap();
+        reset_non_terminal_derivations(&mut tx).await.unwrap();
+        let rows: Vec<(i32, Option<String>)> = sqlx::query_as(
+            "SELECT status_id, failure_kind FROM derivations WHERE id = ANY($1) ORDER BY id",
+        )
+        .bind(&ids)
+        .fetch_all(&mut *tx)
+        .await
+        .unwrap();
+        tx.rollback().await.unwrap();
+
+        for (status_id, failure_kind) in rows {
+            assert_eq!(status_id, EvaluationStatus::BuildFailed.as_id());
+            assert_eq!(failure_kind.as_deref(), Some("cancelled"));
+        }
+    }
+}
@@ -66,6 +66,11 @@ pub async fn get_all_flakes_from_db(
                 repo_url: row.repo_url,
                 auto_poll: true,
                 initial_commit_depth: config_flake.map(|f| f.initial_commit_depth).unwrap_or(5), // fallback to 5 for database-only flakes
+                auth: config_flake.and_then(|f| f.auth.clone()),
+                eval_filter: config_flake
+                    .map(|f| f.eval_filter.clone())
+                    .unwrap_or_default(),
+                allow_dirty: config_flake.is_some_and(|f| f.allow_dirty),
             }
         })
         .collect())
@@ -0,0 +1,139 @@
+use anyhow::{Result, bail};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::info;
+
+/// Row counts removed by [`prune_old_derivations`]
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct PruneSummary {
+    /// Derivations belonging to commits outside the retention window
+    pub commit_derivations: u64,
+    /// Standalone package derivations no longer referenced by anything
+    pub orphaned_packages: u64,
+}
+
+impl PruneSummary {
+    pub fn total(&self) -> u64 {
+        self.commit_derivations + self.orphaned_packages
+    }
+}
+
+/// Delete derivations older than the newest `keep_commits_per_flake` commits of
+/// each flake, then sweep up standalone package derivations that nothing depends
+/// on anymore. Rows in `cache_push_jobs`, `cve_scans` (and their packages),
+/// and `derivation_dependencies` go with them via `ON DELETE CASCADE`.
+///
+/// Derivations that are currently deployed, targeted for deployment, or
+/// reserved by a builder are always kept.
+pub async fn prune_old_derivations(
+    pool: &PgPool,
+    keep_commits_per_flake: i64,
+) -> Result<PruneSummary> {
+    if keep_commits_per_flake < 1 {
+        bail!("keep_commits_per_flake must be at least 1");
+    }
+
+    let mut tx = pool.begin().await?;
+
+    // The protected-derivation filter is shared with the orphan sweep below;
+    // keep both in sync
+    let commit_derivations = sqlx::query!(
+        r#"
+        WITH ranked_commits AS (
+            SELECT
+                c.id,
+                ROW_NUMBER() OVER (
+                    PARTITION BY c.flake_id
+                    ORDER BY c.commit_timestamp DESC, c.id DESC
+                ) AS rn
+            FROM commits c
+        )
+        DELETE FROM derivations d
+        USING ranked_commits rc
+        WHERE d.commit_id = rc.id
+          AND rc.rn > $1
+          -- Never prune anything a system is told to run, anything a system
+          -- last reported running, or anything a builder holds, including
+          -- the system a reserved package is being built for
+          AND NOT EXISTS (
+              SELECT 1 FROM systems s
+              WHERE s.desired_derivation_id = d.id
+                 OR (d.store_path IS NOT NULL AND s.desired_target = d.store_path)
+                 OR s.desired_target = d.derivation_path
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM (
+                  SELECT DISTINCT ON (hostname) store_path
+                  FROM system_states
+                  ORDER BY hostname, timestamp DESC
+              ) current_state
+              WHERE d.store_path IS NOT NULL AND current_state.store_path = d.store_path
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM build_reservations br
+              WHERE br.derivation_id = d.id OR br.nixos_derivation_id = d.id
+          )
+        "#,
+        keep_commits_per_flake
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    let mut summary = PruneSummary {
+        commit_derivations,
+        ..Default::default()
+    };
+
+    // Removing a package can orphan its own dependencies, so repeat until
+    // nothing else falls out.
+    loop {
+        let deleted = sqlx::query!(
+            r#"
+            DELETE FROM derivations d
+            WHERE d.commit_id IS NULL
+              AND d.derivation_type = 'package'
+              AND NOT EXISTS (
+                  SELECT 1 FROM derivation_dependencies dd WHERE dd.depends_on_id = d.id
+              )
+              -- Same protected derivations as the commit prune above
+              AND NOT EXISTS (
+                  SELECT 1 FROM systems s
+                  WHERE s.desired_derivation_id = d.id
+                     OR (d.store_path IS NOT NULL AND s.desired_target = d.store_path)
+                     OR s.desired_target = d.derivation_path
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM (
+                      SELECT DISTINCT ON (hostname) store_path
+                      FROM system_states
+                      ORDER BY hostname, timestamp DESC
+                  ) current_state
+                  WHERE d.store_path IS NOT NULL AND current_state.store_path = d.store_path
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM build_reservations br
+                  WHERE br.derivation_id = d.id OR br.nixos_derivation_id = d.id
+              )
+            "#
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if deleted == 0 {
+            break;
+        }
+        summary.orphaned_packages += deleted;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "🧹 Pruned {} derivations ({} from old commits, {} orphaned packages), keeping {} commits per flake",
+        summary.total(),
+        summary.commit_derivations,
+        summary.orphaned_packages,
+        keep_commits_per_flake
+    );
+
+    Ok(summary)
+}
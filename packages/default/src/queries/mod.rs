@@ -7,6 +7,8 @@ pub mod deployment;
 pub mod derivations;
 pub mod environments;
 pub mod flakes;
+pub mod maintenance;
+pub mod sboms;
 pub mod system_states;
 pub mod systems;
 pub mod users;
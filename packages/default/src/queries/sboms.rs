@@ -0,0 +1,53 @@
+use crate::sbom::Sbom;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// The SBOM recorded for a derivation
+#[derive(Debug, Serialize)]
+pub struct StoredSbom {
+    pub derivation_id: i32,
+    pub format: String,
+    pub document: serde_json::Value,
+    pub component_count: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Record the SBOM of a derivation, replacing any earlier one
+pub async fn save_sbom(pool: &PgPool, derivation_id: i32, sbom: &Sbom) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO derivation_sboms (derivation_id, format, document, component_count)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (derivation_id) DO UPDATE SET
+            format = EXCLUDED.format,
+            document = EXCLUDED.document,
+            component_count = EXCLUDED.component_count,
+            created_at = NOW()
+        "#,
+        derivation_id,
+        sbom.format.as_str(),
+        &sbom.document,
+        sbom.component_count as i32
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The SBOM recorded for a derivation, if one has been generated
+pub async fn get_sbom(pool: &PgPool, derivation_id: i32) -> Result<Option<StoredSbom>> {
+    let sbom = sqlx::query_as!(
+        StoredSbom,
+        r#"
+        SELECT derivation_id, format, document, component_count, created_at
+        FROM derivation_sboms
+        WHERE derivation_id = $1
+        "#,
+        derivation_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(sbom)
+}
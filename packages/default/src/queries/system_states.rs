@@ -22,6 +22,7 @@ pub async fn insert_system_state(
             uptime_secs, 
             cpu_brand, 
             cpu_cores,
+            nix_store_free_bytes,
             board_serial, 
             product_uuid, 
             rootfs_uuid,
@@ -31,6 +32,7 @@ pub async fn insert_system_state(
             network_interfaces,
             primary_mac_address,
             primary_ip_address,
+            primary_ipv6_address,
             gateway_ip,
             selinux_status,
             tpm_present,
@@ -40,8 +42,9 @@ pub async fn insert_system_state(
             agent_build_hash,
             nixos_version,
             agent_compatible,
-            partial_data
-        ) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16,$17,$18,$19,$20,$21,$22,$23,$24,$25,$26,$27,$28)"#,
+            partial_data,
+            deployment_source
+        ) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16,$17,$18,$19,$20,$21,$22,$23,$24,$25,$26,$27,$28,$29,$30,$31)"#,
     )
     .bind(&state.hostname)
     .bind(change_reason)
@@ -52,6 +55,7 @@ pub async fn insert_system_state(
     .bind(state.uptime_secs)
     .bind(&state.cpu_brand)
     .bind(state.cpu_cores)
+    .bind(state.nix_store_free_bytes)
     .bind(&state.board_serial)
     .bind(&state.product_uuid)
     .bind(&state.rootfs_uuid)
@@ -61,6 +65,7 @@ pub async fn insert_system_state(
     .bind(&state.network_interfaces)
     .bind(&state.primary_mac_address)
     .bind(&state.primary_ip_address)
+    .bind(&state.primary_ipv6_address)
     .bind(&state.gateway_ip)
     .bind(&state.selinux_status)
     .bind(state.tpm_present)
@@ -69,8 +74,9 @@ pub async fn insert_system_state(
     .bind(&state.agent_version)
     .bind(&state.agent_build_hash)
     .bind(&state.nixos_version)
-    .bind(version_compatible)  // $27
-    .bind(!version_compatible) // $28 - partial_data flag
+    .bind(version_compatible)  // $29
+    .bind(!version_compatible) // $30 - partial_data flag
+    .bind(&state.deployment_source)
     .execute(pool)
     .await
     .map_err(|e| anyhow::anyhow!("SQL error: {e:?}"))?;
@@ -130,6 +136,9 @@ mod tests {
             id: None,
             hostname: "test-host".to_string(),
             change_reason: "test-context".to_string(),
+            deployment_source: None,
+            last_deployment: None,
+            wants_prefetch: false,
             store_path: Some("/nix/store/test".to_string()),
             os: Some("NixOS".to_string()),
             kernel: Some("6.1.0".to_string()),
@@ -149,6 +158,8 @@ mod tests {
             ),
             primary_mac_address: Some("00:11:22:33:44:55".to_string()),
             primary_ip_address: Some("192.168.1.100".to_string()),
+            primary_ipv6_address: Some("2001:db8::100".to_string()),
+            nix_store_free_bytes: Some(50 * 1024 * 1024 * 1024),
             gateway_ip: Some("192.168.1.1".to_string()),
             selinux_status: Some("disabled".to_string()),
             tpm_present: Some(true),
@@ -1,6 +1,27 @@
 use crate::models::systems::System;
+use crate::queries::derivations::EvaluationStatus;
 use anyhow::Result;
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+
+/// A system whose agent has not reported for a while
+#[derive(Debug, FromRow, Serialize)]
+pub struct StaleSystem {
+    pub hostname: String,
+    /// When the agent last reported its state
+    pub last_seen: DateTime<Utc>,
+    /// Store path the system was running at that point
+    pub last_store_path: Option<String>,
+}
+
+/// A system placed in a production environment
+#[derive(Debug)]
+pub struct ProductionSystem {
+    pub hostname: String,
+    pub environment: String,
+}
 
 pub async fn update_hostname(pool: &PgPool, system: &System, new_hostname: &str) -> Result<()> {
     sqlx::query("UPDATE systems SET hostname = $1, updated_at = NOW() WHERE id = $2")
@@ -83,6 +104,50 @@ pub async fn get_desired_target_by_hostname(
     Ok(result.flatten())
 }
 
+/// Package name of the agent inside a NixOS system closure
+const AGENT_PNAME: &str = "agent";
+
+/// Store path of the agent package in the newest built, agent-enabled
+/// configuration of `hostname`, if one has been built on its own. Offered
+/// to the agent so it can update itself without a full system switch.
+pub async fn get_agent_update_for_hostname(
+    pool: &PgPool,
+    hostname: &str,
+) -> Result<Option<String>> {
+    let store_path = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT p.store_path
+        FROM systems s
+        JOIN LATERAL (
+            SELECT d.id
+            FROM derivations d
+            JOIN commits c ON c.id = d.commit_id
+            WHERE c.flake_id = s.flake_id
+              AND d.derivation_type = 'nixos'
+              AND d.derivation_name = s.hostname
+              AND d.status_id = $3
+              AND d.cf_agent_enabled = true
+            ORDER BY c.commit_timestamp DESC, d.id DESC
+            LIMIT 1
+        ) latest ON true
+        JOIN derivation_dependencies dd ON dd.derivation_id = latest.id
+        JOIN derivations p ON p.id = dd.depends_on_id
+        WHERE s.hostname = $1
+          AND p.pname = $2
+          AND p.status_id = $3
+          AND p.store_path IS NOT NULL
+        LIMIT 1
+        "#,
+    )
+    .bind(hostname)
+    .bind(AGENT_PNAME)
+    .bind(EvaluationStatus::BuildComplete.as_id())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(store_path)
+}
+
 pub async fn get_desired_target_by_id(pool: &PgPool, system_id: i32) -> Result<Option<String>> {
     let result =
         sqlx::query_scalar::<_, Option<String>>("SELECT desired_target FROM systems WHERE id = $1")
@@ -93,3 +158,174 @@ pub async fn get_desired_target_by_id(pool: &PgPool, system_id: i32) -> Result<O
     // Handle the nested Option from fetch_optional + nullable column
     Ok(result.flatten())
 }
+
+/// Systems not heard from for longer than `older_than`, neither through a
+/// state change nor a heartbeat, e.g. because their agent crashed or the
+/// host fell off the network. Systems that never reported are not included.
+/// Longest silent first.
+pub async fn stale_systems(pool: &PgPool, older_than: Duration) -> Result<Vec<StaleSystem>> {
+    let stale = sqlx::query_as::<_, StaleSystem>(
+        r#"
+        SELECT
+            s.hostname,
+            GREATEST(latest.timestamp, heartbeat.timestamp) AS last_seen,
+            latest.store_path AS last_store_path
+        FROM systems s
+        JOIN LATERAL (
+            SELECT ss.id, ss.timestamp, ss.store_path
+            FROM system_states ss
+            WHERE ss.hostname = s.hostname
+            ORDER BY ss.timestamp DESC
+            LIMIT 1
+        ) latest ON true
+        LEFT JOIN LATERAL (
+            SELECT MAX(ah.timestamp) AS timestamp
+            FROM agent_heartbeats ah
+            WHERE ah.system_state_id = latest.id
+        ) heartbeat ON true
+        WHERE GREATEST(latest.timestamp, heartbeat.timestamp)
+              < NOW() - make_interval(secs => $1)
+        ORDER BY last_seen ASC
+        "#,
+    )
+    .bind(older_than.as_secs_f64())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(stale)
+}
+
+/// Systems of flake `flake_id` in a production environment: one named
+/// `prod` or `production`, or with a HIGH or CRITICAL risk profile, as
+/// `EnvironmentConfig::is_production` decides for configured environments
+pub async fn get_production_systems_for_flake(
+    pool: &PgPool,
+    flake_id: i32,
+) -> Result<Vec<ProductionSystem>> {
+    let systems = sqlx::query_as!(
+        ProductionSystem,
+        r#"
+        SELECT s.hostname, e.name AS environment
+        FROM systems s
+        JOIN environments e ON e.id = s.environment_id
+        LEFT JOIN risk_profiles rp ON rp.id = e.risk_profile_id
+        WHERE s.flake_id = $1
+          AND (LOWER(e.name) IN ('prod', 'production')
+               OR UPPER(rp.name) IN ('HIGH', 'CRITICAL'))
+        ORDER BY s.hostname
+        "#,
+        flake_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(systems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    #[tokio::test]
+    async fn recent_heartbeat_keeps_a_system_fresh() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let silent = format!("stale-test-{}", uuid::Uuid::new_v4());
+        let beating = format!("stale-test-{}", uuid::Uuid::new_v4());
+
+        for hostname in [&silent, &beating] {
+            sqlx::query(
+                "INSERT INTO systems (hostname, public_key, derivation) VALUES ($1, 'key', $1)",
+            )
+            .bind(hostname)
+            .execute(&pool)
+            .await
+            .unwrap();
+            let state_id: i32 = sqlx::query_scalar(
+                r#"
+                INSERT INTO system_states (hostname, store_path, change_reason, timestamp)
+                VALUES ($1, '/nix/store/aaa-system', 'startup', NOW() - INTERVAL '2 hours')
+                RETURNING id
+                "#,
+            )
+            .bind(hostname)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            if hostname == &beating {
+                sqlx::query("INSERT INTO agent_heartbeats (system_state_id) VALUES ($1)")
+                    .bind(state_id)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let stale: Vec<String> = stale_systems(&pool, Duration::from_secs(3600))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|system| system.hostname)
+            .collect();
+        assert!(stale.contains(&silent));
+        assert!(!stale.contains(&beating));
+    }
+
+    #[tokio::test]
+    async fn production_systems_are_found_by_name_or_risk_profile() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let name = format!("prod-test-{}", uuid::Uuid::new_v4());
+        let flake_id: i32 =
+            sqlx::query_scalar("INSERT INTO flakes (name, repo_url) VALUES ($1, $1) RETURNING id")
+                .bind(&name)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let environment = |risk: &'static str| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_scalar::<_, uuid::Uuid>(
+                    r#"
+                    INSERT INTO environments (name, risk_profile_id)
+                    SELECT $1, id FROM risk_profiles WHERE name = $2
+                    RETURNING id
+                    "#,
+                )
+                .bind(format!("env-{}", uuid::Uuid::new_v4().simple()))
+                .bind(risk)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+        let low = environment("LOW").await;
+        let critical = environment("CRITICAL").await;
+
+        for (suffix, environment_id) in [("dev", low), ("db", critical)] {
+            sqlx::query(
+                r#"
+                INSERT INTO systems (hostname, public_key, derivation, flake_id, environment_id)
+                VALUES ($1, 'key', $1, $2, $3)
+                "#,
+            )
+            .bind(format!("{}-{}", name, suffix))
+            .bind(flake_id)
+            .bind(environment_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let production: Vec<String> = get_production_systems_for_flake(&pool, flake_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|system| system.hostname)
+            .collect();
+        assert_eq!(production, vec![format!("{}-db", name)]);
+    }
+}
@@ -0,0 +1,232 @@
+//! Software bills of materials for built derivations.
+//!
+//! Components are the derivations that produced the runtime closure of the
+//! built path, i.e. what actually ships, with names and versions taken from
+//! their `.drv` paths by [`parse_derivation_path`]. Paths whose name can't
+//! be parsed are left out.
+
+use crate::config::SbomFormat;
+use crate::derivations::Derivation;
+use crate::derivations::closure::runtime_closure_derivations;
+use crate::derivations::eval::parse_derivation_path;
+use anyhow::{Result, bail};
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+/// One package listed in an SBOM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SbomComponent {
+    pub name: String,
+    pub version: Option<String>,
+    /// `.drv` path for closure components, store path for the root
+    pub path: String,
+}
+
+impl SbomComponent {
+    /// Component for a `.drv` path, or `None` if no name can be parsed
+    fn from_derivation_path(drv_path: &str) -> Option<Self> {
+        let info = parse_derivation_path(drv_path)?;
+        Some(Self {
+            name: info.pname?,
+            version: info.version,
+            path: drv_path.to_string(),
+        })
+    }
+}
+
+/// A generated SBOM document
+#[derive(Debug, Clone)]
+pub struct Sbom {
+    pub format: SbomFormat,
+    pub document: Value,
+    pub component_count: usize,
+}
+
+impl Sbom {
+    /// SBOM of `root` whose closure is the derivations in `closure`
+    pub fn from_closure(format: SbomFormat, root: &SbomComponent, closure: &[String]) -> Self {
+        let mut components: Vec<SbomComponent> = closure
+            .iter()
+            .filter_map(|drv_path| SbomComponent::from_derivation_path(drv_path))
+            .collect();
+        components.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+        let document = match format {
+            SbomFormat::CycloneDx => cyclonedx_document(root, &components),
+            SbomFormat::Spdx => spdx_document(root, &components),
+        };
+        Self {
+            format,
+            document,
+            component_count: components.len(),
+        }
+    }
+}
+
+/// Generate the SBOM of a built derivation from its runtime closure
+pub async fn generate(derivation: &Derivation, format: SbomFormat) -> Result<Sbom> {
+    let Some(drv_path) = derivation.derivation_path.as_deref() else {
+        bail!("{} has no derivation path", derivation.derivation_name);
+    };
+    let Some(store_path) = derivation.store_path.as_deref() else {
+        bail!("{} has not been built", derivation.derivation_name);
+    };
+
+    let root = SbomComponent {
+        name: derivation.derivation_name.clone(),
+        version: derivation
+            .version
+            .clone()
+            .or_else(|| parse_derivation_path(drv_path)?.version),
+        path: store_path.to_string(),
+    };
+    let closure = runtime_closure_derivations(store_path).await?;
+    Ok(Sbom::from_closure(format, &root, &closure))
+}
+
+fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// CycloneDX 1.5 JSON
+fn cyclonedx_document(root: &SbomComponent, components: &[SbomComponent]) -> Value {
+    let component = |c: &SbomComponent, kind: &str| {
+        json!({
+            "type": kind,
+            "bom-ref": c.path,
+            "name": c.name,
+            "version": c.version,
+        })
+    };
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "serialNumber": format!("urn:uuid:{}", Uuid::new_v4()),
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp(),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "crystal-forge",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": component(root, "application"),
+        },
+        "components": components
+            .iter()
+            .map(|c| component(c, "library"))
+            .collect::<Vec<_>>(),
+        "dependencies": [{
+            "ref": root.path,
+            "dependsOn": components.iter().map(|c| &c.path).collect::<Vec<_>>(),
+        }],
+    })
+}
+
+/// SPDX 2.3 JSON
+fn spdx_document(root: &SbomComponent, components: &[SbomComponent]) -> Value {
+    let package = |id: String, c: &SbomComponent| {
+        json!({
+            "SPDXID": id,
+            "name": c.name,
+            "versionInfo": c.version.as_deref().unwrap_or("NOASSERTION"),
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "comment": c.path,
+        })
+    };
+    let root_id = "SPDXRef-Root";
+
+    let mut packages = vec![package(root_id.to_string(), root)];
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": root_id,
+    })];
+    for (index, c) in components.iter().enumerate() {
+        let id = format!("SPDXRef-Package-{}", index);
+        relationships.push(json!({
+            "spdxElementId": root_id,
+            "relationshipType": "DEPENDS_ON",
+            "relatedSpdxElement": id,
+        }));
+        packages.push(package(id, c));
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": root.name,
+        "documentNamespace": format!(
+            "https://crystal-forge.invalid/spdx/{}-{}",
+            root.name,
+            Uuid::new_v4()
+        ),
+        "creationInfo": {
+            "created": timestamp(),
+            "creators": [format!("Tool: crystal-forge-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sbom(format: SbomFormat) -> Sbom {
+        let root = SbomComponent {
+            name: "web01".to_string(),
+            version: Some("25.05".to_string()),
+            path: "/nix/store/aaa-nixos-system-web01-25.05".to_string(),
+        };
+        let closure = [
+            "/nix/store/bbb-openssl-3.0.13.drv".to_string(),
+            "/nix/store/ccc-bash-5.2p26.drv".to_string(),
+            "/nix/store/ddd-source".to_string(),
+        ];
+        Sbom::from_closure(format, &root, &closure)
+    }
+
+    #[test]
+    fn cyclonedx_lists_parsed_closure_components() {
+        let sbom = sbom(SbomFormat::CycloneDx);
+        assert_eq!(sbom.component_count, 2);
+
+        let doc = &sbom.document;
+        assert_eq!(doc["bomFormat"], "CycloneDX");
+        assert_eq!(doc["metadata"]["component"]["name"], "web01");
+        assert_eq!(doc["components"][0]["name"], "bash");
+        assert_eq!(doc["components"][0]["version"], "5.2p26");
+        assert_eq!(doc["components"][1]["name"], "openssl");
+        assert_eq!(
+            doc["dependencies"][0]["dependsOn"][1],
+            "/nix/store/bbb-openssl-3.0.13.drv"
+        );
+    }
+
+    #[test]
+    fn spdx_root_depends_on_every_package() {
+        let sbom = sbom(SbomFormat::Spdx);
+        let doc = &sbom.document;
+        assert_eq!(doc["spdxVersion"], "SPDX-2.3");
+        assert_eq!(doc["packages"].as_array().unwrap().len(), 3);
+        assert_eq!(doc["packages"][2]["name"], "openssl");
+        assert_eq!(doc["packages"][2]["versionInfo"], "3.0.13");
+
+        let relationships = doc["relationships"].as_array().unwrap();
+        assert_eq!(relationships.len(), 3);
+        assert!(
+            relationships[1..]
+                .iter()
+                .all(|r| r["relationshipType"] == "DEPENDS_ON"
+                    && r["spdxElementId"] == "SPDXRef-Root")
+        );
+    }
+}
@@ -1,6 +1,10 @@
-use crate::config::{CrystalForgeConfig, FlakeConfig};
+use crate::config::{BuildConfig, CrystalForgeConfig, FlakeConfig};
+use crate::db;
 use crate::deployment::spawn_deployment_policy_manager;
-use crate::flake::commits::sync_all_watched_flakes_commits;
+use crate::derivations::evaluate_pending_flake_attrs;
+use crate::flake::commits::{SignatureCheckout, sync_all_watched_flakes_commits};
+use crate::flake::eval_cache::{evaluation_input_key, plan_cached_evaluation};
+use crate::flake::incremental::plan_incremental_evaluation;
 use crate::log::log_builder_worker_status;
 use crate::models::commits::Commit;
 use crate::models::deployment_policies::DeploymentPolicy;
@@ -8,22 +12,30 @@ use crate::models::evaluate_with_policies::evaluate_with_nix_eval_jobs;
 use crate::models::flakes::Flake;
 // NOTE: removed increment_commit_list_attempt_count – we now rely on the new evaluation_* fields
 use crate::queries::flakes::get_all_flakes_from_db;
+use crate::queries::systems::get_production_systems_for_flake;
+use crate::shutdown::{self, ShutdownRx};
+use crate::telemetry::commit_span;
 use anyhow::Result;
 use sqlx::PgPool;
+use std::collections::HashMap;
 use tokio::time;
 use tokio::time::Duration;
 use tokio::time::Instant;
 use tokio::time::interval;
-use tracing::{debug, error, info, warn};
+use tracing::{Instrument, debug, error, info, warn};
 
 // ⬇️ bring in the commit-eval helpers you said you added in queries/commits.rs
 use crate::queries::commits::{
     get_commits_pending_evaluation, mark_commit_evaluation_complete, mark_commit_evaluation_failed,
-    mark_commit_evaluation_started, reset_stuck_commit_evaluations,
+    mark_commit_evaluation_started, mark_fully_built_commits, record_cached_evaluation,
+    reject_commit_evaluation, reset_stuck_commit_evaluations,
+};
+use crate::queries::derivations::{
+    apply_label_rules, carry_forward_derivations, cleanup_partial_derivations,
+    mark_cache_only_derivations,
 };
-use crate::queries::derivations::cleanup_partial_derivations;
 
-pub fn spawn_background_tasks(cfg: CrystalForgeConfig, pool: PgPool) {
+pub fn spawn_background_tasks(cfg: CrystalForgeConfig, pool: PgPool, shutdown: ShutdownRx) {
     let flake_pool = pool.clone();
     let commit_pool = pool.clone();
     let target_pool = pool.clone();
@@ -32,18 +44,42 @@ pub fn spawn_background_tasks(cfg: CrystalForgeConfig, pool: PgPool) {
     // Get the flake config with a fallback
     let flake_config = cfg.flakes.clone();
 
-    tokio::spawn(run_flake_polling_loop(flake_pool, flake_config.clone()));
+    let startup_jitter = cfg.startup_jitter;
+
+    tokio::spawn(run_flake_polling_loop(
+        flake_pool,
+        flake_config.clone(),
+        startup_jitter,
+        shutdown.clone(),
+    ));
     tokio::spawn(run_commit_evaluation_loop(
         commit_pool,
+        cfg.build.clone(),
         flake_config.commit_evaluation_interval,
+        flake_config.max_eval_attempts,
+        flake_config.eval_batch_size,
+        startup_jitter,
+        shutdown.clone(),
     ));
 
-    tokio::spawn(spawn_deployment_policy_manager(cfg, deployment_pool));
+    tokio::spawn(spawn_deployment_policy_manager(
+        cfg,
+        deployment_pool,
+        shutdown,
+    ));
 }
 
 /// Runs the periodic flake polling loop to check for new commits
-async fn run_flake_polling_loop(pool: PgPool, flake_config: FlakeConfig) {
+async fn run_flake_polling_loop(
+    pool: PgPool,
+    flake_config: FlakeConfig,
+    startup_jitter: Duration,
+    mut shutdown: ShutdownRx,
+) {
     info!("🔄 Starting periodic flake polling loop...");
+    if shutdown::jittered_start(startup_jitter, &mut shutdown).await {
+        return;
+    }
     loop {
         // Get all flakes from database instead of just config ones
         match get_all_flakes_from_db(&pool, &flake_config).await {
@@ -56,17 +92,35 @@ async fn run_flake_polling_loop(pool: PgPool, flake_config: FlakeConfig) {
             }
             Err(e) => error!("❌ Failed to get flakes from database: {e}"),
         }
-        tokio::time::sleep(flake_config.flake_polling_interval).await;
+        if shutdown::sleep_or_shutdown(flake_config.flake_polling_interval, &mut shutdown).await {
+            info!("🛑 Flake polling loop stopped");
+            return;
+        }
     }
 }
 
 /// Runs the periodic commit evaluation check loop
-pub async fn run_commit_evaluation_loop(pool: PgPool, interval: Duration) {
+pub async fn run_commit_evaluation_loop(
+    pool: PgPool,
+    build_config: BuildConfig,
+    interval: Duration,
+    max_eval_attempts: u32,
+    eval_batch_size: usize,
+    startup_jitter: Duration,
+    mut shutdown: ShutdownRx,
+) {
+    // 0 would dead-letter commits before they were ever evaluated
+    let max_eval_attempts = max_eval_attempts.max(1) as i32;
+
     info!(
         "🔁 Starting periodic commit evaluation check loop (every {:?})...",
         interval
     );
 
+    if shutdown::jittered_start(startup_jitter, &mut shutdown).await {
+        return;
+    }
+
     // ⬇️ cleanup any stranded 'in_progress' from previous runs
     if let Err(e) = reset_stuck_commit_evaluations(&pool).await {
         error!("❌ Failed to reset stuck commit evaluations: {}", e);
@@ -83,17 +137,53 @@ pub async fn run_commit_evaluation_loop(pool: PgPool, interval: Duration) {
     let mut ticker = time::interval_at(Instant::now() + interval, interval);
 
     loop {
-        if let Err(e) = process_pending_commits(&pool).await {
+        if let Err(e) = process_pending_commits(&pool, max_eval_attempts, eval_batch_size).await {
             error!("❌ Error in commit evaluation cycle: {e}");
         }
-        ticker.tick().await;
+        // Flake attributes requested through `POST /commits/:hash/builds`
+        if let Err(e) = evaluate_pending_flake_attrs(&pool, &build_config).await {
+            error!("❌ Failed to evaluate requested flake attributes: {e}");
+        }
+        match mark_fully_built_commits(&pool).await {
+            Ok(built) => {
+                for hash in built {
+                    info!("🏁 Commit {} is fully built", hash);
+                }
+            }
+            Err(e) => error!("❌ Failed to mark fully built commits: {e}"),
+        }
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown::requested(&mut shutdown) => {
+                info!("🛑 Commit evaluation loop stopped");
+                return;
+            }
+        }
     }
 }
 
-async fn process_pending_commits(pool: &PgPool) -> Result<()> {
-    match get_commits_pending_evaluation(&pool).await {
+async fn process_pending_commits(
+    pool: &PgPool,
+    max_eval_attempts: i32,
+    eval_batch_size: usize,
+) -> Result<()> {
+    match db::retry_connection("fetching commits pending evaluation", || {
+        get_commits_pending_evaluation(pool, max_eval_attempts, None, eval_batch_size)
+    })
+    .await
+    {
         Ok(pending_commits) => {
             info!("📌 Found {} pending commits", pending_commits.len());
+            // Signatures are checked from one fetch of each flake's pending
+            // commits per cycle; `None` once that fetch failed
+            let mut pending_hashes: HashMap<i32, Vec<String>> = HashMap::new();
+            for commit in &pending_commits {
+                pending_hashes
+                    .entry(commit.flake_id)
+                    .or_default()
+                    .push(commit.git_commit_hash.clone());
+            }
+            let mut checkouts: HashMap<i32, Option<SignatureCheckout>> = HashMap::new();
             for commit in pending_commits {
                 // Get flake info
                 let flake = match commit.get_flake(&pool).await {
@@ -118,6 +208,67 @@ async fn process_pending_commits(pool: &PgPool) -> Result<()> {
                 let build_config = cfg.get_build_config();
                 let server_config = cfg.get_server_config();
 
+                // allow_dirty flakes are evaluated from their working tree
+                let working_tree = working_tree_ref(&pool, &cfg, &flake, &commit).await;
+                if let Some(path) = &working_tree {
+                    warn!(
+                        "🧪 Evaluating uncommitted working tree {} for commit {}",
+                        path, commit.git_commit_hash
+                    );
+                }
+                let eval_repo_url = working_tree.as_deref().unwrap_or(&flake.repo_url);
+
+                if cfg.flakes.require_signed_commits {
+                    if !checkouts.contains_key(&flake.id) {
+                        let hashes: Vec<&str> = pending_hashes[&flake.id]
+                            .iter()
+                            .map(String::as_str)
+                            .collect();
+                        let checkout = match SignatureCheckout::fetch(&flake.repo_url, &hashes)
+                            .await
+                        {
+                            Ok(checkout) => Some(checkout),
+                            Err(e) => {
+                                warn!(
+                                    "⚠️ Could not fetch pending commits of {} together, fetching them one by one: {}",
+                                    flake.name, e
+                                );
+                                None
+                            }
+                        };
+                        checkouts.insert(flake.id, checkout);
+                    }
+                    let check = match &checkouts[&flake.id] {
+                        Some(checkout) => {
+                            checkout
+                                .verify(
+                                    &commit.git_commit_hash,
+                                    cfg.flakes.allowed_signers_file.as_deref(),
+                                )
+                                .await
+                        }
+                        None => match SignatureCheckout::fetch(
+                            &flake.repo_url,
+                            &[commit.git_commit_hash.as_str()],
+                        )
+                        .await
+                        {
+                            Ok(checkout) => {
+                                checkout
+                                    .verify(
+                                        &commit.git_commit_hash,
+                                        cfg.flakes.allowed_signers_file.as_deref(),
+                                    )
+                                    .await
+                            }
+                            Err(e) => Err(e),
+                        },
+                    };
+                    if !signature_allows_evaluation(pool, &commit, check, max_eval_attempts).await {
+                        continue;
+                    }
+                }
+
                 // Set up deployment policies - check CF agent for all systems
                 // Using non-strict mode to collect data without failing evaluations
                 let policies = vec![DeploymentPolicy::RequireCrystalForgeAgent { strict: false }];
@@ -131,25 +282,125 @@ async fn process_pending_commits(pool: &PgPool) -> Result<()> {
                     continue;
                 }
 
+                // With the evaluation cache a commit whose flake.lock, sources
+                // and systems match an earlier evaluated commit copies that
+                // commit's derivations instead of evaluating
+                let input_key = if cfg.flakes.eval_cache && working_tree.is_none() {
+                    let systems: Vec<String> = cfg
+                        .systems
+                        .iter()
+                        .filter(|system| system.flake_name.as_deref() == Some(flake.name.as_str()))
+                        .map(|system| system.hostname.clone())
+                        .collect();
+                    match evaluation_input_key(
+                        &flake.repo_url,
+                        &commit.git_commit_hash,
+                        &cfg.flakes.eval_cache_ignore,
+                        &systems,
+                    )
+                    .await
+                    {
+                        Ok(key) => Some(key),
+                        Err(e) => {
+                            warn!(
+                                "⚠️ Could not hash evaluation inputs of {}, skipping the evaluation cache: {}",
+                                commit.git_commit_hash, e
+                            );
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+                let cached_plan = match &input_key {
+                    Some(key) => match plan_cached_evaluation(pool, &commit, key).await {
+                        Ok(plan) => plan,
+                        Err(e) => {
+                            warn!(
+                                "⚠️ Could not look up cached evaluation of {}: {}",
+                                commit.git_commit_hash, e
+                            );
+                            None
+                        }
+                    },
+                    None => None,
+                };
+
+                // With incremental evaluation only systems touched since the
+                // previous evaluated commit are evaluated; the rest are copied
+                // over once evaluation succeeds
+                let plan = match cached_plan {
+                    Some(plan) => Some(plan),
+                    None if cfg.flakes.incremental_eval && working_tree.is_none() => {
+                        match plan_incremental_evaluation(pool, &commit, &flake.repo_url).await {
+                            Ok(plan) => plan,
+                            Err(e) => {
+                                warn!(
+                                    "⚠️ Could not plan incremental evaluation of {}, evaluating everything: {}",
+                                    commit.git_commit_hash, e
+                                );
+                                None
+                            }
+                        }
+                    }
+                    None => None,
+                };
+
                 // Use nix-eval-jobs to discover AND evaluate all nixosConfigurations
                 // This will:
                 // 1. Evaluate all systems in parallel
                 // 2. Check deployment policies (CF agent status) for each system
                 // 3. Store policy results in database (cf_agent_enabled column)
                 // 4. Insert/update derivation records
-                match evaluate_with_nix_eval_jobs(
-                    pool,
-                    &commit,
-                    &flake,
-                    &flake.repo_url,
-                    &commit.git_commit_hash,
-                    "all", // Evaluate all systems
-                    &build_config,
-                    &server_config,
-                    &policies, // Check deployment policies
-                )
-                .await
-                {
+                let evaluation = async {
+                    let (results, policy_checks) = match &plan {
+                        Some(plan) if plan.evaluate.is_empty() => (Vec::new(), Vec::new()),
+                        _ => {
+                            evaluate_with_nix_eval_jobs(
+                                pool,
+                                &commit,
+                                &flake,
+                                eval_repo_url,
+                                &commit.git_commit_hash,
+                                "all", // Evaluate all systems
+                                plan.as_ref().map(|plan| plan.evaluate.as_slice()),
+                                &build_config,
+                                &server_config,
+                                &policies, // Check deployment policies
+                            )
+                            .await?
+                        }
+                    };
+                    if let Some(plan) = &plan {
+                        let carried = carry_forward_derivations(
+                            pool,
+                            &plan.previous,
+                            &commit,
+                            &plan.carry_forward,
+                        )
+                        .await?;
+                        info!(
+                            "⏩ Carried {} unchanged systems forward to commit {}",
+                            carried.len(),
+                            commit.git_commit_hash
+                        );
+                    }
+                    // Without the mark a lost store path is rebuilt rather than
+                    // flagged; no reason to fail an otherwise good evaluation
+                    if let Err(e) =
+                        mark_cache_only_derivations(pool, commit.id, &cfg.cache_only_systems())
+                            .await
+                    {
+                        warn!(
+                            "⚠️ Failed to mark cache-only systems of commit {}: {}",
+                            commit.git_commit_hash, e
+                        );
+                    }
+                    apply_label_rules(pool, commit.id, build_config).await?;
+                    anyhow::Ok((results, policy_checks))
+                };
+                let span = commit_span("commit_evaluation", Some(&commit.git_commit_hash), None);
+                match evaluation.instrument(span).await {
                     Ok((results, policy_checks)) => {
                         // ⬇️ mark COMPLETE
                         if let Err(e) = mark_commit_evaluation_complete(pool, commit.id).await {
@@ -158,6 +409,15 @@ async fn process_pending_commits(pool: &PgPool) -> Result<()> {
                                 commit.git_commit_hash, e
                             );
                         }
+                        if let Some(key) = &input_key
+                            && let Err(e) =
+                                record_cached_evaluation(pool, flake.id, key, commit.id).await
+                        {
+                            warn!(
+                                "⚠️ Failed to cache evaluation of commit {}: {}",
+                                commit.git_commit_hash, e
+                            );
+                        }
 
                         let total = results.len();
                         let with_agent = policy_checks
@@ -193,15 +453,25 @@ async fn process_pending_commits(pool: &PgPool) -> Result<()> {
                             commit.git_commit_hash, e
                         );
 
-                        // ⬇️ mark FAILED (function will set 'pending' or terminal 'failed'
-                        // depending on attempt limit inside your SQL)
-                        if let Err(mark_err) =
-                            mark_commit_evaluation_failed(pool, commit.id, &e.to_string()).await
+                        // ⬇️ mark FAILED ('pending' for a retry, or terminal 'eval_failed'
+                        // once max_eval_attempts is used up)
+                        match mark_commit_evaluation_failed(
+                            pool,
+                            commit.id,
+                            &e.to_string(),
+                            max_eval_attempts,
+                        )
+                        .await
                         {
-                            error!(
+                            Ok(true) => warn!(
+                                "☠️ Commit {} failed evaluation {} times, giving up until reset",
+                                commit.git_commit_hash, max_eval_attempts
+                            ),
+                            Ok(false) => {}
+                            Err(mark_err) => error!(
                                 "❌ Failed to mark commit {} evaluation failed: {}",
                                 commit.git_commit_hash, mark_err
-                            );
+                            ),
                         }
                     }
                 }
@@ -212,6 +482,96 @@ async fn process_pending_commits(pool: &PgPool) -> Result<()> {
     Ok(())
 }
 
+/// Act on the signature check of `commit`. A rejected signature
+/// dead-letters the commit; a check that could not run (e.g. the fetch
+/// failed) counts as a failed evaluation attempt, so the commit is retried
+/// with the usual backoff and dead-lettered once its attempts run out.
+/// Returns whether the commit may be evaluated.
+/// Working tree to evaluate `commit` of `flake` from, if the flake is marked
+/// `allow_dirty` and none of its systems is in production, whether placed
+/// there by the config or registered in the database
+async fn working_tree_ref(
+    pool: &PgPool,
+    cfg: &CrystalForgeConfig,
+    flake: &Flake,
+    commit: &Commit,
+) -> Option<String> {
+    let refused = |reason: String| {
+        warn!(
+            "🚫 Not evaluating the working tree of {}, pinning {} instead: {}",
+            flake.name, commit.git_commit_hash, reason
+        );
+        None
+    };
+
+    let path = match cfg.dirty_flake_ref(&flake.name) {
+        Ok(path) => path?,
+        Err(reason) => return refused(reason),
+    };
+    match get_production_systems_for_flake(pool, flake.id).await {
+        Ok(production) => match production.first() {
+            Some(system) => refused(format!(
+                "system '{}' is in production environment '{}'",
+                system.hostname, system.environment
+            )),
+            None => Some(path),
+        },
+        Err(e) => refused(format!("could not check for production systems: {}", e)),
+    }
+}
+
+async fn signature_allows_evaluation(
+    pool: &PgPool,
+    commit: &Commit,
+    check: Result<std::result::Result<(), String>>,
+    max_eval_attempts: i32,
+) -> bool {
+    match check {
+        Ok(Ok(())) => {
+            debug!(
+                "🔏 Signature verified for commit {}",
+                commit.git_commit_hash
+            );
+            true
+        }
+        Ok(Err(reason)) => {
+            let reason = format!("commit signature verification failed: {}", reason);
+            warn!(
+                "🚫 Refusing to evaluate {}: {}",
+                commit.git_commit_hash, reason
+            );
+            if let Err(e) = reject_commit_evaluation(pool, commit.id, &reason).await {
+                error!(
+                    "❌ Failed to mark commit {} as rejected: {}",
+                    commit.git_commit_hash, e
+                );
+            }
+            false
+        }
+        Err(e) => {
+            let reason = format!("could not verify commit signature: {:#}", e);
+            error!("❌ {} for commit {}", reason, commit.git_commit_hash);
+            let marked = async {
+                mark_commit_evaluation_started(pool, commit.id).await?;
+                mark_commit_evaluation_failed(pool, commit.id, &reason, max_eval_attempts).await
+            }
+            .await;
+            match marked {
+                Ok(true) => warn!(
+                    "☠️ Commit {} failed signature checks {} times, giving up until reset",
+                    commit.git_commit_hash, max_eval_attempts
+                ),
+                Ok(false) => {}
+                Err(e) => error!(
+                    "❌ Failed to mark commit {} evaluation failed: {}",
+                    commit.git_commit_hash, e
+                ),
+            }
+            false
+        }
+    }
+}
+
 pub async fn memory_monitor_task(pool: PgPool) {
     let mut interval = interval(Duration::from_secs(30));
     loop {
@@ -220,28 +580,34 @@ pub async fn memory_monitor_task(pool: PgPool) {
     }
 }
 
+/// Number from a `Key:   1234 kB` line of a /proc file such as
+/// /proc/self/status or /proc/meminfo
+fn proc_kb_field<'a>(contents: &'a str, key: &str) -> Option<&'a str> {
+    contents.lines().find_map(|line| {
+        let value = line.strip_prefix(key)?.strip_prefix(':')?;
+        value.split_whitespace().next()
+    })
+}
+
+/// Memory available to new processes on this host in MiB, from
+/// `MemAvailable` in /proc/meminfo (`MemFree` on kernels without it)
+pub async fn available_memory_mb() -> Option<u64> {
+    let contents = tokio::fs::read_to_string("/proc/meminfo").await.ok()?;
+    let kb = proc_kb_field(&contents, "MemAvailable")
+        .or_else(|| proc_kb_field(&contents, "MemFree"))?
+        .parse::<u64>()
+        .ok()?;
+    Some(kb / 1024)
+}
+
 async fn log_memory_usage(pool: &PgPool) {
     // Memory stats from /proc/self/status
     if let Ok(contents) = tokio::fs::read_to_string("/proc/self/status").await {
-        let mut vm_rss = None;
-        let mut vm_size = None;
-        let mut vm_peak = None;
-
-        for line in contents.lines() {
-            if line.starts_with("VmRSS:") {
-                vm_rss = line.split_whitespace().nth(1);
-            } else if line.starts_with("VmSize:") {
-                vm_size = line.split_whitespace().nth(1);
-            } else if line.starts_with("VmPeak:") {
-                vm_peak = line.split_whitespace().nth(1);
-            }
-        }
-
         debug!(
             "📊 Memory - RSS: {} kB, Size: {} kB, Peak: {} kB",
-            vm_rss.unwrap_or("?"),
-            vm_size.unwrap_or("?"),
-            vm_peak.unwrap_or("?")
+            proc_kb_field(&contents, "VmRSS").unwrap_or("?"),
+            proc_kb_field(&contents, "VmSize").unwrap_or("?"),
+            proc_kb_field(&contents, "VmPeak").unwrap_or("?")
         );
     }
 
@@ -264,3 +630,83 @@ async fn log_memory_usage(pool: &PgPool) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_kb_field_reads_exact_keys() {
+        let meminfo = "MemTotal:       16318480 kB\nMemFree:          512000 kB\nMemAvailable:    8159240 kB\n";
+        assert_eq!(proc_kb_field(meminfo, "MemFree"), Some("512000"));
+        assert_eq!(proc_kb_field(meminfo, "MemAvailable"), Some("8159240"));
+        assert_eq!(proc_kb_field(meminfo, "Mem"), None);
+        assert_eq!(proc_kb_field(meminfo, "SwapFree"), None);
+    }
+
+    async fn insert_test_commit(pool: &PgPool) -> Commit {
+        let name = format!("signature-test-{}", uuid::Uuid::new_v4());
+        let flake_id: i32 =
+            sqlx::query_scalar("INSERT INTO flakes (name, repo_url) VALUES ($1, $1) RETURNING id")
+                .bind(&name)
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        sqlx::query_as::<_, Commit>(
+            r#"
+            INSERT INTO commits (flake_id, git_commit_hash, commit_timestamp)
+            VALUES ($1, $2, NOW())
+            RETURNING id, flake_id, git_commit_hash, commit_timestamp, attempt_count
+            "#,
+        )
+        .bind(flake_id)
+        .bind(&name)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn evaluation_state(pool: &PgPool, commit: &Commit) -> (String, i32) {
+        sqlx::query_as(
+            "SELECT evaluation_status, COALESCE(evaluation_attempt_count, 0) FROM commits WHERE id = $1",
+        )
+        .bind(commit.id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejected_signature_dead_letters_the_commit() {
+        let Some(pool) = crate::db::test_pool().await else {
+            return;
+        };
+        let commit = insert_test_commit(&pool).await;
+
+        let check = Ok(Err("commit is not signed".to_string()));
+        assert!(!signature_allows_evaluation(&pool, &commit, check, 5).await);
+        assert_eq!(evaluation_state(&pool, &commit).await.0, "eval_failed");
+    }
+
+    #[tokio::test]
+    async fn failed_signature_check_uses_up_attempts() {
+        let Some(pool) = crate::db::test_pool().await else {
+            return;
+        };
+        let commit = insert_test_commit(&pool).await;
+
+        let check = Err(anyhow::anyhow!("git fetch failed"));
+        assert!(!signature_allows_evaluation(&pool, &commit, check, 2).await);
+        assert_eq!(
+            evaluation_state(&pool, &commit).await,
+            ("pending".to_string(), 1)
+        );
+
+        let check = Err(anyhow::anyhow!("git fetch failed"));
+        assert!(!signature_allows_evaluation(&pool, &commit, check, 2).await);
+        assert_eq!(
+            evaluation_state(&pool, &commit).await,
+            ("eval_failed".to_string(), 2)
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use rand::Rng;
+use std::time::Duration;
+use tokio::signal;
+use tokio::sync::watch;
+use tracing::info;
+
+/// Receiver side of the shutdown signal threaded through background loops.
+/// The value flips to `true` once shutdown has been requested.
+pub type ShutdownRx = watch::Receiver<bool>;
+
+/// Create a new shutdown channel. Keep the sender in `main` and hand clones
+/// of the receiver to each loop.
+pub fn channel() -> (watch::Sender<bool>, ShutdownRx) {
+    watch::channel(false)
+}
+
+/// Resolves once SIGINT or SIGTERM is received
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        let _ = signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("🛑 Received SIGINT"),
+        _ = terminate => info!("🛑 Received SIGTERM"),
+    }
+}
+
+/// Resolves once shutdown has been requested (or the sender is gone)
+pub async fn requested(rx: &mut ShutdownRx) {
+    if *rx.borrow() {
+        return;
+    }
+    while rx.changed().await.is_ok() {
+        if *rx.borrow() {
+            return;
+        }
+    }
+}
+
+/// Sleep for `duration`, waking early on shutdown.
+/// Returns `true` if shutdown was requested.
+pub async fn sleep_or_shutdown(duration: Duration, rx: &mut ShutdownRx) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => *rx.borrow(),
+        _ = requested(rx) => true,
+    }
+}
+
+/// Random delay between zero and `max`
+pub fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    let max_ms = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max_ms))
+}
+
+/// Wait a random delay of up to `max` before a loop's first iteration so
+/// processes restarted together don't hit the database in lockstep.
+/// Returns `true` if shutdown was requested meanwhile.
+pub async fn jittered_start(max: Duration, rx: &mut ShutdownRx) -> bool {
+    let delay = jitter(max);
+    if delay.is_zero() {
+        return *rx.borrow();
+    }
+    sleep_or_shutdown(delay, rx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_stays_within_max() {
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+        let max = Duration::from_millis(50);
+        assert!((0..100).all(|_| jitter(max) <= max));
+    }
+}
@@ -0,0 +1,171 @@
+//! Logging setup and, with the `otel` feature, OpenTelemetry trace export.
+//!
+//! Spans created with [`commit_span`] use a trace id derived from the commit
+//! hash, so the evaluation, builds and cache pushes of one commit end up in
+//! a single trace even though they run in different processes.
+
+use crate::config::CrystalForgeConfig;
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Keeps the trace exporter alive; pending spans are flushed on drop
+#[derive(Default)]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("Failed to flush traces: {e}");
+        }
+    }
+}
+
+/// Install the global tracing subscriber (RUST_LOG filtered console output)
+/// plus an OTLP exporter when `[telemetry]` configures one. Keep the guard
+/// alive for the life of the process.
+pub fn init(service_name: &str) -> TelemetryGuard {
+    let config = CrystalForgeConfig::load()
+        .map(|cfg| cfg.telemetry)
+        .unwrap_or_default();
+    let registry = tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer());
+
+    let Some(endpoint) = config.otlp_endpoint.as_deref() else {
+        registry.init();
+        return TelemetryGuard::default();
+    };
+
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let service_name = config.service_name.as_deref().unwrap_or(service_name);
+        match otel::provider(endpoint, service_name) {
+            Ok(provider) => {
+                let tracer = provider.tracer("crystal-forge");
+                registry
+                    .with(tracing_opentelemetry::layer().with_tracer(tracer))
+                    .init();
+                tracing::info!("📡 Exporting traces to {} as {}", endpoint, service_name);
+                TelemetryGuard {
+                    provider: Some(provider),
+                }
+            }
+            Err(e) => {
+                registry.init();
+                tracing::warn!("⚠️ Trace export to {} disabled: {:#}", endpoint, e);
+                TelemetryGuard::default()
+            }
+        }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        registry.init();
+        tracing::warn!(
+            "⚠️ telemetry.otlp_endpoint is set to {} but {} was built without the otel feature",
+            endpoint,
+            service_name
+        );
+        TelemetryGuard::default()
+    }
+}
+
+/// Span for work on `commit_hash`, parented to the commit's trace when
+/// traces are exported
+pub fn commit_span(
+    name: &'static str,
+    commit_hash: Option<&str>,
+    derivation_id: Option<i32>,
+) -> Span {
+    let span = tracing::info_span!(
+        "pipeline",
+        otel.name = name,
+        commit = commit_hash.unwrap_or_default(),
+        derivation_id = derivation_id.unwrap_or_default(),
+    );
+
+    #[cfg(feature = "otel")]
+    if let Some((trace_id, span_id)) = commit_hash.and_then(commit_trace_ids) {
+        otel::set_remote_parent(&span, trace_id, span_id);
+    }
+
+    span
+}
+
+/// Trace and root span ids for a commit, taken from the hex digits of its
+/// hash. `None` for anything that is not at least 40 hex digits.
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+fn commit_trace_ids(commit_hash: &str) -> Option<(u128, u64)> {
+    if commit_hash.len() < 40 || !commit_hash.is_ascii() {
+        return None;
+    }
+    let trace_id = u128::from_str_radix(&commit_hash[..32], 16).ok()?;
+    let span_id = u64::from_str_radix(&commit_hash[24..40], 16).ok()?;
+    (trace_id != 0 && span_id != 0).then_some((trace_id, span_id))
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use anyhow::Result;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing::Span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    pub(super) fn provider(endpoint: &str, service_name: &str) -> Result<SdkTracerProvider> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        Ok(SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(service_name.to_string())
+                    .build(),
+            )
+            .build())
+    }
+
+    pub(super) fn set_remote_parent(span: &Span, trace_id: u128, span_id: u64) {
+        let parent = SpanContext::new(
+            TraceId::from_bytes(trace_id.to_be_bytes()),
+            SpanId::from_bytes(span_id.to_be_bytes()),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        span.set_parent(opentelemetry::Context::new().with_remote_span_context(parent));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit_trace_ids_come_from_the_hash() {
+        let (trace_id, span_id) =
+            commit_trace_ids("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert_eq!(trace_id, 0x0123456789abcdef0123456789abcdef);
+        assert_eq!(span_id, 0x89abcdef01234567);
+
+        assert!(commit_trace_ids("0123456").is_none());
+        assert!(commit_trace_ids("zz23456789abcdef0123456789abcdef01234567").is_none());
+        assert!(commit_trace_ids(&"0".repeat(40)).is_none());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::derivations::utils::get_store_path_from_drv;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Single entry from vulnix JSON output - represents one affected derivation
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -118,6 +118,25 @@ impl VulnixParser {
             unknown_count: total_counts.unknown as usize,
         }
     }
+
+    /// Calculate statistics counting only actionable findings, i.e. leaving
+    /// out CVEs present in `whitelist`
+    pub fn calculate_actionable_stats(
+        entries: &VulnixScanOutput,
+        whitelist: &HashSet<String>,
+    ) -> ScanStats {
+        let actionable: VulnixScanOutput = entries
+            .iter()
+            .map(|entry| {
+                let mut entry = entry.clone();
+                entry
+                    .affected_by
+                    .retain(|cve_id| !whitelist.contains(cve_id));
+                entry
+            })
+            .collect();
+        Self::calculate_stats(&actionable)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -276,6 +295,31 @@ mod tests {
         assert_eq!(stats.high_count, 1); // CVE-2023-5678
     }
 
+    #[test]
+    fn test_calculate_actionable_stats_skips_whitelisted() {
+        let entries = vec![VulnixEntry {
+            name: "pkg1-1.0".to_string(),
+            pname: "pkg1".to_string(),
+            version: "1.0".to_string(),
+            affected_by: vec!["CVE-2023-1234".to_string(), "CVE-2023-5678".to_string()],
+            whitelisted: vec![],
+            derivation: "/nix/store/pkg1".to_string(),
+            cvssv3_basescore: [
+                ("CVE-2023-1234".to_string(), 9.5),
+                ("CVE-2023-5678".to_string(), 7.5),
+            ]
+            .into_iter()
+            .collect(),
+        }];
+        let whitelist: HashSet<String> = ["CVE-2023-1234".to_string()].into_iter().collect();
+
+        let stats = VulnixParser::calculate_actionable_stats(&entries, &whitelist);
+
+        assert_eq!(stats.total_vulnerabilities, 1);
+        assert_eq!(stats.critical_count, 0);
+        assert_eq!(stats.high_count, 1);
+    }
+
     #[test]
     fn test_all_cve_ids() {
         let entry = VulnixEntry {
@@ -1,8 +1,10 @@
-use crate::config::VulnixConfig;
+use crate::config::{BuildConfig, VulnixConfig};
+use crate::derivations::closure::list_transitive_input_drvs;
 use crate::vulnix::vulnix_parser::VulnixEntry;
 
 use anyhow::{Result, anyhow};
 use sqlx::PgPool;
+use std::collections::HashSet;
 use std::process::Command;
 use tokio::process::Command as AsyncCommand;
 use tracing::{error, info};
@@ -10,24 +12,40 @@ use tracing::{error, info};
 /// Array of VulnixEntry - this is what vulnix outputs as JSON
 pub type VulnixScanOutput = Vec<VulnixEntry>;
 
+/// `nix derivation show` calls in flight while collecting build inputs
+const BUILD_INPUT_CONCURRENCY: usize = 8;
+
+/// Build inputs passed to a single vulnix run, to stay clear of the
+/// argument length limit
+const BUILD_INPUTS_PER_RUN: usize = 256;
+
 #[derive(Debug)]
 pub struct VulnixRunner {
     config: VulnixConfig,
+    build_config: BuildConfig,
 }
 
 impl VulnixRunner {
     pub fn new() -> Self {
         Self {
             config: VulnixConfig::default(),
+            build_config: BuildConfig::default(),
         }
     }
 
     pub fn with_config(config: &VulnixConfig) -> Self {
         Self {
             config: config.clone(),
+            build_config: BuildConfig::default(),
         }
     }
 
+    /// Nix options used when walking build inputs
+    pub fn with_build_config(mut self, build_config: &BuildConfig) -> Self {
+        self.build_config = build_config.clone();
+        self
+    }
+
     /// Check if vulnix is available on the system
     pub async fn check_vulnix_available() -> bool {
         match Command::new("vulnix").arg("--version").output() {
@@ -51,20 +69,22 @@ impl VulnixRunner {
         }
     }
 
-    /// Scan a specific derivation
+    /// Scan a specific derivation: its runtime closure, and with
+    /// `scan_build_inputs` every derivation it was built from
     pub async fn scan_derivation(
         &self,
         pool: &PgPool,
         derivation_id: i32,
         vulnix_version: Option<String>,
     ) -> Result<VulnixScanOutput> {
-        // Fetch store path in a separate scope so connection is released
-        let store_path = {
+        // Fetch paths in a separate scope so connection is released
+        let (store_path, drv_path) = {
             let derivation =
                 crate::queries::derivations::get_derivation_by_id(pool, derivation_id).await?;
-            derivation
+            let store_path = derivation
                 .store_path
-                .ok_or_else(|| anyhow!("Derivation {} has no store_path", derivation_id))?
+                .ok_or_else(|| anyhow!("Derivation {} has no store_path", derivation_id))?;
+            (store_path, derivation.derivation_path)
         }; // Connection released here when `derivation` goes out of scope
 
         // Only scan if the path exists
@@ -79,10 +99,40 @@ impl VulnixRunner {
             "🔍 Scanning derivation {} with store path: {}",
             derivation_id, store_path
         );
+        let mut entries = self.run_vulnix(std::slice::from_ref(&store_path)).await?;
+
+        let Some(drv_path) = drv_path.filter(|_| self.config.scan_build_inputs) else {
+            return Ok(entries);
+        };
+        let inputs = list_transitive_input_drvs(
+            &drv_path,
+            usize::MAX,
+            BUILD_INPUT_CONCURRENCY,
+            &self.build_config,
+        )
+        .await?;
+        info!(
+            "🔍 Scanning {} build inputs of derivation {}",
+            inputs.len(),
+            derivation_id
+        );
+
+        let mut seen: HashSet<String> = entries.iter().map(|e| e.derivation.clone()).collect();
+        for chunk in inputs.chunks(BUILD_INPUTS_PER_RUN) {
+            for entry in self.run_vulnix(chunk).await? {
+                if seen.insert(entry.derivation.clone()) {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
 
+    /// Run vulnix on `paths` and parse its report
+    async fn run_vulnix(&self, paths: &[String]) -> Result<VulnixScanOutput> {
         // Build vulnix command
         let mut cmd = AsyncCommand::new("vulnix");
-        cmd.arg("--json").arg(&store_path);
+        cmd.arg("--json").args(paths);
 
         if self.config.enable_whitelist {
             cmd.arg("--whitelist").arg("/etc/vulnix-whitelist.toml");